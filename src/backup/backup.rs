@@ -0,0 +1,250 @@
+use std::{
+    collections::HashSet,
+    fs::{self, read_dir},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    database::{checkpoint, database::Database},
+    sstable::{
+        blob::{blob_path, BLOB_EXT},
+        sstable::{file_number, table_files, table_path},
+    },
+    version::{
+        edit::VersionEdit,
+        version_set::{read_manifest, VersionSet},
+    },
+    Error, Result,
+};
+
+const SHARED_DIR: &str = "shared";
+
+/// A backup as listed by `BackupEngine::backups`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupInfo {
+    pub id: u64,
+    pub tables: usize,
+    /// Size of the table files the backup refers to, shared or not.
+    pub size: u64,
+    pub last_sequence: u64,
+}
+
+/// Incremental backups of one database.
+///
+/// Tables and blob files never change once written, so each of their files is copied into
+/// `shared/` only by the first backup that needs it. A backup itself is a directory named
+/// after its id that holds a MANIFEST describing the tables it consists of.
+pub struct BackupEngine {
+    dir: PathBuf,
+}
+
+impl BackupEngine {
+    pub fn open(dir: &Path) -> Result<BackupEngine> {
+        fs::create_dir_all(dir.join(SHARED_DIR))?;
+        Ok(BackupEngine {
+            dir: dir.to_owned(),
+        })
+    }
+
+    /// Backs up the current state of `db`, flushing its memtable first. Returns the new
+    /// backup and the number of tables that had to be copied for it.
+    pub fn create_backup(&mut self, db: &mut Database) -> Result<(BackupInfo, usize)> {
+        let snapshot = db.snapshot()?;
+        let shared = self.dir.join(SHARED_DIR);
+        let mut copied = 0;
+        for (_, file) in snapshot.new_files.iter() {
+            let from = table_files(&table_path(db.dir(), file.number));
+            let to = table_files(&table_path(&shared, file.number));
+            // the table file is renamed into place last and marks a complete copy
+            if to[4].exists() {
+                continue;
+            }
+            for (from, to) in from.iter().zip(to.iter()).take(4) {
+                checkpoint::copy_file(from, to, false)?;
+            }
+            let tmp = to[4].with_extension("tmp");
+            checkpoint::copy_file(&from[4], &tmp, false)?;
+            fs::rename(tmp, &to[4])?;
+            copied += 1;
+        }
+        for file in snapshot.new_blob_files.iter() {
+            let to = blob_path(&shared, file.number);
+            if to.exists() {
+                continue;
+            }
+            let tmp = to.with_extension("tmp");
+            checkpoint::copy_file(&blob_path(db.dir(), file.number), &tmp, false)?;
+            fs::rename(tmp, to)?;
+        }
+        let id = self.ids()?.last().map_or(1, |id| id + 1);
+        let tmp = self.dir.join(format!("{}.tmp", id));
+        fs::remove_dir_all(&tmp).ok();
+        fs::create_dir(&tmp)?;
+        VersionSet::create(
+            &tmp,
+            snapshot.next_file_number.unwrap_or(1),
+            snapshot.last_sequence.unwrap_or(0),
+            snapshot,
+        )?;
+        fs::rename(&tmp, self.backup_dir(id))?;
+        Ok((self.info(id)?, copied))
+    }
+
+    /// All backups, oldest first.
+    pub fn backups(&self) -> Result<Vec<BackupInfo>> {
+        self.ids()?.into_iter().map(|id| self.info(id)).collect()
+    }
+
+    /// Recreates backup `id` as a database in `target`, which must not exist yet.
+    pub fn restore(&self, id: u64, target: &Path) -> Result<()> {
+        let snapshot = self.snapshot(id)?;
+        checkpoint::write_database(&self.dir.join(SHARED_DIR), &snapshot, target, false)
+    }
+
+    /// Deletes backup `id` and every shared table and blob file no other backup refers to.
+    pub fn delete_backup(&mut self, id: u64) -> Result<()> {
+        self.snapshot(id)?;
+        fs::remove_dir_all(self.backup_dir(id))?;
+        let (mut referenced, mut referenced_blobs) = (HashSet::new(), HashSet::new());
+        for id in self.ids()? {
+            let snapshot = self.snapshot(id)?;
+            referenced.extend(snapshot.new_files.iter().map(|(_, f)| f.number));
+            referenced_blobs.extend(snapshot.new_blob_files.iter().map(|f| f.number));
+        }
+        let shared = self.dir.join(SHARED_DIR);
+        let mut unreferenced = HashSet::new();
+        for file in read_dir(&shared)? {
+            let path = file?.path();
+            let Some(number) = file_number(&path) else {
+                continue;
+            };
+            // a blob file is numbered like the table that wrote it
+            if path.extension().is_some_and(|ext| ext == BLOB_EXT) {
+                if !referenced_blobs.contains(&number) {
+                    fs::remove_file(path)?;
+                }
+            } else if !referenced.contains(&number) {
+                unreferenced.insert(number);
+            }
+        }
+        for number in unreferenced {
+            // the table file goes first so a crash never leaves it looking complete
+            for file in table_files(&table_path(&shared, number)).iter().rev() {
+                if file.exists() {
+                    fs::remove_file(file)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn backup_dir(&self, id: u64) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    fn ids(&self) -> Result<Vec<u64>> {
+        let mut ids = Vec::new();
+        for file in read_dir(&self.dir)? {
+            let file = file?;
+            let id = file.file_name().to_str().and_then(|n| n.parse().ok());
+            if let (Some(id), true) = (id, file.file_type()?.is_dir()) {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// The MANIFEST of backup `id` as a single record.
+    fn snapshot(&self, id: u64) -> Result<VersionEdit> {
+        let state = read_manifest(&self.backup_dir(id))?
+            .ok_or_else(|| Error::NotFound(format!("backup {}", id)))?;
+        Ok(state.snapshot())
+    }
+
+    fn info(&self, id: u64) -> Result<BackupInfo> {
+        let snapshot = self.snapshot(id)?;
+        Ok(BackupInfo {
+            id,
+            tables: snapshot.new_files.len(),
+            size: snapshot.new_files.iter().map(|(_, f)| f.size).sum(),
+            last_sequence: snapshot.last_sequence.unwrap_or(0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_dirs(name: &str) -> (Database, BackupEngine, PathBuf) {
+        let dir = PathBuf::from("data").join(name);
+        fs::remove_dir_all(&dir).ok();
+        let db = Database::open(&dir.join("db")).unwrap();
+        let engine = BackupEngine::open(&dir.join("backup")).unwrap();
+        (db, engine, dir)
+    }
+
+    #[test]
+    fn test_backups_only_copy_new_tables() {
+        let (mut db, mut engine, dir) = create_dirs("backup_incremental");
+        db.set(b"a", b"1", 1).unwrap();
+        let (first, copied) = engine.create_backup(&mut db).unwrap();
+        assert_eq!((first.id, first.tables, copied), (1, 1, 1));
+        db.set(b"a", b"2", 2).unwrap();
+        db.set(b"b", b"2", 2).unwrap();
+        let (second, copied) = engine.create_backup(&mut db).unwrap();
+        assert_eq!((second.id, second.tables, copied), (2, 2, 1));
+        assert_eq!(engine.backups().unwrap(), vec![first, second]);
+
+        engine.restore(1, &dir.join("restored1")).unwrap();
+        engine.restore(2, &dir.join("restored2")).unwrap();
+        let restored = Database::open(&dir.join("restored1")).unwrap();
+        assert_eq!(
+            restored.get(b"a").unwrap().unwrap().value,
+            Some(b"1".to_vec())
+        );
+        assert!(restored.get(b"b").unwrap().is_none());
+        let restored = Database::open(&dir.join("restored2")).unwrap();
+        assert_eq!(
+            restored.get(b"a").unwrap().unwrap().value,
+            Some(b"2".to_vec())
+        );
+        assert_eq!(restored.stats().last_sequence, 3);
+    }
+
+    #[test]
+    fn test_delete_backup_keeps_shared_tables_in_use() {
+        let (mut db, mut engine, dir) = create_dirs("backup_delete");
+        db.set(b"a", b"1", 1).unwrap();
+        engine.create_backup(&mut db).unwrap();
+        db.set(b"b", b"2", 2).unwrap();
+        db.flush().unwrap();
+        db.compact().unwrap();
+        engine.create_backup(&mut db).unwrap();
+        db.set(b"c", b"3", 3).unwrap();
+        engine.create_backup(&mut db).unwrap();
+        let shared = || {
+            read_dir(dir.join("backup").join(SHARED_DIR))
+                .unwrap()
+                .count()
+        };
+        assert_eq!(shared(), 3 * 5);
+
+        engine.delete_backup(1).unwrap();
+        assert_eq!(shared(), 2 * 5);
+        engine.delete_backup(2).unwrap();
+        assert_eq!(shared(), 2 * 5);
+        assert!(matches!(engine.delete_backup(2), Err(Error::NotFound(_))));
+        engine.restore(3, &dir.join("restored")).unwrap();
+        let restored = Database::open(&dir.join("restored")).unwrap();
+        assert_eq!(
+            restored.get(b"b").unwrap().unwrap().value,
+            Some(b"2".to_vec())
+        );
+        assert_eq!(
+            restored.get(b"c").unwrap().unwrap().value,
+            Some(b"3".to_vec())
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use rustdb::{
+    database::async_database::AsyncDatabase,
+    server::{binary::BinaryServer, http::HttpServer, server::Server},
+};
+use tokio::{sync::watch, task::JoinSet};
+
+const USAGE: &str =
+    "usage: rustdb-server [--dir <path>] [--bind <addr>] [--http <addr>] [--binary <addr>]";
+
+struct Args {
+    dir: PathBuf,
+    bind: String,
+    http: Option<String>,
+    binary: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        dir: PathBuf::from("data"),
+        bind: "127.0.0.1:6379".to_string(),
+        http: None,
+        binary: None,
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        let mut value = || argv.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--dir" => args.dir = PathBuf::from(value()?),
+            "--bind" => args.bind = value()?,
+            "--http" => args.http = Some(value()?),
+            "--binary" => args.binary = Some(value()?),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => return Err(format!("unknown argument {}\n{}", arg, USAGE)),
+        }
+    }
+    Ok(args)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = async {
+        let db = AsyncDatabase::open(args.dir).await?;
+        let (stop, stopped) = watch::channel(false);
+        let shutdown = |mut stopped: watch::Receiver<bool>| async move {
+            stopped.wait_for(|stopped| *stopped).await.ok();
+        };
+        let mut servers = JoinSet::new();
+
+        let server = Server::bind(&args.bind, db.clone()).await?;
+        eprintln!("listening on {}", server.local_addr()?);
+        servers.spawn(server.run_until(shutdown(stopped.clone())));
+        if let Some(addr) = &args.http {
+            let http = HttpServer::bind(addr, db.clone()).await?;
+            eprintln!("http gateway listening on {}", http.local_addr()?);
+            servers.spawn(http.run_until(shutdown(stopped.clone())));
+        }
+        if let Some(addr) = &args.binary {
+            let binary = BinaryServer::bind(addr, db).await?;
+            eprintln!("binary protocol listening on {}", binary.local_addr()?);
+            servers.spawn(binary.run_until(shutdown(stopped)));
+        }
+
+        tokio::signal::ctrl_c().await.ok();
+        stop.send(true).ok();
+        let mut result = Ok(());
+        while let Some(joined) = servers.join_next().await {
+            let server_result = joined.expect("server panicked");
+            result = result.and(server_result);
+        }
+        result
+    }
+    .await;
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("rustdb-server: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,650 @@
+use std::{
+    io::{self, BufRead, IsTerminal, Write},
+    ops::Bound,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use rustdb::{
+    database::{
+        column_family::DEFAULT_COLUMN_FAMILY, comparator, database::Database, options::Options,
+        repair::RepairedFile,
+    },
+    encoding::{base64_decode, base64_encode, hex_decode, hex_encode},
+    sstable::sstable::{file_number, table_path, SSTable},
+    version::version_set::read_manifest,
+    wal::repair,
+    Error, Result,
+};
+
+const USAGE: &str = "usage: rustdb [--dir <path>] [--format utf8|hex|base64]
+       rustdb sst-dump [--format utf8|hex|base64] [--summary] <table>
+       rustdb wal-dump [--format utf8|hex|base64] [--summary] <wal>
+       rustdb wal-repair [--salvage <dir>] <wal>
+       rustdb [--dir <path>] check
+       rustdb [--dir <path>] repair
+
+Without a command, reads commands from stdin, prompting for them when stdin is a terminal.
+Without a terminal the first failing command ends the run with a non-zero exit code.
+
+sst-dump prints the index, blocks, records and statistics of a table given by any of its
+files and checks it; it exits with a non-zero code if the table has problems.
+
+wal-dump prints the records of a WAL and where a batch cut short by a crash begins.
+wal-repair truncates a WAL to its last complete batch, or with --salvage writes the newest
+version of every key of the default column family into a new table in dir, leaving the
+WAL untouched.
+
+check verifies every live table and WAL of a database and exits with a non-zero code if
+it finds problems.
+
+repair rebuilds the tables of a database, replays its WALs and writes a new MANIFEST.
+
+Keys are ordered by the comparator recorded in the database's MANIFEST, which has to be
+one of the built-in comparators. Tables and WALs outside a database are taken to be
+ordered bytewise.";
+
+const HELP: &str = "commands:
+  get <key>                      print the value of key
+  set <key> <value>              set key to value
+  del <key>                      delete key
+  scan [<start> [<end>]] [limit] print entries in [start, end), - for unbounded
+  flush                          write the memtable to a table
+  compact                        merge all tables
+  stats                          print table and memtable sizes
+  format utf8|hex|base64         change how keys and values are printed
+  help                           print this help
+  quit                           leave the shell
+
+Arguments are utf8 unless prefixed with hex: or b64:, and may be quoted with \"...\".";
+
+const DEFAULT_SCAN_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Utf8,
+    Hex,
+    Base64,
+}
+
+impl Format {
+    fn parse(name: &str) -> Result<Format> {
+        match name {
+            "utf8" => Ok(Format::Utf8),
+            "hex" => Ok(Format::Hex),
+            "base64" => Ok(Format::Base64),
+            _ => Err(Error::InvalidArgument(format!("unknown format {}", name))),
+        }
+    }
+
+    fn display(self, bytes: &[u8]) -> String {
+        match self {
+            Format::Utf8 => match std::str::from_utf8(bytes) {
+                Ok(text) => text.to_string(),
+                Err(_) => bytes.escape_ascii().to_string(),
+            },
+            Format::Hex => hex_encode(bytes),
+            Format::Base64 => base64_encode(bytes),
+        }
+    }
+}
+
+struct Args {
+    dir: PathBuf,
+    format: Format,
+    summary: bool,
+    salvage: Option<PathBuf>,
+    // the command followed by its operands
+    command: Vec<String>,
+}
+
+fn parse_args() -> std::result::Result<Args, String> {
+    let mut args = Args {
+        dir: PathBuf::from("data"),
+        format: Format::Utf8,
+        summary: false,
+        salvage: None,
+        command: Vec::new(),
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        let mut value = || argv.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--dir" => args.dir = PathBuf::from(value()?),
+            "--format" => args.format = Format::parse(&value()?).map_err(|err| err.to_string())?,
+            "--summary" => args.summary = true,
+            "--salvage" => args.salvage = Some(PathBuf::from(value()?)),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => {
+                return Err(format!("unknown argument {}\n{}", arg, USAGE))
+            }
+            _ => args.command.push(arg),
+        }
+    }
+    let arity_ok = match args.command.first().map(String::as_str) {
+        None | Some("check" | "repair") => args.command.len() <= 1,
+        Some("sst-dump" | "wal-dump" | "wal-repair") => args.command.len() == 2,
+        Some(command) => return Err(format!("unknown command {}\n{}", command, USAGE)),
+    };
+    if !arity_ok {
+        return Err(USAGE.to_string());
+    }
+    Ok(args)
+}
+
+struct Shell {
+    db: Database,
+    format: Format,
+}
+
+enum Outcome {
+    Continue,
+    Quit,
+}
+
+impl Shell {
+    fn execute(&mut self, line: &str, out: &mut impl Write) -> Result<Outcome> {
+        let words = split_words(line)?;
+        let Some((command, args)) = words.split_first() else {
+            return Ok(Outcome::Continue);
+        };
+        let arity_ok = match command.as_str() {
+            "get" | "del" | "format" => args.len() == 1,
+            "set" => args.len() == 2,
+            "scan" => args.len() <= 3,
+            "flush" | "compact" | "stats" | "help" | "quit" | "exit" => args.is_empty(),
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown command {}, try help",
+                    command
+                )))
+            }
+        };
+        if !arity_ok {
+            return Err(Error::InvalidArgument(format!(
+                "wrong number of arguments for {}",
+                command
+            )));
+        }
+        match command.as_str() {
+            "get" => match self.db.get(&parse_bytes(&args[0])?)? {
+                Some(entry) if !entry.deleted => {
+                    let value = entry.value.unwrap_or_default();
+                    writeln!(out, "{}", self.format.display(&value))?;
+                }
+                _ => writeln!(out, "(nil)")?,
+            },
+            "set" => {
+                let (key, value) = (parse_bytes(&args[0])?, parse_bytes(&args[1])?);
+                self.db.set(&key, &value, self.db.timestamp())?;
+                self.db.sync()?;
+                writeln!(out, "OK")?;
+            }
+            "del" => {
+                self.db
+                    .delete(&parse_bytes(&args[0])?, self.db.timestamp())?;
+                self.db.sync()?;
+                writeln!(out, "OK")?;
+            }
+            "scan" => self.scan(args, out)?,
+            "flush" => {
+                self.db.flush()?;
+                writeln!(out, "OK")?;
+            }
+            "compact" => {
+                self.db.compact()?;
+                writeln!(out, "OK")?;
+            }
+            "stats" => writeln!(out, "{}", self.db.stats())?,
+            "format" => self.format = Format::parse(&args[0])?,
+            "help" => writeln!(out, "{}", HELP)?,
+            _ => return Ok(Outcome::Quit),
+        }
+        Ok(Outcome::Continue)
+    }
+
+    fn scan(&self, args: &[String], out: &mut impl Write) -> Result<()> {
+        let bound = |arg: Option<&String>, bound: fn(Vec<u8>) -> Bound<Vec<u8>>| match arg {
+            None => Ok(Bound::Unbounded),
+            Some(arg) if arg == "-" => Ok(Bound::Unbounded),
+            Some(arg) => Ok::<_, Error>(bound(parse_bytes(arg)?)),
+        };
+        let start = bound(args.first(), Bound::Included)?;
+        let end = bound(args.get(1), Bound::Excluded)?;
+        let limit = match args.get(2) {
+            Some(limit) => limit
+                .parse()
+                .map_err(|_| Error::InvalidArgument(format!("invalid limit {}", limit)))?,
+            None => DEFAULT_SCAN_LIMIT,
+        };
+        for entry in self.db.scan((start, end))?.take(limit) {
+            let entry = entry?;
+            writeln!(
+                out,
+                "{}\t{}",
+                self.format.display(&entry.key),
+                self.format.display(&entry.value.unwrap_or_default())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a command line at whitespace, keeping quoted words together.
+fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(words);
+        };
+        let mut word = String::new();
+        if first == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') if chars.peek().is_some() => word.extend(chars.next()),
+                    Some(c) => word.push(c),
+                    None => return Err(Error::InvalidArgument("unterminated quote".to_string())),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+}
+
+fn parse_bytes(arg: &str) -> Result<Vec<u8>> {
+    let decoded = if let Some(hex) = arg.strip_prefix("hex:") {
+        hex_decode(hex)
+    } else if let Some(base64) = arg.strip_prefix("b64:") {
+        base64_decode(base64)
+    } else {
+        Some(arg.as_bytes().to_vec())
+    };
+    decoded.ok_or_else(|| Error::InvalidArgument(format!("cannot decode {}", arg)))
+}
+
+/// Prints what `SSTable::inspect` finds in the table that `file` belongs to. Returns
+/// whether the table is healthy.
+fn sst_dump(file: &Path, format: Format, summary: bool, out: &mut impl Write) -> Result<bool> {
+    let path = match (file.parent(), file_number(file)) {
+        (Some(dir), Some(number)) => table_path(dir, number),
+        _ => file.to_owned(),
+    };
+    writeln!(out, "table {}", path.display())?;
+    if !summary {
+        writeln!(out, "records:")?;
+    }
+    let mut write_error = None;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let comparator = options(dir)?.comparator;
+    let report = SSTable::inspect(&path, &*comparator, |offset, entry| {
+        if summary || write_error.is_some() {
+            return;
+        }
+        let value = match (&entry.value, entry.blob) {
+            (Some(value), _) if !entry.deleted => format!("value_len={}", value.len()),
+            (None, Some(blob)) if !entry.deleted => format!(
+                "blob={:06} blob_offset={} value_len={}",
+                blob.file_number, blob.offset, blob.len
+            ),
+            (None, None) if !entry.deleted => "no_base".to_string(),
+            _ if entry.single_delete => "single_delete".to_string(),
+            _ => "tombstone".to_string(),
+        };
+        let line = writeln!(
+            out,
+            "  offset={} key={} timestamp={} {}{}{}",
+            offset,
+            format.display(&entry.key),
+            entry.timestamp,
+            value,
+            operands(&entry.operands),
+            expiry(entry.expires_at)
+        );
+        write_error = line.err();
+    })?;
+    if let Some(err) = write_error {
+        return Err(err.into());
+    }
+    if !summary {
+        writeln!(out, "index:")?;
+        for entry in &report.index {
+            writeln!(
+                out,
+                "  offset={} key={}",
+                entry.offset,
+                format.display(&entry.key)
+            )?;
+        }
+        writeln!(out, "blocks:")?;
+        for block in &report.blocks {
+            let checksum = match block.checksum_ok {
+                Some(true) => "ok",
+                Some(false) => "mismatch",
+                None => "none",
+            };
+            writeln!(
+                out,
+                "  offset={} len={} entries={} checksum={}",
+                block.offset, block.len, block.entries, checksum
+            )?;
+        }
+        writeln!(out, "range_tombstones:")?;
+        for tombstone in &report.range_tombstones {
+            writeln!(
+                out,
+                "  start={} end={} timestamp={}",
+                format.display(&tombstone.start),
+                format.display(&tombstone.end),
+                tombstone.timestamp
+            )?;
+        }
+    }
+    writeln!(out, "summary:")?;
+    if let (Some(smallest), Some(largest)) = (&report.smallest, &report.largest) {
+        writeln!(
+            out,
+            "  key_range: {} .. {}",
+            format.display(smallest),
+            format.display(largest)
+        )?;
+    }
+    writeln!(out, "  entries: {}", report.entries)?;
+    writeln!(out, "  tombstones: {}", report.tombstones)?;
+    writeln!(out, "  range_tombstones: {}", report.range_tombstones.len())?;
+    writeln!(out, "  blocks: {}", report.blocks.len())?;
+    writeln!(out, "  key_bytes: {}", report.key_bytes)?;
+    writeln!(out, "  value_bytes: {}", report.value_bytes)?;
+    writeln!(out, "  data_bytes: {}", report.data_bytes)?;
+    writeln!(out, "  index_bytes: {}", report.index_bytes)?;
+    writeln!(out, "  filter_bytes: {}", report.filter_bytes)?;
+    if report.is_ok() {
+        writeln!(out, "ok")?;
+    } else {
+        writeln!(out, "problems:")?;
+        for problem in &report.problems {
+            writeln!(out, "  {}", problem)?;
+        }
+    }
+    Ok(report.is_ok())
+}
+
+/// Prints the complete records of the WAL at `path`. Returns whether it ends with a
+/// complete batch.
+fn expiry(expires_at: Option<u128>) -> String {
+    match expires_at {
+        Some(expires_at) => format!(" expires_at={}", expires_at),
+        None => String::new(),
+    }
+}
+
+fn operands(operands: &[Vec<u8>]) -> String {
+    match operands.len() {
+        0 => String::new(),
+        count => format!(" operands={}", count),
+    }
+}
+
+fn wal_dump(path: &Path, format: Format, summary: bool, out: &mut impl Write) -> Result<bool> {
+    writeln!(out, "wal {}", path.display())?;
+    if !summary {
+        writeln!(out, "records:")?;
+    }
+    let mut write_error = None;
+    let report = repair::inspect(path, |entry| {
+        if summary || write_error.is_some() {
+            return;
+        }
+        let (op, value) = match (&entry.value, &entry.range_end) {
+            (_, Some(end)) => ("delete_range", format!(" end={}", format.display(end))),
+            _ if !entry.operands.is_empty() => {
+                ("merge", format!(" operand_len={}", entry.operands[0].len()))
+            }
+            (Some(value), None) if !entry.deleted => ("put", format!(" value_len={}", value.len())),
+            _ if entry.single_delete => ("single_delete", String::new()),
+            _ => ("delete", String::new()),
+        };
+        let line = writeln!(
+            out,
+            "  offset={} seqno={} op={} key={}{} timestamp={}{}",
+            entry.offset,
+            entry.seqno,
+            op,
+            format.display(&entry.key),
+            value,
+            entry.timestamp,
+            expiry(entry.expires_at)
+        );
+        write_error = line.err();
+    })?;
+    if let Some(err) = write_error {
+        return Err(err.into());
+    }
+    writeln!(out, "summary:")?;
+    writeln!(out, "  records: {}", report.records)?;
+    writeln!(out, "  valid_len: {}", report.valid_len)?;
+    writeln!(out, "  file_len: {}", report.file_len)?;
+    match report.partial_batch() {
+        Some(offset) => writeln!(
+            out,
+            "partial batch at offset {} ({} bytes)",
+            offset,
+            report.file_len - offset
+        )?,
+        None => writeln!(out, "ok")?,
+    }
+    Ok(report.partial_batch().is_none())
+}
+
+fn wal_repair(path: &Path, salvage: Option<&Path>, out: &mut impl Write) -> Result<bool> {
+    let Some(dir) = salvage else {
+        let report = repair::truncate(path)?;
+        match report.partial_batch() {
+            Some(offset) => writeln!(
+                out,
+                "truncated {} to {} bytes, dropping {} bytes",
+                path.display(),
+                offset,
+                report.file_len - offset
+            )?,
+            None => writeln!(out, "{} has no partial batch", path.display())?,
+        }
+        return Ok(true);
+    };
+    std::fs::create_dir_all(dir)?;
+    let number = file_number(path).unwrap_or(0);
+    let comparator = options(path.parent().unwrap_or(Path::new(".")))?.comparator;
+    match repair::salvage(path, dir, number, DEFAULT_COLUMN_FAMILY, comparator)? {
+        Some(sstable) => writeln!(out, "salvaged into {}", sstable.path.display())?,
+        None => writeln!(out, "{} has no records to salvage", path.display())?,
+    }
+    Ok(true)
+}
+
+/// Prints what `Database::verify` finds. Returns whether the database is healthy.
+fn check(dir: &Path, out: &mut impl Write) -> Result<bool> {
+    let report = Database::open_with_options(dir, options(dir)?)?.verify()?;
+    for (level, table) in &report.tables {
+        let name = table.path.file_name().unwrap_or_default().to_string_lossy();
+        write!(
+            out,
+            "table {} level={} entries={} blocks={}",
+            name,
+            level,
+            table.entries,
+            table.blocks.len()
+        )?;
+        if table.is_ok() {
+            writeln!(out, " ok")?;
+        } else {
+            writeln!(out, " problems:")?;
+            for problem in &table.problems {
+                writeln!(out, "  {}", problem)?;
+            }
+        }
+    }
+    for (path, log) in &report.logs {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        write!(out, "wal {} records={}", name, log.records)?;
+        match log.partial_batch() {
+            Some(offset) => writeln!(
+                out,
+                " partial batch at offset {} ({} bytes)",
+                offset,
+                log.file_len - offset
+            )?,
+            None => writeln!(out, " ok")?,
+        }
+    }
+    if !report.problems.is_empty() {
+        writeln!(out, "problems:")?;
+        for problem in &report.problems {
+            writeln!(out, "  {}", problem)?;
+        }
+    }
+    writeln!(out, "{}", if report.is_ok() { "ok" } else { "damaged" })?;
+    Ok(report.is_ok())
+}
+
+fn repair(dir: &Path, out: &mut impl Write) -> Result<bool> {
+    let report = Database::repair_with_options(dir, options(dir)?)?;
+    if !report.manifest_ok {
+        writeln!(out, "manifest unreadable, keeping every table on level 0")?;
+    }
+    let print = |out: &mut dyn Write, file: &RepairedFile| -> io::Result<()> {
+        let table = match file.tables.as_slice() {
+            [] => "nothing".to_string(),
+            numbers => numbers
+                .iter()
+                .map(|number| format!("table {:06}", number))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        writeln!(
+            out,
+            "{}: {} records into {}, dropped {} records and {} bytes",
+            file.path.display(),
+            file.records,
+            table,
+            file.dropped_records,
+            file.dropped_bytes
+        )
+    };
+    for file in report.tables.iter().chain(&report.logs) {
+        print(out, file)?;
+    }
+    for path in &report.obsolete {
+        writeln!(out, "{}: obsolete, removed", path.display())?;
+    }
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut out = io::stdout().lock();
+    let result = match args.command.first().map(String::as_str) {
+        Some("sst-dump") => sst_dump(
+            Path::new(&args.command[1]),
+            args.format,
+            args.summary,
+            &mut out,
+        ),
+        Some("wal-dump") => wal_dump(
+            Path::new(&args.command[1]),
+            args.format,
+            args.summary,
+            &mut out,
+        ),
+        Some("wal-repair") => wal_repair(
+            Path::new(&args.command[1]),
+            args.salvage.as_deref(),
+            &mut out,
+        ),
+        Some("check") => check(&args.dir, &mut out),
+        Some("repair") => repair(&args.dir, &mut out),
+        _ => return run_shell(args, &mut out),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("rustdb: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Options for opening the database in `dir` with the comparator it was created with.
+fn options(dir: &Path) -> Result<Options> {
+    let name = read_manifest(dir)
+        .ok()
+        .flatten()
+        .and_then(|state| state.current.comparator().map(str::to_string));
+    let Some(name) = name else {
+        return Ok(Options::default());
+    };
+    let comparator = comparator::builtin(&name).ok_or_else(|| {
+        Error::InvalidArgument(format!(
+            "{} uses the unknown comparator {}",
+            dir.display(),
+            name
+        ))
+    })?;
+    Ok(Options {
+        comparator,
+        ..Default::default()
+    })
+}
+
+fn run_shell(args: Args, out: &mut impl Write) -> ExitCode {
+    let db = match options(&args.dir).and_then(|o| Database::open_with_options(&args.dir, o)) {
+        Ok(db) => db,
+        Err(err) => {
+            eprintln!("rustdb: cannot open {}: {}", args.dir.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut shell = Shell {
+        db,
+        format: args.format,
+    };
+    let interactive = io::stdin().is_terminal();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        if interactive {
+            write!(out, "rustdb> ").ok();
+            out.flush().ok();
+        }
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(err)) => {
+                eprintln!("rustdb: {}", err);
+                return ExitCode::FAILURE;
+            }
+            None => break,
+        };
+        match shell.execute(&line, out) {
+            Ok(Outcome::Continue) => (),
+            Ok(Outcome::Quit) => break,
+            Err(err) if interactive => eprintln!("error: {}", err),
+            Err(err) => {
+                eprintln!("rustdb: {}: {}", line.trim(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
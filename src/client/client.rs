@@ -0,0 +1,331 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    ops::RangeBounds,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::{
+    io::{AsyncWriteExt, BufReader, BufWriter},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream, ToSocketAddrs,
+    },
+    sync::{mpsc, Mutex as AsyncMutex},
+};
+
+use crate::{
+    database::{
+        column_family::DEFAULT_COLUMN_FAMILY,
+        entry::Entry,
+        write_batch::{BatchOp, WriteBatch},
+    },
+    server::protocol::{self, Frame, Kind, ScanRequest},
+    Error, Result,
+};
+
+/// Number of entries a scan may send ahead of the ones consumed.
+const SCAN_WINDOW: u32 = 1024;
+
+/// Client of `BinaryServer`.
+///
+/// Clones share one connection; requests made concurrently are pipelined on it and their
+/// responses matched by request id.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    writer: AsyncMutex<BufWriter<OwnedWriteHalf>>,
+    next_id: AtomicU64,
+    // requests waiting for responses, `None` once the connection is closed
+    waiting: Mutex<Option<HashMap<u64, mpsc::UnboundedSender<Frame>>>>,
+}
+
+impl Client {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Client> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        let inner = Arc::new(Inner {
+            writer: AsyncMutex::new(BufWriter::new(writer)),
+            next_id: AtomicU64::new(1),
+            waiting: Mutex::new(Some(HashMap::new())),
+        });
+        tokio::spawn(read_responses(reader, inner.clone()));
+        Ok(Client { inner })
+    }
+
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let frame = self.inner.call(Kind::Get, key.to_vec()).await?;
+        match frame.kind {
+            Kind::Value => Ok(protocol::decode_entries(&frame.payload)?
+                .pop()
+                .and_then(|entry| entry.value)),
+            Kind::NotFound => Ok(None),
+            _ => Err(unexpected(&frame)),
+        }
+    }
+
+    /// Sets `key` with the server's clock as timestamp.
+    pub async fn set(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value, 0);
+        self.write(&batch).await
+    }
+
+    pub async fn delete(&self, key: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key, 0);
+        self.write(&batch).await
+    }
+
+    /// See `Database::single_delete` for when this is safe.
+    pub async fn single_delete(&self, key: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.single_delete(key, 0);
+        self.write(&batch).await
+    }
+
+    /// See `Database::merge`; the server's merge operator folds the operand.
+    pub async fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.merge(key, operand, 0);
+        self.write(&batch).await
+    }
+
+    /// Applies `batch` atomically. Operations with timestamp 0 get the server's clock.
+    /// Only the default column family can be written remotely, and range deletes cannot.
+    pub async fn write(&self, batch: &WriteBatch) -> Result<()> {
+        if batch
+            .ops()
+            .iter()
+            .any(|op| op.column_family() != DEFAULT_COLUMN_FAMILY)
+        {
+            return Err(Error::InvalidArgument(
+                "only the default column family can be written remotely".to_string(),
+            ));
+        }
+        let entries: Vec<Entry> = batch
+            .ops()
+            .iter()
+            .map(|op| match op {
+                BatchOp::DeleteRange { .. } => Err(Error::InvalidArgument(
+                    "range deletes cannot be written remotely".to_string(),
+                )),
+                BatchOp::Put {
+                    key,
+                    value,
+                    timestamp,
+                    expires_at,
+                    ..
+                } => Ok(Entry {
+                    key: key.clone(),
+                    value: Some(value.clone()),
+                    timestamp: *timestamp,
+                    deleted: false,
+                    single_delete: false,
+                    blob: None,
+                    expires_at: *expires_at,
+                    operands: Vec::new(),
+                }),
+                BatchOp::Delete { key, timestamp, .. } => Ok(Entry {
+                    key: key.clone(),
+                    value: None,
+                    timestamp: *timestamp,
+                    deleted: true,
+                    single_delete: false,
+                    blob: None,
+                    expires_at: None,
+                    operands: Vec::new(),
+                }),
+                BatchOp::SingleDelete { key, timestamp, .. } => Ok(Entry {
+                    key: key.clone(),
+                    value: None,
+                    timestamp: *timestamp,
+                    deleted: true,
+                    single_delete: true,
+                    blob: None,
+                    expires_at: None,
+                    operands: Vec::new(),
+                }),
+                BatchOp::Merge {
+                    key,
+                    operand,
+                    timestamp,
+                    ..
+                } => Ok(Entry {
+                    key: key.clone(),
+                    value: None,
+                    timestamp: *timestamp,
+                    deleted: false,
+                    single_delete: false,
+                    blob: None,
+                    expires_at: None,
+                    operands: vec![operand.clone()],
+                }),
+            })
+            .collect::<Result<_>>()?;
+        let frame = self
+            .inner
+            .call(Kind::Write, protocol::encode_entries(entries.iter()))
+            .await?;
+        match frame.kind {
+            Kind::Ok => Ok(()),
+            _ => Err(unexpected(&frame)),
+        }
+    }
+
+    /// Streams the live entries with keys in `range`, at most `limit` of them. The server
+    /// stays at most a window of entries ahead of the caller.
+    pub async fn scan(&self, range: impl RangeBounds<Vec<u8>>, limit: Option<u64>) -> Result<Scan> {
+        let request = ScanRequest {
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+            limit: limit.unwrap_or(0),
+            window: SCAN_WINDOW,
+        };
+        let (id, responses) = self.inner.register()?;
+        let scan = Scan {
+            inner: self.inner.clone(),
+            id,
+            responses,
+            entries: VecDeque::new(),
+            consumed: 0,
+            done: false,
+        };
+        self.inner
+            .send(Frame::new(id, Kind::Scan, request.encode()))
+            .await?;
+        Ok(scan)
+    }
+}
+
+/// Entries of a scan in progress. Dropping it cancels the scan.
+pub struct Scan {
+    inner: Arc<Inner>,
+    id: u64,
+    responses: mpsc::UnboundedReceiver<Frame>,
+    entries: VecDeque<Entry>,
+    // entries consumed since credit was last returned
+    consumed: u32,
+    done: bool,
+}
+
+impl Scan {
+    pub async fn next(&mut self) -> Result<Option<Entry>> {
+        while self.entries.is_empty() {
+            if self.done {
+                return Ok(None);
+            }
+            let frame = self.responses.recv().await.ok_or_else(closed)?;
+            match frame.kind {
+                Kind::Entries => self
+                    .entries
+                    .extend(protocol::decode_entries(&frame.payload)?),
+                Kind::End => self.done = true,
+                _ => {
+                    self.done = true;
+                    return Err(unexpected(&frame));
+                }
+            }
+        }
+        self.consumed += 1;
+        if self.consumed >= SCAN_WINDOW / 2 && !self.done {
+            let credit = self.consumed.to_le_bytes().to_vec();
+            self.inner
+                .send(Frame::new(self.id, Kind::Credit, credit))
+                .await?;
+            self.consumed = 0;
+        }
+        Ok(self.entries.pop_front())
+    }
+}
+
+impl Drop for Scan {
+    fn drop(&mut self) {
+        self.inner.unregister(self.id);
+        if self.done {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let (inner, id) = (self.inner.clone(), self.id);
+            runtime.spawn(async move {
+                inner
+                    .send(Frame::new(id, Kind::Cancel, Vec::new()))
+                    .await
+                    .ok();
+            });
+        }
+    }
+}
+
+impl Inner {
+    fn register(&self) -> Result<(u64, mpsc::UnboundedReceiver<Frame>)> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        match self.waiting.lock().unwrap().as_mut() {
+            Some(waiting) => waiting.insert(id, sender),
+            None => return Err(closed()),
+        };
+        Ok((id, receiver))
+    }
+
+    fn unregister(&self, id: u64) {
+        if let Some(waiting) = self.waiting.lock().unwrap().as_mut() {
+            waiting.remove(&id);
+        }
+    }
+
+    async fn send(&self, frame: Frame) -> Result<()> {
+        let mut writer = self.writer.lock().await;
+        frame.write_to(&mut *writer).await?;
+        Ok(writer.flush().await?)
+    }
+
+    /// Sends a request and waits for its response, turning error responses into errors.
+    async fn call(&self, kind: Kind, payload: Vec<u8>) -> Result<Frame> {
+        let (id, mut responses) = self.register()?;
+        let result = self.send(Frame::new(id, kind, payload)).await;
+        let response = match result {
+            Ok(()) => responses.recv().await.ok_or_else(closed),
+            Err(err) => Err(err),
+        };
+        self.unregister(id);
+        let frame = response?;
+        match frame.kind {
+            Kind::Error => Err(protocol::decode_error(&frame.payload)),
+            _ => Ok(frame),
+        }
+    }
+}
+
+async fn read_responses(reader: OwnedReadHalf, inner: Arc<Inner>) {
+    let mut reader = BufReader::new(reader);
+    while let Ok(Some(frame)) = Frame::read_from(&mut reader).await {
+        let waiting = inner.waiting.lock().unwrap();
+        let Some(sender) = waiting.as_ref().and_then(|waiting| waiting.get(&frame.id)) else {
+            // a cancelled scan or a response to a request that could not be parsed
+            continue;
+        };
+        sender.send(frame).ok();
+    }
+    // wakes up everyone still waiting
+    inner.waiting.lock().unwrap().take();
+}
+
+fn closed() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "connection closed",
+    ))
+}
+
+fn unexpected(frame: &Frame) -> Error {
+    match frame.kind {
+        Kind::Error => protocol::decode_error(&frame.payload),
+        kind => Error::InvalidArgument(format!("Protocol error: unexpected {:?} response", kind)),
+    }
+}
@@ -0,0 +1,457 @@
+use std::{
+    fs::File,
+    io,
+    ops::RangeBounds,
+    panic,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::{mpsc, watch},
+    task,
+};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{version::edit::VersionEdit, Result};
+
+use super::{
+    changes::Change,
+    clock::WriteTimestamps,
+    column_family::ColumnFamily,
+    database::Database,
+    entry::Entry,
+    iterator::DatabaseIterator,
+    options::{ColumnFamilyOptions, Options},
+    stats::Stats,
+    verify::VerifyReport,
+    write_batch::WriteBatch,
+};
+
+const SCAN_BUFFER: usize = 128;
+const CHANGE_BUFFER: usize = 128;
+
+pub type ScanStream = ReceiverStream<Result<Entry>>;
+pub type ChangeStream = ReceiverStream<Result<Change>>;
+
+/// Handle on a `Database` for use from tokio tasks.
+///
+/// All file IO runs on tokio's blocking pool so callers never block the reactor. Handles
+/// are cheap to clone and share one database; operations are applied one at a time.
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    db: Arc<Mutex<Database>>,
+    // seqno of the latest write, watched by change streams
+    last_sequence: Arc<watch::Sender<u64>>,
+    timestamps: Arc<WriteTimestamps>,
+}
+
+impl AsyncDatabase {
+    pub async fn open(dir: impl Into<PathBuf>) -> Result<AsyncDatabase> {
+        Self::open_with_options(dir, Options::default()).await
+    }
+
+    pub async fn open_with_options(
+        dir: impl Into<PathBuf>,
+        options: Options,
+    ) -> Result<AsyncDatabase> {
+        let dir = dir.into();
+        let db = run_blocking(move || Database::open_with_options(&dir, options)).await?;
+        Ok(AsyncDatabase::from(db))
+    }
+
+    pub async fn get(&self, key: impl Into<Vec<u8>>) -> Result<Option<Entry>> {
+        let key = key.into();
+        self.run(move |db| db.get(&key)).await
+    }
+
+    pub async fn set(
+        &self,
+        key: impl Into<Vec<u8>>,
+        value: impl Into<Vec<u8>>,
+        timestamp: u128,
+    ) -> Result<()> {
+        let (key, value) = (key.into(), value.into());
+        self.run_write(move |db| db.set(&key, &value, timestamp))
+            .await
+    }
+
+    pub async fn set_with_ttl(
+        &self,
+        key: impl Into<Vec<u8>>,
+        value: impl Into<Vec<u8>>,
+        timestamp: u128,
+        ttl: Duration,
+    ) -> Result<()> {
+        let (key, value) = (key.into(), value.into());
+        self.run_write(move |db| db.set_with_ttl(&key, &value, timestamp, ttl))
+            .await
+    }
+
+    pub async fn delete(&self, key: impl Into<Vec<u8>>, timestamp: u128) -> Result<()> {
+        let key = key.into();
+        self.run_write(move |db| db.delete(&key, timestamp)).await
+    }
+
+    pub async fn single_delete(&self, key: impl Into<Vec<u8>>, timestamp: u128) -> Result<()> {
+        let key = key.into();
+        self.run_write(move |db| db.single_delete(&key, timestamp))
+            .await
+    }
+
+    pub async fn merge(
+        &self,
+        key: impl Into<Vec<u8>>,
+        operand: impl Into<Vec<u8>>,
+        timestamp: u128,
+    ) -> Result<()> {
+        let (key, operand) = (key.into(), operand.into());
+        self.run_write(move |db| db.merge(&key, &operand, timestamp))
+            .await
+    }
+
+    pub async fn delete_range(
+        &self,
+        start: impl Into<Vec<u8>>,
+        end: impl Into<Vec<u8>>,
+        timestamp: u128,
+    ) -> Result<()> {
+        let (start, end) = (start.into(), end.into());
+        self.run_write(move |db| db.delete_range(&start, &end, timestamp))
+            .await
+    }
+
+    pub async fn get_cf(
+        &self,
+        column_family: &ColumnFamily,
+        key: impl Into<Vec<u8>>,
+    ) -> Result<Option<Entry>> {
+        let (column_family, key) = (column_family.clone(), key.into());
+        self.run(move |db| db.get_cf(&column_family, &key)).await
+    }
+
+    pub async fn set_cf(
+        &self,
+        column_family: &ColumnFamily,
+        key: impl Into<Vec<u8>>,
+        value: impl Into<Vec<u8>>,
+        timestamp: u128,
+    ) -> Result<()> {
+        let (column_family, key, value) = (column_family.clone(), key.into(), value.into());
+        self.run_write(move |db| db.set_cf(&column_family, &key, &value, timestamp))
+            .await
+    }
+
+    pub async fn delete_cf(
+        &self,
+        column_family: &ColumnFamily,
+        key: impl Into<Vec<u8>>,
+        timestamp: u128,
+    ) -> Result<()> {
+        let (column_family, key) = (column_family.clone(), key.into());
+        self.run_write(move |db| db.delete_cf(&column_family, &key, timestamp))
+            .await
+    }
+
+    pub async fn single_delete_cf(
+        &self,
+        column_family: &ColumnFamily,
+        key: impl Into<Vec<u8>>,
+        timestamp: u128,
+    ) -> Result<()> {
+        let (column_family, key) = (column_family.clone(), key.into());
+        self.run_write(move |db| db.single_delete_cf(&column_family, &key, timestamp))
+            .await
+    }
+
+    pub async fn merge_cf(
+        &self,
+        column_family: &ColumnFamily,
+        key: impl Into<Vec<u8>>,
+        operand: impl Into<Vec<u8>>,
+        timestamp: u128,
+    ) -> Result<()> {
+        let (column_family, key, operand) = (column_family.clone(), key.into(), operand.into());
+        self.run_write(move |db| db.merge_cf(&column_family, &key, &operand, timestamp))
+            .await
+    }
+
+    pub async fn delete_range_cf(
+        &self,
+        column_family: &ColumnFamily,
+        start: impl Into<Vec<u8>>,
+        end: impl Into<Vec<u8>>,
+        timestamp: u128,
+    ) -> Result<()> {
+        let (column_family, start, end) = (column_family.clone(), start.into(), end.into());
+        self.run_write(move |db| db.delete_range_cf(&column_family, &start, &end, timestamp))
+            .await
+    }
+
+    pub async fn create_column_family(
+        &self,
+        name: impl Into<String>,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamily> {
+        let name = name.into();
+        self.run(move |db| db.create_column_family(&name, options))
+            .await
+    }
+
+    pub async fn drop_column_family(&self, column_family: &ColumnFamily) -> Result<()> {
+        let column_family = column_family.clone();
+        self.run(move |db| db.drop_column_family(&column_family))
+            .await
+    }
+
+    pub async fn column_family(&self, name: impl Into<String>) -> Result<Option<ColumnFamily>> {
+        let name = name.into();
+        self.run(move |db| Ok(db.column_family(&name))).await
+    }
+
+    pub async fn column_families(&self) -> Result<Vec<ColumnFamily>> {
+        self.run(|db| Ok(db.column_families())).await
+    }
+
+    pub async fn write(&self, batch: WriteBatch) -> Result<()> {
+        self.run_write(move |db| db.write(&batch)).await
+    }
+
+    pub async fn sync(&self) -> Result<()> {
+        self.run(|db| db.sync()).await
+    }
+
+    pub async fn flush(&self) -> Result<()> {
+        self.run(|db| db.flush()).await
+    }
+
+    pub async fn compact(&self) -> Result<()> {
+        self.run(|db| db.compact()).await
+    }
+
+    pub async fn stats(&self) -> Result<Stats> {
+        self.run(|db| Ok(db.stats())).await
+    }
+
+    /// See `Database::checkpoint`; other operations wait while the tables are linked.
+    pub async fn checkpoint(&self, target: PathBuf) -> Result<()> {
+        self.run(move |db| db.checkpoint(&target)).await
+    }
+
+    pub async fn verify(&self) -> Result<VerifyReport> {
+        self.run(|db| db.verify()).await
+    }
+
+    /// See `Database::timestamp`; does not wait for other operations.
+    pub fn timestamp(&self) -> u128 {
+        self.timestamps.next()
+    }
+
+    /// Seqno of the latest write.
+    pub fn last_sequence(&self) -> u64 {
+        *self.last_sequence.borrow()
+    }
+
+    pub(crate) async fn open_snapshot(&self) -> Result<(VersionEdit, Vec<(PathBuf, File)>)> {
+        self.run(|db| db.open_snapshot()).await
+    }
+
+    /// Streams the live entries with keys in `range` in key order; an error ends the stream.
+    ///
+    /// The tables to read are chosen when the scan starts; the database stays available to
+    /// other operations while the stream is consumed.
+    pub async fn scan(
+        &self,
+        range: impl RangeBounds<Vec<u8>> + Send + 'static,
+    ) -> Result<ScanStream> {
+        let iterator = self.run(move |db| db.scan(range)).await?;
+        Ok(stream_entries(iterator))
+    }
+
+    pub async fn scan_cf(
+        &self,
+        column_family: &ColumnFamily,
+        range: impl RangeBounds<Vec<u8>> + Send + 'static,
+    ) -> Result<ScanStream> {
+        let column_family = column_family.clone();
+        let iterator = self
+            .run(move |db| db.scan_cf(&column_family, range))
+            .await?;
+        Ok(stream_entries(iterator))
+    }
+
+    /// Streams every write from `seqno` on and then keeps following new writes until the
+    /// stream is dropped. See `Database::changes_since` for which writes are available; an
+    /// error ends the stream.
+    pub async fn changes_since(&self, seqno: u64) -> Result<ChangeStream> {
+        let mut writes = self.last_sequence.subscribe();
+        writes.borrow_and_update();
+        let mut changes = self.run(move |db| db.changes_since(seqno)).await?;
+        let (sender, receiver) = mpsc::channel(CHANGE_BUFFER);
+        let db = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let blocking_sender = sender.clone();
+                let next = run_blocking(move || {
+                    for change in changes.by_ref() {
+                        let failed = change.is_err();
+                        if blocking_sender.blocking_send(change).is_err() || failed {
+                            return Ok(None);
+                        }
+                    }
+                    Ok(Some(changes.next_seqno()))
+                })
+                .await;
+                let Ok(Some(next)) = next else {
+                    return;
+                };
+                tokio::select! {
+                    changed = writes.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                    }
+                    _ = sender.closed() => return,
+                }
+                writes.borrow_and_update();
+                changes = match db.run(move |db| db.changes_since(next)).await {
+                    Ok(changes) => changes,
+                    Err(err) => {
+                        sender.send(Err(err)).await.ok();
+                        return;
+                    }
+                };
+            }
+        });
+        Ok(ReceiverStream::new(receiver))
+    }
+
+    async fn run_write<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Database) -> Result<()> + Send + 'static,
+    {
+        let last_sequence = self
+            .run(move |db| {
+                f(db)?;
+                Ok(db.last_sequence())
+            })
+            .await?;
+        self.last_sequence.send_replace(last_sequence);
+        Ok(())
+    }
+
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Database) -> Result<T> + Send + 'static,
+    {
+        let db = self.db.clone();
+        run_blocking(move || f(&mut db.lock().unwrap())).await
+    }
+}
+
+impl From<Database> for AsyncDatabase {
+    fn from(db: Database) -> Self {
+        let (last_sequence, _) = watch::channel(db.last_sequence());
+        AsyncDatabase {
+            timestamps: db.write_timestamps(),
+            db: Arc::new(Mutex::new(db)),
+            last_sequence: Arc::new(last_sequence),
+        }
+    }
+}
+
+/// Hands the entries of `iterator` to a stream fed from the blocking pool.
+fn stream_entries(iterator: DatabaseIterator) -> ScanStream {
+    let (sender, receiver) = mpsc::channel(SCAN_BUFFER);
+    task::spawn_blocking(move || {
+        for entry in iterator {
+            // the receiver was dropped, nobody wants the rest
+            if sender.blocking_send(entry).is_err() {
+                break;
+            }
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+pub(crate) async fn run_blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(err) if err.is_panic() => panic::resume_unwind(err.into_panic()),
+        Err(err) => Err(io::Error::other(err).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tokio_stream::StreamExt;
+
+    fn create_path(name: &str) -> PathBuf {
+        let path = PathBuf::from("data").join(name);
+        fs::remove_dir_all(&path).ok();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_read_after_write() {
+        let db = AsyncDatabase::open(create_path("async_read_after_write"))
+            .await
+            .unwrap();
+        db.set(vec![1], vec![9], 1).await.unwrap();
+        db.flush().await.unwrap();
+        db.set(vec![2], vec![8], 2).await.unwrap();
+        db.delete(vec![2], 3).await.unwrap();
+        let entry = db.get(vec![1]).await.unwrap().unwrap();
+        assert_eq!(entry.value, Some(vec![9]));
+        assert!(db.get(vec![2]).await.unwrap().unwrap().deleted);
+    }
+
+    #[tokio::test]
+    async fn test_scan_streams_entries_in_order() {
+        let db = AsyncDatabase::open(create_path("async_scan"))
+            .await
+            .unwrap();
+        for key in (0..20u8).rev() {
+            db.set(vec![key], vec![key], 1).await.unwrap();
+            if key % 5 == 0 {
+                db.flush().await.unwrap();
+            }
+        }
+        let keys: Vec<u8> = db
+            .scan(vec![3]..)
+            .await
+            .unwrap()
+            .map(|e| e.unwrap().key[0])
+            .collect()
+            .await;
+        assert_eq!(keys, (3..20).collect::<Vec<u8>>());
+    }
+
+    #[tokio::test]
+    async fn test_changes_follow_new_writes() {
+        let db = AsyncDatabase::open(create_path("async_changes"))
+            .await
+            .unwrap();
+        db.set(vec![1], vec![1], 1).await.unwrap();
+        let mut changes = db.changes_since(1).await.unwrap();
+        assert_eq!(changes.next().await.unwrap().unwrap().key, vec![1]);
+
+        let writer = db.clone();
+        tokio::spawn(async move {
+            writer.set(vec![2], vec![2], 2).await.unwrap();
+            writer.delete(vec![1], 3).await.unwrap();
+        });
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!((change.seqno, change.value), (2, Some(vec![2])));
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!((change.seqno, change.value), (3, None));
+    }
+}
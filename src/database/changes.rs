@@ -0,0 +1,237 @@
+use std::{collections::VecDeque, io::ErrorKind, path::PathBuf};
+
+use crate::{
+    wal::{iterator::WALIterator, wal::first_seqno},
+    Error, Result,
+};
+
+/// A committed write as seen by `Database::changes_since`; `value` is `None` for deletes,
+/// merges and range deletes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub seqno: u64,
+    /// Seqno of the first write of the batch the write belongs to.
+    pub batch_seqno: u64,
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+    /// Operand of a merge, see `Database::merge`.
+    pub operand: Option<Vec<u8>>,
+    /// Whether a delete was written by `Database::single_delete`.
+    pub single_delete: bool,
+    pub timestamp: u128,
+    /// See `Entry::expires_at`.
+    pub expires_at: Option<u128>,
+    /// Id of the column family written to, see `ColumnFamily::id`.
+    pub column_family: u32,
+    /// End of the range deleted by `Database::delete_range`, which starts at `key`.
+    pub range_end: Option<Vec<u8>>,
+}
+
+/// Changes in seqno order, read from WAL files oldest first.
+///
+/// Records that were logged twice, as happens when a database is reopened, are skipped.
+/// A missing seqno ends the iteration with `Error::Gone`.
+pub struct Changes {
+    files: VecDeque<PathBuf>,
+    current: Option<WALIterator>,
+    next_seqno: u64,
+    peeked: Option<Result<Change>>,
+}
+
+impl Changes {
+    pub(crate) fn new(files: Vec<PathBuf>, seqno: u64, last_sequence: u64) -> Result<Changes> {
+        let mut files = VecDeque::from(files);
+        // a WAL is not read at all if the next one starts at or before `seqno`
+        while files.len() > 1 && first_seqno(&files[1])?.is_some_and(|first| first <= seqno) {
+            files.pop_front();
+        }
+        let mut changes = Changes {
+            files,
+            current: None,
+            next_seqno: seqno,
+            peeked: None,
+        };
+        // report a position that cannot be served right away rather than on first use
+        match changes.read() {
+            Some(Err(err)) => return Err(err),
+            None if seqno <= last_sequence => {
+                return Err(Error::Gone(format!(
+                    "changes since seqno {} are no longer logged",
+                    seqno
+                )))
+            }
+            change => changes.peeked = change,
+        }
+        Ok(changes)
+    }
+
+    /// Seqno of the change that comes next.
+    pub fn next_seqno(&self) -> u64 {
+        match &self.peeked {
+            Some(Ok(change)) => change.seqno,
+            _ => self.next_seqno,
+        }
+    }
+
+    fn read(&mut self) -> Option<Result<Change>> {
+        loop {
+            if self.current.is_none() {
+                let path = self.files.pop_front()?;
+                match WALIterator::new(path) {
+                    // the newest WAL may be in the middle of a write
+                    Ok(iterator) => self.current = Some(iterator.tolerate_partial_batch()),
+                    // deleted since the list was taken; a gap it leaves is found below
+                    Err(Error::Io(err)) if err.kind() == ErrorKind::NotFound => continue,
+                    Err(err) => {
+                        self.files.clear();
+                        return Some(Err(err));
+                    }
+                }
+            }
+            let entry = match self.current.as_mut().and_then(|wal| wal.next()) {
+                Some(Ok(entry)) => entry,
+                Some(Err(err)) => {
+                    self.files.clear();
+                    self.current = None;
+                    return Some(Err(err));
+                }
+                None => {
+                    self.current = None;
+                    continue;
+                }
+            };
+            if entry.seqno < self.next_seqno {
+                continue;
+            }
+            if entry.seqno > self.next_seqno {
+                return Some(Err(self.gone()));
+            }
+            self.next_seqno += 1;
+            return Some(Ok(Change {
+                seqno: entry.seqno,
+                batch_seqno: entry.batch_seqno,
+                key: entry.key,
+                value: entry.value.filter(|_| !entry.deleted),
+                operand: entry.operands.into_iter().next(),
+                single_delete: entry.single_delete,
+                timestamp: entry.timestamp,
+                expires_at: entry.expires_at,
+                column_family: entry.column_family,
+                range_end: entry.range_end,
+            }));
+        }
+    }
+
+    fn gone(&mut self) -> Error {
+        self.files.clear();
+        self.current = None;
+        Error::Gone(format!(
+            "changes since seqno {} are no longer logged",
+            self.next_seqno
+        ))
+    }
+}
+
+impl Iterator for Changes {
+    type Item = Result<Change>;
+
+    fn next(&mut self) -> Option<Result<Change>> {
+        match self.peeked.take() {
+            Some(change) => Some(change),
+            None => self.read(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use crate::{
+        database::{database::Database, options::Options, write_batch::WriteBatch},
+        wal::wal::log_files,
+    };
+
+    use super::*;
+
+    fn open(dir: &Path, archive: bool) -> Database {
+        let options = Options {
+            wal_archive_dir: archive.then(|| dir.join("archive")),
+            ..Default::default()
+        };
+        Database::open_with_options(&dir.join("db"), options).unwrap()
+    }
+
+    fn create_dir(name: &str) -> PathBuf {
+        let dir = PathBuf::from("data").join(name);
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn seqnos(db: &mut Database, seqno: u64) -> Vec<u64> {
+        db.changes_since(seqno)
+            .unwrap()
+            .map(|change| change.unwrap().seqno)
+            .collect()
+    }
+
+    #[test]
+    fn test_changes_span_archived_and_live_wals() {
+        let dir = create_dir("changes_archived");
+        let mut db = open(&dir, true);
+        db.set(b"a", b"1", 1).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"b", b"2", 2);
+        batch.delete(b"a", 2);
+        db.write(&batch).unwrap();
+        db.flush().unwrap();
+        db.set(b"c", b"3", 3).unwrap();
+        drop(db);
+        // reopening logs seqno 4 a second time
+        let mut db = open(&dir, true);
+        db.set(b"d", b"4", 4).unwrap();
+
+        let changes: Vec<Change> = db.changes_since(2).unwrap().map(|c| c.unwrap()).collect();
+        assert_eq!(changes.len(), 4);
+        assert_eq!(changes[0].key, b"b");
+        assert_eq!((changes[1].batch_seqno, changes[1].timestamp), (2, 2));
+        assert_eq!(
+            (changes[1].key.as_slice(), &changes[1].value),
+            (&b"a"[..], &None)
+        );
+        assert_eq!(seqnos(&mut db, 1), vec![1, 2, 3, 4, 5]);
+        assert_eq!(seqnos(&mut db, 6), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_older_wals_are_skipped() {
+        let dir = create_dir("changes_skip_wals");
+        let mut db = open(&dir, true);
+        for seqno in 1..4u8 {
+            db.set(&[seqno], &[seqno], seqno.into()).unwrap();
+            db.flush().unwrap();
+        }
+        db.set(&[4], &[4], 4).unwrap();
+        // reading the oldest WAL would fail
+        let oldest = log_files(&dir.join("archive")).unwrap().remove(0);
+        fs::remove_file(&oldest).unwrap();
+        fs::create_dir(&oldest).unwrap();
+        assert_eq!(seqnos(&mut db, 3), vec![3, 4]);
+        assert!(db.changes_since(1).is_err());
+    }
+
+    #[test]
+    fn test_discarded_changes_are_gone() {
+        let dir = create_dir("changes_gone");
+        let mut db = open(&dir, false);
+        db.set(b"a", b"1", 1).unwrap();
+        db.flush().unwrap();
+        db.set(b"b", b"2", 2).unwrap();
+        assert!(matches!(db.changes_since(1), Err(Error::Gone(_))));
+        assert_eq!(seqnos(&mut db, 2), vec![2]);
+        assert!(matches!(
+            db.changes_since(4),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,57 @@
+use std::{
+    fs::{self, File},
+    path::Path,
+};
+
+use crate::{
+    sstable::{
+        blob::blob_path,
+        sstable::{table_files, table_path},
+    },
+    version::{
+        edit::VersionEdit,
+        version_set::{sync_dir, VersionSet},
+    },
+    Result,
+};
+
+/// Creates `target` holding the tables and blob files of `snapshot`, taken from `source`,
+/// and a MANIFEST listing them. CURRENT is written last, once the files are synced, so an
+/// interrupted copy is not mistaken for a database. With `link` the table files are
+/// hard-linked when both directories are on the same filesystem.
+pub(crate) fn write_database(
+    source: &Path,
+    snapshot: &VersionEdit,
+    target: &Path,
+    link: bool,
+) -> Result<()> {
+    fs::create_dir(target)?;
+    for (_, file) in snapshot.new_files.iter() {
+        let from = table_files(&table_path(source, file.number));
+        let to = table_files(&table_path(target, file.number));
+        for (from, to) in from.iter().zip(to.iter()) {
+            copy_file(from, to, link)?;
+        }
+    }
+    for file in snapshot.new_blob_files.iter() {
+        let from = blob_path(source, file.number);
+        copy_file(&from, &blob_path(target, file.number), link)?;
+    }
+    sync_dir(target)?;
+    VersionSet::create(
+        target,
+        snapshot.next_file_number.unwrap_or(1),
+        snapshot.last_sequence.unwrap_or(0),
+        snapshot.clone(),
+    )?;
+    Ok(())
+}
+
+/// Links or copies `from` to `to`; a copy is synced, a link shares the synced source.
+pub(crate) fn copy_file(from: &Path, to: &Path, link: bool) -> Result<()> {
+    if link && fs::hard_link(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    Ok(File::open(to)?.sync_all()?)
+}
@@ -0,0 +1,84 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Source of the time entries expire by, in microseconds since the Unix epoch.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u128;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u128 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros()
+    }
+}
+
+/// Clock that only moves when told to, for tests.
+#[derive(Default)]
+pub struct ManualClock {
+    now: Mutex<u128>,
+}
+
+impl ManualClock {
+    pub fn new(now: u128) -> ManualClock {
+        ManualClock {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by.as_micros();
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u128 {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Timestamps for writes read off a `Clock`.
+///
+/// They are strictly increasing even if the clock goes backwards, so a later write always
+/// wins during merges.
+pub struct WriteTimestamps {
+    clock: Arc<dyn Clock>,
+    last: Mutex<u128>,
+}
+
+impl WriteTimestamps {
+    pub fn new(clock: Arc<dyn Clock>) -> WriteTimestamps {
+        WriteTimestamps {
+            clock,
+            last: Mutex::new(0),
+        }
+    }
+
+    pub fn next(&self) -> u128 {
+        let now = self.clock.now();
+        let mut last = self.last.lock().unwrap();
+        *last = now.max(*last + 1);
+        *last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_timestamps_increase() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let timestamps = WriteTimestamps::new(clock.clone());
+        assert_eq!(timestamps.next(), 1_000);
+        assert_eq!(timestamps.next(), 1_001);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(timestamps.next(), 2_000);
+    }
+}
@@ -0,0 +1,32 @@
+/// Id of the column family that exists in every database and cannot be dropped. Writes
+/// without a column family go to it.
+pub const DEFAULT_COLUMN_FAMILY: u32 = 0;
+pub const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+/// Handle of a column family, returned by `Database::create_column_family` and
+/// `Database::column_family`.
+///
+/// A handle stays valid until its family is dropped; using it afterwards fails with
+/// `Error::NotFound`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnFamily {
+    id: u32,
+    name: String,
+}
+
+impl ColumnFamily {
+    pub(crate) fn new(id: u32, name: &str) -> ColumnFamily {
+        ColumnFamily {
+            id,
+            name: name.to_string(),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
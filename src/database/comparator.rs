@@ -0,0 +1,98 @@
+use std::{cmp::Ordering, sync::Arc};
+
+/// Orders the keys of a database.
+///
+/// The name is recorded in the MANIFEST when the database is created, and opening the
+/// database with a comparator of another name fails, since its tables would be searched in
+/// the wrong order. `compare` must only return `Equal` for identical keys.
+pub trait Comparator: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+}
+
+/// Compares keys byte by byte; the order of databases without a comparator of their own.
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        "rustdb.BytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// The reverse of `BytewiseComparator`, so scans return the largest keys first.
+pub struct ReverseBytewiseComparator;
+
+impl Comparator for ReverseBytewiseComparator {
+    fn name(&self) -> &str {
+        "rustdb.ReverseBytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+/// Orders keys as big endian unsigned numbers, so `[2]` comes before `[0, 0, 1]`. Keys of
+/// the same number compare by length, which keeps `[1]` and `[0, 1]` apart.
+pub struct U64Comparator;
+
+impl Comparator for U64Comparator {
+    fn name(&self) -> &str {
+        "rustdb.U64Comparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        let significant = |key: &[u8]| {
+            let zeros = key.iter().take_while(|byte| **byte == 0).count();
+            key[zeros..].to_vec()
+        };
+        let (x, y) = (significant(a), significant(b));
+        x.len()
+            .cmp(&y.len())
+            .then_with(|| x.cmp(&y))
+            .then_with(|| a.len().cmp(&b.len()))
+    }
+}
+
+/// The built-in comparator with the given name, for tools that open databases they did
+/// not create.
+pub fn builtin(name: &str) -> Option<Arc<dyn Comparator>> {
+    let comparators: [Arc<dyn Comparator>; 3] = [
+        Arc::new(BytewiseComparator),
+        Arc::new(ReverseBytewiseComparator),
+        Arc::new(U64Comparator),
+    ];
+    comparators
+        .into_iter()
+        .find(|comparator| comparator.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_comparators() {
+        assert_eq!(BytewiseComparator.compare(&[1], &[0, 2]), Ordering::Greater);
+        assert_eq!(
+            ReverseBytewiseComparator.compare(&[1], &[0, 2]),
+            Ordering::Less
+        );
+        let mut keys = vec![vec![0, 1], 256u64.to_be_bytes().to_vec(), vec![2], vec![1]];
+        keys.sort_by(|a, b| U64Comparator.compare(a, b));
+        assert_eq!(
+            keys,
+            vec![vec![1], vec![0, 1], vec![2], 256u64.to_be_bytes().to_vec()]
+        );
+        assert_eq!(
+            builtin("rustdb.U64Comparator").unwrap().name(),
+            U64Comparator.name()
+        );
+        assert!(builtin("unknown").is_none());
+    }
+}
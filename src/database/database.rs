@@ -1,82 +1,1040 @@
-#![allow(dead_code)]
-use crate::{memtable::MemTable, sstable::sstable::SSTable, wal::wal::WAL};
-use std::{io, path::PathBuf};
+use crate::{
+    memtable::MemTable,
+    sstable::{
+        blob::{self, blob_path, BlobCache, BlobOptions, BLOB_EXT},
+        sstable::{file_number, remove_table, table_files, table_path, SSTable, TABLE_EXT},
+        table_cache::TableCache,
+    },
+    version::{
+        edit::VersionEdit,
+        version::{BlobFileMetaData, ColumnFamilyMetaData, FileMetaData, NUM_LEVELS},
+        version_set::{manifest_name, read_current, sync_dir, VersionSet, MANIFEST_PREFIX},
+    },
+    wal::wal::{log_files, retire, WAL},
+    Error, Result,
+};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet},
+    fs::{self, read_dir, remove_file, File, OpenOptions, TryLockError},
+    ops::{Bound, RangeBounds},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use std::path::Path;
+use super::{
+    changes::Changes,
+    checkpoint,
+    clock::{Clock, WriteTimestamps},
+    column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY, DEFAULT_COLUMN_FAMILY_NAME},
+    comparator::{BytewiseComparator, Comparator},
+    entry::Entry,
+    iterator::DatabaseIterator,
+    merge_operator::{resolve, stack, MergeOperator},
+    options::{ColumnFamilyOptions, Options},
+    range_tombstone::{self, RangeTombstone},
+    stats::{LevelStats, Stats},
+    verify::{self, VerifyReport},
+    write_batch::{BatchOp, WriteBatch},
+};
 
-use super::entry::Entry;
+const LOCK_FILE: &str = "LOCK";
 
-struct Database {
-    memtable: MemTable,
+pub struct Database {
+    dir: PathBuf,
+    // one per column family, all logged to the same WAL
+    memtables: BTreeMap<u32, MemTable>,
     wal: WAL,
-    sstables: Vec<PathBuf>,
+    versions: VersionSet,
+    table_cache: TableCache,
+    blob_cache: Arc<BlobCache>,
+    min_blob_size: Option<usize>,
+    blob_garbage_ratio: f64,
+    wal_archive_dir: Option<PathBuf>,
+    clock: Arc<dyn Clock>,
+    timestamps: Arc<WriteTimestamps>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    comparator: Arc<dyn Comparator>,
+    // held while the database is open so no other process writes to the directory
+    _lock: File,
 }
 
 impl Database {
-    pub fn new(dir: &Path) -> io::Result<Database> {
-        let wal = WAL::new(dir)?;
-        let memtable = MemTable::new();
-        let sstables: Vec<PathBuf> = Vec::new();
-        Ok(Database {
+    pub fn open(dir: &Path) -> Result<Database> {
+        Self::open_with_options(dir, Options::default())
+    }
+
+    /// Opens the database in `dir`, creating it if necessary, and recovers the live tables
+    /// from the MANIFEST and unflushed writes from the WAL. Fails with
+    /// `Error::InvalidArgument` if the database was created with another comparator.
+    pub fn open_with_options(dir: &Path, options: Options) -> Result<Database> {
+        fs::create_dir_all(dir)?;
+        let lock = lock_dir(dir)?;
+        let archive = options.wal_archive_dir.as_deref();
+        if let Some(archive) = archive {
+            fs::create_dir_all(archive)?;
+        }
+        let created = read_current(dir)?.is_none();
+        let mut versions = VersionSet::recover(dir)?;
+        check_comparator(dir, versions.current().comparator(), created, &options)?;
+        // files created after the last MANIFEST write must not have their number handed out again
+        for file in read_dir(dir)? {
+            if let Some(number) = file_number(&file?.path()) {
+                versions.mark_file_number_used(number);
+            }
+        }
+        // WALs older than the log number only hold writes that were flushed. Newer ones were
+        // being written by a recovery that did not record them, so the WALs they copy are
+        // still here.
+        for wal in log_files(dir)? {
+            match file_number(&wal) {
+                Some(n) if n < versions.log_number => retire(&wal, archive)?,
+                Some(n) if n > versions.log_number && versions.log_number > 0 => remove_file(&wal)?,
+                _ => {}
+            }
+        }
+        let comparator = options.comparator;
+        let (wal, mut memtables) =
+            WAL::load_from_dir(dir, versions.new_file_number(), comparator.clone())?;
+        // writes to dropped families stay in the WAL until the next flush
+        memtables.retain(|id, _| {
+            *id == DEFAULT_COLUMN_FAMILY || versions.current().column_families().contains_key(id)
+        });
+        let families = versions.current().column_families().keys().copied();
+        for id in std::iter::once(DEFAULT_COLUMN_FAMILY).chain(families) {
+            memtables
+                .entry(id)
+                .or_insert_with(|| MemTable::with_comparator(comparator.clone()));
+        }
+        if let Some((_, last)) = wal.seqno_range() {
+            versions.last_sequence = versions.last_sequence.max(last);
+        }
+        let recorded = versions.current().comparator().is_some();
+        versions.log_and_apply(VersionEdit {
+            log_number: file_number(&wal.path),
+            comparator: (!recorded).then(|| comparator.name().to_string()),
+            ..Default::default()
+        })?;
+        // the replayed WALs are copied into the new one
+        for replayed in log_files(dir)? {
+            if replayed != wal.path {
+                retire(&replayed, archive)?;
+            }
+        }
+        let table_cache = TableCache::new(
+            dir,
+            options.max_open_files,
+            options.block_cache,
+            comparator.clone(),
+        );
+        let db = Database {
+            dir: dir.to_owned(),
+            memtables,
             wal,
-            memtable,
-            sstables,
+            versions,
+            table_cache,
+            blob_cache: Arc::new(BlobCache::new(dir)),
+            min_blob_size: options.min_blob_size,
+            blob_garbage_ratio: options.blob_garbage_ratio,
+            wal_archive_dir: options.wal_archive_dir,
+            timestamps: Arc::new(WriteTimestamps::new(options.clock.clone())),
+            clock: options.clock,
+            merge_operator: options.merge_operator,
+            comparator,
+            _lock: lock,
+        };
+        db.remove_obsolete_files()?;
+        Ok(db)
+    }
+
+    /// Timestamp for the next write, from the database's clock.
+    pub fn timestamp(&self) -> u128 {
+        self.timestamps.next()
+    }
+
+    pub(crate) fn write_timestamps(&self) -> Arc<WriteTimestamps> {
+        self.timestamps.clone()
+    }
+
+    pub fn set(&mut self, key: &[u8], value: &[u8], timestamp: u128) -> Result<()> {
+        let seqno = self.versions.last_sequence + 1;
+        self.wal.set(seqno, key, value, timestamp)?;
+        self.default_memtable().set(key, value, timestamp);
+        self.versions.last_sequence = seqno;
+        Ok(())
+    }
+
+    /// Sets `key` so that it counts as absent once `ttl` passed on the database's clock.
+    pub fn set_with_ttl(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        timestamp: u128,
+        ttl: Duration,
+    ) -> Result<()> {
+        let expires_at = Some(self.clock.now() + ttl.as_micros());
+        let seqno = self.versions.last_sequence + 1;
+        self.wal
+            .set_with_expiry(seqno, key, value, timestamp, expires_at)?;
+        self.default_memtable()
+            .set_with_expiry(key, value, timestamp, expires_at);
+        self.versions.last_sequence = seqno;
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: &[u8], timestamp: u128) -> Result<()> {
+        let seqno = self.versions.last_sequence + 1;
+        self.wal.delete(seqno, key, timestamp)?;
+        self.default_memtable().delete(key, timestamp);
+        self.versions.last_sequence = seqno;
+        Ok(())
+    }
+
+    /// Deletes `key`, which must have been set once since it was last deleted and never
+    /// merged. Compaction drops the tombstone together with that value instead of keeping
+    /// it until it reaches the oldest tables; deleting a key written more often this way
+    /// may bring back one of its older values.
+    pub fn single_delete(&mut self, key: &[u8], timestamp: u128) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.single_delete(key, timestamp);
+        self.write(&batch)
+    }
+
+    pub fn set_cf(
+        &mut self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        value: &[u8],
+        timestamp: u128,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put_cf(column_family, key, value, timestamp);
+        self.write(&batch)
+    }
+
+    pub fn delete_cf(
+        &mut self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        timestamp: u128,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete_cf(column_family, key, timestamp);
+        self.write(&batch)
+    }
+
+    pub fn single_delete_cf(
+        &mut self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        timestamp: u128,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.single_delete_cf(column_family, key, timestamp);
+        self.write(&batch)
+    }
+
+    /// Records `operand` for `key` without reading it. The operand is folded into the
+    /// key's value by `Options::merge_operator` when the key is read or compacted.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8], timestamp: u128) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.merge(key, operand, timestamp);
+        self.write(&batch)
+    }
+
+    pub fn merge_cf(
+        &mut self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        operand: &[u8],
+        timestamp: u128,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.merge_cf(column_family, key, operand, timestamp);
+        self.write(&batch)
+    }
+
+    /// Deletes every key from `start` up to but not including `end` with a single range
+    /// tombstone, which hides the versions of those keys not newer than `timestamp`. Fails
+    /// with `Error::InvalidArgument` unless `start` comes before `end`.
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8], timestamp: u128) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete_range(start, end, timestamp);
+        self.write(&batch)
+    }
+
+    pub fn delete_range_cf(
+        &mut self,
+        column_family: &ColumnFamily,
+        start: &[u8],
+        end: &[u8],
+        timestamp: u128,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete_range_cf(column_family, start, end, timestamp);
+        self.write(&batch)
+    }
+
+    fn default_memtable(&mut self) -> &mut MemTable {
+        self.memtable_mut(DEFAULT_COLUMN_FAMILY)
+    }
+
+    fn memtable_mut(&mut self, column_family: u32) -> &mut MemTable {
+        let comparator = &self.comparator;
+        self.memtables
+            .entry(column_family)
+            .or_insert_with(|| MemTable::with_comparator(comparator.clone()))
+    }
+
+    fn memtable(&self, column_family: u32) -> Result<&MemTable> {
+        self.memtables
+            .get(&column_family)
+            .ok_or_else(|| Error::NotFound(format!("column family {}", column_family)))
+    }
+
+    /// Applies all changes of `batch` atomically: after a crash either all or none of them
+    /// are recovered. Fails with `Error::NotFound` if a column family of the batch does not
+    /// exist and with `Error::InvalidArgument` for an empty range delete, in which case
+    /// nothing is written.
+    pub fn write(&mut self, batch: &WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        for op in batch.ops() {
+            self.memtable(op.column_family())?;
+            if let BatchOp::DeleteRange { start, end, .. } = op {
+                if self.comparator.compare(start, end) != Ordering::Less {
+                    return Err(Error::InvalidArgument(format!(
+                        "range delete from {:?} to {:?} is empty",
+                        start, end
+                    )));
+                }
+            }
+        }
+        let seqno = self.versions.last_sequence + 1;
+        self.wal.write_batch(seqno, batch)?;
+        for op in batch.ops() {
+            let memtable = self.memtable_mut(op.column_family());
+            match op {
+                BatchOp::Put {
+                    key,
+                    value,
+                    timestamp,
+                    expires_at,
+                    ..
+                } => memtable.set_with_expiry(key, value, *timestamp, *expires_at),
+                BatchOp::Delete { key, timestamp, .. } => memtable.delete(key, *timestamp),
+                BatchOp::SingleDelete { key, timestamp, .. } => {
+                    memtable.single_delete(key, *timestamp)
+                }
+                BatchOp::Merge {
+                    key,
+                    operand,
+                    timestamp,
+                    ..
+                } => memtable.merge(key, operand, *timestamp),
+                BatchOp::DeleteRange {
+                    start,
+                    end,
+                    timestamp,
+                    ..
+                } => memtable.delete_range(start, end, *timestamp),
+            }
+        }
+        self.versions.last_sequence = seqno + batch.len() as u64 - 1;
+        Ok(())
+    }
+
+    /// Hands buffered WAL records to the OS so they survive a crash of the process.
+    pub fn sync(&mut self) -> Result<()> {
+        self.wal.flush()
+    }
+
+    /// Newest entry of `key`, which may be a tombstone. Expired entries count as absent.
+    /// Merge operands are folded into the value, see `Options::merge_operator`.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Entry>> {
+        self.get_from(DEFAULT_COLUMN_FAMILY, key)
+    }
+
+    pub fn get_cf(&self, column_family: &ColumnFamily, key: &[u8]) -> Result<Option<Entry>> {
+        self.get_from(column_family.id(), key)
+    }
+
+    fn get_from(&self, column_family: u32, key: &[u8]) -> Result<Option<Entry>> {
+        let now = self.clock.now();
+        let memtable = self.memtable(column_family)?;
+        let mut tables = Vec::new();
+        for file in self
+            .versions
+            .current()
+            .tables_for_key(column_family, key, &*self.comparator)
+        {
+            tables.push(self.table_cache.get(file.number)?);
+        }
+        // a range tombstone anywhere may cover any version
+        let tombstones: Vec<RangeTombstone> = memtable
+            .range_tombstones()
+            .iter()
+            .chain(tables.iter().flat_map(|table| table.range_tombstones()))
+            .filter(|tombstone| tombstone.contains(key, &*self.comparator))
+            .cloned()
+            .collect();
+        let cover = |entry| range_tombstone::cover(entry, &tombstones, &*self.comparator);
+        let mut found = memtable.get(key).cloned().map(cover);
+        for table in tables {
+            // operands need the versions below them
+            if found.as_ref().is_some_and(|entry| !entry.is_pending()) {
+                break;
+            }
+            if let Some(older) = table.get(key)?.map(cover) {
+                found = Some(match found {
+                    Some(newer) => stack(newer, older),
+                    None => older,
+                });
+            }
+        }
+        let found = match found {
+            Some(entry) if !entry.is_expired(now) => {
+                blob::load(entry, |number| self.blob_cache.get(number))?
+            }
+            _ => return Ok(None),
+        };
+        let found = resolve(found, self.merge_operator.as_deref(), now);
+        Ok(Some(found).filter(|entry| !entry.is_expired(now)))
+    }
+
+    /// Iterates over the live entries with keys in `range`, in key order. A value that
+    /// cannot be read, e.g. from a damaged blob file, is returned as an error and ends the
+    /// iteration.
+    pub fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Result<DatabaseIterator> {
+        self.scan_from(DEFAULT_COLUMN_FAMILY, range)
+    }
+
+    pub fn scan_cf(
+        &self,
+        column_family: &ColumnFamily,
+        range: impl RangeBounds<Vec<u8>>,
+    ) -> Result<DatabaseIterator> {
+        self.scan_from(column_family.id(), range)
+    }
+
+    fn scan_from(
+        &self,
+        column_family: u32,
+        range: impl RangeBounds<Vec<u8>>,
+    ) -> Result<DatabaseIterator> {
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        let (start_key, end_key) = (
+            start.as_ref().map(Vec::as_slice),
+            end.as_ref().map(Vec::as_slice),
+        );
+        let memtable = self.memtable(column_family)?;
+        let mut sources: Vec<Box<dyn Iterator<Item = Result<Entry>> + Send>> =
+            vec![Box::new(memtable.scan(start_key, end_key).map(Ok))];
+        let mut range_tombstones = memtable.range_tombstones().to_vec();
+        for file in self.versions.current().tables_for_range(
+            column_family,
+            start_key,
+            end_key,
+            &*self.comparator,
+        ) {
+            let table = self.table_cache.get(file.number)?;
+            let from = match start_key {
+                Bound::Included(key) | Bound::Excluded(key) => Some(key),
+                Bound::Unbounded => None,
+            };
+            sources.push(Box::new(table.iter_from(from)?));
+            range_tombstones.extend_from_slice(table.range_tombstones());
+        }
+        let mut blob_files = BTreeMap::new();
+        for file in self.versions.current().blob_files().values() {
+            if file.column_family == column_family {
+                blob_files.insert(file.number, self.blob_cache.get(file.number)?);
+            }
+        }
+        Ok(DatabaseIterator::new(
+            sources,
+            range_tombstones,
+            start,
+            end,
+            self.clock.now(),
+            self.merge_operator.clone(),
+            self.comparator.clone(),
+        )
+        .with_blob_files(blob_files))
+    }
+
+    /// Writes the memtable of every column family to a new level 0 table and starts a fresh
+    /// WAL. All families are flushed together since they share the WAL. Families reaching
+    /// their `ColumnFamilyOptions::compaction_trigger` are compacted afterwards.
+    pub fn flush(&mut self) -> Result<()> {
+        let Some((smallest_seqno, largest_seqno)) = self.wal.seqno_range() else {
+            return Ok(());
+        };
+        let mut tables = Vec::new();
+        for (id, memtable) in self.memtables.iter() {
+            if memtable.is_empty() {
+                continue;
+            }
+            let options = self.column_family_options(*id);
+            let mut sstable = SSTable::new(&self.dir, self.versions.new_file_number())?;
+            sstable.set_bits_per_key(options.bloom_bits_per_key);
+            sstable.set_compression(options.compression);
+            sstable.set_comparator(self.comparator.clone());
+            sstable.set_blobs(self.blob_options(HashSet::new()));
+            for entry in memtable {
+                sstable.write(&entry)?;
+            }
+            for tombstone in memtable.range_tombstones() {
+                sstable.add_range_tombstone(tombstone.clone());
+            }
+            sstable.flush()?;
+            tables.push((*id, sstable));
+        }
+        let wal = WAL::new(&self.dir, self.versions.new_file_number())?;
+
+        let mut edit = VersionEdit {
+            log_number: file_number(&wal.path),
+            ..Default::default()
+        };
+        for (id, sstable) in tables.iter() {
+            if let Some(file) = file_meta_data(sstable, *id, smallest_seqno, largest_seqno) {
+                edit.add_file(0, file);
+            }
+            if let Some(file) = blob_file_meta_data(sstable, *id) {
+                edit.add_blob_file(file);
+            }
+        }
+        // the new tables and WAL have to be found once the edit names them
+        sync_dir(&self.dir)?;
+        self.versions.log_and_apply(edit)?;
+
+        let old_wal = std::mem::replace(&mut self.wal, wal);
+        for memtable in self.memtables.values_mut() {
+            *memtable = MemTable::with_comparator(self.comparator.clone());
+        }
+        let old_wal_path = old_wal.path.clone();
+        drop(old_wal);
+        retire(&old_wal_path, self.wal_archive_dir.as_deref())?;
+        for (id, _) in tables {
+            let trigger = self.column_family_options(id).compaction_trigger;
+            if trigger
+                .is_some_and(|trigger| self.versions.current().family_files(id).count() >= trigger)
+            {
+                self.compact_family(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a copy of the database that can be opened on its own into `target`, which must
+    /// not exist yet. The memtable is flushed first so the live tables hold every write;
+    /// their files are hard-linked where possible and copied otherwise.
+    pub fn checkpoint(&mut self, target: &Path) -> Result<()> {
+        let snapshot = self.snapshot()?;
+        checkpoint::write_database(&self.dir, &snapshot, target, true)
+    }
+
+    /// Iterates over every write from `seqno` on, in commit order, as far as the WALs
+    /// reach when called. Writes that were flushed are only available if their WALs were
+    /// archived, see `Options::wal_archive_dir`; otherwise asking for them fails with
+    /// `Error::Gone`.
+    pub fn changes_since(&mut self, seqno: u64) -> Result<Changes> {
+        let last_sequence = self.versions.last_sequence;
+        if seqno > last_sequence + 1 {
+            return Err(Error::InvalidArgument(format!(
+                "seqno {} is past the last write {}",
+                seqno, last_sequence
+            )));
+        }
+        self.sync()?;
+        let mut files = log_files(&self.dir)?;
+        if let Some(archive) = &self.wal_archive_dir {
+            files.extend(log_files(archive)?);
+        }
+        files.sort_by_key(|path| file_number(path));
+        Changes::new(files, seqno.max(1), last_sequence)
+    }
+
+    /// Flushes the memtables and describes the column families and live tables as one
+    /// MANIFEST record.
+    pub(crate) fn snapshot(&mut self) -> Result<VersionEdit> {
+        self.flush()?;
+        Ok(VersionEdit {
+            log_number: file_number(&self.wal.path),
+            next_file_number: Some(self.versions.next_file_number()),
+            last_sequence: Some(self.versions.last_sequence),
+            ..self.versions.current().snapshot()
         })
     }
 
-    pub fn set(&mut self, key: &[u8], value: &[u8], timestamp: u128) -> Result<(), std::io::Error> {
-        self.memtable.set(key, value, timestamp);
-        self.wal.set(key, value, timestamp)
+    /// Creates a column family with its own memtable, tables and options. Its writes share
+    /// the WAL with every other family. Fails with `Error::InvalidArgument` if a family
+    /// named `name` exists.
+    pub fn create_column_family(
+        &mut self,
+        name: &str,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamily> {
+        if self.column_family(name).is_some() {
+            return Err(Error::InvalidArgument(format!(
+                "column family {} exists",
+                name
+            )));
+        }
+        let id = self.versions.current().next_column_family();
+        let mut edit = VersionEdit {
+            next_column_family: Some(id + 1),
+            ..Default::default()
+        };
+        edit.add_column_family(
+            id,
+            ColumnFamilyMetaData {
+                name: name.to_string(),
+                options,
+            },
+        );
+        self.versions.log_and_apply(edit)?;
+        self.memtables
+            .insert(id, MemTable::with_comparator(self.comparator.clone()));
+        Ok(ColumnFamily::new(id, name))
+    }
+
+    /// Drops a column family and deletes its tables. Its writes that are still in the WAL
+    /// are ignored from now on. The default family cannot be dropped.
+    pub fn drop_column_family(&mut self, column_family: &ColumnFamily) -> Result<()> {
+        if column_family.id() == DEFAULT_COLUMN_FAMILY {
+            return Err(Error::InvalidArgument(
+                "the default column family cannot be dropped".to_string(),
+            ));
+        }
+        self.memtable(column_family.id())?;
+        let mut edit = VersionEdit::default();
+        edit.drop_column_family(column_family.id());
+        let files: Vec<(usize, u64)> = self
+            .versions
+            .current()
+            .family_files(column_family.id())
+            .map(|(level, file)| (level, file.number))
+            .collect();
+        for (level, number) in files.iter() {
+            edit.delete_file(*level, *number);
+        }
+        let blob_files: Vec<u64> = self
+            .versions
+            .current()
+            .blob_files()
+            .values()
+            .filter(|f| f.column_family == column_family.id())
+            .map(|f| f.number)
+            .collect();
+        self.versions.log_and_apply(edit)?;
+        self.memtables.remove(&column_family.id());
+        for (_, number) in files {
+            self.table_cache.evict(number);
+            remove_table(&self.dir, number)?;
+        }
+        self.remove_blob_files(blob_files.into_iter())
+    }
+
+    /// Handle of the column family named `name`, if it exists.
+    pub fn column_family(&self, name: &str) -> Option<ColumnFamily> {
+        if name == DEFAULT_COLUMN_FAMILY_NAME {
+            return Some(ColumnFamily::new(DEFAULT_COLUMN_FAMILY, name));
+        }
+        self.versions
+            .current()
+            .column_families()
+            .iter()
+            .find(|(_, family)| family.name == name)
+            .map(|(id, family)| ColumnFamily::new(*id, &family.name))
+    }
+
+    /// Every column family, the default one first.
+    pub fn column_families(&self) -> Vec<ColumnFamily> {
+        let mut families = vec![ColumnFamily::new(
+            DEFAULT_COLUMN_FAMILY,
+            DEFAULT_COLUMN_FAMILY_NAME,
+        )];
+        for (id, family) in self.versions.current().column_families() {
+            families.push(ColumnFamily::new(*id, &family.name));
+        }
+        families
     }
 
-    pub fn delete(&mut self, key: &[u8], timestamp: u128) -> Result<(), std::io::Error> {
-        self.memtable.delete(key, timestamp);
-        self.wal.delete(key, timestamp)
+    fn column_family_options(&self, column_family: u32) -> ColumnFamilyOptions {
+        self.versions
+            .current()
+            .column_families()
+            .get(&column_family)
+            .map(|family| family.options.clone())
+            .unwrap_or_default()
     }
 
-    pub fn get(&self, key: &[u8]) -> Option<Entry> {
-        if let Some(entry) = self.memtable.get(key) {
-            Some(entry.clone())
-        } else {
-            for path in self.sstables.iter() {
-                let sstable = SSTable::from_path(path).ok().unwrap();
-                if let Some(entry) = sstable.get(key).ok().unwrap() {
-                    return Some(entry.clone());
+    /// Takes a snapshot and opens the files of its tables, which stay readable even after
+    /// a compaction removes them.
+    pub(crate) fn open_snapshot(&mut self) -> Result<(VersionEdit, Vec<(PathBuf, File)>)> {
+        let snapshot = self.snapshot()?;
+        let mut files = Vec::new();
+        for (_, file) in snapshot.new_files.iter() {
+            for path in table_files(&table_path(&self.dir, file.number)) {
+                files.push((path.clone(), File::open(path)?));
+            }
+        }
+        for file in snapshot.new_blob_files.iter() {
+            let path = blob_path(&self.dir, file.number);
+            files.push((path.clone(), File::open(path)?));
+        }
+        Ok((snapshot, files))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Seqno of the latest write.
+    pub fn last_sequence(&self) -> u64 {
+        self.versions.last_sequence
+    }
+
+    /// Merges the live tables of each column family into a single table on level 1, keeping
+    /// only the newest version of each key.
+    ///
+    /// Values in blob files that are no longer pointed to count as garbage of their file.
+    /// Blob files with a share of garbage of at least `Options::blob_garbage_ratio` have
+    /// their remaining values written anew, and blob files holding only garbage are
+    /// deleted.
+    pub fn compact(&mut self) -> Result<()> {
+        let families: Vec<u32> = self.memtables.keys().copied().collect();
+        for column_family in families {
+            self.compact_family(column_family)?;
+        }
+        Ok(())
+    }
+
+    fn compact_family(&mut self, column_family: u32) -> Result<()> {
+        let inputs: Vec<(usize, FileMetaData)> = self
+            .versions
+            .current()
+            .family_files_newest_first(column_family)
+            .map(|(level, file)| (level, file.clone()))
+            .collect();
+        if inputs.len() < 2 {
+            return Ok(());
+        }
+        let mut outputs = Vec::new();
+        let mut edit = VersionEdit::default();
+        // values per blob file before the compaction: those the inputs point to and those
+        // the merges wrote
+        let mut blob_values: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+        let merged = self.merge_inputs(
+            column_family,
+            &inputs,
+            &mut outputs,
+            &mut edit,
+            &mut blob_values,
+        );
+        let output = match merged {
+            Ok(Some(output)) => output,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                // the inputs stay live, only what the merges wrote goes
+                for number in outputs.iter() {
+                    remove_table(&self.dir, *number)?;
                 }
+                self.remove_blob_files(outputs.into_iter())?;
+                return Err(err);
+            }
+        };
+        for (number, (count, bytes)) in blob_values.iter() {
+            let (kept_count, kept_bytes) =
+                output.blob_refs().get(number).copied().unwrap_or_default();
+            if kept_count < *count {
+                edit.add_blob_garbage(*number, count - kept_count, bytes - kept_bytes);
             }
-            None
         }
+
+        for (level, file) in inputs.iter() {
+            edit.delete_file(*level, file.number);
+        }
+        let smallest_seqno = inputs.iter().map(|(_, f)| f.smallest_seqno).min();
+        let largest_seqno = inputs.iter().map(|(_, f)| f.largest_seqno).max();
+        if let Some(file) = file_meta_data(
+            &output,
+            column_family,
+            smallest_seqno.unwrap_or_default(),
+            largest_seqno.unwrap_or_default(),
+        ) {
+            edit.add_file(1, file);
+        }
+        sync_dir(&self.dir)?;
+        self.versions.log_and_apply(edit)?;
+
+        let live = self.versions.live_files();
+        for number in inputs.iter().map(|(_, f)| f.number).chain(outputs) {
+            if !live.contains(&number) {
+                self.table_cache.evict(number);
+                remove_table(&self.dir, number)?;
+            }
+        }
+        self.remove_blob_files(blob_values.into_keys())
+    }
+
+    /// Merges `inputs`, newest first, one by one into a single table, so the table merged
+    /// so far is the newer one of each merge. The numbers of the tables the merges
+    /// write go to `outputs` before they are written, the blob files they write to `edit`,
+    /// and the values of each blob file the inputs and outputs point to to `blob_values`.
+    fn merge_inputs(
+        &mut self,
+        column_family: u32,
+        inputs: &[(usize, FileMetaData)],
+        outputs: &mut Vec<u64>,
+        edit: &mut VersionEdit,
+        blob_values: &mut BTreeMap<u64, (u64, u64)>,
+    ) -> Result<Option<SSTable>> {
+        let options = self.column_family_options(column_family);
+        let relocate = self
+            .versions
+            .current()
+            .blob_files()
+            .values()
+            .filter(|f| f.column_family == column_family)
+            .filter(|f| f.garbage_bytes > 0 && f.garbage_ratio() >= self.blob_garbage_ratio)
+            .map(|f| f.number)
+            .collect();
+        let blobs = self.blob_options(relocate);
+        let now = self.clock.now();
+        let mut merged: Option<SSTable> = None;
+        let merge_operator = self.merge_operator.clone();
+        for (i, (_, file)) in inputs.iter().enumerate() {
+            let mut table = self.open_table(file.number)?;
+            table.set_bits_per_key(options.bloom_bits_per_key);
+            table.set_compression(options.compression);
+            table.set_blobs(blobs.clone());
+            for (number, (count, bytes)) in table.blob_refs() {
+                let values = blob_values.entry(*number).or_default();
+                values.0 += count;
+                values.1 += bytes;
+            }
+            // every table of the family goes into the last merge
+            let bottommost = i == inputs.len() - 1;
+            merged = Some(match merged {
+                None => table,
+                Some(merged) => {
+                    let number = self.versions.new_file_number();
+                    outputs.push(number);
+                    let output = merged.merge(
+                        table,
+                        &self.dir,
+                        number,
+                        now,
+                        merge_operator.as_deref(),
+                        bottommost,
+                    )?;
+                    if let Some(file) = blob_file_meta_data(&output, column_family) {
+                        blob_values.insert(file.number, (file.count, file.bytes));
+                        edit.add_blob_file(file);
+                    }
+                    output
+                }
+            });
+        }
+        Ok(merged)
+    }
+
+    /// Deletes those of the blob files numbered `numbers` that are no longer live.
+    fn remove_blob_files(&self, numbers: impl Iterator<Item = u64>) -> Result<()> {
+        let live = self.versions.live_blob_files();
+        for number in numbers.filter(|number| !live.contains(number)) {
+            self.blob_cache.evict(number);
+            match remove_file(blob_path(&self.dir, number)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn blob_options(&self, relocate: HashSet<u64>) -> BlobOptions {
+        BlobOptions {
+            cache: self.blob_cache.clone(),
+            min_blob_size: self.min_blob_size,
+            relocate,
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let version = self.versions.current();
+        Stats {
+            memtable_entries: self.memtables.values().map(MemTable::len).sum(),
+            memtable_bytes: self.memtables.values().map(|m| m.size).sum(),
+            last_sequence: self.versions.last_sequence,
+            levels: (0..NUM_LEVELS)
+                .map(|level| LevelStats {
+                    tables: version.files(level).len(),
+                    bytes: version.files(level).iter().map(|f| f.size).sum(),
+                })
+                .collect(),
+            blob_files: version.blob_files().len(),
+            blob_bytes: version.blob_files().values().map(|f| f.bytes).sum(),
+            blob_garbage_bytes: version.blob_files().values().map(|f| f.garbage_bytes).sum(),
+        }
+    }
+
+    /// Checks every live table and WAL, syncing the WAL first so its buffered records are
+    /// checked too.
+    pub fn verify(&mut self) -> Result<VerifyReport> {
+        self.sync()?;
+        verify::verify(&self.dir, self.versions.current(), &*self.comparator)
+    }
+
+    fn open_table(&self, number: u64) -> Result<SSTable> {
+        let path = table_path(&self.dir, number);
+        if !path.exists() {
+            return Err(Error::NotFound(format!("sstable {}", path.display())));
+        }
+        let mut table = SSTable::from_path(&path)?;
+        table.set_comparator(self.comparator.clone());
+        Ok(table)
     }
-    fn flush(&mut self, dir: &Path) -> io::Result<()> {
-        let mut sstable = SSTable::new(dir)?;
-        for entry in &self.memtable {
-            let entry = Entry {
-                key: entry.key,
-                value: entry.value,
-                deleted: entry.deleted,
-                timestamp: entry.timestamp,
+
+    /// Deletes tables, WALs and manifests left behind by crashes or finished compactions.
+    fn remove_obsolete_files(&self) -> Result<()> {
+        let live = self.versions.live_files();
+        let live_blobs = self.versions.live_blob_files();
+        let manifest = manifest_name(self.versions.manifest_number());
+        for file in read_dir(&self.dir)? {
+            let path = file?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
             };
-            sstable.write(&entry)?;
+            if name.ends_with(".log")
+                && path != self.wal.path
+                && file_number(&path).is_some_and(|n| n < self.versions.log_number)
+            {
+                retire(&path, self.wal_archive_dir.as_deref())?;
+                continue;
+            }
+            let obsolete = if path.extension().is_some_and(|ext| ext == TABLE_EXT) {
+                file_number(&path).is_some_and(|n| !live.contains(&n))
+            } else if path.extension().is_some_and(|ext| ext == BLOB_EXT) {
+                file_number(&path).is_some_and(|n| !live_blobs.contains(&n))
+            } else if name.starts_with(MANIFEST_PREFIX) {
+                name != manifest
+            } else {
+                false
+            };
+            if obsolete {
+                remove_file(&path)?;
+            }
         }
-        sstable.flush()?;
-        self.sstables.push(sstable.path);
-        self.memtable = MemTable::new();
-        self.wal = WAL::new(&self.wal.path.parent().unwrap())?;
         Ok(())
     }
 }
 
+/// The blob file `sstable` wrote values to, which its tables point into.
+pub(crate) fn blob_file_meta_data(
+    sstable: &SSTable,
+    column_family: u32,
+) -> Option<BlobFileMetaData> {
+    let writer = sstable.blob_file()?;
+    Some(BlobFileMetaData {
+        number: writer.number(),
+        column_family,
+        count: writer.count(),
+        bytes: writer.bytes(),
+        garbage_count: 0,
+        garbage_bytes: 0,
+    })
+}
+
+pub(crate) fn file_meta_data(
+    sstable: &SSTable,
+    column_family: u32,
+    smallest_seqno: u64,
+    largest_seqno: u64,
+) -> Option<FileMetaData> {
+    let (smallest, largest) = sstable.key_range()?;
+    Some(FileMetaData {
+        number: sstable.id,
+        size: sstable.size(),
+        smallest: smallest.to_vec(),
+        largest: largest.to_vec(),
+        smallest_seqno,
+        largest_seqno,
+        column_family,
+    })
+}
+
+/// Fails unless `options` has the comparator recorded for the database in `dir`. Databases
+/// created before comparators were recorded are bytewise.
+pub(crate) fn check_comparator(
+    dir: &Path,
+    recorded: Option<&str>,
+    created: bool,
+    options: &Options,
+) -> Result<()> {
+    let recorded = match recorded {
+        Some(name) => name,
+        None if created => return Ok(()),
+        None => BytewiseComparator.name(),
+    };
+    if recorded != options.comparator.name() {
+        return Err(Error::InvalidArgument(format!(
+            "{} was created with comparator {}, not {}",
+            dir.display(),
+            recorded,
+            options.comparator.name()
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn lock_dir(dir: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir.join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(Error::Busy(format!(
+            "{} is locked by another process",
+            dir.display()
+        ))),
+        Err(TryLockError::Error(err)) => Err(err.into()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        database::{
+            clock::ManualClock,
+            comparator::U64Comparator,
+            merge_operator::{MaxOperator, StringAppendOperator, U64AddOperator},
+            options::Compression,
+        },
+        sstable::{
+            blob::{blob_path, BLOB_EXT},
+            cache::BlockCache,
+            sstable::{data_path, files_with_ext, table_path, TABLE_EXT},
+        },
+        wal::wal::log_path,
+    };
 
-    fn create_path() -> PathBuf {
-        PathBuf::from("data")
+    fn create_path(name: &str) -> PathBuf {
+        let path = PathBuf::from("data").join(name);
+        fs::remove_dir_all(&path).ok();
+        path
     }
 
-    fn create_database() -> Database {
-        let path = create_path();
-        Database::new(&path).unwrap()
+    fn create_database(name: &str) -> Database {
+        let path = create_path(name);
+        Database::open(&path).unwrap()
     }
 
     fn create_entry() -> Entry {
@@ -85,84 +1043,922 @@ mod tests {
             value: Some(vec![9]),
             timestamp: 1,
             deleted: false,
+            single_delete: false,
+            blob: None,
+            expires_at: None,
+            operands: Vec::new(),
         }
     }
 
     #[test]
     fn test_read_after_write() {
-        let mut db = create_database();
+        let mut db = create_database("test_read_after_write");
         let entry = create_entry();
         write_entry_to_db(&mut db, &entry);
-        let db_entry = db.get(&entry.key.as_slice()).unwrap();
+        let db_entry = db.get(entry.key.as_slice()).unwrap().unwrap();
         assert_eq!(&entry.value.unwrap(), db_entry.value.as_ref().unwrap());
     }
 
     #[test]
     fn test_sstable_path_is_added_on_flush() {
-        let mut db = create_database();
+        let mut db = create_database("test_sstable_path_is_added_on_flush");
         let entry = create_entry();
         write_entry_to_db(&mut db, &entry);
-        let path = create_path();
-        db.flush(&path).ok();
-        let sstables = &db.sstables;
+        db.flush().unwrap();
+        let sstables = db.versions.current().files(0);
         assert_eq!(sstables.len(), 1);
     }
 
     #[test]
     fn test_memtable_is_empty_after_flush() {
-        let mut db = create_database();
+        let mut db = create_database("test_memtable_is_empty_after_flush");
         let entry = create_entry();
         write_entry_to_db(&mut db, &entry);
-        let path = create_path();
-        db.flush(&path).ok();
-        assert_eq!(db.memtable.size, 0);
+        db.flush().unwrap();
+        assert_eq!(db.memtables[&DEFAULT_COLUMN_FAMILY].size, 0);
     }
 
     #[test]
     fn test_wal_is_empty_after_flush() {
-        let mut db = create_database();
+        let mut db = create_database("test_wal_is_empty_after_flush");
         let entry = create_entry();
         write_entry_to_db(&mut db, &entry);
-        let path = create_path();
-        db.flush(&path).ok();
-        assert_eq!(db.wal.into_iter().count(), 0);
+        db.flush().unwrap();
+        assert_eq!(db.wal.iter().unwrap().count(), 0);
     }
 
     #[test]
     fn test_items_from_database_and_sstable_are_identical() {
-        let mut db = create_database();
-        let path = create_path();
-        let mut sstable = SSTable::new(&path).unwrap();
+        let mut db = create_database("test_items_from_database_and_sstable_are_identical");
+        let path = db.dir.clone();
+        let mut sstable = SSTable::new(&path, db.versions.new_file_number()).unwrap();
         let entry = create_entry();
         write_entry_to_db(&mut db, &entry);
         write_entry_to_sstable(&mut sstable, &entry);
         sstable.flush().ok();
-        db.flush(&path).ok();
+        db.flush().unwrap();
         let item = sstable.get(entry.key.as_slice()).unwrap();
         assert_eq!(entry.value.unwrap(), item.unwrap().value.unwrap());
     }
 
     #[test]
     fn test_scan_sstable_for_entries_when_not_found_in_memtable() {
-        let mut db = create_database();
+        let mut db = create_database("test_scan_sstable_for_entries_when_not_found_in_memtable");
         let entry = create_entry();
         write_entry_to_db(&mut db, &entry);
-        let path = create_path();
-        db.flush(&path).ok();
-        let return_value = db.get(&entry.key.as_slice());
+        db.flush().unwrap();
+        let return_value = db.get(entry.key.as_slice()).unwrap();
         assert!(return_value.is_some());
     }
 
     #[test]
     fn test_scanning_sstables_for_non_existent_entry_returns_none() {
-        let mut db = create_database();
+        let mut db = create_database("test_scanning_sstables_for_non_existent_entry_returns_none");
         let entry = create_entry();
         write_entry_to_db(&mut db, &entry);
-        let path = create_path();
-        db.flush(&path).ok();
+        db.flush().unwrap();
         let key = vec![0, 0, 0, 0];
         assert_ne!(key.as_slice(), entry.key.as_slice());
-        assert!(db.get(key.as_slice()).is_none());
+        assert!(db.get(key.as_slice()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_reopen_recovers_writes_from_wal() {
+        let path = create_path("test_reopen_recovers_writes_from_wal");
+        let mut db = Database::open(&path).unwrap();
+        let entry = create_entry();
+        write_entry_to_db(&mut db, &entry);
+        drop(db);
+        let db = Database::open(&path).unwrap();
+        assert!(db.get(entry.key.as_slice()).unwrap().is_some());
+        assert_eq!(db.versions.last_sequence, 1);
+    }
+
+    #[test]
+    fn test_reopen_recovers_flushed_tables() {
+        let path = create_path("test_reopen_recovers_flushed_tables");
+        let mut db = Database::open(&path).unwrap();
+        let entry = create_entry();
+        write_entry_to_db(&mut db, &entry);
+        db.flush().unwrap();
+        drop(db);
+        let db = Database::open(&path).unwrap();
+        assert_eq!(db.versions.current().files(0).len(), 1);
+        assert_eq!(db.memtables[&DEFAULT_COLUMN_FAMILY].size, 0);
+        assert!(db.get(entry.key.as_slice()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_scan_merges_memtable_and_tables() {
+        let mut db = create_database("test_scan_merges_memtable_and_tables");
+        for key in 0..6u8 {
+            db.set(&[key], &[1], 1).unwrap();
+        }
+        db.flush().unwrap();
+        db.set(&[2], &[2], 2).unwrap();
+        db.delete(&[3], 2).unwrap();
+        let entries: Vec<(u8, u8)> = db
+            .scan(vec![1]..vec![5])
+            .unwrap()
+            .map(|e| e.unwrap())
+            .map(|e| (e.key[0], e.value.unwrap()[0]))
+            .collect();
+        assert_eq!(entries, vec![(1, 1), (2, 2), (4, 1)]);
+    }
+
+    #[test]
+    fn test_write_batch_is_recovered() {
+        let path = create_path("test_write_batch_is_recovered");
+        let mut db = Database::open(&path).unwrap();
+        db.set(&[1], &[1], 1).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(&[2], &[2], 2);
+        batch.delete(&[1], 2);
+        db.write(&batch).unwrap();
+        assert_eq!(db.versions.last_sequence, 3);
+        drop(db);
+        let db = Database::open(&path).unwrap();
+        assert!(db.get(&[1]).unwrap().unwrap().deleted);
+        assert_eq!(db.get(&[2]).unwrap().unwrap().value, Some(vec![2]));
+        assert_eq!(db.versions.last_sequence, 3);
+    }
+
+    #[test]
+    fn test_second_open_is_busy() {
+        let path = create_path("test_second_open_is_busy");
+        let _db = Database::open(&path).unwrap();
+        assert!(matches!(Database::open(&path), Err(Error::Busy(_))));
+    }
+
+    #[test]
+    fn test_orphan_tables_are_removed_on_open() {
+        let path = create_path("test_orphan_tables_are_removed_on_open");
+        let db = Database::open(&path).unwrap();
+        let mut orphan = SSTable::new(&path, 1000).unwrap();
+        write_entry_to_sstable(&mut orphan, &create_entry());
+        orphan.flush().unwrap();
+        drop(db);
+        let _db = Database::open(&path).unwrap();
+        assert!(!data_path(&table_path(&path, orphan.id)).exists());
+    }
+
+    #[test]
+    fn test_compact_keeps_newest_values() {
+        let mut db = create_database("test_compact_keeps_newest_values");
+        for timestamp in 1..4 {
+            db.set(&[1], &[timestamp as u8], timestamp).unwrap();
+            db.set(&[timestamp as u8 + 1], &[0], timestamp).unwrap();
+            db.flush().unwrap();
+        }
+        db.compact().unwrap();
+        assert_eq!(db.versions.current().files(0).len(), 0);
+        assert_eq!(db.versions.current().files(1).len(), 1);
+        let entry = db.get(&[1]).unwrap().unwrap();
+        assert_eq!(entry.value, Some(vec![3]));
+        assert!(db.get(&[4]).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_compact_keeps_last_write_with_equal_timestamps() {
+        let mut db = create_database("test_compact_keeps_last_write_with_equal_timestamps");
+        for value in 1..4u8 {
+            db.set(&[1], &[value], 1).unwrap();
+            db.flush().unwrap();
+        }
+        assert_eq!(db.get(&[1]).unwrap().unwrap().value, Some(vec![3]));
+        db.compact().unwrap();
+        assert_eq!(db.get(&[1]).unwrap().unwrap().value, Some(vec![3]));
+    }
+
+    #[test]
+    fn test_expired_entries_are_absent() {
+        let path = create_path("test_expired_entries_are_absent");
+        let clock = Arc::new(ManualClock::new(1_000));
+        let open = |clock: &Arc<ManualClock>| {
+            let options = Options {
+                clock: clock.clone(),
+                ..Default::default()
+            };
+            Database::open_with_options(&path, options).unwrap()
+        };
+        let mut db = open(&clock);
+        db.set(&[1], &[1], 1).unwrap();
+        db.flush().unwrap();
+        db.set_with_ttl(&[1], &[2], 2, Duration::from_secs(1))
+            .unwrap();
+        db.set_with_ttl(&[2], &[2], 2, Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(db.get(&[1]).unwrap().unwrap().value, Some(vec![2]));
+
+        clock.advance(Duration::from_secs(1));
+        // the expired entry still hides the older value
+        assert!(db.get(&[1]).unwrap().is_none());
+        let keys: Vec<Vec<u8>> = db.scan(..).unwrap().map(|e| e.unwrap().key).collect();
+        assert_eq!(keys, vec![vec![2]]);
+
+        // the expiry survives recovery from the WAL, a flush and a compaction
+        drop(db);
+        let mut db = open(&clock);
+        assert!(db.get(&[1]).unwrap().is_none());
+        db.flush().unwrap();
+        db.compact().unwrap();
+        assert!(db.get(&[1]).unwrap().is_none());
+        assert!(db.get(&[2]).unwrap().is_some());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(db.scan(..).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_delete_range_hides_older_versions() {
+        let path = create_path("test_delete_range_hides_older_versions");
+        let mut db = Database::open(&path).unwrap();
+        for key in 0..8u8 {
+            db.set(&[key], &[key], 1).unwrap();
+        }
+        db.flush().unwrap();
+        db.set(&[3], &[3], 2).unwrap();
+        db.delete_range(&[2], &[6], 3).unwrap();
+        db.set(&[4], &[9], 4).unwrap();
+        let keys = |db: &Database| -> Vec<u8> {
+            db.scan(..).unwrap().map(|e| e.unwrap().key[0]).collect()
+        };
+        assert_eq!(keys(&db), vec![0, 1, 4, 6, 7]);
+        assert!(db.get(&[3]).unwrap().unwrap().is_tombstone());
+        assert_eq!(db.get(&[4]).unwrap().unwrap().value, Some(vec![9]));
+        assert_eq!(db.get(&[6]).unwrap().unwrap().value, Some(vec![6]));
+        assert!(matches!(
+            db.delete_range(&[6], &[6], 5),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        // the tombstone survives recovery from the WAL and a flush
+        drop(db);
+        let mut db = Database::open(&path).unwrap();
+        assert_eq!(keys(&db), vec![0, 1, 4, 6, 7]);
+        db.flush().unwrap();
+        assert_eq!(keys(&db), vec![0, 1, 4, 6, 7]);
+        assert!(db.get(&[2]).unwrap().unwrap().is_tombstone());
+        assert!(db.verify().unwrap().is_ok());
+
+        // compaction drops the tombstone with the versions it covers
+        db.compact().unwrap();
+        assert_eq!(keys(&db), vec![0, 1, 4, 6, 7]);
+        assert!(db.get(&[2]).unwrap().is_none());
+        let table = db.versions.current().files(1)[0].number;
+        let table = db.open_table(table).unwrap();
+        assert!(table.range_tombstones().is_empty());
+        assert_eq!(table.iter().unwrap().count(), 5);
+    }
+
+    #[test]
+    fn test_damaged_block_fails_get() {
+        let path = create_path("test_damaged_block_fails_get");
+        let mut db = Database::open(&path).unwrap();
+        for key in 0..10u8 {
+            db.set(&[key], &[key; 64], 1).unwrap();
+        }
+        db.flush().unwrap();
+        let table = db.versions.current().files(0)[0].number;
+        drop(db);
+        // flip a byte of the first value
+        let data = data_path(&table_path(&path, table));
+        let mut bytes = fs::read(&data).unwrap();
+        bytes[20] ^= 0xff;
+        fs::write(&data, bytes).unwrap();
+
+        let db = Database::open(&path).unwrap();
+        assert!(matches!(db.get(&[0]), Err(Error::Corruption(_))));
+    }
+
+    #[test]
+    fn test_truncated_record_fails_scan_and_compaction() {
+        let path = create_path("test_truncated_record_fails_scan_and_compaction");
+        let mut db = Database::open(&path).unwrap();
+        for key in 0..10u8 {
+            db.set(&[key], &[key; 64], 1).unwrap();
+        }
+        db.flush().unwrap();
+        db.set(&[20], &[20], 2).unwrap();
+        db.flush().unwrap();
+        let table = db.versions.current().files(0)[0].number;
+        drop(db);
+        // cut the last record of the older table in half
+        let data = data_path(&table_path(&path, table));
+        let len = fs::metadata(&data).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&data)
+            .unwrap()
+            .set_len(len - 10)
+            .unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        assert!(matches!(db.get(&[9]), Err(Error::Corruption(_))));
+        let entries: Vec<Result<Entry>> = db.scan(..).unwrap().collect();
+        assert!(matches!(entries.last(), Some(Err(Error::Corruption(_)))));
+        let tables = || {
+            let mut tables = files_with_ext(&path, TABLE_EXT).unwrap();
+            tables.sort();
+            tables
+        };
+        let before = tables();
+        assert!(matches!(db.compact(), Err(Error::Corruption(_))));
+        // the inputs are still live and nothing the merge wrote is left behind
+        assert_eq!(db.versions.current().files(0).len(), 2);
+        assert_eq!(tables(), before);
+        assert_eq!(db.get(&[20]).unwrap().unwrap().value, Some(vec![20]));
+    }
+
+    #[test]
+    fn test_compaction_does_not_resurrect_deleted_keys() {
+        let mut db = create_database("test_compaction_does_not_resurrect_deleted_keys");
+        db.set(b"a", b"1", 1).unwrap();
+        db.set(b"r", b"1", 1).unwrap();
+        db.flush().unwrap();
+        db.set(b"x", b"1", 1).unwrap();
+        db.flush().unwrap();
+        db.compact().unwrap();
+        // level 0 now holds newer versions and tombstones above the oldest values on level 1
+        db.set(b"a", b"2", 2).unwrap();
+        db.set(b"r", b"2", 2).unwrap();
+        db.flush().unwrap();
+        db.delete(b"a", 3).unwrap();
+        db.delete_range(b"q", b"s", 3).unwrap();
+        db.flush().unwrap();
+        db.set(b"y", b"4", 4).unwrap();
+        db.flush().unwrap();
+        assert_eq!(db.versions.current().family_files(0).count(), 4);
+        let keys = |db: &Database| -> Vec<Vec<u8>> {
+            db.scan(..).unwrap().map(|e| e.unwrap().key).collect()
+        };
+        assert_eq!(keys(&db), vec![b"x".to_vec(), b"y".to_vec()]);
+
+        db.compact().unwrap();
+        assert_eq!(keys(&db), vec![b"x".to_vec(), b"y".to_vec()]);
+        assert!(db.get(b"a").unwrap().is_none());
+        assert!(db.get(b"r").unwrap().is_none());
+        // the tombstones reached the oldest values and were dropped with them
+        let table = db.versions.current().files(1)[0].number;
+        let table = db.open_table(table).unwrap();
+        assert_eq!(table.iter().unwrap().count(), 2);
+        assert!(table.range_tombstones().is_empty());
+    }
+
+    #[test]
+    fn test_single_delete_hides_the_key() {
+        let path = create_path("test_single_delete_hides_the_key");
+        let mut db = Database::open(&path).unwrap();
+        db.set(b"a", b"1", 1).unwrap();
+        db.set(b"b", b"1", 1).unwrap();
+        db.flush().unwrap();
+        db.single_delete(b"a", 2).unwrap();
+        assert!(db.get(b"a").unwrap().unwrap().is_tombstone());
+
+        // the kind of tombstone survives recovery from the WAL and a flush
+        drop(db);
+        let mut db = Database::open(&path).unwrap();
+        db.flush().unwrap();
+        let table = db.versions.current().files(0)[1].number;
+        let entries: Vec<Entry> = db
+            .open_table(table)
+            .unwrap()
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert!(entries[0].is_tombstone() && entries[0].single_delete);
+        let keys: Vec<Vec<u8>> = db.scan(..).unwrap().map(|e| e.unwrap().key).collect();
+        assert_eq!(keys, vec![b"b".to_vec()]);
+
+        db.compact().unwrap();
+        assert!(db.get(b"a").unwrap().is_none());
+        let table = db.versions.current().files(1)[0].number;
+        assert_eq!(db.open_table(table).unwrap().iter().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_unrecorded_wal_copy_is_not_replayed() {
+        let path = create_path("test_unrecorded_wal_copy_is_not_replayed");
+        let open = || {
+            let options = Options {
+                merge_operator: Some(Arc::new(U64AddOperator)),
+                ..Default::default()
+            };
+            Database::open_with_options(&path, options).unwrap()
+        };
+        let mut db = open();
+        db.merge(b"count", &2u64.to_le_bytes(), 1).unwrap();
+        db.sync().unwrap();
+        drop(db);
+        // a recovery that crashed before recording its WAL in the MANIFEST left a copy
+        let wal = log_files(&path).unwrap().pop().unwrap();
+        let number = file_number(&wal).unwrap();
+        fs::copy(&wal, log_path(&path, number + 10)).unwrap();
+
+        let db = open();
+        assert_eq!(log_files(&path).unwrap().len(), 1);
+        assert_eq!(
+            db.get(b"count").unwrap().unwrap().value,
+            Some(2u64.to_le_bytes().to_vec())
+        );
+        let batches: Vec<u64> = db
+            .wal
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|entry| entry.batch_seqno)
+            .collect();
+        assert_eq!(batches, vec![1]);
+    }
+
+    #[test]
+    fn test_merge_operands_are_folded() {
+        let path = create_path("test_merge_operands_are_folded");
+        let open = |merge_operator: Arc<dyn MergeOperator>| {
+            let options = Options {
+                merge_operator: Some(merge_operator),
+                ..Default::default()
+            };
+            Database::open_with_options(&path, options).unwrap()
+        };
+        let mut db = open(Arc::new(U64AddOperator));
+        db.set(b"count", &5u64.to_le_bytes(), 1).unwrap();
+        db.flush().unwrap();
+        db.merge(b"count", &2u64.to_le_bytes(), 2).unwrap();
+        db.flush().unwrap();
+        db.merge(b"count", &3u64.to_le_bytes(), 3).unwrap();
+        db.merge(b"new", &1u64.to_le_bytes(), 3).unwrap();
+        let entry = db.get(b"count").unwrap().unwrap();
+        assert_eq!(entry.value, Some(10u64.to_le_bytes().to_vec()));
+        assert!(entry.operands.is_empty());
+        let values: Vec<Option<Vec<u8>>> = db.scan(..).unwrap().map(|e| e.unwrap().value).collect();
+        assert_eq!(
+            values,
+            vec![
+                Some(10u64.to_le_bytes().to_vec()),
+                Some(1u64.to_le_bytes().to_vec())
+            ]
+        );
+
+        // operands survive recovery from the WAL and are folded by compaction
+        drop(db);
+        let mut db = open(Arc::new(U64AddOperator));
+        db.delete(b"new", 4).unwrap();
+        db.merge(b"new", &7u64.to_le_bytes(), 5).unwrap();
+        db.flush().unwrap();
+        db.compact().unwrap();
+        let table = db.versions.current().files(1)[0].number;
+        let entries: Vec<Entry> = db
+            .open_table(table)
+            .unwrap()
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert!(entries.iter().all(|entry| entry.operands.is_empty()));
+        assert_eq!(
+            db.get(b"new").unwrap().unwrap().value,
+            Some(7u64.to_le_bytes().to_vec())
+        );
+        drop(db);
+
+        // without an operator the operands are returned as they are
+        let options = Options::default();
+        let mut db = Database::open_with_options(&path, options).unwrap();
+        db.merge(b"count", &1u64.to_le_bytes(), 6).unwrap();
+        let entry = db.get(b"count").unwrap().unwrap();
+        assert_eq!(entry.value, Some(10u64.to_le_bytes().to_vec()));
+        assert_eq!(entry.operands, vec![1u64.to_le_bytes().to_vec()]);
+        drop(db);
+        let db = open(Arc::new(MaxOperator));
+        assert_eq!(
+            db.get(b"count").unwrap().unwrap().value,
+            Some(10u64.to_le_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn test_comparator_orders_keys() {
+        let path = create_path("test_comparator_orders_keys");
+        let options = || Options {
+            comparator: Arc::new(U64Comparator),
+            ..Default::default()
+        };
+        let mut db = Database::open_with_options(&path, options()).unwrap();
+        for key in [256u64, 1, 3] {
+            db.set(&key.to_be_bytes()[6..], &[1], 1).unwrap();
+        }
+        db.flush().unwrap();
+        for key in [2u64, 300] {
+            db.set(&key.to_be_bytes()[6..], &[2], 2).unwrap();
+        }
+        db.set(&[3], &[2], 2).unwrap();
+        db.flush().unwrap();
+        db.compact().unwrap();
+        db.set(&[0, 4], &[3], 3).unwrap();
+        let keys = |db: &Database, range: (Bound<Vec<u8>>, Bound<Vec<u8>>)| -> Vec<Vec<u8>> {
+            db.scan(range)
+                .unwrap()
+                .map(|entry| entry.unwrap().key)
+                .collect()
+        };
+        // bytewise, [3] would come last
+        assert_eq!(
+            keys(&db, (Bound::Excluded(vec![0, 2]), Bound::Unbounded)),
+            vec![vec![3], vec![0, 3], vec![0, 4], vec![1, 0], vec![1, 44]]
+        );
+        assert_eq!(db.get(&[1, 44]).unwrap().unwrap().value, Some(vec![2]));
+        assert!(db.verify().unwrap().is_ok());
+
+        // the comparator is recorded and carried over into checkpoints
+        db.checkpoint(&path.join("checkpoint")).unwrap();
+        drop(db);
+        for dir in [path.clone(), path.join("checkpoint")] {
+            assert!(matches!(
+                Database::open(&dir),
+                Err(Error::InvalidArgument(_))
+            ));
+            let db = Database::open_with_options(&dir, options()).unwrap();
+            assert_eq!(db.scan(..).unwrap().count(), 7);
+        }
+    }
+
+    #[test]
+    fn test_column_families_are_separate_keyspaces() {
+        let path = create_path("test_column_families_are_separate_keyspaces");
+        let mut db = Database::open(&path).unwrap();
+        let options = ColumnFamilyOptions {
+            bloom_bits_per_key: 4,
+            compression: Compression::Snappy,
+            compaction_trigger: Some(8),
+        };
+        let users = db.create_column_family("users", options.clone()).unwrap();
+        assert!(matches!(
+            db.create_column_family("users", ColumnFamilyOptions::default()),
+            Err(Error::InvalidArgument(_))
+        ));
+        db.set(&[1], &[1], 1).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put_cf(&users, &[1], &[2], 2);
+        batch.put_cf(&users, &[2], &[2], 2);
+        batch.delete(&[1], 2);
+        db.write(&batch).unwrap();
+        db.flush().unwrap();
+        db.set_cf(&users, &[3], &[3], 3).unwrap();
+        assert!(db.get(&[1]).unwrap().unwrap().deleted);
+        assert_eq!(
+            db.get_cf(&users, &[1]).unwrap().unwrap().value,
+            Some(vec![2])
+        );
+        assert!(db.get(&[2]).unwrap().is_none());
+
+        // the batch and the family's options survive reopening and compaction
+        drop(db);
+        let mut db = Database::open(&path).unwrap();
+        let users = db.column_family("users").unwrap();
+        db.compact().unwrap();
+        let keys: Vec<Vec<u8>> = db
+            .scan_cf(&users, ..)
+            .unwrap()
+            .map(|e| e.unwrap().key)
+            .collect();
+        assert_eq!(keys, vec![vec![1], vec![2], vec![3]]);
+        assert_eq!(db.scan(..).unwrap().count(), 0);
+        assert_eq!(db.column_family_options(users.id()), options);
+        let families: Vec<String> = db
+            .column_families()
+            .iter()
+            .map(|cf| cf.name().to_string())
+            .collect();
+        assert_eq!(families, vec!["default", "users"]);
+    }
+
+    #[test]
+    fn test_dropped_column_family_is_gone() {
+        let path = create_path("test_dropped_column_family_is_gone");
+        let mut db = Database::open(&path).unwrap();
+        let users = db
+            .create_column_family("users", ColumnFamilyOptions::default())
+            .unwrap();
+        db.set_cf(&users, &[1], &[1], 1).unwrap();
+        db.flush().unwrap();
+        let table = db.versions.current().family_files(users.id()).next();
+        let table = table.map(|(_, f)| f.number).unwrap();
+        db.set_cf(&users, &[2], &[2], 2).unwrap();
+        assert!(matches!(
+            db.drop_column_family(&db.column_family("default").unwrap()),
+            Err(Error::InvalidArgument(_))
+        ));
+        db.drop_column_family(&users).unwrap();
+        assert!(!data_path(&table_path(&path, table)).exists());
+        assert!(matches!(
+            db.set_cf(&users, &[3], &[3], 3),
+            Err(Error::NotFound(_))
+        ));
+
+        // its writes still in the WAL are not recovered, and the name can be reused
+        drop(db);
+        let mut db = Database::open(&path).unwrap();
+        assert!(db.column_family("users").is_none());
+        let users_again = db
+            .create_column_family("users", ColumnFamilyOptions::default())
+            .unwrap();
+        assert_ne!(users_again.id(), users.id());
+        assert_eq!(db.scan_cf(&users_again, ..).unwrap().count(), 0);
+        assert!(matches!(db.get_cf(&users, &[2]), Err(Error::NotFound(_))));
+    }
+
+    fn family_data_bytes(db: &Database, column_family: &ColumnFamily) -> u64 {
+        db.versions
+            .current()
+            .family_files(column_family.id())
+            .map(|(_, file)| {
+                let path = data_path(&table_path(&db.dir, file.number));
+                fs::metadata(path).unwrap().len()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_compressed_family_roundtrip() {
+        let path = create_path("test_compressed_family_roundtrip");
+        let mut db = Database::open(&path).unwrap();
+        let options = ColumnFamilyOptions {
+            compression: Compression::Snappy,
+            ..Default::default()
+        };
+        let logs = db.create_column_family("logs", options).unwrap();
+        let plain = db
+            .create_column_family("plain", ColumnFamilyOptions::default())
+            .unwrap();
+        for round in 0..2u8 {
+            for i in 0..2000u32 {
+                let value = vec![round + (i % 4) as u8; 200];
+                let timestamp = round as u128 + 1;
+                db.set_cf(&logs, &i.to_be_bytes(), &value, timestamp)
+                    .unwrap();
+                db.set_cf(&plain, &i.to_be_bytes(), &value, timestamp)
+                    .unwrap();
+            }
+            db.flush().unwrap();
+        }
+        assert!(family_data_bytes(&db, &logs) * 4 < family_data_bytes(&db, &plain));
+
+        drop(db);
+        let mut db = Database::open(&path).unwrap();
+        let logs = db.column_family("logs").unwrap();
+        db.compact().unwrap();
+        assert_eq!(db.versions.current().family_files(logs.id()).count(), 1);
+        assert!(db.verify().unwrap().is_ok());
+        for i in [0u32, 777, 1999] {
+            let entry = db.get_cf(&logs, &i.to_be_bytes()).unwrap().unwrap();
+            assert_eq!(entry.value, Some(vec![1 + (i % 4) as u8; 200]));
+        }
+        let start = 1000u32.to_be_bytes().to_vec();
+        assert_eq!(db.scan_cf(&logs, start..).unwrap().count(), 1000);
+    }
+
+    #[test]
+    fn test_compaction_trigger_compacts_after_flush() {
+        let mut db = create_database("test_compaction_trigger_compacts_after_flush");
+        let options = ColumnFamilyOptions {
+            compaction_trigger: Some(3),
+            ..Default::default()
+        };
+        let users = db.create_column_family("users", options).unwrap();
+        for i in 0..3u8 {
+            db.set_cf(&users, &[i], &[i], 1).unwrap();
+            db.set(&[i], &[i], 1).unwrap();
+            db.flush().unwrap();
+            let tables = db.versions.current().family_files(users.id()).count();
+            assert_eq!(tables, if i < 2 { i as usize + 1 } else { 1 });
+        }
+        let files: Vec<usize> = db
+            .versions
+            .current()
+            .family_files(users.id())
+            .map(|(level, _)| level)
+            .collect();
+        assert_eq!(files, vec![1]);
+        assert_eq!(db.versions.current().files(0).len(), 3);
+        assert_eq!(db.scan_cf(&users, ..).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_stats_count_memtable_and_tables() {
+        let mut db = create_database("test_stats_count_memtable_and_tables");
+        db.set(&[1], &[1], 1).unwrap();
+        db.flush().unwrap();
+        db.set(&[2], &[2], 2).unwrap();
+        let stats = db.stats();
+        assert_eq!(stats.memtable_entries, 1);
+        assert_eq!(stats.last_sequence, 2);
+        assert_eq!(stats.levels[0].tables, 1);
+        assert!(stats.levels[0].bytes > 0);
+        assert_eq!(stats.levels[1], LevelStats::default());
+    }
+
+    #[test]
+    fn test_file_numbers_keep_increasing_after_reopen() {
+        let path = create_path("test_file_numbers_keep_increasing_after_reopen");
+        let mut db = Database::open(&path).unwrap();
+        write_entry_to_db(&mut db, &create_entry());
+        db.flush().unwrap();
+        let first = db.versions.current().files(0)[0].number;
+        drop(db);
+        let mut db = Database::open(&path).unwrap();
+        assert!(file_number(&db.wal.path).unwrap() > first);
+        db.set(&[7], &[7], 2).unwrap();
+        db.flush().unwrap();
+        let numbers: Vec<u64> = db
+            .versions
+            .current()
+            .files(0)
+            .iter()
+            .map(|f| f.number)
+            .collect();
+        assert_eq!(numbers.len(), 2);
+        assert!(numbers[1] > numbers[0]);
+        assert!(table_path(&path, numbers[1]).ends_with(format!("{:06}.sst", numbers[1])));
+    }
+
+    #[test]
+    fn test_checkpoint_opens_as_database() {
+        let mut db = create_database("test_checkpoint_source");
+        db.set(&[1], &[1], 1).unwrap();
+        db.flush().unwrap();
+        db.set(&[2], &[2], 2).unwrap();
+        let target = PathBuf::from("data").join("test_checkpoint_target");
+        fs::remove_dir_all(&target).ok();
+        db.checkpoint(&target).unwrap();
+        assert!(db.checkpoint(&target).is_err());
+        let modified = || -> Vec<(PathBuf, std::time::SystemTime)> {
+            let mut files: Vec<_> = fs::read_dir(&target)
+                .unwrap()
+                .map(|file| file.unwrap().path())
+                .map(|path| {
+                    (
+                        path.clone(),
+                        fs::metadata(path).unwrap().modified().unwrap(),
+                    )
+                })
+                .collect();
+            files.sort();
+            files
+        };
+        let before = modified();
+        // later writes and compactions do not reach the checkpoint, whose tables are linked
+        // to the compacted ones
+        db.set(&[1], &[3], 3).unwrap();
+        db.compact().unwrap();
+        assert_eq!(modified(), before);
+
+        let checkpoint = Database::open(&target).unwrap();
+        assert_eq!(checkpoint.get(&[1]).unwrap().unwrap().value, Some(vec![1]));
+        assert_eq!(checkpoint.get(&[2]).unwrap().unwrap().value, Some(vec![2]));
+        assert_eq!(checkpoint.stats().levels[0].tables, 2);
+        assert_eq!(checkpoint.stats().last_sequence, 2);
+    }
+
+    #[test]
+    fn test_databases_share_block_cache() {
+        let block_cache = Arc::new(BlockCache::new(1 << 20));
+        let open = |name: &str, value: &[u8]| {
+            let options = Options {
+                block_cache: block_cache.clone(),
+                ..Default::default()
+            };
+            let mut db = Database::open_with_options(&create_path(name), options).unwrap();
+            db.set(b"k", value, 1).unwrap();
+            db.flush().unwrap();
+            db
+        };
+        // both tables are numbered the same
+        let a = open("test_databases_share_block_cache_a", b"from-a");
+        let b = open("test_databases_share_block_cache_b", b"from-b");
+        assert_eq!(
+            a.get(b"k").unwrap().unwrap().value,
+            Some(b"from-a".to_vec())
+        );
+        assert_eq!(
+            b.get(b"k").unwrap().unwrap().value,
+            Some(b"from-b".to_vec())
+        );
+        assert_eq!(
+            a.get(b"k").unwrap().unwrap().value,
+            Some(b"from-a".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_large_values_are_read_from_blob_files() {
+        let path = create_path("test_large_values_are_read_from_blob_files");
+        let options = || Options {
+            min_blob_size: Some(100),
+            merge_operator: Some(Arc::new(StringAppendOperator::new(b","))),
+            ..Default::default()
+        };
+        let mut db = Database::open_with_options(&path, options()).unwrap();
+        db.set(b"large", &[7; 1000], 1).unwrap();
+        db.set(b"small", &[7; 10], 1).unwrap();
+        db.flush().unwrap();
+        let table = db.versions.current().files(0)[0].number;
+        assert!(blob_path(&path, table).exists());
+        let entries: Vec<Entry> = db
+            .open_table(table)
+            .unwrap()
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert!(entries[0].value.is_none() && entries[0].blob.is_some());
+        assert_eq!(entries[1].value, Some(vec![7; 10]));
+        assert_eq!(db.stats().blob_bytes, 1000);
+
+        let entry = db.get(b"large").unwrap().unwrap();
+        assert_eq!((entry.value, entry.blob), (Some(vec![7; 1000]), None));
+        let values: Vec<usize> = db
+            .scan(..)
+            .unwrap()
+            .map(|e| e.unwrap().value.unwrap().len())
+            .collect();
+        assert_eq!(values, vec![1000, 10]);
+        // operands are folded into the value in the blob file by reads and compaction
+        db.merge(b"large", b"x", 2).unwrap();
+        db.flush().unwrap();
+        db.compact().unwrap();
+        let value = db.get(b"large").unwrap().unwrap().value.unwrap();
+        assert_eq!((value.len(), value.last()), (1002, Some(&b'x')));
+
+        // the blob files are part of a checkpoint, which reads them without the option
+        let target = PathBuf::from("data").join("test_blob_checkpoint_target");
+        fs::remove_dir_all(&target).ok();
+        db.checkpoint(&target).unwrap();
+        let checkpoint = Database::open(&target).unwrap();
+        assert_eq!(
+            checkpoint.get(b"large").unwrap().unwrap().value,
+            Some(value)
+        );
+        assert!(checkpoint.stats().blob_files > 0);
+    }
+
+    #[test]
+    fn test_compaction_collects_blob_garbage() {
+        let path = create_path("test_compaction_collects_blob_garbage");
+        let options = Options {
+            min_blob_size: Some(10),
+            blob_garbage_ratio: 0.5,
+            ..Default::default()
+        };
+        let mut db = Database::open_with_options(&path, options).unwrap();
+        for key in 0..4u8 {
+            db.set(&[key], &[key; 100], 1).unwrap();
+        }
+        db.flush().unwrap();
+        let first = db.versions.current().files(0)[0].number;
+        // overwrite three of the four values, so three quarters of the blob file are garbage
+        for key in 0..3u8 {
+            db.set(&[key], &[key + 10; 100], 2).unwrap();
+        }
+        db.flush().unwrap();
+        db.compact().unwrap();
+        assert_eq!(db.stats().blob_garbage_bytes, 300);
+        assert!(blob_path(&path, first).exists());
+        // the next compaction writes the value left to another blob file and deletes the
+        // old one
+        db.set(&[9], &[9], 3).unwrap();
+        db.flush().unwrap();
+        db.compact().unwrap();
+        assert!(!blob_path(&path, first).exists());
+        let stats = db.stats();
+        assert_eq!((stats.blob_files, stats.blob_bytes), (2, 400));
+        assert_eq!(stats.blob_garbage_bytes, 0);
+        let values: Vec<Vec<u8>> = db
+            .scan(..)
+            .unwrap()
+            .map(|e| e.unwrap().value.unwrap())
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                vec![10; 100],
+                vec![11; 100],
+                vec![12; 100],
+                vec![3; 100],
+                vec![9]
+            ]
+        );
+        assert!(db.verify().unwrap().is_ok());
+
+        db.delete_range(&[0], &[10], 4).unwrap();
+        db.flush().unwrap();
+        db.compact().unwrap();
+        assert_eq!(db.stats().blob_files, 0);
+        let blob_files = fs::read_dir(&path)
+            .unwrap()
+            .filter(|f| f.as_ref().unwrap().path().extension() == Some(BLOB_EXT.as_ref()))
+            .count();
+        assert_eq!(blob_files, 0);
     }
 
     fn write_entry_to_sstable(sstable: &mut SSTable, entry: &Entry) {
@@ -170,7 +1966,11 @@ mod tests {
             key: entry.key.clone(),
             value: entry.value.clone(),
             deleted: entry.deleted,
+            single_delete: false,
+            blob: None,
+            expires_at: None,
             timestamp: entry.timestamp,
+            operands: Vec::new(),
         };
         sstable.write(&entry).ok();
     }
@@ -1,7 +1,37 @@
+use crate::sstable::blob::BlobIndex;
+
 #[derive(Clone)]
 pub struct Entry {
     pub key: Vec<u8>,
     pub value: Option<Vec<u8>>,
     pub timestamp: u128,
     pub deleted: bool,
+    /// Marks a tombstone written by `Database::single_delete`, which may be dropped together
+    /// with the one value it deletes.
+    pub single_delete: bool,
+    /// Time in microseconds since the Unix epoch from which on the entry counts as absent.
+    pub expires_at: Option<u128>,
+    /// Merge operands written after the version given by `value` or `deleted`, oldest
+    /// first. With neither a value nor a tombstone they apply to the older versions of the
+    /// key. Reads fold them with `Options::merge_operator`.
+    pub operands: Vec<Vec<u8>>,
+    /// Where the value is if a table moved it to a blob file, in which case `value` is
+    /// `None`. Reads return entries with the value loaded.
+    pub blob: Option<BlobIndex>,
+}
+
+impl Entry {
+    pub fn is_expired(&self, now: u128) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Whether the entry deletes the key, as opposed to a tombstone with operands on top.
+    pub fn is_tombstone(&self) -> bool {
+        self.deleted && self.operands.is_empty()
+    }
+
+    /// Whether the entry only holds merge operands, which need the key's older versions.
+    pub fn is_pending(&self) -> bool {
+        self.value.is_none() && self.blob.is_none() && !self.deleted && !self.operands.is_empty()
+    }
 }
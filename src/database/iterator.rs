@@ -0,0 +1,402 @@
+use std::{cmp::Ordering, collections::BTreeMap, ops::Bound, sync::Arc};
+
+use crate::{
+    sstable::blob::{self, BlobFile},
+    Error, Result,
+};
+
+use super::{
+    comparator::Comparator,
+    entry::Entry,
+    merge_operator::{resolve, stack, MergeOperator},
+    range_tombstone::{cover, RangeTombstone},
+};
+
+type Source = Box<dyn Iterator<Item = Result<Entry>> + Send>;
+
+/// Iterates over the live entries of a key range in key order.
+///
+/// Sources are ordered newest first and must each be sorted by `comparator`. When several sources
+/// hold the same key only the entry of the newest one is used, with the older ones below
+/// it if it only holds merge operands, and keys whose newest entry is a tombstone or
+/// expired at `now` are skipped. Versions covered by one of the range tombstones count as
+/// tombstones.
+///
+/// Values in blob files are read from the files handed to `with_blob_files` when their
+/// entry is returned. A value that cannot be read, or a source failing, is returned as the
+/// error and ends the iteration.
+pub struct DatabaseIterator {
+    sources: Vec<Source>,
+    // next entry of each source; there are few sources, so the smallest is found by
+    // looking at all of them
+    heads: Vec<Option<Entry>>,
+    // first error of a source, returned instead of the next entry
+    error: Option<Error>,
+    range_tombstones: Vec<RangeTombstone>,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    now: u128,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    comparator: Arc<dyn Comparator>,
+    // kept open so compactions deleting them do not affect the iteration
+    blob_files: BTreeMap<u64, Arc<BlobFile>>,
+}
+
+impl DatabaseIterator {
+    pub fn new(
+        sources: Vec<Source>,
+        range_tombstones: Vec<RangeTombstone>,
+        start: Bound<Vec<u8>>,
+        end: Bound<Vec<u8>>,
+        now: u128,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Self {
+        let mut iterator = DatabaseIterator {
+            heads: vec![None; sources.len()],
+            error: None,
+            sources,
+            range_tombstones,
+            start,
+            end,
+            now,
+            merge_operator,
+            comparator,
+            blob_files: BTreeMap::new(),
+        };
+        for source in 0..iterator.sources.len() {
+            iterator.advance(source);
+        }
+        iterator
+    }
+
+    /// Blob files the values of the sources may be in, by number.
+    pub fn with_blob_files(mut self, blob_files: BTreeMap<u64, Arc<BlobFile>>) -> Self {
+        self.blob_files = blob_files;
+        self
+    }
+
+    fn advance(&mut self, source: usize) {
+        // table sources start at a block boundary, so skip what precedes the range
+        let entry = loop {
+            match self.sources[source].next() {
+                Some(Ok(entry)) if !self.after_start(&entry.key) => continue,
+                Some(Ok(entry)) => break Some(entry),
+                Some(Err(err)) => {
+                    self.error.get_or_insert(err);
+                    break None;
+                }
+                None => break None,
+            }
+        };
+        self.heads[source] =
+            entry.map(|entry| cover(entry, &self.range_tombstones, &*self.comparator));
+    }
+
+    /// Source with the smallest head, the newest one if several have the same key.
+    fn smallest(&self) -> Option<usize> {
+        let mut smallest: Option<(usize, &[u8])> = None;
+        for (source, head) in self.heads.iter().enumerate() {
+            let Some(head) = head else {
+                continue;
+            };
+            if smallest.is_none_or(|(_, key)| self.compare(&head.key, key) == Ordering::Less) {
+                smallest = Some((source, &head.key));
+            }
+        }
+        smallest.map(|(source, _)| source)
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.comparator.compare(a, b)
+    }
+
+    fn after_start(&self, key: &[u8]) -> bool {
+        match &self.start {
+            Bound::Included(start) => self.compare(key, start) != Ordering::Less,
+            Bound::Excluded(start) => self.compare(key, start) == Ordering::Greater,
+            Bound::Unbounded => true,
+        }
+    }
+
+    /// Ends the iteration with `err`.
+    fn fail(&mut self, err: Error) -> Option<Result<Entry>> {
+        self.heads.iter_mut().for_each(|head| *head = None);
+        Some(Err(err))
+    }
+
+    fn before_end(&self, key: &[u8]) -> bool {
+        match &self.end {
+            Bound::Included(end) => self.compare(key, end) != Ordering::Greater,
+            Bound::Excluded(end) => self.compare(key, end) == Ordering::Less,
+            Bound::Unbounded => true,
+        }
+    }
+}
+
+impl Iterator for DatabaseIterator {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Result<Entry>> {
+        loop {
+            if let Some(err) = self.error.take() {
+                return self.fail(err);
+            }
+            let source = self.smallest()?;
+            let mut entry = self.heads[source].take()?;
+            if !self.before_end(&entry.key) {
+                self.heads.iter_mut().for_each(|head| *head = None);
+                return None;
+            }
+            self.advance(source);
+            // drop older versions of the same key, unless operands have to go on top of them
+            for older in source + 1..self.sources.len() {
+                if self.heads[older]
+                    .as_ref()
+                    .is_some_and(|head| self.compare(&head.key, &entry.key) == Ordering::Equal)
+                {
+                    let older_entry = self.heads[older].take()?;
+                    entry = stack(entry, older_entry);
+                    self.advance(older);
+                }
+            }
+            if let Some(err) = self.error.take() {
+                return self.fail(err);
+            }
+            if entry.is_expired(self.now) {
+                continue;
+            }
+            let loaded = blob::load(entry, |number| {
+                self.blob_files
+                    .get(&number)
+                    .cloned()
+                    .ok_or_else(|| Error::NotFound(format!("blob file {:06}", number)))
+            });
+            let entry = match loaded {
+                Ok(entry) => entry,
+                Err(err) => return self.fail(err),
+            };
+            let entry = resolve(entry, self.merge_operator.as_deref(), self.now);
+            if !entry.is_tombstone() && !entry.is_expired(self.now) {
+                return Some(Ok(entry));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            comparator::{BytewiseComparator, ReverseBytewiseComparator},
+            merge_operator::StringAppendOperator,
+        },
+        sstable::blob::BlobIndex,
+    };
+
+    fn create_entry(key: u8, value: u8, deleted: bool) -> Entry {
+        Entry {
+            key: vec![key],
+            value: (!deleted).then_some(vec![value]),
+            timestamp: value.into(),
+            deleted,
+            single_delete: false,
+            blob: None,
+            expires_at: None,
+            operands: Vec::new(),
+        }
+    }
+
+    fn create_source(entries: Vec<Entry>) -> Source {
+        Box::new(entries.into_iter().map(Ok))
+    }
+
+    #[test]
+    fn test_newest_source_wins() {
+        let mut expired = create_entry(4, 2, false);
+        expired.expires_at = Some(5);
+        let newest = create_source(vec![
+            create_entry(1, 2, false),
+            create_entry(3, 2, true),
+            expired,
+        ]);
+        let oldest = create_source(vec![
+            create_entry(1, 1, false),
+            create_entry(2, 1, false),
+            create_entry(3, 1, false),
+            create_entry(4, 1, false),
+        ]);
+        let entries: Vec<(u8, u8)> = DatabaseIterator::new(
+            vec![newest, oldest],
+            Vec::new(),
+            Bound::Unbounded,
+            Bound::Unbounded,
+            5,
+            None,
+            Arc::new(BytewiseComparator),
+        )
+        .map(|e| e.unwrap())
+        .map(|e| (e.key[0], e.value.unwrap()[0]))
+        .collect();
+        assert_eq!(entries, vec![(1, 2), (2, 1)]);
+    }
+
+    #[test]
+    fn test_entries_outside_the_range_are_skipped() {
+        let source = create_source((0..10).map(|i| create_entry(i, i, false)).collect());
+        let keys: Vec<u8> = DatabaseIterator::new(
+            vec![source],
+            Vec::new(),
+            Bound::Excluded(vec![2]),
+            Bound::Excluded(vec![6]),
+            0,
+            None,
+            Arc::new(BytewiseComparator),
+        )
+        .map(|e| e.unwrap().key[0])
+        .collect();
+        assert_eq!(keys, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn test_operands_are_folded_over_older_sources() {
+        let operand = |key: u8, operand: u8| Entry {
+            operands: vec![vec![operand]],
+            ..create_entry(key, 0, true)
+        };
+        let pending = |key, o| Entry {
+            deleted: false,
+            ..operand(key, o)
+        };
+        let newest = create_source(vec![pending(1, 3), pending(2, 3), operand(3, 3)]);
+        let oldest = create_source(vec![create_entry(1, 1, false), create_entry(3, 1, false)]);
+        let entries: Vec<(u8, Vec<u8>)> = DatabaseIterator::new(
+            vec![newest, oldest],
+            Vec::new(),
+            Bound::Unbounded,
+            Bound::Unbounded,
+            0,
+            Some(Arc::new(StringAppendOperator::new(b""))),
+            Arc::new(BytewiseComparator),
+        )
+        .map(|e| e.unwrap())
+        .map(|e| (e.key[0], e.value.unwrap()))
+        .collect();
+        // key 3 was deleted before its operand was written
+        assert_eq!(entries, vec![(1, vec![1, 3]), (2, vec![3]), (3, vec![3])]);
+    }
+
+    #[test]
+    fn test_range_tombstones_hide_older_versions() {
+        let newest = create_source(vec![create_entry(2, 3, false)]);
+        let oldest = create_source((1..5).map(|i| create_entry(i, 1, false)).collect());
+        let tombstone = RangeTombstone {
+            start: vec![2],
+            end: vec![4],
+            timestamp: 2,
+        };
+        let entries: Vec<(u8, u8)> = DatabaseIterator::new(
+            vec![newest, oldest],
+            vec![tombstone],
+            Bound::Unbounded,
+            Bound::Unbounded,
+            0,
+            None,
+            Arc::new(BytewiseComparator),
+        )
+        .map(|e| e.unwrap())
+        .map(|e| (e.key[0], e.value.unwrap()[0]))
+        .collect();
+        // key 2 was written again after the range delete
+        assert_eq!(entries, vec![(1, 1), (2, 3), (4, 1)]);
+    }
+
+    #[test]
+    fn test_sources_are_merged_in_comparator_order() {
+        let newest = create_source(vec![create_entry(5, 2, false), create_entry(2, 2, false)]);
+        let oldest = create_source(vec![
+            create_entry(6, 1, false),
+            create_entry(5, 1, false),
+            create_entry(1, 1, false),
+        ]);
+        let entries: Vec<(u8, u8)> = DatabaseIterator::new(
+            vec![newest, oldest],
+            Vec::new(),
+            Bound::Included(vec![5]),
+            Bound::Excluded(vec![1]),
+            0,
+            None,
+            Arc::new(ReverseBytewiseComparator),
+        )
+        .map(|e| e.unwrap())
+        .map(|e| (e.key[0], e.value.unwrap()[0]))
+        .collect();
+        assert_eq!(entries, vec![(5, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn test_unreadable_blob_value_is_an_error() {
+        let in_missing_file = Entry {
+            value: None,
+            blob: Some(BlobIndex {
+                file_number: 9,
+                offset: 0,
+                len: 10,
+            }),
+            ..create_entry(2, 1, false)
+        };
+        let source = create_source(vec![
+            create_entry(1, 1, false),
+            in_missing_file,
+            create_entry(3, 1, false),
+        ]);
+        let mut iterator = DatabaseIterator::new(
+            vec![source],
+            Vec::new(),
+            Bound::Unbounded,
+            Bound::Unbounded,
+            0,
+            None,
+            Arc::new(BytewiseComparator),
+        );
+        assert_eq!(iterator.next().unwrap().unwrap().key, vec![1]);
+        assert!(matches!(iterator.next(), Some(Err(Error::NotFound(_)))));
+        assert!(iterator.next().is_none());
+    }
+
+    // orders keys by their first byte only
+    struct FirstByteComparator;
+
+    impl Comparator for FirstByteComparator {
+        fn name(&self) -> &str {
+            "test.FirstByteComparator"
+        }
+
+        fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+            a.first().cmp(&b.first())
+        }
+    }
+
+    #[test]
+    fn test_keys_equal_under_the_comparator_are_one_key() {
+        let newest = create_source(vec![Entry {
+            key: vec![1, 9],
+            ..create_entry(1, 2, false)
+        }]);
+        let oldest = create_source(vec![create_entry(1, 1, false), create_entry(2, 1, false)]);
+        let entries: Vec<(Vec<u8>, u8)> = DatabaseIterator::new(
+            vec![newest, oldest],
+            Vec::new(),
+            Bound::Unbounded,
+            Bound::Unbounded,
+            0,
+            None,
+            Arc::new(FirstByteComparator),
+        )
+        .map(|e| e.unwrap())
+        .map(|e| (e.key, e.value.unwrap()[0]))
+        .collect();
+        assert_eq!(entries, vec![(vec![1, 9], 2), (vec![2], 1)]);
+    }
+}
@@ -0,0 +1,165 @@
+use super::entry::Entry;
+
+/// Combines a value with the operands written by `Database::merge`.
+///
+/// Operands are stored as they are written and only folded when a read or a compaction
+/// meets them, possibly in several steps, so `merge` must give the same result however the
+/// operands of a key are split between calls.
+pub trait MergeOperator: Send + Sync {
+    /// Applies `operands`, oldest first, to `existing`, which is `None` if the key has no
+    /// value or was deleted.
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[Vec<u8>]) -> Vec<u8>;
+}
+
+/// Adds little endian u64s, wrapping on overflow. Values that are not 8 bytes long count
+/// as 0.
+pub struct U64AddOperator;
+
+impl MergeOperator for U64AddOperator {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Vec<u8>]) -> Vec<u8> {
+        let to_u64 = |bytes: &[u8]| bytes.try_into().map_or(0, u64::from_le_bytes);
+        let sum = operands
+            .iter()
+            .fold(existing.map_or(0, to_u64), |sum, operand| {
+                sum.wrapping_add(to_u64(operand))
+            });
+        sum.to_le_bytes().to_vec()
+    }
+}
+
+/// Appends operands to the value, separated by a delimiter.
+pub struct StringAppendOperator {
+    delimiter: Vec<u8>,
+}
+
+impl StringAppendOperator {
+    pub fn new(delimiter: &[u8]) -> StringAppendOperator {
+        StringAppendOperator {
+            delimiter: delimiter.to_vec(),
+        }
+    }
+}
+
+impl MergeOperator for StringAppendOperator {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Vec<u8>]) -> Vec<u8> {
+        let mut parts = existing
+            .into_iter()
+            .chain(operands.iter().map(Vec::as_slice));
+        let mut value = parts.next().unwrap_or_default().to_vec();
+        for part in parts {
+            value.extend_from_slice(&self.delimiter);
+            value.extend_from_slice(part);
+        }
+        value
+    }
+}
+
+/// Keeps the largest of the value and the operands, comparing bytes lexicographically;
+/// big endian numbers compare by their value.
+pub struct MaxOperator;
+
+impl MergeOperator for MaxOperator {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Vec<u8>]) -> Vec<u8> {
+        existing
+            .into_iter()
+            .chain(operands.iter().map(Vec::as_slice))
+            .max()
+            .unwrap_or_default()
+            .to_vec()
+    }
+}
+
+/// Puts the operands of `newer` on top of `older`, the previous version of the same key.
+/// Entries that do not need older versions are returned as they are.
+pub(crate) fn stack(newer: Entry, older: Entry) -> Entry {
+    if !newer.is_pending() {
+        return newer;
+    }
+    let mut operands = older.operands;
+    operands.extend(newer.operands);
+    Entry {
+        key: newer.key,
+        value: older.value,
+        blob: older.blob,
+        timestamp: newer.timestamp,
+        deleted: older.deleted,
+        single_delete: false,
+        // operands share the fate of the value they were merged into
+        expires_at: older.expires_at,
+        operands,
+    }
+}
+
+/// Folds the operands of `entry` into its value. A pending entry is folded as if the key
+/// had no older versions. Without an operator, and for expired entries, the entry is left
+/// as it is. A value in a blob file has to be loaded first.
+pub(crate) fn resolve(entry: Entry, operator: Option<&dyn MergeOperator>, now: u128) -> Entry {
+    let Some(operator) = operator else {
+        return entry;
+    };
+    if entry.operands.is_empty() || entry.is_expired(now) {
+        return entry;
+    }
+    let existing = entry.value.as_deref().filter(|_| !entry.deleted);
+    let value = operator.merge(&entry.key, existing, &entry.operands);
+    Entry {
+        value: Some(value),
+        deleted: false,
+        operands: Vec::new(),
+        blob: None,
+        ..entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(operator: &dyn MergeOperator, existing: Option<&[u8]>, operands: &[&[u8]]) -> Vec<u8> {
+        let operands: Vec<Vec<u8>> = operands.iter().map(|o| o.to_vec()).collect();
+        operator.merge(b"k", existing, &operands)
+    }
+
+    #[test]
+    fn test_builtin_operators() {
+        let one = 1u64.to_le_bytes();
+        let two = 2u64.to_le_bytes();
+        assert_eq!(
+            merge(&U64AddOperator, None, &[&one, &two]),
+            3u64.to_le_bytes()
+        );
+        assert_eq!(merge(&U64AddOperator, Some(b"bad"), &[&one]), one);
+        assert_eq!(
+            merge(&StringAppendOperator::new(b","), Some(b"a"), &[b"b", b"c"]),
+            b"a,b,c"
+        );
+        assert_eq!(merge(&StringAppendOperator::new(b","), None, &[b"b"]), b"b");
+        assert_eq!(merge(&MaxOperator, Some(b"b"), &[b"c", b"a"]), b"c");
+    }
+
+    #[test]
+    fn test_stacked_operands_fold_in_write_order() {
+        let entry = |value: Option<&[u8]>, deleted, operands: &[&[u8]]| Entry {
+            key: b"k".to_vec(),
+            value: value.map(|v| v.to_vec()),
+            timestamp: 1,
+            deleted,
+            single_delete: false,
+            blob: None,
+            expires_at: None,
+            operands: operands.iter().map(|o| o.to_vec()).collect(),
+        };
+        let operator = StringAppendOperator::new(b",");
+        let stacked = stack(entry(None, false, &[b"c"]), entry(None, false, &[b"b"]));
+        assert!(stacked.is_pending());
+        let stacked = stack(stacked, entry(Some(b"a"), false, &[]));
+        let resolved = resolve(stacked, Some(&operator), 0);
+        assert_eq!(resolved.value, Some(b"a,b,c".to_vec()));
+
+        // a tombstone ends the stack
+        let stacked = stack(entry(None, false, &[b"b"]), entry(None, true, &[]));
+        assert!(!stacked.is_pending() && !stacked.is_tombstone());
+        let resolved = resolve(stacked, Some(&operator), 0);
+        assert_eq!(resolved.value, Some(b"b".to_vec()));
+    }
+}
@@ -1,2 +1,17 @@
+pub mod async_database;
+pub mod changes;
+pub(crate) mod checkpoint;
+pub mod clock;
+pub mod column_family;
+pub mod comparator;
 pub mod database;
 pub mod entry;
+pub mod iterator;
+pub mod merge_operator;
+pub mod options;
+pub mod range_tombstone;
+pub mod repair;
+pub mod restore;
+pub mod stats;
+pub mod verify;
+pub mod write_batch;
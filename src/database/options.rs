@@ -0,0 +1,86 @@
+use std::{path::PathBuf, sync::Arc};
+
+use crate::sstable::{cache::BlockCache, filter::DEFAULT_BITS_PER_KEY};
+
+use super::{
+    clock::{Clock, SystemClock},
+    comparator::{BytewiseComparator, Comparator},
+    merge_operator::MergeOperator,
+};
+
+const DEFAULT_BLOCK_CACHE_CAPACITY: usize = 8 << 20;
+const DEFAULT_MAX_OPEN_FILES: usize = 1000;
+const DEFAULT_BLOB_GARBAGE_RATIO: f64 = 0.5;
+
+#[derive(Clone)]
+pub struct Options {
+    /// Cache for sstable blocks, may be shared between databases.
+    pub block_cache: Arc<BlockCache>,
+    /// Number of sstable readers kept open at once.
+    pub max_open_files: usize,
+    /// Where WALs go once their writes are in tables, instead of being deleted, so they
+    /// can be replayed on top of a checkpoint by `Database::restore_to`.
+    pub wal_archive_dir: Option<PathBuf>,
+    /// Decides when entries written with a TTL expire.
+    pub clock: Arc<dyn Clock>,
+    /// Folds the operands written by `Database::merge`. Without one, reads return entries
+    /// with their operands unresolved and compaction keeps them as they are.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Order of the keys in every column family. A database can only be opened with the
+    /// comparator it was created with.
+    pub comparator: Arc<dyn Comparator>,
+    /// Values of at least this many bytes are written to blob files when they are flushed,
+    /// so compaction moves small pointers instead of them. `None` keeps all values in the
+    /// tables; values already in blob files stay readable either way.
+    pub min_blob_size: Option<usize>,
+    /// Compaction writes the values still in use of a blob file elsewhere once this share
+    /// of its bytes is garbage, so the file can be deleted.
+    pub blob_garbage_ratio: f64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            block_cache: Arc::new(BlockCache::new(DEFAULT_BLOCK_CACHE_CAPACITY)),
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            wal_archive_dir: None,
+            clock: Arc::new(SystemClock),
+            merge_operator: None,
+            comparator: Arc::new(BytewiseComparator),
+            min_blob_size: None,
+            blob_garbage_ratio: DEFAULT_BLOB_GARBAGE_RATIO,
+        }
+    }
+}
+
+/// How the data blocks of a table are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Snappy,
+}
+
+/// Settings of one column family, recorded in the MANIFEST when the family is created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnFamilyOptions {
+    /// Filter bits per key of each table; fewer bits mean smaller filters that rule out
+    /// fewer tables on `get`.
+    pub bloom_bits_per_key: usize,
+    /// Compression of the data blocks of tables written from now on; tables keep the
+    /// format they were written in.
+    pub compression: Compression,
+    /// Once a flush leaves the family with this many tables, they are compacted into one.
+    /// `None` only compacts on `Database::compact`.
+    pub compaction_trigger: Option<usize>,
+}
+
+impl Default for ColumnFamilyOptions {
+    fn default() -> Self {
+        ColumnFamilyOptions {
+            bloom_bits_per_key: DEFAULT_BITS_PER_KEY,
+            compression: Compression::None,
+            compaction_trigger: None,
+        }
+    }
+}
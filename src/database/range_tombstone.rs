@@ -0,0 +1,119 @@
+use std::{cmp::Ordering, io::Read};
+
+use super::{comparator::Comparator, entry::Entry};
+
+/// Deletes every version of the keys from `start` up to but not including `end` that is
+/// not newer than `timestamp`, see `Database::delete_range`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeTombstone {
+    pub start: Vec<u8>,
+    pub end: Vec<u8>,
+    pub timestamp: u128,
+}
+
+// +-----------------+-------+---------------+-----+-----------------+
+// | Start Size (8B) | Start | End Size (8B) | End | Timestamp (16B) |
+// +-----------------+-------+---------------+-----+-----------------+
+
+impl RangeTombstone {
+    pub fn contains(&self, key: &[u8], comparator: &dyn Comparator) -> bool {
+        comparator.compare(key, &self.start) != Ordering::Less
+            && comparator.compare(key, &self.end) == Ordering::Less
+    }
+
+    /// Whether the tombstone deletes `entry`, a single version of its key.
+    pub fn covers(&self, entry: &Entry, comparator: &dyn Comparator) -> bool {
+        entry.timestamp <= self.timestamp && self.contains(&entry.key, comparator)
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.start.len().to_le_bytes());
+        buf.extend_from_slice(&self.start);
+        buf.extend_from_slice(&self.end.len().to_le_bytes());
+        buf.extend_from_slice(&self.end);
+        buf.extend_from_slice(&self.timestamp.to_le_bytes());
+    }
+
+    /// Reads a tombstone written by `encode`; `None` at the end of `reader` or if the
+    /// tombstone is cut short.
+    pub fn decode(reader: &mut impl Read) -> Option<RangeTombstone> {
+        let start = read_key(reader)?;
+        let end = read_key(reader)?;
+        let mut timestamp = [0; 16];
+        reader.read_exact(&mut timestamp).ok()?;
+        Some(RangeTombstone {
+            start,
+            end,
+            timestamp: u128::from_le_bytes(timestamp),
+        })
+    }
+}
+
+fn read_key(reader: &mut impl Read) -> Option<Vec<u8>> {
+    let mut len = [0; 8];
+    reader.read_exact(&mut len).ok()?;
+    let len = u64::from_le_bytes(len);
+    // a corrupt length must not decide how much is allocated
+    let mut key = Vec::new();
+    reader.take(len).read_to_end(&mut key).ok()?;
+    (key.len() as u64 == len).then_some(key)
+}
+
+/// Turns `entry` into a tombstone, keeping its timestamp, if one of `tombstones` covers it.
+pub(crate) fn cover(
+    entry: Entry,
+    tombstones: &[RangeTombstone],
+    comparator: &dyn Comparator,
+) -> Entry {
+    if !tombstones.iter().any(|t| t.covers(&entry, comparator)) {
+        return entry;
+    }
+    Entry {
+        value: None,
+        deleted: true,
+        single_delete: false,
+        blob: None,
+        expires_at: None,
+        operands: Vec::new(),
+        ..entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::comparator::BytewiseComparator;
+
+    #[test]
+    fn test_tombstone_covers_older_versions_in_range() {
+        let tombstone = RangeTombstone {
+            start: vec![2],
+            end: vec![5],
+            timestamp: 10,
+        };
+        let entry = |key: u8, timestamp| Entry {
+            key: vec![key],
+            value: Some(vec![key]),
+            timestamp,
+            deleted: false,
+            single_delete: false,
+            blob: None,
+            expires_at: None,
+            operands: Vec::new(),
+        };
+        let comparator = BytewiseComparator;
+        assert!(tombstone.covers(&entry(2, 10), &comparator));
+        assert!(tombstone.covers(&entry(4, 1), &comparator));
+        assert!(!tombstone.covers(&entry(5, 1), &comparator));
+        assert!(!tombstone.covers(&entry(1, 1), &comparator));
+        assert!(!tombstone.covers(&entry(3, 11), &comparator));
+        let covered = cover(entry(3, 1), std::slice::from_ref(&tombstone), &comparator);
+        assert!(covered.is_tombstone() && covered.value.is_none());
+        assert_eq!(covered.timestamp, 1);
+
+        let mut buf = Vec::new();
+        tombstone.encode(&mut buf);
+        assert_eq!(RangeTombstone::decode(&mut buf.as_slice()), Some(tombstone));
+        assert_eq!(RangeTombstone::decode(&mut &buf[..buf.len() - 1]), None);
+    }
+}
@@ -0,0 +1,422 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fs::{self, read_dir, remove_file},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    sstable::{
+        blob::{blob_path, scan_blob_file, BLOB_EXT},
+        data::DataIterator,
+        sstable::{data_path, file_number, remove_table, table_path, SSTable},
+    },
+    version::{
+        edit::VersionEdit,
+        version::{BlobFileMetaData, FileMetaData},
+        version_set::{manifest_name, read_manifest, VersionSet, MANIFEST_PREFIX},
+    },
+    wal::{repair, wal::log_files},
+    Error, Result,
+};
+
+use super::{
+    column_family::DEFAULT_COLUMN_FAMILY,
+    comparator::Comparator,
+    database::{check_comparator, file_meta_data, lock_dir, Database},
+    options::{ColumnFamilyOptions, Options},
+};
+
+/// What `Database::repair` kept of one table or WAL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepairedFile {
+    pub path: PathBuf,
+    pub records: usize,
+    /// Records that could not be kept, e.g. keys out of order.
+    pub dropped_records: usize,
+    /// Unreadable bytes at the end of the file.
+    pub dropped_bytes: u64,
+    /// Numbers of the tables holding the salvaged records, one per column family; empty if
+    /// nothing was left.
+    pub tables: Vec<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// Whether the old MANIFEST could be read; if not every table found is kept.
+    pub manifest_ok: bool,
+    pub tables: Vec<RepairedFile>,
+    pub logs: Vec<RepairedFile>,
+    /// Files left over from flushes and compactions that were removed.
+    pub obsolete: Vec<PathBuf>,
+}
+
+impl Database {
+    /// Rebuilds the database in `dir` from the files that survived.
+    ///
+    /// The readable records of every table's data file are rewritten into a new table,
+    /// which regenerates its index, filter and checksums. Every WAL is replayed into a table
+    /// of its own and a new MANIFEST listing all of them replaces the old one. If the old
+    /// MANIFEST is readable, tables it does not list and WALs it marks as flushed are
+    /// obsolete and removed, and tables keep their level and column family; otherwise all
+    /// tables go to level 0 of the default column family, ordered by file number, and WAL
+    /// records of other column families are dropped.
+    ///
+    /// Records pointing into a missing blob file are dropped. Blob files are kept as long
+    /// as a table points into them, with the values no table points to counted as garbage.
+    pub fn repair(dir: &Path) -> Result<RepairReport> {
+        Self::repair_with_options(dir, Options::default())
+    }
+
+    /// Like `repair`, with keys ordered by `options.comparator`, which has to be the
+    /// comparator recorded in the old MANIFEST if that is readable.
+    pub fn repair_with_options(dir: &Path, options: Options) -> Result<RepairReport> {
+        let _lock = lock_dir(dir)?;
+        let manifest = read_manifest(dir).ok().flatten();
+        let recorded = manifest.as_ref().and_then(|m| m.current.comparator());
+        check_comparator(dir, recorded, manifest.is_none(), &options)?;
+        let comparator = options.comparator;
+        let mut report = RepairReport {
+            manifest_ok: manifest.is_some(),
+            ..Default::default()
+        };
+        let live: Option<HashMap<u64, (usize, FileMetaData)>> = manifest.as_ref().map(|m| {
+            m.current
+                .all_files()
+                .map(|(level, file)| (file.number, (level, file.clone())))
+                .collect()
+        });
+        let log_number = manifest.as_ref().map_or(0, |m| m.log_number);
+        let mut edit = VersionEdit {
+            comparator: Some(comparator.name().to_string()),
+            ..Default::default()
+        };
+        if let Some(manifest) = &manifest {
+            for (id, family) in manifest.current.column_families() {
+                edit.add_column_family(*id, family.clone());
+            }
+            edit.next_column_family = Some(manifest.current.next_column_family());
+        }
+        let families = edit.new_column_families.clone();
+        let family_options = |column_family: u32| {
+            families
+                .iter()
+                .find(|(id, _)| *id == column_family)
+                .map(|(_, family)| family.options.clone())
+        };
+
+        let (mut tables, mut blob_files) = (Vec::new(), Vec::new());
+        let mut next_number = manifest.as_ref().map_or(1, |m| m.next_file_number);
+        for file in read_dir(dir)? {
+            let path = file?.path();
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            let number = match name.strip_prefix(MANIFEST_PREFIX) {
+                Some(number) => number.parse().ok(),
+                None => file_number(&path),
+            };
+            let Some(number) = number else {
+                continue;
+            };
+            next_number = next_number.max(number + 1);
+            if path == data_path(&table_path(dir, number)) {
+                tables.push(number);
+            } else if path.extension().is_some_and(|ext| ext == BLOB_EXT) {
+                blob_files.push(number);
+            }
+        }
+        tables.sort();
+
+        let mut replaced = Vec::new();
+        // column family, count and length of the values per blob file the tables point to
+        let mut blob_refs: BTreeMap<u64, (u32, u64, u64)> = BTreeMap::new();
+        for number in tables {
+            let (level, old) = match &live {
+                Some(live) => match live.get(&number) {
+                    Some((level, file)) => (*level, Some(file)),
+                    None => {
+                        report.obsolete.push(table_path(dir, number));
+                        replaced.push(number);
+                        continue;
+                    }
+                },
+                None => (0, None),
+            };
+            let column_family = old.map_or(DEFAULT_COLUMN_FAMILY, |f| f.column_family);
+            let options = family_options(column_family).unwrap_or_default();
+            let (sstable, repaired) =
+                rebuild_table(dir, number, next_number, &options, &comparator)?;
+            next_number += 1;
+            let (smallest, largest) = old.map_or((0, 0), |f| (f.smallest_seqno, f.largest_seqno));
+            if let Some(sstable) = sstable {
+                for (number, (count, bytes)) in sstable.blob_refs() {
+                    let refs = blob_refs.entry(*number).or_insert((column_family, 0, 0));
+                    refs.1 += count;
+                    refs.2 += bytes;
+                }
+                if let Some(file) = file_meta_data(&sstable, column_family, smallest, largest) {
+                    edit.add_file(level, file);
+                }
+            }
+            report.tables.push(repaired);
+            replaced.push(number);
+        }
+
+        let mut last_sequence = manifest.as_ref().map_or(0, |m| m.last_sequence);
+        let mut wals = Vec::new();
+        for path in log_files(dir)? {
+            if file_number(&path).is_some_and(|n| n < log_number) {
+                report.obsolete.push(path.clone());
+                wals.push(path);
+                continue;
+            }
+            let mut seqnos: Option<(u64, u64)> = None;
+            let mut records: BTreeMap<u32, usize> = BTreeMap::new();
+            let wal = repair::inspect(&path, |entry| {
+                let (first, _) = seqnos.unwrap_or((entry.seqno, entry.seqno));
+                seqnos = Some((first, entry.seqno));
+                *records.entry(entry.column_family).or_default() += 1;
+            })?;
+            let mut repaired = RepairedFile {
+                path: path.clone(),
+                records: wal.records,
+                dropped_records: 0,
+                dropped_bytes: wal.file_len - wal.valid_len,
+                tables: Vec::new(),
+            };
+            for (column_family, count) in records {
+                if column_family != DEFAULT_COLUMN_FAMILY && family_options(column_family).is_none()
+                {
+                    // written to a family that was dropped or is unknown
+                    repaired.records -= count;
+                    repaired.dropped_records += count;
+                    continue;
+                }
+                let number = next_number;
+                next_number += 1;
+                let salvaged =
+                    repair::salvage(&path, dir, number, column_family, comparator.clone())?;
+                let Some(sstable) = salvaged else {
+                    continue;
+                };
+                if let Some((first, last)) = seqnos {
+                    if let Some(file) = file_meta_data(&sstable, column_family, first, last) {
+                        edit.add_file(0, file);
+                    }
+                }
+                repaired.tables.push(sstable.id);
+            }
+            if let Some((_, last)) = seqnos {
+                last_sequence = last_sequence.max(last);
+            }
+            report.logs.push(repaired);
+            wals.push(path);
+        }
+
+        let mut obsolete_blob_files = Vec::new();
+        for number in blob_files {
+            let path = blob_path(dir, number);
+            let Some((column_family, count, bytes)) = blob_refs.get(&number).copied() else {
+                report.obsolete.push(path.clone());
+                obsolete_blob_files.push(path);
+                continue;
+            };
+            let (total_count, total_bytes, _) = scan_blob_file(&path)?;
+            edit.add_blob_file(BlobFileMetaData {
+                number,
+                column_family,
+                count: total_count,
+                bytes: total_bytes,
+                garbage_count: total_count.saturating_sub(count),
+                garbage_bytes: total_bytes.saturating_sub(bytes),
+            });
+        }
+
+        // nothing is removed before the new MANIFEST is in place, so a crash leaves the
+        // old files to repair again
+        edit.log_number = Some(next_number);
+        let versions = VersionSet::create(dir, next_number, last_sequence, edit)?;
+        for number in replaced {
+            remove_table(dir, number)?;
+        }
+        for file in wals.into_iter().chain(obsolete_blob_files) {
+            remove_file(file)?;
+        }
+        let manifest = manifest_name(versions.manifest_number());
+        for file in read_dir(dir)? {
+            let path = file?.path();
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            if name.starts_with(MANIFEST_PREFIX) && name != manifest {
+                remove_file(path)?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Writes the records of table `number` that can still be read in key order into a new
+/// table numbered `new_number`.
+fn rebuild_table(
+    dir: &Path,
+    number: u64,
+    new_number: u64,
+    options: &ColumnFamilyOptions,
+    comparator: &Arc<dyn Comparator>,
+) -> Result<(Option<SSTable>, RepairedFile)> {
+    let path = data_path(&table_path(dir, number));
+    let len = fs::metadata(&path)?.len();
+    let mut repaired = RepairedFile {
+        path: table_path(dir, number),
+        records: 0,
+        dropped_records: 0,
+        dropped_bytes: 0,
+        tables: Vec::new(),
+    };
+    let mut sstable = SSTable::new(dir, new_number)?;
+    sstable.set_bits_per_key(options.bloom_bits_per_key);
+    sstable.set_compression(options.compression);
+    sstable.set_comparator(comparator.clone());
+    let mut previous: Option<Vec<u8>> = None;
+    let mut records = DataIterator::new(path, 0)?;
+    for entry in records.by_ref() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // the damaged record and everything after it are dropped
+            Err(Error::Corruption(_)) => break,
+            Err(err) => return Err(err),
+        };
+        let blob_missing = entry
+            .blob
+            .is_some_and(|blob| !blob_path(dir, blob.file_number).exists());
+        if blob_missing
+            || previous
+                .as_ref()
+                .is_some_and(|p| comparator.compare(p, &entry.key) != Ordering::Less)
+        {
+            repaired.dropped_records += 1;
+            continue;
+        }
+        sstable.write(&entry)?;
+        repaired.records += 1;
+        previous = Some(entry.key);
+    }
+    repaired.dropped_bytes = len - records.end().min(len);
+    sstable.flush()?;
+    if repaired.records == 0 {
+        remove_table(dir, new_number)?;
+        return Ok((None, repaired));
+    }
+    repaired.tables.push(new_number);
+    Ok((Some(sstable), repaired))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write};
+
+    use crate::sstable::sstable::index_path;
+
+    use super::*;
+
+    fn create_database(name: &str) -> PathBuf {
+        let dir = PathBuf::from("data").join(name);
+        fs::remove_dir_all(&dir).ok();
+        let mut db = Database::open(&dir).unwrap();
+        for i in 0..100u32 {
+            db.set(&i.to_be_bytes(), b"flushed", 1).unwrap();
+        }
+        db.flush().unwrap();
+        db.set(&0u32.to_be_bytes(), b"logged", 2).unwrap();
+        db.delete(&1u32.to_be_bytes(), 2).unwrap();
+        dir
+    }
+
+    fn table_number(dir: &Path) -> u64 {
+        let versions = read_manifest(dir).unwrap().unwrap();
+        let (_, file) = versions.current.all_files().next().unwrap();
+        file.number
+    }
+
+    fn assert_recovered(dir: &Path, last: u32) {
+        let db = Database::open(dir).unwrap();
+        let get = |i: u32| db.get(&i.to_be_bytes()).unwrap().unwrap();
+        assert_eq!(get(0).value, Some(b"logged".to_vec()));
+        assert!(get(1).deleted);
+        assert_eq!(get(last).value, Some(b"flushed".to_vec()));
+        assert!(db.get(&(last + 1).to_be_bytes()).unwrap().is_none());
+        assert!(db.stats().last_sequence >= 102);
+    }
+
+    #[test]
+    fn test_repair_keeps_tables_and_logs() {
+        let dir = create_database("repair_intact");
+        let table = table_number(&dir);
+        let report = Database::repair(&dir).unwrap();
+        assert!(report.manifest_ok);
+        assert_eq!(report.tables.len(), 1);
+        assert_eq!(report.tables[0].records, 100);
+        assert_eq!(report.logs[0].records, 2);
+        assert!(!table_path(&dir, table).exists());
+        assert_recovered(&dir, 99);
+    }
+
+    #[test]
+    fn test_repair_rebuilds_damaged_directory() {
+        let dir = create_database("repair_damaged");
+        let table = table_path(&dir, table_number(&dir));
+        fs::remove_file(index_path(&table)).unwrap();
+        fs::remove_file(dir.join("CURRENT")).unwrap();
+        // cut the last record of the table in half
+        let data = data_path(&table);
+        let len = fs::metadata(&data).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&data)
+            .unwrap()
+            .set_len(len - 10)
+            .unwrap();
+        OpenOptions::new()
+            .append(true)
+            .open(log_files(&dir).unwrap().pop().unwrap())
+            .unwrap()
+            .write_all(&[7; 5])
+            .unwrap();
+
+        let report = Database::repair(&dir).unwrap();
+        assert!(!report.manifest_ok);
+        assert_eq!(report.tables[0].records, 99);
+        // records of a 4 byte key and 7 byte value take 44 bytes
+        assert_eq!(report.tables[0].dropped_bytes, 44 - 10);
+        assert_eq!(report.logs[0].dropped_bytes, 5);
+        assert_recovered(&dir, 98);
+    }
+
+    #[test]
+    fn test_repair_keeps_blob_files() {
+        let dir = PathBuf::from("data").join("repair_blobs");
+        fs::remove_dir_all(&dir).ok();
+        let options = Options {
+            min_blob_size: Some(10),
+            ..Default::default()
+        };
+        let mut db = Database::open_with_options(&dir, options).unwrap();
+        db.set(b"a", &[1; 100], 1).unwrap();
+        db.flush().unwrap();
+        db.set(b"a", &[2; 100], 2).unwrap();
+        db.flush().unwrap();
+        drop(db);
+        fs::remove_file(dir.join("CURRENT")).unwrap();
+
+        let report = Database::repair(&dir).unwrap();
+        assert!(!report.manifest_ok);
+        let db = Database::open(&dir).unwrap();
+        assert_eq!(db.get(b"a").unwrap().unwrap().value, Some(vec![2; 100]));
+        assert_eq!(db.stats().blob_files, 2);
+    }
+}
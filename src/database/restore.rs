@@ -0,0 +1,240 @@
+use std::path::Path;
+
+use crate::{
+    version::version_set::read_manifest,
+    wal::{
+        iterator::{WALEntry, WALIterator},
+        wal::log_files,
+    },
+    Error, Result,
+};
+
+use super::{
+    checkpoint,
+    database::Database,
+    options::Options,
+    write_batch::{BatchOp, WriteBatch},
+};
+
+/// How far `Database::restore_to` replays archived WALs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecoveryTarget {
+    /// Every write up to and including this seqno.
+    Sequence(u64),
+    /// Every write with a timestamp up to and including this one.
+    Timestamp(u128),
+    /// Everything in the archive.
+    Latest,
+}
+
+impl RecoveryTarget {
+    fn includes(&self, entry: &WALEntry) -> bool {
+        match *self {
+            RecoveryTarget::Sequence(seqno) => entry.seqno <= seqno,
+            RecoveryTarget::Timestamp(timestamp) => entry.timestamp <= timestamp,
+            RecoveryTarget::Latest => true,
+        }
+    }
+}
+
+// a batch read from the archive that is not applied yet
+struct PendingBatch {
+    batch_seqno: u64,
+    first_seqno: u64,
+    batch: WriteBatch,
+    included: bool,
+}
+
+impl Database {
+    /// Recreates in `target` the database as it was at `until`: the tables of the checkpoint
+    /// in `checkpoint` are copied and the writes after it are replayed from the WALs in
+    /// `archive`, which must have been the database's `wal_archive_dir`.
+    ///
+    /// Batches are replayed whole; replay ends before the first batch with a write past
+    /// `until`. The restored database is returned open with `options`. Column families are
+    /// those of the checkpoint: replaying a write to a family created after it fails with
+    /// `Error::NotFound`.
+    pub fn restore_to(
+        checkpoint: &Path,
+        archive: &Path,
+        target: &Path,
+        until: RecoveryTarget,
+        options: Options,
+    ) -> Result<Database> {
+        let state = read_manifest(checkpoint)?
+            .ok_or_else(|| Error::NotFound(format!("checkpoint {}", checkpoint.display())))?;
+        checkpoint::write_database(checkpoint, &state.snapshot(), target, false)?;
+        let logs = log_files(archive)?;
+        let mut db = Database::open_with_options(target, options)?;
+        for path in logs {
+            let mut pending: Option<PendingBatch> = None;
+            // reopening a database logs replayed writes again, so the archive can hold
+            // them twice
+            // the last archived WAL may end in a partial batch
+            for entry in WALIterator::new(path)?.tolerate_partial_batch() {
+                let mut entry = entry?;
+                if entry.seqno <= db.last_sequence() {
+                    continue;
+                }
+                let next_batch = pending
+                    .as_ref()
+                    .is_some_and(|p| p.batch_seqno != entry.batch_seqno);
+                if next_batch && !apply(&mut db, pending.take())? {
+                    return Ok(db);
+                }
+                let batch = pending.get_or_insert_with(|| PendingBatch {
+                    batch_seqno: entry.batch_seqno,
+                    first_seqno: entry.seqno,
+                    batch: WriteBatch::new(),
+                    included: true,
+                });
+                batch.included &= until.includes(&entry);
+                batch.batch.push(match entry.value {
+                    _ if entry.range_end.is_some() => BatchOp::DeleteRange {
+                        column_family: entry.column_family,
+                        start: entry.key,
+                        end: entry.range_end.unwrap_or_default(),
+                        timestamp: entry.timestamp,
+                    },
+                    _ if !entry.operands.is_empty() => BatchOp::Merge {
+                        column_family: entry.column_family,
+                        key: entry.key,
+                        operand: entry.operands.remove(0),
+                        timestamp: entry.timestamp,
+                    },
+                    Some(value) if !entry.deleted => BatchOp::Put {
+                        column_family: entry.column_family,
+                        key: entry.key,
+                        value,
+                        timestamp: entry.timestamp,
+                        expires_at: entry.expires_at,
+                    },
+                    _ if entry.single_delete => BatchOp::SingleDelete {
+                        column_family: entry.column_family,
+                        key: entry.key,
+                        timestamp: entry.timestamp,
+                    },
+                    _ => BatchOp::Delete {
+                        column_family: entry.column_family,
+                        key: entry.key,
+                        timestamp: entry.timestamp,
+                    },
+                });
+            }
+            if !apply(&mut db, pending)? {
+                return Ok(db);
+            }
+        }
+        db.sync()?;
+        Ok(db)
+    }
+}
+
+/// Writes `pending` to `db` unless it is past the recovery target. Returns whether replay
+/// goes on.
+fn apply(db: &mut Database, pending: Option<PendingBatch>) -> Result<bool> {
+    let Some(pending) = pending else {
+        return Ok(true);
+    };
+    if !pending.included {
+        db.sync()?;
+        return Ok(false);
+    }
+    let last_sequence = db.last_sequence();
+    if pending.first_seqno != last_sequence + 1 {
+        return Err(Error::Corruption(format!(
+            "archived WALs are missing seqnos {} to {}",
+            last_sequence + 1,
+            pending.first_seqno - 1
+        )));
+    }
+    db.write(&pending.batch)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    fn options(dir: &Path) -> Options {
+        Options {
+            wal_archive_dir: Some(dir.join("archive")),
+            ..Default::default()
+        }
+    }
+
+    // seqno 1 is in the checkpoint, 2 and 3 form a batch, 4 is flushed and 5 is only
+    // archived when the database is reopened
+    fn create_history(name: &str) -> PathBuf {
+        let dir = PathBuf::from("data").join(name);
+        fs::remove_dir_all(&dir).ok();
+        let mut db = Database::open_with_options(&dir.join("db"), options(&dir)).unwrap();
+        db.set(b"a", b"1", 1).unwrap();
+        db.checkpoint(&dir.join("checkpoint")).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"b", b"2", 2);
+        batch.delete(b"a", 2);
+        db.write(&batch).unwrap();
+        db.set(b"c", b"3", 3).unwrap();
+        db.flush().unwrap();
+        db.set(b"d", b"4", 4).unwrap();
+        drop(db);
+        drop(Database::open_with_options(&dir.join("db"), options(&dir)).unwrap());
+        dir
+    }
+
+    fn restore(dir: &Path, name: &str, until: RecoveryTarget) -> Result<Database> {
+        let target = dir.join(name);
+        fs::remove_dir_all(&target).ok();
+        Database::restore_to(
+            &dir.join("checkpoint"),
+            &dir.join("archive"),
+            &target,
+            until,
+            Options::default(),
+        )
+    }
+
+    fn keys(db: &Database) -> Vec<Vec<u8>> {
+        db.scan(..)
+            .unwrap()
+            .map(|entry| entry.unwrap().key)
+            .collect()
+    }
+
+    #[test]
+    fn test_restore_to_sequence_and_timestamp() {
+        let dir = create_history("restore_targets");
+        let db = restore(&dir, "seqno1", RecoveryTarget::Sequence(1)).unwrap();
+        assert_eq!(keys(&db), vec![b"a".to_vec()]);
+        // a batch is never split
+        let db = restore(&dir, "seqno2", RecoveryTarget::Sequence(2)).unwrap();
+        assert_eq!(db.last_sequence(), 1);
+        let db = restore(&dir, "seqno3", RecoveryTarget::Sequence(3)).unwrap();
+        assert_eq!(keys(&db), vec![b"b".to_vec()]);
+        let db = restore(&dir, "time3", RecoveryTarget::Timestamp(3)).unwrap();
+        assert_eq!(keys(&db), vec![b"b".to_vec(), b"c".to_vec()]);
+        drop(db);
+        let db = restore(&dir, "latest", RecoveryTarget::Latest).unwrap();
+        assert_eq!(keys(&db), vec![b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]);
+        assert_eq!(db.last_sequence(), 5);
+        drop(db);
+        // the restored writes survive a reopen
+        let db = Database::open(&dir.join("latest")).unwrap();
+        assert_eq!(db.get(b"d").unwrap().unwrap().value, Some(b"4".to_vec()));
+    }
+
+    #[test]
+    fn test_restore_detects_missing_wal() {
+        let dir = create_history("restore_missing");
+        // the first archived WAL only holds the write in the checkpoint
+        let second = log_files(&dir.join("archive")).unwrap().remove(1);
+        fs::remove_file(second).unwrap();
+        assert!(matches!(
+            restore(&dir, "target", RecoveryTarget::Latest),
+            Err(Error::Corruption(_))
+        ));
+    }
+}
@@ -0,0 +1,41 @@
+use std::fmt;
+
+/// Size of the tables on one level.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LevelStats {
+    pub tables: usize,
+    pub bytes: u64,
+}
+
+/// Figures about a database at one point in time, as returned by `Database::stats`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub memtable_entries: usize,
+    pub memtable_bytes: usize,
+    pub last_sequence: u64,
+    pub levels: Vec<LevelStats>,
+    pub blob_files: usize,
+    pub blob_bytes: u64,
+    /// Bytes of values in blob files that compaction found no table points to anymore.
+    pub blob_garbage_bytes: u64,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "memtable_entries: {}", self.memtable_entries)?;
+        writeln!(f, "memtable_bytes: {}", self.memtable_bytes)?;
+        write!(f, "last_sequence: {}", self.last_sequence)?;
+        for (level, stats) in self.levels.iter().enumerate() {
+            write!(
+                f,
+                "\nlevel_{}: tables={} bytes={}",
+                level, stats.tables, stats.bytes
+            )?;
+        }
+        write!(
+            f,
+            "\nblob_files: {} bytes={} garbage_bytes={}",
+            self.blob_files, self.blob_bytes, self.blob_garbage_bytes
+        )
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    database::comparator::Comparator,
+    sstable::{
+        blob::{blob_path, scan_blob_file},
+        dump::TableReport,
+        sstable::{table_path, SSTable},
+    },
+    version::version::{Version, NUM_LEVELS},
+    wal::{
+        repair::{self, WALReport},
+        wal::log_files,
+    },
+    Result,
+};
+
+/// What `Database::verify` found.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Every live table with its level.
+    pub tables: Vec<(usize, TableReport)>,
+    pub logs: Vec<(PathBuf, WALReport)>,
+    /// Problems spanning files, e.g. overlapping tables or a MANIFEST out of date.
+    pub problems: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+            && self.tables.iter().all(|(_, table)| table.is_ok())
+            && self
+                .logs
+                .iter()
+                .all(|(_, log)| log.partial_batch().is_none())
+    }
+}
+
+pub(crate) fn verify(
+    dir: &Path,
+    version: &Version,
+    comparator: &dyn Comparator,
+) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    for (level, file) in version.all_files() {
+        let path = table_path(dir, file.number);
+        let table = match SSTable::inspect(&path, comparator, |_, _| ()) {
+            Ok(table) => table,
+            Err(err) => {
+                report
+                    .problems
+                    .push(format!("table {:06}: {}", file.number, err));
+                continue;
+            }
+        };
+        if table.smallest.as_ref() != Some(&file.smallest)
+            || table.largest.as_ref() != Some(&file.largest)
+        {
+            report.problems.push(format!(
+                "table {:06} holds other keys than the MANIFEST records",
+                file.number
+            ));
+        }
+        report.tables.push((level, table));
+    }
+    // level 0 tables may overlap, tables on higher levels must not within each column
+    // family
+    let families = std::iter::once(0).chain(version.column_families().keys().copied());
+    for (column_family, level) in families.flat_map(|cf| (1..NUM_LEVELS).map(move |l| (cf, l))) {
+        let mut files: Vec<_> = version
+            .files(level)
+            .iter()
+            .filter(|f| f.column_family == column_family)
+            .collect();
+        files.sort_by(|a, b| comparator.compare(&a.smallest, &b.smallest));
+        for pair in files.windows(2) {
+            if comparator.compare(&pair[0].largest, &pair[1].smallest) != Ordering::Less {
+                report.problems.push(format!(
+                    "tables {:06} and {:06} on level {} overlap",
+                    pair[0].number, pair[1].number, level
+                ));
+            }
+        }
+    }
+    for file in version.blob_files().values() {
+        let problem = match scan_blob_file(&blob_path(dir, file.number)) {
+            Err(err) => Some(err.to_string()),
+            Ok((_, _, true)) => Some("a value is unreadable".to_string()),
+            Ok((count, bytes, false)) if (count, bytes) != (file.count, file.bytes) => {
+                Some(format!(
+                    "holds {} values, the MANIFEST records {}",
+                    count, file.count
+                ))
+            }
+            Ok(_) => None,
+        };
+        if let Some(problem) = problem {
+            report
+                .problems
+                .push(format!("blob file {:06}: {}", file.number, problem));
+        }
+    }
+    for path in log_files(dir)? {
+        let log = repair::inspect(&path, |_| ())?;
+        report.logs.push((path, log));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use crate::{
+        database::{comparator::BytewiseComparator, database::Database},
+        sstable::sstable::data_path,
+        version::{edit::VersionEdit, version::FileMetaData},
+    };
+
+    use super::*;
+
+    fn create_database(name: &str) -> (PathBuf, Database) {
+        let dir = PathBuf::from("data").join(name);
+        fs::remove_dir_all(&dir).ok();
+        let mut db = Database::open(&dir).unwrap();
+        for round in 0..2 {
+            for i in 0..100u32 {
+                db.set(&i.to_be_bytes(), &[round; 100], round as u128 + 1)
+                    .unwrap();
+            }
+            db.flush().unwrap();
+        }
+        db.compact().unwrap();
+        db.set(b"a", b"1", 1).unwrap();
+        (dir, db)
+    }
+
+    #[test]
+    fn test_verify_healthy_database() {
+        let (_, mut db) = create_database("verify_healthy");
+        let report = db.verify().unwrap();
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.tables.len(), 1);
+        assert_eq!(report.tables[0].0, 1);
+        assert_eq!(report.logs[0].1.records, 1);
+    }
+
+    #[test]
+    fn test_verify_finds_damage() {
+        let (_, mut db) = create_database("verify_damaged");
+        let report = db.verify().unwrap();
+        let data = data_path(&report.tables[0].1.path);
+        let mut bytes = fs::read(&data).unwrap();
+        bytes[100] ^= 0xff;
+        fs::write(&data, bytes).unwrap();
+        let (log, _) = &report.logs[0];
+        fs::File::options()
+            .append(true)
+            .open(log)
+            .unwrap()
+            .write_all(&[1; 3])
+            .unwrap();
+
+        let report = db.verify().unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.tables[0].1.blocks[0].checksum_ok, Some(false));
+        assert!(report.logs[0].1.partial_batch().is_some());
+    }
+
+    #[test]
+    fn test_verify_finds_overlapping_levels() {
+        let dir = PathBuf::from("data").join("verify_overlap");
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let file = |number, smallest: u8, largest: u8| FileMetaData {
+            number,
+            size: 0,
+            smallest: vec![smallest],
+            largest: vec![largest],
+            smallest_seqno: 0,
+            largest_seqno: 0,
+            column_family: 0,
+        };
+        let mut version = Version::default();
+        let mut edit = VersionEdit::default();
+        edit.add_file(1, file(1, 0, 5));
+        edit.add_file(1, file(2, 5, 9));
+        version.apply(&edit);
+        let report = verify(&dir, &version, &BytewiseComparator).unwrap();
+        assert!(report
+            .problems
+            .iter()
+            .any(|p| p == "tables 000001 and 000002 on level 1 overlap"));
+        assert!(report.problems.iter().any(|p| p.contains("not found")));
+    }
+}
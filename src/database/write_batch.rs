@@ -0,0 +1,197 @@
+use super::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY};
+
+/// One change in a `WriteBatch`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchOp {
+    Put {
+        column_family: u32,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        timestamp: u128,
+        /// See `Entry::expires_at`.
+        expires_at: Option<u128>,
+    },
+    Delete {
+        column_family: u32,
+        key: Vec<u8>,
+        timestamp: u128,
+    },
+    /// See `Database::single_delete`.
+    SingleDelete {
+        column_family: u32,
+        key: Vec<u8>,
+        timestamp: u128,
+    },
+    /// See `Database::merge`.
+    Merge {
+        column_family: u32,
+        key: Vec<u8>,
+        operand: Vec<u8>,
+        timestamp: u128,
+    },
+    /// See `Database::delete_range`.
+    DeleteRange {
+        column_family: u32,
+        start: Vec<u8>,
+        end: Vec<u8>,
+        timestamp: u128,
+    },
+}
+
+impl BatchOp {
+    pub fn column_family(&self) -> u32 {
+        match self {
+            BatchOp::Put { column_family, .. }
+            | BatchOp::Delete { column_family, .. }
+            | BatchOp::SingleDelete { column_family, .. }
+            | BatchOp::Merge { column_family, .. }
+            | BatchOp::DeleteRange { column_family, .. } => *column_family,
+        }
+    }
+}
+
+/// Changes applied together by `Database::write`: after a crash either all of them or none
+/// are recovered, even if they go to different column families.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch::default()
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8], timestamp: u128) {
+        self.put_with_expiry(key, value, timestamp, None);
+    }
+
+    /// Puts a value that counts as absent from `expires_at` on, see `Entry::expires_at`.
+    pub fn put_with_expiry(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        timestamp: u128,
+        expires_at: Option<u128>,
+    ) {
+        self.push(BatchOp::Put {
+            column_family: DEFAULT_COLUMN_FAMILY,
+            key: key.to_vec(),
+            value: value.to_vec(),
+            timestamp,
+            expires_at,
+        });
+    }
+
+    pub fn put_cf(
+        &mut self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        value: &[u8],
+        timestamp: u128,
+    ) {
+        self.push(BatchOp::Put {
+            column_family: column_family.id(),
+            key: key.to_vec(),
+            value: value.to_vec(),
+            timestamp,
+            expires_at: None,
+        });
+    }
+
+    pub fn delete(&mut self, key: &[u8], timestamp: u128) {
+        self.push(BatchOp::Delete {
+            column_family: DEFAULT_COLUMN_FAMILY,
+            key: key.to_vec(),
+            timestamp,
+        });
+    }
+
+    pub fn delete_cf(&mut self, column_family: &ColumnFamily, key: &[u8], timestamp: u128) {
+        self.push(BatchOp::Delete {
+            column_family: column_family.id(),
+            key: key.to_vec(),
+            timestamp,
+        });
+    }
+
+    pub fn single_delete(&mut self, key: &[u8], timestamp: u128) {
+        self.push(BatchOp::SingleDelete {
+            column_family: DEFAULT_COLUMN_FAMILY,
+            key: key.to_vec(),
+            timestamp,
+        });
+    }
+
+    pub fn single_delete_cf(&mut self, column_family: &ColumnFamily, key: &[u8], timestamp: u128) {
+        self.push(BatchOp::SingleDelete {
+            column_family: column_family.id(),
+            key: key.to_vec(),
+            timestamp,
+        });
+    }
+
+    pub fn merge(&mut self, key: &[u8], operand: &[u8], timestamp: u128) {
+        self.push(BatchOp::Merge {
+            column_family: DEFAULT_COLUMN_FAMILY,
+            key: key.to_vec(),
+            operand: operand.to_vec(),
+            timestamp,
+        });
+    }
+
+    pub fn merge_cf(
+        &mut self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        operand: &[u8],
+        timestamp: u128,
+    ) {
+        self.push(BatchOp::Merge {
+            column_family: column_family.id(),
+            key: key.to_vec(),
+            operand: operand.to_vec(),
+            timestamp,
+        });
+    }
+
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8], timestamp: u128) {
+        self.push(BatchOp::DeleteRange {
+            column_family: DEFAULT_COLUMN_FAMILY,
+            start: start.to_vec(),
+            end: end.to_vec(),
+            timestamp,
+        });
+    }
+
+    pub fn delete_range_cf(
+        &mut self,
+        column_family: &ColumnFamily,
+        start: &[u8],
+        end: &[u8],
+        timestamp: u128,
+    ) {
+        self.push(BatchOp::DeleteRange {
+            column_family: column_family.id(),
+            start: start.to_vec(),
+            end: end.to_vec(),
+            timestamp,
+        });
+    }
+
+    pub fn push(&mut self, op: BatchOp) {
+        self.ops.push(op);
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+}
@@ -0,0 +1,50 @@
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum Error {
+    /// An operating system IO call failed.
+    Io(io::Error),
+    /// Data on disk could not be decoded or violates an invariant.
+    Corruption(String),
+    /// A file or object that was expected to exist is missing.
+    NotFound(String),
+    /// The caller passed an argument that cannot be used.
+    InvalidArgument(String),
+    /// Two writes disagree in a way that cannot be resolved.
+    Conflict(String),
+    /// The resource is in use, e.g. the directory is held by another process.
+    Busy(String),
+    /// The requested history was discarded, e.g. WALs deleted after a flush.
+    Gone(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Corruption(msg) => write!(f, "corruption: {}", msg),
+            Error::NotFound(msg) => write!(f, "not found: {}", msg),
+            Error::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            Error::Conflict(msg) => write!(f, "conflict: {}", msg),
+            Error::Busy(msg) => write!(f, "busy: {}", msg),
+            Error::Gone(msg) => write!(f, "gone: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
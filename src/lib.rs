@@ -1,16 +1,25 @@
+#![allow(clippy::module_inception)]
+pub mod backup;
+mod checksum;
+pub mod client;
 pub mod database;
+pub mod encoding;
+mod error;
 pub mod memtable;
+pub mod replication;
+pub mod server;
 pub mod sstable;
+pub mod version;
 pub mod wal;
 
+pub use error::{Error, Result};
+
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::memtable::MemTable;
-
     use super::*;
 
     #[test]
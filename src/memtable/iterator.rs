@@ -1,12 +1,14 @@
 use crate::database::entry::Entry;
 
 pub struct MemTableIterator {
-    entries: Vec<Entry>,
+    entries: std::vec::IntoIter<Entry>,
 }
 
 impl MemTableIterator {
     pub fn new(entries: Vec<Entry>) -> Self {
-        MemTableIterator { entries }
+        MemTableIterator {
+            entries: entries.into_iter(),
+        }
     }
 }
 
@@ -14,7 +16,7 @@ impl Iterator for MemTableIterator {
     type Item = Entry;
 
     fn next(&mut self) -> Option<Entry> {
-        // pop first entry from entries and decrease size accordingly
-        self.entries.pop()
+        // yield entries in key order so flushed sstables are sorted
+        self.entries.next()
     }
 }
@@ -1,9 +1,20 @@
-use crate::database::entry::Entry;
+use std::{cmp::Ordering, ops::Bound, sync::Arc};
+
+use crate::database::{
+    comparator::{BytewiseComparator, Comparator},
+    entry::Entry,
+    merge_operator::stack,
+    range_tombstone::{cover, RangeTombstone},
+};
 
 use super::iterator::MemTableIterator;
 
 pub struct MemTable {
+    // sorted by `comparator`
     entries: Vec<Entry>,
+    // entries they cover are kept, reads and flushed tables apply them
+    range_tombstones: Vec<RangeTombstone>,
+    comparator: Arc<dyn Comparator>,
     pub size: usize,
 }
 
@@ -16,25 +27,59 @@ impl IntoIterator for &MemTable {
     }
 }
 
+impl Default for MemTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MemTable {
     pub fn new() -> MemTable {
+        Self::with_comparator(Arc::new(BytewiseComparator))
+    }
+
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> MemTable {
         MemTable {
             entries: Vec::new(),
+            range_tombstones: Vec::new(),
+            comparator,
             size: 0,
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.range_tombstones.is_empty()
+    }
+
     fn get_index(&self, key: &[u8]) -> Result<usize, usize> {
         self.entries
-            .binary_search_by_key(&key, |e| e.key.as_slice())
+            .binary_search_by(|e| self.comparator.compare(&e.key, key))
     }
 
     pub fn set(&mut self, key: &[u8], value: &[u8], timestamp: u128) {
+        self.set_with_expiry(key, value, timestamp, None);
+    }
+
+    pub fn set_with_expiry(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        timestamp: u128,
+        expires_at: Option<u128>,
+    ) {
         let entry = Entry {
             key: key.to_owned(),
             value: Some(value.to_owned()),
             timestamp,
             deleted: false,
+            single_delete: false,
+            blob: None,
+            expires_at,
+            operands: Vec::new(),
         };
 
         match self.get_index(key) {
@@ -64,12 +109,50 @@ impl MemTable {
         None
     }
 
+    /// Iterates over the entries between `start` and `end`, tombstones included.
+    pub fn scan(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> MemTableIterator {
+        let compare = |e: &Entry, key: &[u8]| self.comparator.compare(&e.key, key);
+        let from = match start {
+            Bound::Included(key) => self
+                .entries
+                .partition_point(|e| compare(e, key) == Ordering::Less),
+            Bound::Excluded(key) => self
+                .entries
+                .partition_point(|e| compare(e, key) != Ordering::Greater),
+            Bound::Unbounded => 0,
+        };
+        let to = match end {
+            Bound::Included(key) => self
+                .entries
+                .partition_point(|e| compare(e, key) != Ordering::Greater),
+            Bound::Excluded(key) => self
+                .entries
+                .partition_point(|e| compare(e, key) == Ordering::Less),
+            Bound::Unbounded => self.entries.len(),
+        };
+        MemTableIterator::new(self.entries[from..to.max(from)].to_vec())
+    }
+
     pub fn delete(&mut self, key: &[u8], timestamp: u128) {
+        self.insert_tombstone(key, timestamp, false);
+    }
+
+    /// Deletes `key` with a tombstone that compaction may drop together with the value it
+    /// meets, see `Database::single_delete`.
+    pub fn single_delete(&mut self, key: &[u8], timestamp: u128) {
+        self.insert_tombstone(key, timestamp, true);
+    }
+
+    fn insert_tombstone(&mut self, key: &[u8], timestamp: u128, single_delete: bool) {
         let entry = Entry {
             key: key.to_owned(),
             value: None,
             timestamp,
             deleted: true,
+            single_delete,
+            blob: None,
+            expires_at: None,
+            operands: Vec::new(),
         };
         match self.get_index(key) {
             Ok(idx) => {
@@ -86,15 +169,61 @@ impl MemTable {
             }
         }
     }
+
+    /// Adds a merge operand on top of the entry of `key`; operands are only folded by reads.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8], timestamp: u128) {
+        let entry = Entry {
+            key: key.to_owned(),
+            value: None,
+            timestamp,
+            deleted: false,
+            single_delete: false,
+            blob: None,
+            expires_at: None,
+            operands: vec![operand.to_owned()],
+        };
+        self.size += operand.len();
+        match self.get_index(key) {
+            Ok(idx) => {
+                let older = self.entries[idx].clone();
+                let older = cover(older, &self.range_tombstones, &*self.comparator);
+                self.entries[idx] = stack(entry, older);
+            }
+            Err(idx) => {
+                let timestamp_size = 16;
+                let boolean_size = 1;
+                self.size += key.len() + timestamp_size + boolean_size;
+                self.entries.insert(idx, entry);
+            }
+        }
+    }
+
+    /// Deletes the keys from `start` up to but not including `end` that are not newer than
+    /// `timestamp`, see `RangeTombstone`.
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8], timestamp: u128) {
+        let timestamp_size = 16;
+        self.size += start.len() + end.len() + timestamp_size;
+        self.range_tombstones.push(RangeTombstone {
+            start: start.to_owned(),
+            end: end.to_owned(),
+            timestamp,
+        });
+    }
+
+    /// Range tombstones in the order they were written.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::database::comparator::ReverseBytewiseComparator;
 
     #[test]
     fn create_memtable() {
-        let table: MemTable = MemTable::new();
+        let _table: MemTable = MemTable::new();
     }
 
     pub fn prepare_memtable() -> MemTable {
@@ -104,11 +233,17 @@ pub mod tests {
                 value: Some(vec![i]),
                 timestamp: 12,
                 deleted: false,
+                single_delete: false,
+                blob: None,
+                expires_at: None,
+                operands: Vec::new(),
             })
             .collect();
         let table: MemTable = MemTable {
             size: entries.len(),
             entries,
+            range_tombstones: Vec::new(),
+            comparator: Arc::new(BytewiseComparator),
         };
         table
     }
@@ -127,4 +262,50 @@ pub mod tests {
             assert_eq!(entry.timestamp, 12)
         }
     }
+
+    #[test]
+    fn scan_yields_keys_in_range() {
+        let table = prepare_memtable();
+        let keys: Vec<Vec<u8>> = table
+            .scan(Bound::Excluded(&[2]), Bound::Included(&[5]))
+            .map(|e| e.key)
+            .collect();
+        assert_eq!(keys, vec![vec![3], vec![4], vec![5]]);
+    }
+
+    #[test]
+    fn comparator_orders_keys() {
+        let mut table = MemTable::with_comparator(Arc::new(ReverseBytewiseComparator));
+        for key in 0..5u8 {
+            table.set(&[key], &[key], 1);
+        }
+        assert_eq!(table.get(&[3]).unwrap().value, Some(vec![3]));
+        let keys: Vec<Vec<u8>> = table
+            .scan(Bound::Included(&[3]), Bound::Excluded(&[0]))
+            .map(|e| e.key)
+            .collect();
+        assert_eq!(keys, vec![vec![3], vec![2], vec![1]]);
+    }
+
+    #[test]
+    fn merge_does_not_resurrect_range_deleted_value() {
+        let mut table = MemTable::new();
+        table.set(&[1], &[1], 1);
+        table.delete_range(&[0], &[2], 2);
+        assert!(!table.is_empty());
+        assert_eq!(table.range_tombstones().len(), 1);
+        table.merge(&[1], &[3], 3);
+        let entry = table.get(&[1]).unwrap();
+        assert!(entry.deleted && entry.value.is_none());
+        assert_eq!(entry.operands, vec![vec![3]]);
+    }
+
+    #[test]
+    fn iter_yields_keys_in_order() {
+        let table = prepare_memtable();
+        let keys: Vec<Vec<u8>> = table.into_iter().map(|e| e.key).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+    }
 }
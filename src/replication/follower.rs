@@ -0,0 +1,391 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::BufReader,
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::RwLock,
+    task::JoinHandle,
+    time,
+};
+
+use crate::{
+    database::{
+        async_database::{run_blocking, AsyncDatabase, ScanStream},
+        column_family::ColumnFamily,
+        entry::Entry,
+        options::Options,
+        write_batch::{BatchOp, WriteBatch},
+    },
+    server::protocol::{self, Frame, Kind},
+    sstable::sstable::file_number,
+    version::{edit::VersionEdit, version_set::VersionSet},
+    Error, Result,
+};
+
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+const FOLLOW_ID: u64 = 1;
+const CHECKPOINT_ID: u64 = 2;
+
+/// Read-only replica of a database served by a `BinaryServer`.
+///
+/// The follower keeps its own database in `dir` and applies the leader's writes to it batch
+/// by batch, with the leader's seqnos and timestamps. When the leader no longer has the
+/// writes it needs, it replaces its database with a checkpoint of the leader's and goes on
+/// from there. Lost connections are retried.
+///
+/// Reads fail with `Error::Busy` unless the follower had every write of the leader at some
+/// point within `max_staleness`, which should be well above
+/// `binary::HEARTBEAT_INTERVAL`.
+pub struct Follower {
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+struct Shared {
+    dir: PathBuf,
+    // the database is reopened with these after each bootstrap
+    options: Options,
+    max_staleness: Duration,
+    // `None` while a checkpoint replaces the database
+    db: RwLock<Option<AsyncDatabase>>,
+    // when the follower last had every write of the leader
+    caught_up_at: Mutex<Option<Instant>>,
+}
+
+// writes of a leader batch that did not arrive completely yet
+struct PendingBatch {
+    batch_seqno: u64,
+    last_seqno: u64,
+    batch: WriteBatch,
+}
+
+impl Follower {
+    pub async fn start(
+        dir: impl Into<PathBuf>,
+        leader: SocketAddr,
+        max_staleness: Duration,
+    ) -> Result<Follower> {
+        Self::start_with_options(dir, leader, max_staleness, Options::default()).await
+    }
+
+    /// Starts a follower whose database is opened with `options`. The comparator and merge
+    /// operator must be those of the leader.
+    pub async fn start_with_options(
+        dir: impl Into<PathBuf>,
+        leader: SocketAddr,
+        max_staleness: Duration,
+        options: Options,
+    ) -> Result<Follower> {
+        let dir = dir.into();
+        let db = AsyncDatabase::open_with_options(dir.clone(), options.clone()).await?;
+        let shared = Arc::new(Shared {
+            dir,
+            options,
+            max_staleness,
+            db: RwLock::new(Some(db)),
+            caught_up_at: Mutex::new(None),
+        });
+        let task = tokio::spawn(replicate(shared.clone(), leader));
+        Ok(Follower { shared, task })
+    }
+
+    pub async fn get(&self, key: impl Into<Vec<u8>>) -> Result<Option<Entry>> {
+        let db = self.shared.db.read().await;
+        self.readable(db.as_ref())?.get(key).await
+    }
+
+    pub async fn scan(
+        &self,
+        range: impl RangeBounds<Vec<u8>> + Send + 'static,
+    ) -> Result<ScanStream> {
+        let db = self.shared.db.read().await;
+        self.readable(db.as_ref())?.scan(range).await
+    }
+
+    pub async fn get_cf(
+        &self,
+        column_family: &ColumnFamily,
+        key: impl Into<Vec<u8>>,
+    ) -> Result<Option<Entry>> {
+        let db = self.shared.db.read().await;
+        self.readable(db.as_ref())?.get_cf(column_family, key).await
+    }
+
+    pub async fn scan_cf(
+        &self,
+        column_family: &ColumnFamily,
+        range: impl RangeBounds<Vec<u8>> + Send + 'static,
+    ) -> Result<ScanStream> {
+        let db = self.shared.db.read().await;
+        self.readable(db.as_ref())?
+            .scan_cf(column_family, range)
+            .await
+    }
+
+    /// Handle of a column family of the replica. Families created on the leader appear once
+    /// the follower bootstrapped from a checkpoint holding them.
+    pub async fn column_family(&self, name: impl Into<String>) -> Result<Option<ColumnFamily>> {
+        let db = self.shared.db.read().await;
+        self.readable(db.as_ref())?.column_family(name).await
+    }
+
+    /// Seqno of the latest write applied, or `None` while a checkpoint is being installed.
+    pub async fn last_sequence(&self) -> Option<u64> {
+        let db = self.shared.db.read().await;
+        db.as_ref().map(|db| db.last_sequence())
+    }
+
+    /// How long ago the follower last had every write of the leader.
+    pub fn staleness(&self) -> Option<Duration> {
+        self.shared
+            .caught_up_at
+            .lock()
+            .unwrap()
+            .map(|at| at.elapsed())
+    }
+
+    /// Stops replicating and closes the database.
+    pub async fn stop(mut self) {
+        self.task.abort();
+        (&mut self.task).await.ok();
+    }
+
+    fn readable<'a>(&self, db: Option<&'a AsyncDatabase>) -> Result<&'a AsyncDatabase> {
+        let db = db.ok_or_else(|| Error::Busy("replica is being bootstrapped".to_string()))?;
+        match self.staleness() {
+            Some(staleness) if staleness <= self.shared.max_staleness => Ok(db),
+            Some(staleness) => Err(Error::Busy(format!(
+                "replica is {} ms behind the leader",
+                staleness.as_millis()
+            ))),
+            None => Err(Error::Busy("replica has not caught up yet".to_string())),
+        }
+    }
+}
+
+impl Drop for Follower {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Shared {
+    async fn open(&self) -> Result<AsyncDatabase> {
+        AsyncDatabase::open_with_options(self.dir.clone(), self.options.clone()).await
+    }
+}
+
+async fn replicate(shared: Arc<Shared>, leader: SocketAddr) {
+    loop {
+        if matches!(follow(&shared, leader).await, Err(Error::Gone(_)))
+            && bootstrap(&shared, leader).await.is_ok()
+        {
+            continue;
+        }
+        // the connection broke, the leader went away or the bootstrap failed
+        time::sleep(RETRY_DELAY).await;
+    }
+}
+
+/// Applies the leader's writes until the connection fails.
+async fn follow(shared: &Shared, leader: SocketAddr) -> Result<()> {
+    let db = match shared.db.read().await.clone() {
+        Some(db) => db,
+        None => {
+            // an earlier bootstrap failed half way
+            let db = shared.open().await?;
+            shared.db.write().await.insert(db).clone()
+        }
+    };
+    let (mut reader, mut writer) = connect(leader).await?;
+    let seqno = db.last_sequence() + 1;
+    Frame::new(FOLLOW_ID, Kind::Follow, seqno.to_le_bytes().to_vec())
+        .write_to(&mut writer)
+        .await?;
+    let mut pending: Option<PendingBatch> = None;
+    loop {
+        let frame = read_frame(&mut reader).await?;
+        let (leader_sequence, changes) = match frame.kind {
+            Kind::Changes => protocol::decode_changes(&frame.payload)?,
+            _ => return Err(unexpected(&frame)),
+        };
+        for change in changes {
+            if pending
+                .as_ref()
+                .is_some_and(|p| p.batch_seqno != change.batch_seqno)
+            {
+                apply(&db, pending.take()).await?;
+            }
+            let expected = match &pending {
+                Some(pending) => pending.last_seqno + 1,
+                None => db.last_sequence() + 1,
+            };
+            if change.seqno != expected {
+                return Err(Error::Corruption(format!(
+                    "leader sent seqno {} instead of {}",
+                    change.seqno, expected
+                )));
+            }
+            let batch = pending.get_or_insert_with(|| PendingBatch {
+                batch_seqno: change.batch_seqno,
+                last_seqno: change.seqno,
+                batch: WriteBatch::new(),
+            });
+            batch.last_seqno = change.seqno;
+            batch
+                .batch
+                .push(match (change.value, change.operand, change.range_end) {
+                    (_, _, Some(end)) => BatchOp::DeleteRange {
+                        column_family: change.column_family,
+                        start: change.key,
+                        end,
+                        timestamp: change.timestamp,
+                    },
+                    (_, Some(operand), None) => BatchOp::Merge {
+                        column_family: change.column_family,
+                        key: change.key,
+                        operand,
+                        timestamp: change.timestamp,
+                    },
+                    (Some(value), None, None) => BatchOp::Put {
+                        column_family: change.column_family,
+                        key: change.key,
+                        value,
+                        timestamp: change.timestamp,
+                        expires_at: change.expires_at,
+                    },
+                    (None, None, None) if change.single_delete => BatchOp::SingleDelete {
+                        column_family: change.column_family,
+                        key: change.key,
+                        timestamp: change.timestamp,
+                    },
+                    (None, None, None) => BatchOp::Delete {
+                        column_family: change.column_family,
+                        key: change.key,
+                        timestamp: change.timestamp,
+                    },
+                });
+        }
+        // the leader's latest seqno always ends a batch
+        if pending
+            .as_ref()
+            .is_some_and(|p| p.last_seqno == leader_sequence)
+        {
+            apply(&db, pending.take()).await?;
+        }
+        if db.last_sequence() >= leader_sequence {
+            *shared.caught_up_at.lock().unwrap() = Some(Instant::now());
+        }
+    }
+}
+
+async fn apply(db: &AsyncDatabase, pending: Option<PendingBatch>) -> Result<()> {
+    match pending {
+        // column families are created in the MANIFEST, not the WAL, so a family the
+        // follower lacks only comes with a checkpoint
+        Some(pending) => db.write(pending.batch).await.map_err(|err| match err {
+            Error::NotFound(family) => Error::Gone(format!("{} only exists on the leader", family)),
+            err => err,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Replaces the database with a checkpoint of the leader's. Reads fail while it is
+/// installed.
+async fn bootstrap(shared: &Shared, leader: SocketAddr) -> Result<()> {
+    let (mut reader, mut writer) = connect(leader).await?;
+    Frame::new(CHECKPOINT_ID, Kind::Checkpoint, Vec::new())
+        .write_to(&mut writer)
+        .await?;
+    let tmp = shared.dir.with_extension("bootstrap");
+    let dir = tmp.clone();
+    run_blocking(move || {
+        fs::remove_dir_all(&dir).ok();
+        Ok(fs::create_dir(&dir)?)
+    })
+    .await?;
+    let snapshot = loop {
+        let frame = read_frame(&mut reader).await?;
+        match frame.kind {
+            Kind::File => {
+                let dir = tmp.clone();
+                run_blocking(move || write_chunk(&dir, &frame.payload)).await?;
+            }
+            Kind::End => break VersionEdit::decode(&frame.payload)?,
+            _ => return Err(unexpected(&frame)),
+        }
+    };
+    let mut db = shared.db.write().await;
+    // closes the database, readers are done with it
+    db.take();
+    let dir = shared.dir.clone();
+    run_blocking(move || {
+        VersionSet::create(
+            &tmp,
+            snapshot.next_file_number.unwrap_or(1),
+            snapshot.last_sequence.unwrap_or(0),
+            snapshot,
+        )?;
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        Ok(fs::rename(tmp, dir)?)
+    })
+    .await?;
+    *db = Some(shared.open().await?);
+    Ok(())
+}
+
+/// Appends a chunk received from the leader to its file in `dir`.
+fn write_chunk(dir: &Path, payload: &[u8]) -> Result<()> {
+    let (name, data) = protocol::decode_file_chunk(payload)?;
+    let path = dir.join(&name);
+    // the name must not lead out of the directory
+    if path.file_name().and_then(|n| n.to_str()) != Some(name.as_str())
+        || file_number(&path).is_none()
+    {
+        return Err(Error::InvalidArgument(format!(
+            "Protocol error: invalid file name {}",
+            name
+        )));
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(file.write_all(data)?)
+}
+
+async fn connect(leader: SocketAddr) -> Result<(BufReader<OwnedReadHalf>, OwnedWriteHalf)> {
+    let (reader, writer) = TcpStream::connect(leader).await?.into_split();
+    Ok((BufReader::new(reader), writer))
+}
+
+async fn read_frame(reader: &mut BufReader<OwnedReadHalf>) -> Result<Frame> {
+    let frame = Frame::read_from(reader).await?.ok_or_else(|| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "connection closed",
+        ))
+    })?;
+    match frame.kind {
+        Kind::Error => Err(protocol::decode_error(&frame.payload)),
+        _ => Ok(frame),
+    }
+}
+
+fn unexpected(frame: &Frame) -> Error {
+    Error::InvalidArgument(format!(
+        "Protocol error: unexpected {:?} response",
+        frame.kind
+    ))
+}
@@ -0,0 +1,431 @@
+use std::{
+    collections::HashMap, fs::File, future::Future, io::Read, net::SocketAddr, path::PathBuf,
+    sync::Arc, time::Duration,
+};
+
+use tokio::{
+    io::{AsyncWriteExt, BufReader, BufWriter},
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{mpsc, watch, Semaphore},
+    task::JoinSet,
+    time,
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    database::{async_database::AsyncDatabase, entry::Entry, write_batch::WriteBatch},
+    Error, Result,
+};
+
+use super::protocol::{self, Frame, Kind, ScanRequest};
+
+const RESPONSE_BUFFER: usize = 256;
+const SCAN_BATCH: usize = 256;
+const CHANGE_BATCH: usize = 256;
+const FILE_CHUNK: usize = 1 << 20;
+/// How often a follower that is caught up hears from the server.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// TCP server speaking the binary protocol of `protocol`.
+pub struct BinaryServer {
+    listener: TcpListener,
+    db: AsyncDatabase,
+}
+
+impl BinaryServer {
+    pub async fn bind(addr: impl ToSocketAddrs, db: AsyncDatabase) -> Result<BinaryServer> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(BinaryServer { listener, db })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections until `shutdown` completes. Requests already read are answered,
+    /// scans in progress are cut short.
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let mut connections = JoinSet::new();
+        let (stop, stopped) = watch::channel(false);
+        let result = tokio::select! {
+            result = self.accept_loop(&mut connections, stopped) => result,
+            _ = shutdown => Ok(()),
+        };
+        stop.send(true).ok();
+        while connections.join_next().await.is_some() {}
+        self.db.sync().await?;
+        result
+    }
+
+    pub async fn run(self) -> Result<()> {
+        self.run_until(std::future::pending()).await
+    }
+
+    async fn accept_loop(
+        &self,
+        connections: &mut JoinSet<()>,
+        stopped: watch::Receiver<bool>,
+    ) -> Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            while connections.try_join_next().is_some() {}
+            let db = self.db.clone();
+            let stopped = stopped.clone();
+            connections.spawn(async move {
+                // errors only end this connection
+                handle_connection(&db, stream, stopped).await.ok();
+            });
+        }
+    }
+}
+
+async fn handle_connection(
+    db: &AsyncDatabase,
+    stream: TcpStream,
+    mut stopped: watch::Receiver<bool>,
+) -> Result<()> {
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let (responses, outgoing) = mpsc::channel(RESPONSE_BUFFER);
+    let writer = tokio::spawn(write_responses(writer, outgoing));
+    // credit of the scans in progress by request id
+    let mut scans: HashMap<u64, Arc<Semaphore>> = HashMap::new();
+    let mut scan_tasks = JoinSet::new();
+    let mut replies = Vec::new();
+    let mut unsynced = false;
+    let result = loop {
+        let frame = tokio::select! {
+            frame = Frame::read_from(&mut reader) => frame,
+            _ = stopped.wait_for(|stopped| *stopped) => break Ok(()),
+        };
+        let frame = match frame {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break Ok(()),
+            Err(err) => {
+                replies.push(Frame::new(0, Kind::Error, protocol::encode_error(&err)));
+                break Err(err);
+            }
+        };
+        while let Some(Ok(id)) = scan_tasks.try_join_next() {
+            scans.remove(&id);
+        }
+        match frame.kind {
+            Kind::Get => replies.push(get(db, &frame).await),
+            Kind::Write => {
+                replies.push(write(db, &frame).await);
+                unsynced = true;
+            }
+            Kind::Scan => match scan(db, &frame, &responses, &mut scan_tasks).await {
+                Ok(credit) => {
+                    scans.insert(frame.id, credit);
+                }
+                Err(err) => replies.push(error(frame.id, &err)),
+            },
+            Kind::Credit => {
+                let credit = <[u8; 4]>::try_from(frame.payload.as_slice())
+                    .map(|credit| u32::from_le_bytes(credit) as usize);
+                if let (Some(scan), Ok(credit)) = (scans.get(&frame.id), credit) {
+                    scan.add_permits(credit.min(Semaphore::MAX_PERMITS - scan.available_permits()));
+                }
+            }
+            Kind::Cancel => {
+                if let Some(scan) = scans.remove(&frame.id) {
+                    scan.close();
+                }
+            }
+            Kind::Follow => {
+                if let Err(err) = follow(db, &frame, &responses, &mut scan_tasks).await {
+                    replies.push(error(frame.id, &err));
+                }
+            }
+            Kind::Checkpoint => {
+                if let Err(err) = checkpoint(db, &frame, &responses, &mut scan_tasks).await {
+                    replies.push(error(frame.id, &err));
+                }
+            }
+            _ => replies.push(error(
+                frame.id,
+                &Error::InvalidArgument("not a request".to_string()),
+            )),
+        }
+        // answer a pipeline once all of it was executed, after its writes reached the WAL
+        if reader.buffer().is_empty() {
+            if unsynced {
+                db.sync().await?;
+                unsynced = false;
+            }
+            for reply in replies.drain(..) {
+                responses.send(reply).await.ok();
+            }
+        }
+    };
+    if unsynced {
+        db.sync().await?;
+    }
+    for reply in replies {
+        responses.send(reply).await.ok();
+    }
+    scan_tasks.abort_all();
+    while scan_tasks.join_next().await.is_some() {}
+    drop(responses);
+    writer.await.ok();
+    result
+}
+
+async fn write_responses(
+    writer: OwnedWriteHalf,
+    mut outgoing: mpsc::Receiver<Frame>,
+) -> Result<()> {
+    let mut writer = BufWriter::new(writer);
+    while let Some(frame) = outgoing.recv().await {
+        frame.write_to(&mut writer).await?;
+        if outgoing.is_empty() {
+            writer.flush().await?;
+        }
+    }
+    Ok(writer.flush().await?)
+}
+
+async fn get(db: &AsyncDatabase, frame: &Frame) -> Frame {
+    match db.get(frame.payload.as_slice()).await {
+        Ok(Some(entry)) if !entry.deleted => Frame::new(
+            frame.id,
+            Kind::Value,
+            protocol::encode_entries([entry].iter()),
+        ),
+        Ok(_) => Frame::new(frame.id, Kind::NotFound, Vec::new()),
+        Err(err) => error(frame.id, &err),
+    }
+}
+
+async fn write(db: &AsyncDatabase, frame: &Frame) -> Frame {
+    let entries = match protocol::decode_entries(&frame.payload) {
+        Ok(entries) => entries,
+        Err(err) => return error(frame.id, &err),
+    };
+    let mut batch = WriteBatch::new();
+    for entry in entries {
+        let timestamp = match entry.timestamp {
+            0 => db.timestamp(),
+            timestamp => timestamp,
+        };
+        if entry.blob.is_some() {
+            return error(frame.id, &invalid_write("holds a blob index"));
+        }
+        match (&entry.value, entry.operands.is_empty()) {
+            (None, false) if !entry.deleted && entry.expires_at.is_none() => {
+                for operand in &entry.operands {
+                    batch.merge(&entry.key, operand, timestamp);
+                }
+            }
+            (Some(value), true) if !entry.deleted => {
+                batch.put_with_expiry(&entry.key, value, timestamp, entry.expires_at)
+            }
+            (None, true) if entry.deleted && entry.expires_at.is_none() => {
+                if entry.single_delete {
+                    batch.single_delete(&entry.key, timestamp)
+                } else {
+                    batch.delete(&entry.key, timestamp)
+                }
+            }
+            (Some(_), false) => {
+                return error(
+                    frame.id,
+                    &invalid_write("holds both a value and merge operands"),
+                )
+            }
+            _ => {
+                return error(
+                    frame.id,
+                    &invalid_write("is neither a put, a merge nor a delete"),
+                )
+            }
+        }
+    }
+    match db.write(batch).await {
+        Ok(()) => Frame::new(frame.id, Kind::Ok, Vec::new()),
+        Err(err) => error(frame.id, &err),
+    }
+}
+
+/// Starts streaming a scan; it sends entries as long as it has credit.
+async fn scan(
+    db: &AsyncDatabase,
+    frame: &Frame,
+    responses: &mpsc::Sender<Frame>,
+    scan_tasks: &mut JoinSet<u64>,
+) -> Result<Arc<Semaphore>> {
+    let request = ScanRequest::decode(&frame.payload)?;
+    let limit = match request.limit {
+        0 => usize::MAX,
+        limit => limit.try_into().unwrap_or(usize::MAX),
+    };
+    // the iterator is created here so the scan sees all writes of earlier requests
+    let mut entries = db.scan((request.start, request.end)).await?.take(limit);
+    let credit = Arc::new(Semaphore::new(
+        (request.window as usize).min(Semaphore::MAX_PERMITS),
+    ));
+    let (id, responses, task_credit) = (frame.id, responses.clone(), credit.clone());
+    scan_tasks.spawn(async move {
+        let mut batch = Vec::new();
+        loop {
+            let permit = match task_credit.try_acquire() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    // out of credit: hand over what we have and wait for the client
+                    if !send_entries(&responses, id, &mut batch).await {
+                        return id;
+                    }
+                    match task_credit.acquire().await {
+                        Ok(permit) => permit,
+                        // cancelled
+                        Err(_) => return id,
+                    }
+                }
+            };
+            permit.forget();
+            let entry = match entries.next().await {
+                Some(Ok(entry)) => entry,
+                Some(Err(err)) => {
+                    // the error takes the place of `End` after the entries read so far
+                    if send_entries(&responses, id, &mut batch).await {
+                        responses.send(error(id, &err)).await.ok();
+                    }
+                    return id;
+                }
+                None => break,
+            };
+            batch.push(entry);
+            if batch.len() >= SCAN_BATCH && !send_entries(&responses, id, &mut batch).await {
+                return id;
+            }
+        }
+        send_entries(&responses, id, &mut batch).await;
+        responses
+            .send(Frame::new(id, Kind::End, Vec::new()))
+            .await
+            .ok();
+        id
+    });
+    Ok(credit)
+}
+
+/// Starts streaming the changes from the seqno in the payload. Heartbeats tell a follower
+/// that is caught up how recent its copy is.
+async fn follow(
+    db: &AsyncDatabase,
+    frame: &Frame,
+    responses: &mpsc::Sender<Frame>,
+    tasks: &mut JoinSet<u64>,
+) -> Result<()> {
+    let seqno = <[u8; 8]>::try_from(frame.payload.as_slice())
+        .map(u64::from_le_bytes)
+        .map_err(|_| Error::InvalidArgument("Protocol error: invalid seqno".to_string()))?;
+    let mut changes = db.changes_since(seqno).await?;
+    let (id, responses, db) = (frame.id, responses.clone(), db.clone());
+    tasks.spawn(async move {
+        loop {
+            let mut batch = Vec::new();
+            let mut failed = None;
+            tokio::select! {
+                change = changes.next() => match change {
+                    Some(Ok(change)) => batch.push(change),
+                    Some(Err(err)) => failed = Some(err),
+                    None => return id,
+                },
+                _ = time::sleep(HEARTBEAT_INTERVAL) => (),
+            }
+            while failed.is_none() && !batch.is_empty() && batch.len() < CHANGE_BATCH {
+                match changes.as_mut().try_recv() {
+                    Ok(Ok(change)) => batch.push(change),
+                    Ok(Err(err)) => failed = Some(err),
+                    Err(_) => break,
+                }
+            }
+            // read after the changes were received, so it is never behind them
+            let payload = protocol::encode_changes(db.last_sequence(), &batch);
+            if responses
+                .send(Frame::new(id, Kind::Changes, payload))
+                .await
+                .is_err()
+            {
+                return id;
+            }
+            if let Some(err) = failed {
+                responses.send(error(id, &err)).await.ok();
+                return id;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Starts sending the table files of a snapshot; the database is flushed first.
+async fn checkpoint(
+    db: &AsyncDatabase,
+    frame: &Frame,
+    responses: &mpsc::Sender<Frame>,
+    tasks: &mut JoinSet<u64>,
+) -> Result<()> {
+    let (snapshot, files) = db.open_snapshot().await?;
+    let (id, responses) = (frame.id, responses.clone());
+    tasks.spawn_blocking(move || {
+        let end = match send_files(&responses, id, files) {
+            Ok(true) => Frame::new(id, Kind::End, snapshot.encode()),
+            Ok(false) => return id,
+            Err(err) => error(id, &err),
+        };
+        responses.blocking_send(end).ok();
+        id
+    });
+    Ok(())
+}
+
+/// Sends `files` in chunks. Returns false once the connection is gone.
+fn send_files(
+    responses: &mpsc::Sender<Frame>,
+    id: u64,
+    files: Vec<(PathBuf, File)>,
+) -> Result<bool> {
+    for (path, file) in files {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let mut file = file.take(u64::MAX);
+        loop {
+            let mut chunk = Vec::new();
+            file.set_limit(FILE_CHUNK as u64);
+            file.read_to_end(&mut chunk)?;
+            let payload = protocol::encode_file_chunk(&name, &chunk);
+            if responses
+                .blocking_send(Frame::new(id, Kind::File, payload))
+                .is_err()
+            {
+                return Ok(false);
+            }
+            if chunk.len() < FILE_CHUNK {
+                break;
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Sends and clears `batch` unless it is empty. Returns false once the connection is gone.
+async fn send_entries(responses: &mpsc::Sender<Frame>, id: u64, batch: &mut Vec<Entry>) -> bool {
+    if batch.is_empty() {
+        return true;
+    }
+    let payload = protocol::encode_entries(batch.iter());
+    batch.clear();
+    responses
+        .send(Frame::new(id, Kind::Entries, payload))
+        .await
+        .is_ok()
+}
+
+fn error(id: u64, err: &Error) -> Frame {
+    Frame::new(id, Kind::Error, protocol::encode_error(err))
+}
+
+fn invalid_write(problem: &str) -> Error {
+    Error::InvalidArgument(format!("written entry {}", problem))
+}
@@ -0,0 +1,271 @@
+use std::{collections::BTreeMap, ops::Bound, sync::atomic::Ordering};
+
+use tokio_stream::StreamExt;
+
+use crate::Result;
+
+use super::{resp::Value, server::Shared};
+
+const DEFAULT_SCAN_COUNT: usize = 10;
+const MAX_CURSORS: usize = 4096;
+
+/// Positions of unfinished `SCAN`s; redis clients expect cursors to be integers so the key
+/// to continue after is kept here.
+#[derive(Default)]
+pub struct Cursors {
+    next_id: u64,
+    keys: BTreeMap<u64, Vec<u8>>,
+}
+
+impl Cursors {
+    fn insert(&mut self, key: Vec<u8>) -> u64 {
+        self.next_id += 1;
+        self.keys.insert(self.next_id, key);
+        // abandoned scans are forgotten, oldest first
+        while self.keys.len() > MAX_CURSORS {
+            self.keys.pop_first();
+        }
+        self.next_id
+    }
+
+    fn get(&self, id: u64) -> Option<Vec<u8>> {
+        self.keys.get(&id).cloned()
+    }
+}
+
+/// Executes one command; failures are turned into error replies.
+pub async fn execute(shared: &Shared, args: &[Vec<u8>]) -> Value {
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+    let args = &args[1..];
+    let arity_ok = match name.as_str() {
+        "ping" => args.len() <= 1,
+        "get" => args.len() == 1,
+        "set" => args.len() == 2,
+        "del" | "exists" | "mget" => !args.is_empty(),
+        "mset" => !args.is_empty() && args.len().is_multiple_of(2),
+        "scan" => !args.is_empty(),
+        "info" | "quit" => true,
+        _ => return Value::error(format!("ERR unknown command '{}'", name)),
+    };
+    if !arity_ok {
+        return Value::error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ));
+    }
+    let result = match name.as_str() {
+        "ping" => Ok(match args.first() {
+            Some(message) => Value::Bulk(message.clone()),
+            None => Value::Simple("PONG".to_string()),
+        }),
+        "get" => get(shared, &args[0]).await,
+        "set" => set(shared, args).await,
+        "del" => del(shared, args).await,
+        "exists" => exists(shared, args).await,
+        "mget" => mget(shared, args).await,
+        "mset" => set(shared, args).await,
+        "scan" => scan(shared, args).await,
+        "info" => Ok(info(shared)),
+        _ => Ok(Value::ok()),
+    };
+    result.unwrap_or_else(|err| Value::error(format!("ERR {}", err)))
+}
+
+async fn lookup(shared: &Shared, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    Ok(shared
+        .db
+        .get(key)
+        .await?
+        .filter(|entry| !entry.deleted)
+        .and_then(|entry| entry.value))
+}
+
+async fn get(shared: &Shared, key: &[u8]) -> Result<Value> {
+    Ok(lookup(shared, key).await?.map_or(Value::Null, Value::Bulk))
+}
+
+async fn set(shared: &Shared, pairs: &[Vec<u8>]) -> Result<Value> {
+    for pair in pairs.chunks(2) {
+        shared
+            .db
+            .set(
+                pair[0].as_slice(),
+                pair[1].as_slice(),
+                shared.db.timestamp(),
+            )
+            .await?;
+    }
+    Ok(Value::ok())
+}
+
+async fn del(shared: &Shared, keys: &[Vec<u8>]) -> Result<Value> {
+    let mut deleted = 0;
+    for key in keys {
+        if lookup(shared, key).await?.is_some() {
+            shared
+                .db
+                .delete(key.as_slice(), shared.db.timestamp())
+                .await?;
+            deleted += 1;
+        }
+    }
+    Ok(Value::Integer(deleted))
+}
+
+async fn exists(shared: &Shared, keys: &[Vec<u8>]) -> Result<Value> {
+    let mut found = 0;
+    for key in keys {
+        if lookup(shared, key).await?.is_some() {
+            found += 1;
+        }
+    }
+    Ok(Value::Integer(found))
+}
+
+async fn mget(shared: &Shared, keys: &[Vec<u8>]) -> Result<Value> {
+    let mut values = Vec::with_capacity(keys.len());
+    for key in keys {
+        values.push(get(shared, key).await?);
+    }
+    Ok(Value::Array(values))
+}
+
+// SCAN cursor [MATCH pattern] [COUNT count]
+async fn scan(shared: &Shared, args: &[Vec<u8>]) -> Result<Value> {
+    let Some(cursor) = std::str::from_utf8(&args[0])
+        .ok()
+        .and_then(|c| c.parse::<u64>().ok())
+    else {
+        return Ok(Value::error("ERR invalid cursor"));
+    };
+    let mut pattern = None;
+    let mut count = DEFAULT_SCAN_COUNT;
+    for option in args[1..].chunks(2) {
+        let [name, value] = option else {
+            return Ok(Value::error("ERR syntax error"));
+        };
+        match name.to_ascii_lowercase().as_slice() {
+            b"match" => pattern = Some(value.as_slice()),
+            b"count" => {
+                match std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|c| c.parse::<usize>().ok())
+                {
+                    Some(c) if c > 0 => count = c,
+                    _ => return Ok(Value::error("ERR value is not an integer or out of range")),
+                }
+            }
+            _ => return Ok(Value::error("ERR syntax error")),
+        }
+    }
+
+    let start = match cursor {
+        0 => Bound::Unbounded,
+        cursor => match shared.cursors.lock().unwrap().get(cursor) {
+            Some(key) => Bound::Excluded(key),
+            None => return Ok(Value::error("ERR invalid cursor")),
+        },
+    };
+    // COUNT bounds the keys examined, not the keys returned, like in redis
+    let entries: Vec<_> = shared
+        .db
+        .scan((start, Bound::Unbounded))
+        .await?
+        .take(count + 1)
+        .collect()
+        .await;
+    let mut entries = entries.into_iter().collect::<Result<Vec<_>>>()?;
+    let next = if entries.len() > count {
+        entries.truncate(count);
+        let last = entries[count - 1].key.clone();
+        shared.cursors.lock().unwrap().insert(last)
+    } else {
+        0
+    };
+    let keys = entries
+        .into_iter()
+        .filter(|entry| pattern.is_none_or(|p| glob_match(p, &entry.key)))
+        .map(|entry| Value::Bulk(entry.key))
+        .collect();
+    Ok(Value::Array(vec![
+        Value::Bulk(next.to_string().into_bytes()),
+        Value::Array(keys),
+    ]))
+}
+
+fn info(shared: &Shared) -> Value {
+    let info = format!(
+        "# Server\r\n\
+         rustdb_version:{}\r\n\
+         uptime_in_seconds:{}\r\n\
+         \r\n\
+         # Clients\r\n\
+         connected_clients:{}\r\n\
+         \r\n\
+         # Stats\r\n\
+         total_connections_received:{}\r\n\
+         total_commands_processed:{}\r\n",
+        env!("CARGO_PKG_VERSION"),
+        shared.started.elapsed().as_secs(),
+        shared.connected_clients.load(Ordering::Relaxed),
+        shared.total_connections.load(Ordering::Relaxed),
+        shared.total_commands.load(Ordering::Relaxed),
+    );
+    Value::Bulk(info.into_bytes())
+}
+
+/// Redis style glob: `*`, `?`, `[abc]`, `[^a-z]` and `\` to escape.
+pub fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => key.is_empty(),
+        Some((b'*', rest)) => (0..=key.len()).any(|skip| glob_match(rest, &key[skip..])),
+        Some((b'?', rest)) => !key.is_empty() && glob_match(rest, &key[1..]),
+        Some((b'[', rest)) => {
+            let Some(end) = rest.iter().skip(1).position(|b| *b == b']').map(|p| p + 1) else {
+                return key.first() == Some(&b'[') && glob_match(rest, &key[1..]);
+            };
+            let Some((first, key_rest)) = key.split_first() else {
+                return false;
+            };
+            let (negate, class) = match rest[..end].strip_prefix(b"^") {
+                Some(class) => (true, class),
+                None => (false, &rest[..end]),
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    let (low, high) = (class[i].min(class[i + 2]), class[i].max(class[i + 2]));
+                    matched |= (low..=high).contains(first);
+                    i += 3;
+                } else {
+                    matched |= class[i] == *first;
+                    i += 1;
+                }
+            }
+            matched != negate && glob_match(&rest[end + 1..], key_rest)
+        }
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            key.first() == Some(&rest[0]) && glob_match(&rest[1..], &key[1..])
+        }
+        Some((c, rest)) => key.first() == Some(c) && glob_match(rest, &key[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"user:*", b"user:42"));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"key[0-9]", b"key7"));
+        assert!(glob_match(b"a\\*", b"a*"));
+        assert!(!glob_match(b"a\\*", b"ab"));
+        assert!(glob_match(b"*", b""));
+    }
+}
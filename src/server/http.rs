@@ -0,0 +1,509 @@
+use std::{future::Future, net::SocketAddr, ops::Bound};
+
+use serde_json::{json, Value as Json};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::watch,
+    task::JoinSet,
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    database::{async_database::AsyncDatabase, write_batch::WriteBatch},
+    encoding::{base64_decode, base64_encode},
+    Result,
+};
+
+const MAX_HEAD_LEN: u64 = 64 << 10;
+const MAX_BODY_LEN: usize = 64 << 20;
+const DEFAULT_SCAN_LIMIT: usize = 1000;
+const CHUNK_SIZE: usize = 16 << 10;
+
+/// HTTP/1.1 gateway to a database.
+///
+/// - `GET|PUT|DELETE /kv/{key}` reads, writes or deletes one key, the value being the raw
+///   request or response body.
+/// - `GET /scan?start=&end=&limit=` streams the entries of `[start, end)` as JSON lines.
+/// - `POST /batch` applies `{"ops": [{"op": "put", "key": k, "value": v},
+///   {"op": "delete", "key": k}]}` atomically.
+///
+/// Keys in paths and query strings are percent-encoded; keys and values in JSON are base64.
+pub struct HttpServer {
+    listener: TcpListener,
+    db: AsyncDatabase,
+}
+
+impl HttpServer {
+    pub async fn bind(addr: impl ToSocketAddrs, db: AsyncDatabase) -> Result<HttpServer> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(HttpServer { listener, db })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections until `shutdown` completes; requests in progress are finished.
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let mut connections = JoinSet::new();
+        let (stop, stopped) = watch::channel(false);
+        let result = tokio::select! {
+            result = self.accept_loop(&mut connections, stopped) => result,
+            _ = shutdown => Ok(()),
+        };
+        stop.send(true).ok();
+        while connections.join_next().await.is_some() {}
+        result
+    }
+
+    pub async fn run(self) -> Result<()> {
+        self.run_until(std::future::pending()).await
+    }
+
+    async fn accept_loop(
+        &self,
+        connections: &mut JoinSet<()>,
+        stopped: watch::Receiver<bool>,
+    ) -> Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            while connections.try_join_next().is_some() {}
+            let db = self.db.clone();
+            let stopped = stopped.clone();
+            connections.spawn(async move {
+                // errors only end this connection
+                handle_connection(&db, stream, stopped).await.ok();
+            });
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>,
+    keep_alive: bool,
+}
+
+/// A response that ends the request with an error status.
+struct Status {
+    code: u16,
+    message: String,
+    // the head of another response is already written, see `interrupted`
+    interrupted: bool,
+}
+
+impl Status {
+    fn new(code: u16, message: impl Into<String>) -> Status {
+        Status {
+            code,
+            message: message.into(),
+            interrupted: false,
+        }
+    }
+}
+
+type Response = std::result::Result<(), Status>;
+
+type QueryParam = (Vec<u8>, Vec<u8>);
+
+async fn handle_connection(
+    db: &AsyncDatabase,
+    stream: TcpStream,
+    mut stopped: watch::Receiver<bool>,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    loop {
+        let request = tokio::select! {
+            request = read_request(&mut reader, &mut writer) => request?,
+            _ = stopped.wait_for(|stopped| *stopped) => break,
+        };
+        let request = match request {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(status) => {
+                // the rest of the stream can't be parsed anymore
+                write_status(&mut writer, &status, false).await?;
+                break;
+            }
+        };
+        if let Err(status) = respond(db, &request, &mut writer).await {
+            if status.interrupted {
+                // the client sees the chunked body end without its last chunk
+                break;
+            }
+            write_status(&mut writer, &status, request.keep_alive).await?;
+        }
+        writer.flush().await?;
+        if !request.keep_alive {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn respond(
+    db: &AsyncDatabase,
+    request: &Request,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Response {
+    let path = request.path.as_str();
+    if let Some(key) = path.strip_prefix("/kv/") {
+        let key = percent_decode(key)?;
+        if key.is_empty() {
+            return Err(Status::new(400, "empty key"));
+        }
+        return match request.method.as_str() {
+            "GET" => match db.get(key).await.map_err(internal)? {
+                Some(entry) if !entry.deleted => {
+                    let value = entry.value.unwrap_or_default();
+                    write_response(writer, 200, "application/octet-stream", &value, request).await
+                }
+                _ => Err(Status::new(404, "key not found")),
+            },
+            "PUT" => {
+                db.set(key, request.body.as_slice(), db.timestamp())
+                    .await
+                    .map_err(internal)?;
+                db.sync().await.map_err(internal)?;
+                write_response(writer, 204, "", &[], request).await
+            }
+            "DELETE" => {
+                db.delete(key, db.timestamp()).await.map_err(internal)?;
+                db.sync().await.map_err(internal)?;
+                write_response(writer, 204, "", &[], request).await
+            }
+            _ => Err(Status::new(405, "method not allowed")),
+        };
+    }
+    match (request.method.as_str(), path) {
+        ("GET", "/scan") => scan(db, request, writer).await,
+        ("POST", "/batch") => batch(db, request, writer).await,
+        (_, "/scan" | "/batch") => Err(Status::new(405, "method not allowed")),
+        _ => Err(Status::new(404, "no such endpoint")),
+    }
+}
+
+async fn scan(
+    db: &AsyncDatabase,
+    request: &Request,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Response {
+    let mut start = Bound::Unbounded;
+    let mut end = Bound::Unbounded;
+    let mut limit = DEFAULT_SCAN_LIMIT;
+    for (name, value) in parse_query(&request.query)? {
+        match name.as_slice() {
+            b"start" => start = Bound::Included(value),
+            b"end" => end = Bound::Excluded(value),
+            b"limit" => {
+                limit = std::str::from_utf8(&value)
+                    .ok()
+                    .and_then(|limit| limit.parse().ok())
+                    .ok_or_else(|| Status::new(400, "limit must be a number"))?
+            }
+            _ => return Err(Status::new(400, "unknown query parameter")),
+        }
+    }
+    let mut entries = db.scan((start, end)).await.map_err(internal)?.take(limit);
+
+    let head = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: application/x-ndjson\r\n\
+         Transfer-Encoding: chunked\r\n\
+         Connection: {}\r\n\r\n",
+        connection(request.keep_alive)
+    );
+    writer
+        .write_all(head.as_bytes())
+        .await
+        .map_err(interrupted)?;
+    let mut chunk = Vec::new();
+    while let Some(entry) = entries.next().await {
+        let entry = entry.map_err(interrupted)?;
+        let line = json!({
+            "key": base64_encode(&entry.key),
+            "value": base64_encode(&entry.value.unwrap_or_default()),
+        });
+        chunk.extend_from_slice(line.to_string().as_bytes());
+        chunk.push(b'\n');
+        if chunk.len() >= CHUNK_SIZE {
+            write_chunk(writer, &chunk).await.map_err(interrupted)?;
+            chunk.clear();
+        }
+    }
+    write_chunk(writer, &chunk).await.map_err(interrupted)?;
+    // the last chunk is empty
+    write_chunk(writer, &[]).await.map_err(interrupted)
+}
+
+async fn batch(
+    db: &AsyncDatabase,
+    request: &Request,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Response {
+    let body: Json = serde_json::from_slice(&request.body)
+        .map_err(|err| Status::new(400, format!("invalid JSON: {}", err)))?;
+    let Some(ops) = body.get("ops").and_then(Json::as_array) else {
+        return Err(Status::new(400, "expected an object with an \"ops\" array"));
+    };
+    let mut batch = WriteBatch::new();
+    for op in ops {
+        let field = |name: &str| -> std::result::Result<Vec<u8>, Status> {
+            op.get(name)
+                .and_then(Json::as_str)
+                .and_then(base64_decode)
+                .ok_or_else(|| Status::new(400, format!("\"{}\" must be a base64 string", name)))
+        };
+        match op.get("op").and_then(Json::as_str) {
+            Some("put") => batch.put(&field("key")?, &field("value")?, db.timestamp()),
+            Some("delete") => batch.delete(&field("key")?, db.timestamp()),
+            _ => return Err(Status::new(400, "\"op\" must be \"put\" or \"delete\"")),
+        }
+    }
+    let applied = batch.len();
+    db.write(batch).await.map_err(internal)?;
+    db.sync().await.map_err(internal)?;
+    let body = json!({ "applied": applied }).to_string();
+    write_response(writer, 200, "application/json", body.as_bytes(), request).await
+}
+
+/// Reads the next request. Returns `Ok(Err(_))` for malformed requests and `Ok(None)` once
+/// the client closed the connection.
+async fn read_request(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Result<std::result::Result<Option<Request>, Status>> {
+    let mut head = reader.take(MAX_HEAD_LEN);
+    let mut lines = Vec::new();
+    loop {
+        let mut line = Vec::new();
+        if head.read_until(b'\n', &mut line).await? == 0 {
+            if lines.is_empty() {
+                return Ok(Ok(None));
+            }
+            return Ok(Err(Status::new(431, "request head too large")));
+        }
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+        if line.is_empty() {
+            // tolerate blank lines between requests
+            if lines.is_empty() {
+                continue;
+            }
+            break;
+        }
+        lines.push(line);
+    }
+
+    let mut request_line = lines[0].split(' ');
+    let (Some(method), Some(target), Some(version)) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return Ok(Err(Status::new(400, "malformed request line")));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut keep_alive = version == "HTTP/1.1";
+    let mut content_length = 0;
+    let mut expect_continue = false;
+    for header in &lines[1..] {
+        let Some((name, value)) = header.split_once(':') else {
+            return Ok(Err(Status::new(400, "malformed header")));
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => match value.parse() {
+                Ok(len) if len <= MAX_BODY_LEN => content_length = len,
+                Ok(_) => return Ok(Err(Status::new(413, "request body too large"))),
+                Err(_) => return Ok(Err(Status::new(400, "invalid Content-Length"))),
+            },
+            "transfer-encoding" => {
+                return Ok(Err(Status::new(
+                    501,
+                    "chunked request bodies are not supported",
+                )))
+            }
+            "connection" => keep_alive = !value.eq_ignore_ascii_case("close"),
+            "expect" => expect_continue = value.eq_ignore_ascii_case("100-continue"),
+            _ => (),
+        }
+    }
+    if expect_continue {
+        writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+        writer.flush().await?;
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(Ok(Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        body,
+        keep_alive,
+    })))
+}
+
+async fn write_response(
+    writer: &mut (impl AsyncWrite + Unpin),
+    code: u16,
+    content_type: &str,
+    body: &[u8],
+    request: &Request,
+) -> Response {
+    let mut response = format!("HTTP/1.1 {} {}\r\n", code, reason(code));
+    if !content_type.is_empty() {
+        response += &format!("Content-Type: {}\r\n", content_type);
+    }
+    if code != 204 {
+        response += &format!("Content-Length: {}\r\n", body.len());
+    }
+    response += &format!("Connection: {}\r\n\r\n", connection(request.keep_alive));
+    writer
+        .write_all(response.as_bytes())
+        .await
+        .map_err(internal)?;
+    writer.write_all(body).await.map_err(internal)
+}
+
+async fn write_status(
+    writer: &mut (impl AsyncWrite + Unpin),
+    status: &Status,
+    keep_alive: bool,
+) -> Result<()> {
+    let body = json!({ "error": status.message }).to_string();
+    let response = format!(
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: {}\r\n\r\n{}",
+        status.code,
+        reason(status.code),
+        body.len(),
+        connection(keep_alive),
+        body
+    );
+    writer.write_all(response.as_bytes()).await?;
+    Ok(writer.flush().await?)
+}
+
+async fn write_chunk(writer: &mut (impl AsyncWrite + Unpin), chunk: &[u8]) -> Result<()> {
+    if chunk.is_empty() {
+        writer.write_all(b"0\r\n\r\n").await?;
+        return Ok(());
+    }
+    writer
+        .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+        .await?;
+    writer.write_all(chunk).await?;
+    Ok(writer.write_all(b"\r\n").await?)
+}
+
+fn reason(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        501 => "Not Implemented",
+        _ => "Internal Server Error",
+    }
+}
+
+fn connection(keep_alive: bool) -> &'static str {
+    if keep_alive {
+        "keep-alive"
+    } else {
+        "close"
+    }
+}
+
+fn internal(err: impl ToString) -> Status {
+    Status::new(500, err.to_string())
+}
+
+/// Fails a response whose head is already written: the connection is closed instead of
+/// writing a second response into the body.
+fn interrupted(err: impl ToString) -> Status {
+    Status {
+        interrupted: true,
+        ..internal(err)
+    }
+}
+
+fn parse_query(query: &str) -> std::result::Result<Vec<QueryParam>, Status> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((
+                percent_decode(&name.replace('+', " "))?,
+                percent_decode(&value.replace('+', " "))?,
+            ))
+        })
+        .collect()
+}
+
+fn percent_decode(input: &str) -> std::result::Result<Vec<u8>, Status> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| Status::new(400, "invalid percent-encoding"))?;
+            decoded.push(hex);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%2Fb%00").ok(), Some(b"a/b\0".to_vec()));
+        assert!(percent_decode("%zz").is_err());
+        assert!(percent_decode("%2").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scan_failing_after_head_is_interrupted() {
+        let path = std::path::PathBuf::from("data").join("http_scan_interrupted");
+        std::fs::remove_dir_all(&path).ok();
+        let db = AsyncDatabase::open(&path).await.unwrap();
+        let request = Request {
+            method: "GET".to_string(),
+            path: "/scan".to_string(),
+            query: String::new(),
+            body: Vec::new(),
+            keep_alive: true,
+        };
+        // the reading end is gone, so writing the head fails
+        let (mut writer, _) = tokio::io::duplex(64);
+        let status = scan(&db, &request, &mut writer).await.unwrap_err();
+        assert!(status.interrupted);
+        let request = Request {
+            query: "limit=x".to_string(),
+            ..request
+        };
+        let status = scan(&db, &request, &mut writer).await.unwrap_err();
+        assert!(!status.interrupted && status.code == 400);
+    }
+}
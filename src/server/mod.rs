@@ -0,0 +1,6 @@
+pub mod binary;
+pub mod command;
+pub mod http;
+pub mod protocol;
+pub mod resp;
+pub mod server;
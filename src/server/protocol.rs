@@ -0,0 +1,556 @@
+use std::{io, ops::Bound};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    database::{changes::Change, entry::Entry},
+    sstable::data::Data,
+    Error, Result,
+};
+
+// Requests and responses are sent as frames. Length counts the bytes after it.
+// +-------------+-----------------+-----------+-----...-----+
+// | Length (4B) | Request Id (8B) | Kind (1B) | Payload     |
+// +-------------+-----------------+-----------+-----...-----+
+//
+// Responses carry the id of their request, so clients can pipeline requests and the entries
+// of several scans can be interleaved. Entries in payloads use the record encoding of
+// `Data::encode`:
+// +--------------+--------------+-----...-----+
+// | Count (4B)   | Record       | Record ...  |
+// +--------------+--------------+-----...-----+
+
+pub const MAX_FRAME_LEN: usize = 64 << 20;
+
+const HEADER_LEN: usize = 8 + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Payload is the key; answered by `Value` with one entry or by `NotFound`.
+    Get,
+    /// Payload is entries applied as one batch: values are puts, operands without a value
+    /// merges and tombstones deletes; answered by `Ok` once the batch is in the WAL, or by an
+    /// `InvalidArgument` error for any other entry. Entries with timestamp 0 get one from the
+    /// database's clock.
+    Write,
+    /// Payload is a `ScanRequest`; answered by `Entries` frames and a final `End`, or an
+    /// `Error` if reading the entries fails.
+    Scan,
+    /// Allows the scan with the frame's id to send the number of entries in the payload.
+    Credit,
+    /// Stops the scan with the frame's id.
+    Cancel,
+    /// Payload is a seqno (8B); answered by `Changes` frames with every write from it on,
+    /// for as long as the connection stays open. Ends with an `Error` if the writes are gone.
+    Follow,
+    /// Answered by `File` frames with the table files of a snapshot of the database and a
+    /// final `End` whose payload is the snapshot as an encoded `VersionEdit`.
+    Checkpoint,
+    Ok,
+    Value,
+    NotFound,
+    Entries,
+    End,
+    /// Payload is encoded by `encode_changes`; sent without changes as a heartbeat.
+    Changes,
+    /// Payload is a chunk of a file, see `encode_file_chunk`.
+    File,
+    /// Payload is an error code followed by the message.
+    Error,
+}
+
+impl Kind {
+    fn code(self) -> u8 {
+        match self {
+            Kind::Get => 1,
+            Kind::Write => 2,
+            Kind::Scan => 3,
+            Kind::Credit => 4,
+            Kind::Cancel => 5,
+            Kind::Follow => 6,
+            Kind::Checkpoint => 7,
+            Kind::Ok => 0x80,
+            Kind::Value => 0x81,
+            Kind::NotFound => 0x82,
+            Kind::Entries => 0x83,
+            Kind::End => 0x84,
+            Kind::Error => 0x85,
+            Kind::Changes => 0x86,
+            Kind::File => 0x87,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Kind> {
+        Some(match code {
+            1 => Kind::Get,
+            2 => Kind::Write,
+            3 => Kind::Scan,
+            4 => Kind::Credit,
+            5 => Kind::Cancel,
+            6 => Kind::Follow,
+            7 => Kind::Checkpoint,
+            0x80 => Kind::Ok,
+            0x81 => Kind::Value,
+            0x82 => Kind::NotFound,
+            0x83 => Kind::Entries,
+            0x84 => Kind::End,
+            0x85 => Kind::Error,
+            0x86 => Kind::Changes,
+            0x87 => Kind::File,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub id: u64,
+    pub kind: Kind,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(id: u64, kind: Kind, payload: Vec<u8>) -> Frame {
+        Frame { id, kind, payload }
+    }
+
+    /// Reads the next frame, or `None` if the stream ended between frames.
+    pub async fn read_from(reader: &mut (impl AsyncRead + Unpin)) -> Result<Option<Frame>> {
+        let mut len = [0; 4];
+        match reader.read_exact(&mut len).await {
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if !(HEADER_LEN..=MAX_FRAME_LEN).contains(&len) {
+            return Err(protocol_error("invalid frame length"));
+        }
+        // grows with the bytes that arrive rather than trusting the peer's length
+        let mut frame = Vec::new();
+        (&mut *reader)
+            .take(len as u64)
+            .read_to_end(&mut frame)
+            .await?;
+        if frame.len() != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let id = u64::from_le_bytes(frame[..8].try_into().unwrap());
+        let kind = Kind::from_code(frame[8]).ok_or_else(|| protocol_error("unknown frame kind"))?;
+        frame.drain(..HEADER_LEN);
+        Ok(Some(Frame::new(id, kind, frame)))
+    }
+
+    pub async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+        let len = HEADER_LEN + self.payload.len();
+        if len > MAX_FRAME_LEN {
+            return Err(Error::InvalidArgument("frame too large".to_string()));
+        }
+        writer.write_all(&(len as u32).to_le_bytes()).await?;
+        writer.write_all(&self.id.to_le_bytes()).await?;
+        writer.write_all(&[self.kind.code()]).await?;
+        Ok(writer.write_all(&self.payload).await?)
+    }
+}
+
+pub fn encode_entries<'a>(entries: impl ExactSizeIterator<Item = &'a Entry>) -> Vec<u8> {
+    let mut payload = (entries.len() as u32).to_le_bytes().to_vec();
+    for entry in entries {
+        Data::encode(&mut payload, entry).unwrap();
+    }
+    payload
+}
+
+pub fn decode_entries(mut payload: &[u8]) -> Result<Vec<Entry>> {
+    let count = read_u32(&mut payload)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        entries.push(
+            Data::read(&mut payload)
+                .ok()
+                .flatten()
+                .ok_or_else(|| protocol_error("truncated entry"))?,
+        );
+    }
+    if !payload.is_empty() {
+        return Err(protocol_error("trailing bytes after entries"));
+    }
+    Ok(entries)
+}
+
+// +-----------------+------------+------------+-------------------+--------------------+-----------+--------+-----
+// | Last Seqno (8B) | Count (4B) | Seqno (8B) | Batch Seqno (8B)  | Column Family (4B) | Kind (1B) | Record | ...
+// +-----------------+------------+------------+-------------------+--------------------+-----------+--------+-----
+// Last seqno is the leader's latest write when the frame was sent; it always ends a batch.
+// The record of a range delete, kind 1, holds its start as key and its end as value.
+const WRITE_CHANGE: u8 = 0;
+const RANGE_DELETE_CHANGE: u8 = 1;
+
+pub fn encode_changes(last_sequence: u64, changes: &[Change]) -> Vec<u8> {
+    let mut payload = last_sequence.to_le_bytes().to_vec();
+    payload.extend_from_slice(&(changes.len() as u32).to_le_bytes());
+    for change in changes {
+        payload.extend_from_slice(&change.seqno.to_le_bytes());
+        payload.extend_from_slice(&change.batch_seqno.to_le_bytes());
+        payload.extend_from_slice(&change.column_family.to_le_bytes());
+        let entry = match &change.range_end {
+            Some(end) => {
+                payload.push(RANGE_DELETE_CHANGE);
+                Entry {
+                    key: change.key.clone(),
+                    value: Some(end.clone()),
+                    timestamp: change.timestamp,
+                    deleted: false,
+                    single_delete: false,
+                    blob: None,
+                    expires_at: None,
+                    operands: Vec::new(),
+                }
+            }
+            None => {
+                payload.push(WRITE_CHANGE);
+                Entry {
+                    key: change.key.clone(),
+                    value: change.value.clone(),
+                    timestamp: change.timestamp,
+                    deleted: change.value.is_none() && change.operand.is_none(),
+                    single_delete: change.single_delete,
+                    blob: None,
+                    expires_at: change.expires_at,
+                    operands: change.operand.iter().cloned().collect(),
+                }
+            }
+        };
+        Data::encode(&mut payload, &entry).unwrap();
+    }
+    payload
+}
+
+pub fn decode_changes(mut payload: &[u8]) -> Result<(u64, Vec<Change>)> {
+    let last_sequence = read_u64(&mut payload)?;
+    let count = read_u32(&mut payload)?;
+    let mut changes = Vec::new();
+    for _ in 0..count {
+        let seqno = read_u64(&mut payload)?;
+        let batch_seqno = read_u64(&mut payload)?;
+        let column_family = read_u32(&mut payload)?;
+        let (&kind, rest) = payload
+            .split_first()
+            .ok_or_else(|| protocol_error("truncated change"))?;
+        payload = rest;
+        let entry = Data::read(&mut payload)
+            .ok()
+            .flatten()
+            .ok_or_else(|| protocol_error("truncated change"))?;
+        let change = match kind {
+            WRITE_CHANGE => Change {
+                seqno,
+                batch_seqno,
+                key: entry.key,
+                value: entry.value.filter(|_| !entry.deleted),
+                operand: entry.operands.into_iter().next(),
+                single_delete: entry.single_delete,
+                timestamp: entry.timestamp,
+                expires_at: entry.expires_at,
+                column_family,
+                range_end: None,
+            },
+            RANGE_DELETE_CHANGE => Change {
+                seqno,
+                batch_seqno,
+                key: entry.key,
+                value: None,
+                operand: None,
+                single_delete: false,
+                timestamp: entry.timestamp,
+                expires_at: None,
+                column_family,
+                range_end: Some(entry.value.unwrap_or_default()),
+            },
+            _ => return Err(protocol_error("unknown change kind")),
+        };
+        changes.push(change);
+    }
+    if !payload.is_empty() {
+        return Err(protocol_error("trailing bytes after changes"));
+    }
+    Ok((last_sequence, changes))
+}
+
+// +----------------+------...------+-----...-----+
+// | Name Size (2B) | Name          | Data        |
+// +----------------+------...------+-----...-----+
+// A file is sent as consecutive chunks, the first one possibly empty.
+pub fn encode_file_chunk(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut payload = (name.len() as u16).to_le_bytes().to_vec();
+    payload.extend_from_slice(name.as_bytes());
+    payload.extend_from_slice(data);
+    payload
+}
+
+pub fn decode_file_chunk(mut payload: &[u8]) -> Result<(String, &[u8])> {
+    let len = u16::from_le_bytes(take(&mut payload, 2)?.try_into().unwrap());
+    let name = String::from_utf8(take(&mut payload, len as usize)?.to_vec())
+        .map_err(|_| protocol_error("file name is not UTF-8"))?;
+    Ok((name, payload))
+}
+
+// +-------------+-------------+---------------+-------------+
+// | Start Bound | End Bound   | Limit (8B)    | Window (4B) |
+// +-------------+-------------+---------------+-------------+
+// A bound is a tag (0 unbounded, 1 included, 2 excluded) followed by the key size (8B) and
+// key unless unbounded. A limit of 0 means no limit; the window is the initial credit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanRequest {
+    pub start: Bound<Vec<u8>>,
+    pub end: Bound<Vec<u8>>,
+    pub limit: u64,
+    pub window: u32,
+}
+
+impl ScanRequest {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for bound in [&self.start, &self.end] {
+            let (tag, key) = match bound {
+                Bound::Unbounded => (0u8, None),
+                Bound::Included(key) => (1, Some(key)),
+                Bound::Excluded(key) => (2, Some(key)),
+            };
+            payload.push(tag);
+            if let Some(key) = key {
+                payload.extend_from_slice(&(key.len() as u64).to_le_bytes());
+                payload.extend_from_slice(key);
+            }
+        }
+        payload.extend_from_slice(&self.limit.to_le_bytes());
+        payload.extend_from_slice(&self.window.to_le_bytes());
+        payload
+    }
+
+    pub fn decode(mut payload: &[u8]) -> Result<ScanRequest> {
+        let start = read_bound(&mut payload)?;
+        let end = read_bound(&mut payload)?;
+        let limit = read_u64(&mut payload)?;
+        let window = read_u32(&mut payload)?;
+        if !payload.is_empty() {
+            return Err(protocol_error("trailing bytes after scan request"));
+        }
+        Ok(ScanRequest {
+            start,
+            end,
+            limit,
+            window,
+        })
+    }
+}
+
+pub fn encode_error(err: &Error) -> Vec<u8> {
+    let (code, message) = match err {
+        Error::Io(err) => (0u8, err.to_string()),
+        Error::Corruption(message) => (1, message.clone()),
+        Error::NotFound(message) => (2, message.clone()),
+        Error::InvalidArgument(message) => (3, message.clone()),
+        Error::Conflict(message) => (4, message.clone()),
+        Error::Busy(message) => (5, message.clone()),
+        Error::Gone(message) => (6, message.clone()),
+    };
+    let mut payload = vec![code];
+    payload.extend_from_slice(message.as_bytes());
+    payload
+}
+
+pub fn decode_error(payload: &[u8]) -> Error {
+    let Some((code, message)) = payload.split_first() else {
+        return protocol_error("empty error");
+    };
+    let message = String::from_utf8_lossy(message).into_owned();
+    match code {
+        0 => Error::Io(io::Error::other(message)),
+        1 => Error::Corruption(message),
+        2 => Error::NotFound(message),
+        4 => Error::Conflict(message),
+        5 => Error::Busy(message),
+        6 => Error::Gone(message),
+        _ => Error::InvalidArgument(message),
+    }
+}
+
+fn protocol_error(message: &str) -> Error {
+    Error::InvalidArgument(format!("Protocol error: {}", message))
+}
+
+fn take<'a>(payload: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if payload.len() < len {
+        return Err(protocol_error("truncated payload"));
+    }
+    let (bytes, rest) = payload.split_at(len);
+    *payload = rest;
+    Ok(bytes)
+}
+
+fn read_u32(payload: &mut &[u8]) -> Result<u32> {
+    Ok(u32::from_le_bytes(take(payload, 4)?.try_into().unwrap()))
+}
+
+fn read_u64(payload: &mut &[u8]) -> Result<u64> {
+    Ok(u64::from_le_bytes(take(payload, 8)?.try_into().unwrap()))
+}
+
+fn read_bound(payload: &mut &[u8]) -> Result<Bound<Vec<u8>>> {
+    let tag = take(payload, 1)?[0];
+    if tag == 0 {
+        return Ok(Bound::Unbounded);
+    }
+    let len = u64::from_le_bytes(take(payload, 8)?.try_into().unwrap());
+    let key = take(payload, len.try_into().unwrap_or(usize::MAX))?.to_vec();
+    match tag {
+        1 => Ok(Bound::Included(key)),
+        2 => Ok(Bound::Excluded(key)),
+        _ => Err(protocol_error("invalid bound")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &[u8], value: Option<&[u8]>, timestamp: u128) -> Entry {
+        Entry {
+            key: key.to_vec(),
+            value: value.map(|value| value.to_vec()),
+            timestamp,
+            deleted: value.is_none(),
+            single_delete: false,
+            blob: None,
+            expires_at: None,
+            operands: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frame_roundtrip() {
+        let frames = [
+            Frame::new(1, Kind::Get, b"key".to_vec()),
+            Frame::new(u64::MAX, Kind::End, Vec::new()),
+        ];
+        let mut buffer = Vec::new();
+        for frame in &frames {
+            frame.write_to(&mut buffer).await.unwrap();
+        }
+        let mut reader = buffer.as_slice();
+        for frame in frames {
+            assert_eq!(Frame::read_from(&mut reader).await.unwrap(), Some(frame));
+        }
+        assert_eq!(Frame::read_from(&mut reader).await.unwrap(), None);
+
+        let mut reader: &[u8] = &[0xff, 0xff, 0xff, 0xff];
+        assert!(Frame::read_from(&mut reader).await.is_err());
+        // a frame claiming more bytes than follow is cut short
+        let mut truncated = (MAX_FRAME_LEN as u32).to_le_bytes().to_vec();
+        truncated.extend_from_slice(&[0; HEADER_LEN]);
+        let mut reader = truncated.as_slice();
+        assert!(Frame::read_from(&mut reader).await.is_err());
+    }
+
+    #[test]
+    fn test_entries_roundtrip() {
+        let entries = [entry(b"a", Some(b"1"), 5), entry(b"\0b", None, 6)];
+        let decoded = decode_entries(&encode_entries(entries.iter())).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].value, Some(b"1".to_vec()));
+        assert!(decoded[1].deleted);
+        assert_eq!(decoded[1].key, b"\0b");
+        assert_eq!(decoded[1].timestamp, 6);
+
+        let mut truncated = encode_entries(entries.iter());
+        truncated.pop();
+        assert!(decode_entries(&truncated).is_err());
+        // a huge key size fails instead of allocating
+        let mut huge = 1u32.to_le_bytes().to_vec();
+        huge.extend_from_slice(&u64::MAX.to_le_bytes());
+        huge.push(1);
+        assert!(decode_entries(&huge).is_err());
+    }
+
+    #[test]
+    fn test_changes_and_file_chunk_roundtrip() {
+        let changes = vec![
+            Change {
+                seqno: 7,
+                batch_seqno: 6,
+                key: b"a".to_vec(),
+                value: None,
+                operand: None,
+                single_delete: true,
+                timestamp: 3,
+                expires_at: None,
+                column_family: 0,
+                range_end: None,
+            },
+            Change {
+                seqno: 8,
+                batch_seqno: 8,
+                key: b"a".to_vec(),
+                value: None,
+                operand: Some(b"+1".to_vec()),
+                single_delete: false,
+                timestamp: 4,
+                expires_at: None,
+                column_family: 0,
+                range_end: None,
+            },
+            Change {
+                seqno: 9,
+                batch_seqno: 9,
+                key: b"b".to_vec(),
+                value: Some(Vec::new()),
+                operand: None,
+                single_delete: false,
+                timestamp: 4,
+                expires_at: Some(5),
+                column_family: 2,
+                range_end: None,
+            },
+            Change {
+                seqno: 10,
+                batch_seqno: 10,
+                key: b"c".to_vec(),
+                value: None,
+                operand: None,
+                single_delete: false,
+                timestamp: 5,
+                expires_at: None,
+                column_family: 0,
+                range_end: Some(b"d".to_vec()),
+            },
+        ];
+        let decoded = decode_changes(&encode_changes(10, &changes)).unwrap();
+        assert_eq!(decoded, (10, changes));
+        assert!(decode_changes(&[0; 11]).is_err());
+
+        let payload = encode_file_chunk("000001.sst", b"data");
+        assert_eq!(
+            decode_file_chunk(&payload).unwrap(),
+            ("000001.sst".to_string(), &b"data"[..])
+        );
+    }
+
+    #[test]
+    fn test_scan_request_roundtrip() {
+        let request = ScanRequest {
+            start: Bound::Included(b"a".to_vec()),
+            end: Bound::Unbounded,
+            limit: 10,
+            window: 64,
+        };
+        assert_eq!(ScanRequest::decode(&request.encode()).unwrap(), request);
+        assert!(ScanRequest::decode(&[3]).is_err());
+    }
+
+    #[test]
+    fn test_error_roundtrip() {
+        let err = decode_error(&encode_error(&Error::Busy("locked".to_string())));
+        assert!(matches!(err, Error::Busy(message) if message == "locked"));
+    }
+}
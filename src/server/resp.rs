@@ -0,0 +1,235 @@
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{Error, Result};
+
+// same limits as redis
+const MAX_BULK_LEN: usize = 512 << 20;
+const MAX_ARRAY_LEN: usize = 1 << 20;
+const MAX_INLINE_LEN: usize = 64 << 10;
+
+/// A reply in the subset of RESP2 the server speaks.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Null,
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn ok() -> Value {
+        Value::Simple("OK".to_string())
+    }
+
+    pub fn error(message: impl Into<String>) -> Value {
+        Value::Error(message.into())
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Simple(s) => {
+                buf.push(b'+');
+                buf.extend_from_slice(s.as_bytes());
+            }
+            Value::Error(s) => {
+                buf.push(b'-');
+                buf.extend_from_slice(s.as_bytes());
+            }
+            Value::Integer(i) => buf.extend_from_slice(format!(":{}", i).as_bytes()),
+            Value::Bulk(bytes) => {
+                buf.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                buf.extend_from_slice(bytes);
+            }
+            Value::Null => buf.extend_from_slice(b"$-1"),
+            Value::Array(values) => {
+                buf.extend_from_slice(format!("*{}\r\n", values.len()).as_bytes());
+                for value in values {
+                    value.encode(buf);
+                }
+                return;
+            }
+        }
+        buf.extend_from_slice(b"\r\n");
+    }
+
+    pub async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        Ok(writer.write_all(&buf).await?)
+    }
+}
+
+/// Reads the next command, either a RESP array of bulk strings or an inline command as
+/// typed into telnet. Returns `None` once the client closed the connection.
+pub async fn read_command(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Result<Option<Vec<Vec<u8>>>> {
+    loop {
+        let Some(line) = read_line(reader).await? else {
+            return Ok(None);
+        };
+        let Some(len) = line.strip_prefix(b"*") else {
+            let args: Vec<Vec<u8>> = line
+                .split(|b| b.is_ascii_whitespace())
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| arg.to_vec())
+                .collect();
+            // blank lines are ignored like redis does
+            if args.is_empty() {
+                continue;
+            }
+            return Ok(Some(args));
+        };
+        let len = parse_len(len, MAX_ARRAY_LEN)?;
+        let mut args = Vec::with_capacity(len);
+        for _ in 0..len {
+            args.push(read_bulk(reader).await?);
+        }
+        if !args.is_empty() {
+            return Ok(Some(args));
+        }
+    }
+}
+
+/// Reads one reply, used by clients of the server.
+pub async fn read_value(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Value> {
+    let Some(line) = read_line(reader).await? else {
+        return Err(protocol_error("connection closed"));
+    };
+    let Some((&kind, rest)) = line.split_first() else {
+        return Err(protocol_error("empty reply"));
+    };
+    let text = || String::from_utf8_lossy(rest).into_owned();
+    Ok(match kind {
+        b'+' => Value::Simple(text()),
+        b'-' => Value::Error(text()),
+        b':' => Value::Integer(
+            text()
+                .parse()
+                .map_err(|_| protocol_error("invalid integer"))?,
+        ),
+        b'$' if rest == b"-1" => Value::Null,
+        b'$' => {
+            let len = parse_len(rest, MAX_BULK_LEN)?;
+            Value::Bulk(read_exact(reader, len).await?)
+        }
+        b'*' => {
+            let len = parse_len(rest, MAX_ARRAY_LEN)?;
+            let mut values = Vec::with_capacity(len);
+            for _ in 0..len {
+                values.push(Box::pin(read_value(reader)).await?);
+            }
+            Value::Array(values)
+        }
+        _ => return Err(protocol_error("unknown reply type")),
+    })
+}
+
+async fn read_bulk(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Vec<u8>> {
+    let Some(line) = read_line(reader).await? else {
+        return Err(protocol_error("connection closed inside a command"));
+    };
+    let Some(len) = line.strip_prefix(b"$") else {
+        return Err(protocol_error("expected '$'"));
+    };
+    let len = parse_len(len, MAX_BULK_LEN)?;
+    read_exact(reader, len).await
+}
+
+async fn read_exact(reader: &mut (impl AsyncBufRead + Unpin), len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; len + 2];
+    reader.read_exact(&mut bytes).await?;
+    if !bytes.ends_with(b"\r\n") {
+        return Err(protocol_error("bulk string is not terminated by CRLF"));
+    }
+    bytes.truncate(len);
+    Ok(bytes)
+}
+
+async fn read_line(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    // the line and its CRLF
+    let limit = MAX_INLINE_LEN as u64 + 2;
+    let len = (&mut *reader)
+        .take(limit)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if len == 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        if len as u64 == limit {
+            return Err(protocol_error("too big inline request"));
+        }
+        return Err(protocol_error("connection closed inside a command"));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse_len(bytes: &[u8], max: usize) -> Result<usize> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|len| len.parse().ok())
+        .filter(|len| *len <= max)
+        .ok_or_else(|| protocol_error("invalid length"))
+}
+
+fn protocol_error(message: &str) -> Error {
+    Error::InvalidArgument(format!("Protocol error: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_array_and_inline_commands() {
+        let mut input: &[u8] = b"*2\r\n$3\r\nGET\r\n$3\r\nk\r\n\r\n\r\nPING  hi\r\n";
+        let command = read_command(&mut input).await.unwrap().unwrap();
+        assert_eq!(command, vec![b"GET".to_vec(), b"k\r\n".to_vec()]);
+        let command = read_command(&mut input).await.unwrap().unwrap();
+        assert_eq!(command, vec![b"PING".to_vec(), b"hi".to_vec()]);
+        assert!(read_command(&mut input).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_long_line_is_rejected() {
+        let line = vec![b'a'; MAX_INLINE_LEN];
+        let mut input = [line.as_slice(), b"\r\n"].concat();
+        let command = read_command(&mut input.as_slice()).await.unwrap().unwrap();
+        assert_eq!(command, vec![line]);
+        input.insert(0, b'a');
+        assert!(matches!(
+            read_command(&mut input.as_slice()).await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reply_roundtrip() {
+        let value = Value::Array(vec![
+            Value::ok(),
+            Value::Integer(-3),
+            Value::Bulk(b"a\r\nb".to_vec()),
+            Value::Null,
+            Value::error("ERR nope"),
+        ]);
+        let mut buf = Vec::new();
+        value.encode(&mut buf);
+        assert_eq!(read_value(&mut buf.as_slice()).await.unwrap(), value);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_bulk_is_rejected() {
+        let mut input: &[u8] = b"*1\r\n$999999999999\r\n";
+        assert!(matches!(
+            read_command(&mut input).await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use tokio::{
+    io::{AsyncWriteExt, BufReader, BufWriter},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::watch,
+    task::JoinSet,
+};
+
+use crate::{database::async_database::AsyncDatabase, Error, Result};
+
+use super::{
+    command::{self, Cursors},
+    resp::{self, Value},
+};
+
+/// State shared by all connections of a server.
+pub struct Shared {
+    pub(super) db: AsyncDatabase,
+    pub(super) cursors: Mutex<Cursors>,
+    pub(super) started: Instant,
+    pub(super) connected_clients: AtomicUsize,
+    pub(super) total_connections: AtomicU64,
+    pub(super) total_commands: AtomicU64,
+}
+
+/// TCP server speaking the subset of the redis protocol understood by `command::execute`.
+pub struct Server {
+    listener: TcpListener,
+    shared: Arc<Shared>,
+}
+
+impl Server {
+    pub async fn bind(addr: impl ToSocketAddrs, db: AsyncDatabase) -> Result<Server> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Server {
+            listener,
+            shared: Arc::new(Shared {
+                db,
+                cursors: Mutex::new(Cursors::default()),
+                started: Instant::now(),
+                connected_clients: AtomicUsize::new(0),
+                total_connections: AtomicU64::new(0),
+                total_commands: AtomicU64::new(0),
+            }),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections until `shutdown` completes. Open connections finish the command
+    /// they are executing before the WAL is synced and the database released.
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let mut connections = JoinSet::new();
+        let (stop, stopped) = watch::channel(false);
+        let result = tokio::select! {
+            result = self.accept_loop(&mut connections, stopped) => result,
+            _ = shutdown => Ok(()),
+        };
+        stop.send(true).ok();
+        while connections.join_next().await.is_some() {}
+        self.shared.db.sync().await?;
+        result
+    }
+
+    pub async fn run(self) -> Result<()> {
+        self.run_until(std::future::pending()).await
+    }
+
+    async fn accept_loop(
+        &self,
+        connections: &mut JoinSet<()>,
+        stopped: watch::Receiver<bool>,
+    ) -> Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            while connections.try_join_next().is_some() {}
+            let shared = self.shared.clone();
+            let stopped = stopped.clone();
+            connections.spawn(async move {
+                shared.connected_clients.fetch_add(1, Ordering::Relaxed);
+                shared.total_connections.fetch_add(1, Ordering::Relaxed);
+                // errors only end this connection
+                handle_connection(&shared, stream, stopped).await.ok();
+                shared.connected_clients.fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+    }
+}
+
+async fn handle_connection(
+    shared: &Shared,
+    stream: TcpStream,
+    mut stopped: watch::Receiver<bool>,
+) -> Result<()> {
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let mut unsynced = false;
+    loop {
+        let command = tokio::select! {
+            command = resp::read_command(&mut reader) => command,
+            _ = stopped.wait_for(|stopped| *stopped) => break,
+        };
+        let args = match command {
+            Ok(Some(args)) => args,
+            Ok(None) => break,
+            Err(err) => {
+                let message = match &err {
+                    Error::InvalidArgument(message) => message.clone(),
+                    err => err.to_string(),
+                };
+                Value::error(format!("ERR {}", message))
+                    .write_to(&mut writer)
+                    .await?;
+                writer.flush().await?;
+                return Err(err);
+            }
+        };
+        shared.total_commands.fetch_add(1, Ordering::Relaxed);
+        let name = args[0].to_ascii_lowercase();
+        unsynced |= matches!(name.as_slice(), b"set" | b"mset" | b"del");
+        command::execute(shared, &args)
+            .await
+            .write_to(&mut writer)
+            .await?;
+        if name == b"quit" {
+            break;
+        }
+        // reply to a pipeline once all of it was executed, after its writes reached the WAL
+        if reader.buffer().is_empty() {
+            if unsynced {
+                shared.db.sync().await?;
+                unsynced = false;
+            }
+            writer.flush().await?;
+        }
+    }
+    if unsynced {
+        shared.db.sync().await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
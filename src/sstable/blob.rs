@@ -0,0 +1,250 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    checksum::crc32, database::entry::Entry, version::version_set::sync_dir, Error, Result,
+};
+
+use super::sstable::file_name;
+
+pub const BLOB_EXT: &str = "blob";
+
+// A blob file is a sequence of values:
+// +-----------------+-------------+-------+
+// | Value Size (8B) | CRC32 (4B)  | Value |
+// +-----------------+-------------+-------+
+
+const HEADER_LEN: u64 = 8 + 4;
+
+/// Where a value moved out of a table is: the blob file, the offset of its record and
+/// the length of the value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlobIndex {
+    pub file_number: u64,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl BlobIndex {
+    pub const ENCODED_LEN: usize = 24;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::ENCODED_LEN);
+        buf.extend_from_slice(&self.file_number.to_le_bytes());
+        buf.extend_from_slice(&self.offset.to_le_bytes());
+        buf.extend_from_slice(&self.len.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<BlobIndex> {
+        if buf.len() != Self::ENCODED_LEN {
+            return None;
+        }
+        let field = |i: usize| u64::from_le_bytes(buf[i * 8..i * 8 + 8].try_into().unwrap());
+        Some(BlobIndex {
+            file_number: field(0),
+            offset: field(1),
+            len: field(2),
+        })
+    }
+}
+
+/// The blob file of the table numbered `number`. It outlives the table as long as later
+/// tables point into it.
+pub fn blob_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(file_name(number, BLOB_EXT))
+}
+
+/// Appends the values a table moves out of its records.
+pub struct BlobWriter {
+    dir: PathBuf,
+    number: u64,
+    file: BufWriter<File>,
+    offset: u64,
+    count: u64,
+    bytes: u64,
+}
+
+impl BlobWriter {
+    pub fn new(dir: &Path, number: u64) -> Result<BlobWriter> {
+        let file = File::create(blob_path(dir, number))?;
+        Ok(BlobWriter {
+            dir: dir.to_owned(),
+            number,
+            file: BufWriter::new(file),
+            offset: 0,
+            count: 0,
+            bytes: 0,
+        })
+    }
+
+    pub fn add(&mut self, value: &[u8]) -> Result<BlobIndex> {
+        self.file.write_all(&(value.len() as u64).to_le_bytes())?;
+        self.file.write_all(&crc32(value).to_le_bytes())?;
+        self.file.write_all(value)?;
+        let index = BlobIndex {
+            file_number: self.number,
+            offset: self.offset,
+            len: value.len() as u64,
+        };
+        self.offset += HEADER_LEN + index.len;
+        self.count += 1;
+        self.bytes += index.len;
+        Ok(index)
+    }
+
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// Number of values written.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Length of the values written, without their headers.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Writes the values out and makes the file survive a crash, so an edit may name it.
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        sync_dir(&self.dir)
+    }
+}
+
+/// An open blob file.
+pub struct BlobFile {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl BlobFile {
+    pub fn open(path: &Path) -> Result<BlobFile> {
+        let file = match OpenOptions::new().read(true).open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::NotFound(format!("blob file {}", path.display())));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        Ok(BlobFile {
+            path: path.to_owned(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// The value at `index`, checked against its checksum.
+    pub fn read(&self, index: &BlobIndex) -> Result<Vec<u8>> {
+        let mut record = Vec::new();
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(index.offset))?;
+            (&mut *file)
+                .take(HEADER_LEN + index.len)
+                .read_to_end(&mut record)?;
+        }
+        match decode_record(&record) {
+            Some(value) if value.len() as u64 == index.len => Ok(value.to_vec()),
+            _ => Err(Error::Corruption(format!(
+                "blob at offset {} of {} is unreadable",
+                index.offset,
+                self.path.display()
+            ))),
+        }
+    }
+}
+
+fn decode_record(record: &[u8]) -> Option<&[u8]> {
+    let (len, rest) = record.split_at_checked(8)?;
+    let (crc, value) = rest.split_at_checked(4)?;
+    let len = u64::from_le_bytes(len.try_into().ok()?);
+    let crc = u32::from_le_bytes(crc.try_into().ok()?);
+    (value.len() as u64 == len && crc32(value) == crc).then_some(value)
+}
+
+/// Number and length of the values in the blob file at `path` up to the first record that
+/// is cut short or fails its checksum, and whether that record exists.
+pub fn scan_blob_file(path: &Path) -> Result<(u64, u64, bool)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let (mut count, mut bytes) = (0, 0);
+    loop {
+        let mut record = Vec::new();
+        (&mut reader).take(HEADER_LEN).read_to_end(&mut record)?;
+        if record.is_empty() {
+            return Ok((count, bytes, false));
+        }
+        if record.len() < HEADER_LEN as usize {
+            return Ok((count, bytes, true));
+        }
+        let len = u64::from_le_bytes(record[..8].try_into().unwrap());
+        (&mut reader).take(len).read_to_end(&mut record)?;
+        if decode_record(&record).is_none() {
+            return Ok((count, bytes, true));
+        }
+        count += 1;
+        bytes += len;
+    }
+}
+
+/// Open blob files by number, shared by the reads of a database.
+pub struct BlobCache {
+    dir: PathBuf,
+    files: Mutex<HashMap<u64, Arc<BlobFile>>>,
+}
+
+impl BlobCache {
+    pub fn new(dir: &Path) -> BlobCache {
+        BlobCache {
+            dir: dir.to_owned(),
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, number: u64) -> Result<Arc<BlobFile>> {
+        let mut files = self.files.lock().unwrap();
+        if let Some(file) = files.get(&number) {
+            return Ok(file.clone());
+        }
+        let file = Arc::new(BlobFile::open(&blob_path(&self.dir, number))?);
+        files.insert(number, file.clone());
+        Ok(file)
+    }
+
+    /// Closes a blob file that is no longer live. Readers still holding it keep it open.
+    pub fn evict(&self, number: u64) {
+        self.files.lock().unwrap().remove(&number);
+    }
+}
+
+/// What a table does with the values written to it, see `SSTable::set_blobs`.
+#[derive(Clone)]
+pub struct BlobOptions {
+    pub cache: Arc<BlobCache>,
+    /// Values of at least this many bytes go to the table's blob file; `None` keeps every
+    /// value in the table.
+    pub min_blob_size: Option<usize>,
+    /// Blob files being collected: their values are written again as if they were new.
+    pub relocate: HashSet<u64>,
+}
+
+/// Replaces the blob index of `entry` with the value it points to, looking the blob file
+/// up with `file`.
+pub(crate) fn load(entry: Entry, file: impl FnOnce(u64) -> Result<Arc<BlobFile>>) -> Result<Entry> {
+    let Some(index) = entry.blob else {
+        return Ok(entry);
+    };
+    let value = file(index.file_number)?.read(&index)?;
+    Ok(Entry {
+        value: Some(value),
+        blob: None,
+        ..entry
+    })
+}
@@ -0,0 +1,317 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::database::entry::Entry;
+
+use super::{filter::BloomFilter, index::IndexEntry};
+
+const DEFAULT_SHARDS: usize = 16;
+
+/// Offsets used to key the decoded index and filter of a table, which live in their own
+/// files and therefore have no offset inside the data file.
+pub const INDEX_BLOCK_OFFSET: u64 = u64::MAX;
+pub const FILTER_BLOCK_OFFSET: u64 = u64::MAX - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockKey {
+    /// Allocated by `BlockCache::new_reader_id`, so tables of different databases sharing
+    /// the cache never share blocks even if their file numbers are the same.
+    pub reader_id: u64,
+    pub offset: u64,
+}
+
+pub enum Block {
+    Data(Vec<Entry>),
+    Index(Vec<IndexEntry>),
+    Filter(BloomFilter),
+}
+
+impl Block {
+    pub fn size(&self) -> usize {
+        match self {
+            Block::Data(entries) => entries
+                .iter()
+                .map(|e| e.key.len() + e.value.as_ref().map_or(0, |v| v.len()) + 17)
+                .sum(),
+            Block::Index(entries) => entries.iter().map(|e| e.key.len() + 8).sum(),
+            Block::Filter(filter) => filter.size(),
+        }
+    }
+}
+
+struct Slot {
+    block: Arc<Block>,
+    size: usize,
+    tick: u64,
+    pinned: bool,
+}
+
+#[derive(Default)]
+struct Shard {
+    capacity: usize,
+    usage: usize,
+    tick: u64,
+    slots: HashMap<BlockKey, Slot>,
+    // least recently used unpinned blocks come first
+    lru: BTreeMap<u64, BlockKey>,
+    // offsets of the blocks of each reader, so a reader's blocks are erased without a scan
+    readers: HashMap<u64, HashSet<u64>>,
+}
+
+impl Shard {
+    fn get(&mut self, key: &BlockKey) -> Option<Arc<Block>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let slot = self.slots.get_mut(key)?;
+        if !slot.pinned {
+            self.lru.remove(&slot.tick);
+            self.lru.insert(tick, *key);
+        }
+        slot.tick = tick;
+        Some(slot.block.clone())
+    }
+
+    fn insert(&mut self, key: BlockKey, block: Arc<Block>, pinned: bool) {
+        self.remove(&key);
+        let size = block.size();
+        if !pinned {
+            while self.usage + size > self.capacity {
+                if !self.evict_one() {
+                    // everything left is pinned, the block does not fit
+                    return;
+                }
+            }
+        }
+        self.tick += 1;
+        if !pinned {
+            self.lru.insert(self.tick, key);
+        }
+        self.usage += size;
+        self.readers
+            .entry(key.reader_id)
+            .or_default()
+            .insert(key.offset);
+        self.slots.insert(
+            key,
+            Slot {
+                block,
+                size,
+                tick: self.tick,
+                pinned,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &BlockKey) {
+        if let Some(slot) = self.take(key) {
+            if !slot.pinned {
+                self.lru.remove(&slot.tick);
+            }
+        }
+    }
+
+    fn evict_one(&mut self) -> bool {
+        match self.lru.pop_first() {
+            Some((_, key)) => {
+                self.take(&key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the slot of `key` from everything but the LRU list.
+    fn take(&mut self, key: &BlockKey) -> Option<Slot> {
+        let slot = self.slots.remove(key)?;
+        self.usage -= slot.size;
+        if let Some(offsets) = self.readers.get_mut(&key.reader_id) {
+            offsets.remove(&key.offset);
+            if offsets.is_empty() {
+                self.readers.remove(&key.reader_id);
+            }
+        }
+        Some(slot)
+    }
+
+    fn erase_reader(&mut self, reader_id: u64) {
+        for offset in self.readers.remove(&reader_id).unwrap_or_default() {
+            self.remove(&BlockKey { reader_id, offset });
+        }
+    }
+}
+
+/// Sharded LRU cache of decoded sstable blocks, shared by all readers of a database.
+///
+/// Blocks are keyed by the id of the reader they belong to and their offset in the data
+/// file of its table. Pinned blocks (table indexes and filters) count towards the capacity
+/// but are never evicted.
+pub struct BlockCache {
+    shards: Vec<Mutex<Shard>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    next_reader_id: AtomicU64,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> BlockCache {
+        Self::with_shards(capacity, DEFAULT_SHARDS)
+    }
+
+    pub fn with_shards(capacity: usize, shards: usize) -> BlockCache {
+        let shards = shards.max(1);
+        let shards = (0..shards)
+            .map(|_| {
+                Mutex::new(Shard {
+                    capacity: capacity / shards,
+                    ..Default::default()
+                })
+            })
+            .collect();
+        BlockCache {
+            shards,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            next_reader_id: AtomicU64::new(1),
+        }
+    }
+
+    fn shard(&self, key: &BlockKey) -> &Mutex<Shard> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    pub fn get(&self, key: &BlockKey) -> Option<Arc<Block>> {
+        let block = self.shard(key).lock().unwrap().get(key);
+        match block {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        block
+    }
+
+    pub fn insert(&self, key: BlockKey, block: Arc<Block>) {
+        self.shard(&key).lock().unwrap().insert(key, block, false);
+    }
+
+    pub fn insert_pinned(&self, key: BlockKey, block: Arc<Block>) {
+        self.shard(&key).lock().unwrap().insert(key, block, true);
+    }
+
+    /// An id for the blocks of a table reader, unique within this cache.
+    pub fn new_reader_id(&self) -> u64 {
+        self.next_reader_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Drops every block (pinned or not) belonging to the given reader.
+    pub fn erase_reader(&self, reader_id: u64) {
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().erase_reader(reader_id);
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().capacity).sum()
+    }
+
+    pub fn usage(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().usage).sum()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_block(key: u8, value_len: usize) -> Arc<Block> {
+        Arc::new(Block::Data(vec![Entry {
+            key: vec![key],
+            value: Some(vec![0; value_len]),
+            timestamp: 1,
+            deleted: false,
+            single_delete: false,
+            blob: None,
+            expires_at: None,
+            operands: Vec::new(),
+        }]))
+    }
+
+    fn create_key(offset: u64) -> BlockKey {
+        BlockKey {
+            reader_id: 1,
+            offset,
+        }
+    }
+
+    #[test]
+    fn test_hits_and_misses_are_counted() {
+        let cache = BlockCache::new(1 << 20);
+        assert!(cache.get(&create_key(0)).is_none());
+        cache.insert(create_key(0), create_block(1, 10));
+        assert!(cache.get(&create_key(0)).is_some());
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.misses(), 1);
+    }
+
+    #[test]
+    fn test_least_recently_used_block_is_evicted() {
+        // a single shard with room for two blocks
+        let block_size = create_block(1, 100).size();
+        let cache = BlockCache::with_shards(block_size * 2, 1);
+        cache.insert(create_key(0), create_block(1, 100));
+        cache.insert(create_key(1), create_block(2, 100));
+        cache.get(&create_key(0));
+        cache.insert(create_key(2), create_block(3, 100));
+        assert!(cache.get(&create_key(0)).is_some());
+        assert!(cache.get(&create_key(1)).is_none());
+        assert!(cache.get(&create_key(2)).is_some());
+        assert!(cache.usage() <= cache.capacity());
+    }
+
+    #[test]
+    fn test_pinned_blocks_are_not_evicted() {
+        let block_size = create_block(1, 100).size();
+        let cache = BlockCache::with_shards(block_size * 2, 1);
+        cache.insert_pinned(create_key(0), create_block(1, 100));
+        for offset in 1..10 {
+            cache.insert(create_key(offset), create_block(2, 100));
+        }
+        assert!(cache.get(&create_key(0)).is_some());
+    }
+
+    #[test]
+    fn test_erase_reader_drops_its_blocks() {
+        let cache = BlockCache::new(1 << 20);
+        cache.insert_pinned(create_key(0), create_block(1, 10));
+        cache.insert(create_key(1), create_block(2, 10));
+        let other = BlockKey {
+            reader_id: 2,
+            offset: 0,
+        };
+        cache.insert(other, create_block(3, 10));
+        cache.erase_reader(1);
+        assert!(cache.get(&create_key(0)).is_none());
+        assert!(cache.get(&create_key(1)).is_none());
+        assert!(cache.get(&other).is_some());
+        cache.erase_reader(2);
+        assert_eq!(cache.usage(), 0);
+        assert!(cache
+            .shards
+            .iter()
+            .all(|s| s.lock().unwrap().readers.is_empty()));
+    }
+}
@@ -1,4 +1,6 @@
-use crate::database::entry::Entry;
+use crate::{database::entry::Entry, Error, Result};
+
+use super::blob::BlobIndex;
 use std::fs::OpenOptions;
 use std::io::{self, BufReader, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
@@ -6,134 +8,406 @@ use std::{fs::File, io::BufWriter, io::Read, io::Write};
 
 const USIZE_LEN: usize = std::mem::size_of::<usize>();
 
+// A compressed data file starts with this instead of a key length, which is never that
+// large, and holds one frame per block:
+// +----------------------+-...-------------+
+// | Compressed Size (4B) | Snappy Payload  |
+// +----------------------+-...-------------+
+const COMPRESSED_MAGIC: [u8; 8] = *b"rdbsnap\xff";
+const MAGIC_LEN: u64 = COMPRESSED_MAGIC.len() as u64;
+
+// bits of the flags byte that follows the key length
+pub const DELETED_FLAG: u8 = 1;
+pub const EXPIRES_FLAG: u8 = 2;
+// only in WAL records, tables belong to a single column family
+pub const COLUMN_FAMILY_FLAG: u8 = 4;
+// merge operands follow, see `Entry::operands`
+pub const MERGE_FLAG: u8 = 8;
+// neither a value nor a tombstone comes before the merge operands
+pub const NO_BASE_FLAG: u8 = 16;
+// only in WAL records, the key starts a range tombstone whose end follows
+pub const RANGE_DELETE_FLAG: u8 = 32;
+// the tombstone was written by `Database::single_delete`
+pub const SINGLE_DELETE_FLAG: u8 = 64;
+// the value is a `BlobIndex` pointing at the actual value
+pub const BLOB_FLAG: u8 = 128;
+
 pub struct Data {
     pub path: PathBuf,
     file: BufWriter<File>,
     offset: u64,
+    compressed: bool,
 }
 
-pub struct DataIterator {
+/// Reads the records of a data file as one stream, decompressing the blocks of a
+/// compressed file.
+pub struct DataReader {
     reader: BufReader<File>,
+    compressed: bool,
+    // offset in the file after the bytes read, and the block being read from if compressed
+    position: u64,
+    block_start: u64,
+    block: Vec<u8>,
+    block_position: usize,
 }
 
-impl DataIterator {
-    pub fn new(path: PathBuf, offset: u64) -> io::Result<DataIterator> {
-        let file = OpenOptions::new().read(true).open(path)?;
+impl DataReader {
+    /// Starts at `offset`, which has to be the start of a record, or of a block if the file
+    /// is compressed.
+    pub fn open(path: &Path, offset: u64) -> Result<DataReader> {
+        let mut file = OpenOptions::new().read(true).open(path)?;
+        let compressed = is_compressed(&mut file)?;
+        let position = match compressed {
+            true => offset.max(MAGIC_LEN),
+            false => offset,
+        };
         let mut reader = BufReader::new(file);
-        reader.seek(SeekFrom::Start(offset))?;
-        Ok(DataIterator { reader })
+        reader.seek(SeekFrom::Start(position))?;
+        Ok(DataReader {
+            reader,
+            compressed,
+            position,
+            block_start: position,
+            block: Vec::new(),
+            block_position: 0,
+        })
     }
 }
 
-impl Iterator for DataIterator {
-    type Item = Entry;
-
-    fn next(&mut self) -> Option<Entry> {
-        // reads the sequence of bytes corresponding to an SSTableEntry from the file
-        let mut len_buffer = [0; 8]; // key length is encoded in first 8 bytes
-        if self.reader.read_exact(&mut len_buffer).is_err() {
-            return None;
-        }
-        let key_len = usize::from_le_bytes(len_buffer); // turn bytes into usize to get key length
-        let mut bool_buffer = [0; 1];
-        // read next byte to get tombstone byte
-        if self.reader.read_exact(&mut bool_buffer).is_err() {
-            return None;
-        }
-        let deleted = bool_buffer[0] != 0;
-        let mut key = vec![0; key_len];
-        let mut value: Option<Vec<u8>> = None;
-        if deleted {
-            // if deleted, then value_len and value don't exist -> next bytes are key bytes
-            if self.reader.read_exact(&mut key).is_err() {
-                return None;
-            }
-        } else {
-            // read the next 8 bytes to get value length as bytes
-            if self.reader.read_exact(&mut len_buffer).is_err() {
-                return None;
-            }
-            let value_len = usize::from_le_bytes(len_buffer);
-            // read next key_len bytes to get key
-            if self.reader.read_exact(&mut key).is_err() {
-                return None;
-            }
-            let mut value_buf = vec![0; value_len];
-            // read next value_len bytes to get value
-            if self.reader.read_exact(&mut value_buf).is_err() {
-                return None;
-            }
-            value = Some(value_buf)
+impl Read for DataReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.compressed {
+            let read = self.reader.read(buf)?;
+            self.position += read as u64;
+            return Ok(read);
         }
-        let mut timestamp_buffer = [0; 16];
-        if self.reader.read_exact(&mut timestamp_buffer).is_err() {
-            return None;
+        if self.block_position == self.block.len() {
+            let Some((block, frame_len)) = read_frame(&mut self.reader)? else {
+                return Ok(0);
+            };
+            self.block = block;
+            self.block_position = 0;
+            self.block_start = self.position;
+            self.position += frame_len;
         }
-        let timestamp = u128::from_le_bytes(timestamp_buffer);
-        Some(Entry {
-            key,
-            value,
-            timestamp,
-            deleted,
+        let read = (&self.block[self.block_position..]).read(buf)?;
+        self.block_position += read;
+        Ok(read)
+    }
+}
+
+pub struct DataIterator {
+    reader: DataReader,
+    offset: u64,
+    end: u64,
+}
+
+impl DataIterator {
+    pub fn new(path: PathBuf, offset: u64) -> Result<DataIterator> {
+        let reader = DataReader::open(&path, offset)?;
+        Ok(DataIterator {
+            offset: reader.position,
+            end: reader.position,
+            reader,
         })
     }
+
+    /// Offset of the last record returned; in a compressed file the offset of its block.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Offset right after the last record returned; in a compressed file the end of its
+    /// block.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+}
+
+impl Iterator for DataIterator {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Result<Entry>> {
+        let start = self.reader.position;
+        let entry = match Data::read(&mut self.reader) {
+            Ok(entry) => entry?,
+            Err(err) => return Some(Err(err)),
+        };
+        self.offset = match self.reader.compressed {
+            true => self.reader.block_start,
+            false => start,
+        };
+        self.end = self.reader.position;
+        Some(Ok(entry))
+    }
+}
+
+/// Whether the data file starts with the header of a compressed file.
+pub fn is_compressed(file: &mut File) -> io::Result<bool> {
+    let mut magic = [0; MAGIC_LEN as usize];
+    let compressed = match file.read_exact(&mut magic) {
+        Ok(()) => magic == COMPRESSED_MAGIC,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(err) => return Err(err),
+    };
+    file.seek(SeekFrom::Start(0))?;
+    Ok(compressed)
+}
+
+/// Reads one frame of a compressed file and returns the block it holds with the length
+/// of the frame, `None` at the end of the file.
+fn read_frame(reader: &mut impl Read) -> io::Result<Option<(Vec<u8>, u64)>> {
+    let mut len_buffer = Vec::new();
+    reader.by_ref().take(4).read_to_end(&mut len_buffer)?;
+    if len_buffer.is_empty() {
+        return Ok(None);
+    }
+    let len_buffer: [u8; 4] = len_buffer
+        .try_into()
+        .map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+    let len = u32::from_le_bytes(len_buffer) as u64;
+    let mut payload = Vec::new();
+    reader.by_ref().take(len).read_to_end(&mut payload)?;
+    if payload.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let block = snap::raw::Decoder::new()
+        .decompress_vec(&payload)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(Some((block, 4 + len)))
+}
+
+/// The frame of a compressed file holding `block`.
+pub fn compress_block(block: &[u8]) -> Result<Vec<u8>> {
+    let payload = snap::raw::Encoder::new()
+        .compress_vec(block)
+        .map_err(|err| Error::InvalidArgument(format!("block cannot be compressed: {}", err)))?;
+    let mut frame = (payload.len() as u32).to_le_bytes().to_vec();
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// The block held by `frame`, a whole frame of a compressed file.
+pub fn decompress_block(mut frame: &[u8]) -> Result<Vec<u8>> {
+    match read_frame(&mut frame) {
+        Ok(Some((block, _))) if frame.is_empty() => Ok(block),
+        _ => Err(Error::Corruption("compressed block is damaged".to_string())),
+    }
 }
 
 impl Data {
-    pub fn new(path: &Path) -> io::Result<Data> {
+    pub fn new(path: &Path) -> Result<Data> {
         Self::from_path(path)
     }
 
-    pub fn from_path(path: &Path) -> io::Result<Data> {
-        let file = OpenOptions::new().append(true).create(true).open(&path)?;
-        let offset = file.metadata().unwrap().len();
+    pub fn from_path(path: &Path) -> Result<Data> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let offset = file.metadata()?.len();
+        let compressed = is_compressed(&mut file)?;
         let file = BufWriter::new(file);
         Ok(Data {
             path: path.to_owned(),
             file,
             offset,
+            compressed,
         })
     }
-    pub fn write(&mut self, entry: &Entry) -> io::Result<()> {
-        self.file.write_all(&entry.key.len().to_le_bytes())?;
-        self.file.write_all(&(entry.deleted as u8).to_le_bytes())?;
-        if let Some(val) = &entry.value {
-            self.file.write_all(&val.len().to_le_bytes())?;
-            self.file.write_all(&entry.key)?;
-            self.file.write_all(&val)?;
-        } else {
-            self.file.write_all(&entry.key)?;
+
+    /// Offset of a block that starts now. The first block decides whether the file is
+    /// compressed.
+    pub fn start_block(&mut self, compress: bool) -> Result<u64> {
+        if self.offset == 0 && compress {
+            self.file.write_all(&COMPRESSED_MAGIC)?;
+            self.offset = MAGIC_LEN;
+            self.compressed = true;
         }
-        self.file.write_all(&entry.timestamp.to_le_bytes())?;
+        Ok(self.offset)
+    }
+
+    /// Whether blocks are written as frames with `write_frame` rather than record by
+    /// record.
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    pub fn write_frame(&mut self, frame: &[u8]) -> Result<()> {
+        self.file.write_all(frame)?;
+        self.offset += frame.len() as u64;
+        Ok(())
+    }
+
+    pub fn write(&mut self, entry: &Entry) -> Result<()> {
+        Self::encode(&mut self.file, entry)?;
         self.offset += Self::size_of_entry(entry);
         Ok(())
     }
 
-    fn size_of_entry(entry: &Entry) -> u64 {
+    /// Writes the record encoding of `entry`, the inverse of `read`. The expiry time and
+    /// merge operands, if any, follow the timestamp.
+    pub fn encode(writer: &mut impl Write, entry: &Entry) -> std::io::Result<()> {
+        let mut flags = 0;
+        if entry.deleted {
+            flags |= DELETED_FLAG;
+        }
+        if entry.expires_at.is_some() {
+            flags |= EXPIRES_FLAG;
+        }
+        if !entry.operands.is_empty() {
+            flags |= MERGE_FLAG;
+        }
+        if entry.is_pending() {
+            flags |= NO_BASE_FLAG;
+        }
+        if entry.deleted && entry.single_delete {
+            flags |= SINGLE_DELETE_FLAG;
+        }
+        let blob = entry
+            .blob
+            .filter(|_| !entry.deleted)
+            .map(|blob| blob.encode());
+        if blob.is_some() {
+            flags |= BLOB_FLAG;
+        }
+        writer.write_all(&entry.key.len().to_le_bytes())?;
+        writer.write_all(&[flags])?;
+        if let Some(val) = blob.as_ref().or(entry.value.as_ref()) {
+            writer.write_all(&val.len().to_le_bytes())?;
+            writer.write_all(&entry.key)?;
+            writer.write_all(val)?;
+        } else {
+            writer.write_all(&entry.key)?;
+        }
+        writer.write_all(&entry.timestamp.to_le_bytes())?;
+        if let Some(expires_at) = entry.expires_at {
+            writer.write_all(&expires_at.to_le_bytes())?;
+        }
+        if !entry.operands.is_empty() {
+            write_operands(writer, &entry.operands)?;
+        }
+        Ok(())
+    }
+
+    pub fn size_of_entry(entry: &Entry) -> u64 {
         let key_size = entry.key.len() + USIZE_LEN;
-        let value_size = match &entry.value {
-            Some(val) => val.len() + USIZE_LEN,
-            None => 0,
+        let value_size = match (&entry.value, entry.blob) {
+            (_, Some(_)) if !entry.deleted => BlobIndex::ENCODED_LEN + USIZE_LEN,
+            (Some(val), _) => val.len() + USIZE_LEN,
+            (None, _) => 0,
         };
         let deleted_size = std::mem::size_of::<bool>();
         let timestamp_size = std::mem::size_of::<u128>();
-        (key_size + value_size + deleted_size + timestamp_size)
+        let expires_size = match entry.expires_at {
+            Some(_) => std::mem::size_of::<u128>(),
+            None => 0,
+        };
+        let operands_size = match entry.operands.len() {
+            0 => 0,
+            _ => operands_size(&entry.operands),
+        };
+        (key_size + value_size + deleted_size + timestamp_size + expires_size + operands_size)
+            as u64
+    }
+
+    /// Reads the next record, `None` if `reader` ends right before it. A record that is
+    /// cut short or cannot be decoded is `Error::Corruption`.
+    pub fn read(reader: &mut impl Read) -> Result<Option<Entry>> {
+        // key length is encoded in first 8 bytes
+        let mut len_buffer = Vec::new();
+        reader
+            .by_ref()
+            .take(USIZE_LEN as u64)
+            .read_to_end(&mut len_buffer)
+            .map_err(record_error)?;
+        if len_buffer.is_empty() {
+            return Ok(None);
+        }
+        let len_buffer: [u8; USIZE_LEN] = len_buffer
             .try_into()
-            .unwrap()
+            .map_err(|_| Error::Corruption("record is truncated".to_string()))?;
+        let key_len = usize::from_le_bytes(len_buffer);
+        Self::read_record(reader, key_len)
+            .map(Some)
+            .map_err(record_error)
+    }
+
+    fn read_record(reader: &mut impl Read, key_len: usize) -> io::Result<Entry> {
+        let mut len_buffer = [0; USIZE_LEN];
+        let mut flags_buffer = [0; 1];
+        // read next byte to get the tombstone and expiry flags
+        reader.read_exact(&mut flags_buffer)?;
+        let deleted = flags_buffer[0] & DELETED_FLAG != 0;
+        let key;
+        let mut value: Option<Vec<u8>> = None;
+        if deleted || flags_buffer[0] & NO_BASE_FLAG != 0 {
+            // if deleted, then value_len and value don't exist -> next bytes are key bytes
+            key = read_bytes(reader, key_len)?;
+        } else {
+            // read the next 8 bytes to get value length as bytes
+            reader.read_exact(&mut len_buffer)?;
+            let value_len = usize::from_le_bytes(len_buffer);
+            // read next key_len bytes to get key
+            key = read_bytes(reader, key_len)?;
+            // read next value_len bytes to get value
+            value = Some(read_bytes(reader, value_len)?)
+        }
+        let mut timestamp_buffer = [0; 16];
+        reader.read_exact(&mut timestamp_buffer)?;
+        let timestamp = u128::from_le_bytes(timestamp_buffer);
+        let mut expires_at = None;
+        if flags_buffer[0] & EXPIRES_FLAG != 0 {
+            reader.read_exact(&mut timestamp_buffer)?;
+            expires_at = Some(u128::from_le_bytes(timestamp_buffer));
+        }
+        let mut operands = Vec::new();
+        if flags_buffer[0] & MERGE_FLAG != 0 {
+            operands = read_operands(reader)?;
+        }
+        let mut blob = None;
+        if value.is_some() && flags_buffer[0] & BLOB_FLAG != 0 {
+            let index = value.take().as_deref().and_then(BlobIndex::decode);
+            blob = Some(index.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "blob index is damaged")
+            })?);
+        }
+        Ok(Entry {
+            key,
+            value,
+            timestamp,
+            deleted,
+            single_delete: deleted && flags_buffer[0] & SINGLE_DELETE_FLAG != 0,
+            blob,
+            expires_at,
+            operands,
+        })
+    }
+
+    /// Decodes all records of a block that was read into memory.
+    pub fn decode_block(mut block: &[u8]) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        while let Some(entry) = Self::read(&mut block)? {
+            entries.push(entry);
+        }
+        Ok(entries)
     }
 
     pub fn get_offset(&self) -> u64 {
         self.offset
     }
 
-    pub fn flush(&mut self) -> io::Result<()> {
-        self.file.flush()
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.file.flush()?)
     }
 
-    pub fn get(&self, key: &[u8]) -> io::Result<Option<Entry>> {
+    #[allow(dead_code)]
+    pub fn get(&self, key: &[u8]) -> Result<Option<Entry>> {
         // simply go through entire sstable
         let iterator = DataIterator::new(self.path.clone(), 0)?;
         for entry in iterator {
+            let entry = entry?;
             if entry.key.as_slice() == key {
                 return Ok(Some(entry));
             }
@@ -142,56 +416,153 @@ impl Data {
     }
 }
 
+// +------------+------------------+---------+-----
+// | Count (4B) | Operand Size (8B) | Operand | ...
+// +------------+------------------+---------+-----
+pub(crate) fn write_operands(writer: &mut impl Write, operands: &[Vec<u8>]) -> std::io::Result<()> {
+    writer.write_all(&(operands.len() as u32).to_le_bytes())?;
+    for operand in operands {
+        writer.write_all(&operand.len().to_le_bytes())?;
+        writer.write_all(operand)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn operands_size(operands: &[Vec<u8>]) -> usize {
+    4 + operands.iter().map(|o| USIZE_LEN + o.len()).sum::<usize>()
+}
+
+pub(crate) fn read_operands(reader: &mut impl Read) -> io::Result<Vec<Vec<u8>>> {
+    let mut count_buffer = [0; 4];
+    reader.read_exact(&mut count_buffer)?;
+    let mut operands = Vec::new();
+    for _ in 0..u32::from_le_bytes(count_buffer) {
+        let mut len_buffer = [0; USIZE_LEN];
+        reader.read_exact(&mut len_buffer)?;
+        operands.push(read_bytes(reader, usize::from_le_bytes(len_buffer))?);
+    }
+    Ok(operands)
+}
+
+/// Reads exactly `len` bytes without trusting `len` for the allocation, since a corrupt
+/// length would otherwise allocate before the read fails.
+fn read_bytes(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    match bytes.len() == len {
+        true => Ok(bytes),
+        false => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+/// A record that ends early or fails to decode is corrupt; other failures are IO errors.
+pub(crate) fn record_error(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Corruption("record is truncated".to_string()),
+        io::ErrorKind::InvalidData => Error::Corruption(format!("record is damaged: {}", err)),
+        _ => Error::Io(err),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::{SystemTime, UNIX_EPOCH};
+    use crate::sstable::test_util::{create_entry, create_path};
 
     #[test]
     fn test_get_entry_from_data() {
-        let mut data = create_data().unwrap();
-        let entry = create_entry();
+        let mut data = create_data("data_get_entry").unwrap();
+        let entry = create_entry(&[1, 2, 3], Some(&[9]), 1);
         data.write(&entry).unwrap();
         data.flush().unwrap();
-        let return_value = data.get(&entry.key.as_slice()).unwrap();
+        let return_value = data.get(entry.key.as_slice()).unwrap();
         assert!(return_value.is_some());
     }
 
-    fn create_entry() -> Entry {
-        Entry {
-            key: vec![1, 2, 3],
-            value: Some(vec![9]),
-            timestamp: 1,
-            deleted: false,
-        }
-    }
-
-    fn create_path() -> PathBuf {
-        PathBuf::from("data")
-    }
-
-    fn create_timestamp() -> u128 {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
-        timestamp
-    }
-
-    fn create_data() -> io::Result<Data> {
-        let path = create_path();
-        let timestamp = create_timestamp();
-        Data::new(&path.join(timestamp.to_string() + ".data.sstable"))
+    fn create_data(name: &str) -> Result<Data> {
+        Data::new(&create_path(name).join("000001.data.sst"))
     }
 
     #[test]
     fn test_size_of_entry() {
-        let mut data = create_data().unwrap();
-        let entry = create_entry();
+        let mut data = create_data("data_entry_size").unwrap();
+        let entry = create_entry(&[1, 2, 3], Some(&[9]), 1);
         data.write(&entry).unwrap();
         let offset = data.get_offset();
         let entry_size: u64 = (USIZE_LEN * 2 + 16 + 1 + 3 + 1).try_into().unwrap();
         assert_ne!(offset, 0);
         assert_eq!(offset, entry_size);
     }
+
+    #[test]
+    fn test_expiry_roundtrip() {
+        let mut entry = create_entry(&[1, 2, 3], Some(&[9]), 1);
+        entry.expires_at = Some(7);
+        let mut record = Vec::new();
+        Data::encode(&mut record, &entry).unwrap();
+        assert_eq!(record.len() as u64, Data::size_of_entry(&entry));
+        let decoded = Data::read(&mut record.as_slice()).unwrap().unwrap();
+        assert_eq!((decoded.expires_at, decoded.deleted), (Some(7), false));
+        // records written before expiry existed still decode
+        record.truncate(record.len() - 16);
+        record[USIZE_LEN] = 0;
+        assert_eq!(
+            Data::read(&mut record.as_slice())
+                .unwrap()
+                .unwrap()
+                .expires_at,
+            None
+        );
+    }
+
+    #[test]
+    fn test_operands_roundtrip() {
+        let mut entry = create_entry(&[1, 2, 3], Some(&[9]), 1);
+        entry.operands = vec![b"a".to_vec(), Vec::new()];
+        let mut pending = entry.clone();
+        pending.value = None;
+        for entry in [entry, pending] {
+            let mut record = Vec::new();
+            Data::encode(&mut record, &entry).unwrap();
+            assert_eq!(record.len() as u64, Data::size_of_entry(&entry));
+            let decoded = Data::read(&mut record.as_slice()).unwrap().unwrap();
+            assert_eq!(decoded.value, entry.value);
+            assert_eq!(decoded.operands, entry.operands);
+            assert_eq!(decoded.is_pending(), entry.is_pending());
+        }
+    }
+
+    #[test]
+    fn test_blob_index_roundtrip() {
+        let mut entry = create_entry(&[1, 2, 3], Some(&[9]), 1);
+        entry.value = None;
+        entry.blob = Some(BlobIndex {
+            file_number: 7,
+            offset: 12,
+            len: 1 << 20,
+        });
+        entry.operands = vec![b"a".to_vec()];
+        let mut record = Vec::new();
+        Data::encode(&mut record, &entry).unwrap();
+        assert_eq!(record.len() as u64, Data::size_of_entry(&entry));
+        let decoded = Data::read(&mut record.as_slice()).unwrap().unwrap();
+        assert_eq!((&decoded.value, decoded.blob), (&None, entry.blob));
+        assert!(!decoded.is_pending());
+    }
+
+    #[test]
+    fn test_partial_record_is_corruption() {
+        let mut block = Vec::new();
+        Data::encode(&mut block, &create_entry(&[1], Some(&[9]), 1)).unwrap();
+        assert_eq!(Data::decode_block(&block).unwrap().len(), 1);
+        for len in [1, block.len() - 1] {
+            let result = Data::decode_block(&block[..len]);
+            assert!(matches!(result, Err(Error::Corruption(_))));
+        }
+        block.push(0);
+        assert!(matches!(
+            Data::decode_block(&block),
+            Err(Error::Corruption(_))
+        ));
+    }
 }
@@ -0,0 +1,366 @@
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    checksum::crc32,
+    database::{comparator::Comparator, entry::Entry, range_tombstone::RangeTombstone},
+    Error, Result,
+};
+
+use super::{
+    data::DataIterator,
+    filter::BloomFilter,
+    index::{IndexEntry, IndexIterator},
+    sstable::{
+        data_path, filter_path, index_path, range_path, read_checksums, read_range_tombstones,
+        SSTable,
+    },
+};
+
+/// One data block as delimited by the index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockInfo {
+    pub offset: u64,
+    pub len: u64,
+    pub entries: usize,
+    /// Whether the stored checksum matches, `None` if the block has no checksum.
+    pub checksum_ok: Option<bool>,
+}
+
+/// What `SSTable::inspect` found in a table.
+#[derive(Debug, Default)]
+pub struct TableReport {
+    pub path: PathBuf,
+    pub index: Vec<IndexEntry>,
+    pub blocks: Vec<BlockInfo>,
+    pub smallest: Option<Vec<u8>>,
+    pub largest: Option<Vec<u8>>,
+    pub entries: usize,
+    pub tombstones: usize,
+    pub range_tombstones: Vec<RangeTombstone>,
+    pub key_bytes: u64,
+    pub value_bytes: u64,
+    pub data_bytes: u64,
+    pub index_bytes: u64,
+    pub filter_bytes: u64,
+    /// Everything that is inconsistent; empty for a healthy table.
+    pub problems: Vec<String>,
+}
+
+impl TableReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl SSTable {
+    /// Reads every file of the table at `path` without modifying it, handing each data
+    /// record with its offset to `on_record`, and checks that keys are ordered by
+    /// `comparator`, that index
+    /// entries point at the first record of their block, that block checksums match and that
+    /// the filter knows every key. The key range includes the range tombstones.
+    pub fn inspect(
+        path: &Path,
+        comparator: &dyn Comparator,
+        mut on_record: impl FnMut(u64, &Entry),
+    ) -> Result<TableReport> {
+        let data_path = data_path(path);
+        if !data_path.exists() {
+            return Err(Error::NotFound(format!("sstable {}", path.display())));
+        }
+        let mut report = TableReport {
+            path: path.to_owned(),
+            data_bytes: fs::metadata(&data_path)?.len(),
+            ..Default::default()
+        };
+
+        match IndexIterator::new(index_path(path)) {
+            Ok(index) => report.index = index.collect(),
+            Err(_) => report.problems.push("index file is missing".to_string()),
+        }
+        report.index_bytes = fs::metadata(index_path(path)).map_or(0, |m| m.len());
+        let filter = match BloomFilter::read_from(&filter_path(path)) {
+            Ok(filter) => Some(filter),
+            Err(err) => {
+                report.problems.push(format!("filter is unusable: {}", err));
+                None
+            }
+        };
+        report.filter_bytes = fs::metadata(filter_path(path)).map_or(0, |m| m.len());
+        for pair in report.index.windows(2) {
+            if comparator.compare(&pair[0].key, &pair[1].key) != Ordering::Less
+                || pair[0].offset >= pair[1].offset
+            {
+                report.problems.push(format!(
+                    "index entries at offsets {} and {} are out of order",
+                    pair[0].offset, pair[1].offset
+                ));
+            }
+        }
+        report.blocks = report
+            .index
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let end = report
+                    .index
+                    .get(i + 1)
+                    .map_or(report.data_bytes, |next| next.offset);
+                BlockInfo {
+                    offset: entry.offset,
+                    len: end.saturating_sub(entry.offset),
+                    entries: 0,
+                    checksum_ok: None,
+                }
+            })
+            .collect();
+
+        inspect_records(
+            &data_path,
+            filter.as_ref(),
+            comparator,
+            &mut report,
+            &mut on_record,
+        )?;
+        inspect_checksums(path, &data_path, &mut report)?;
+        inspect_range_tombstones(path, comparator, &mut report);
+        Ok(report)
+    }
+}
+
+fn inspect_range_tombstones(path: &Path, comparator: &dyn Comparator, report: &mut TableReport) {
+    match read_range_tombstones(&range_path(path)) {
+        Ok(tombstones) => report.range_tombstones = tombstones,
+        Err(err) => report.problems.push(err.to_string()),
+    }
+    for tombstone in report.range_tombstones.iter() {
+        if comparator.compare(&tombstone.start, &tombstone.end) != Ordering::Less {
+            report.problems.push(format!(
+                "range tombstone from {:?} to {:?} is empty",
+                tombstone.start, tombstone.end
+            ));
+        }
+        if report
+            .smallest
+            .as_ref()
+            .is_none_or(|s| comparator.compare(&tombstone.start, s) == Ordering::Less)
+        {
+            report.smallest = Some(tombstone.start.clone());
+        }
+        if report
+            .largest
+            .as_ref()
+            .is_none_or(|l| comparator.compare(&tombstone.end, l) == Ordering::Greater)
+        {
+            report.largest = Some(tombstone.end.clone());
+        }
+    }
+}
+
+fn inspect_records(
+    data_path: &Path,
+    filter: Option<&BloomFilter>,
+    comparator: &dyn Comparator,
+    report: &mut TableReport,
+    on_record: &mut impl FnMut(u64, &Entry),
+) -> Result<()> {
+    let mut records = DataIterator::new(data_path.to_owned(), 0)?;
+    let mut index = report.index.iter().peekable();
+    let mut block = None;
+    let mut previous: Option<Vec<u8>> = None;
+    while let Some(entry) = records.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // reported below as bytes that are not a complete record
+            Err(Error::Corruption(_)) => break,
+            Err(err) => return Err(err),
+        };
+        let offset = records.offset();
+        on_record(offset, &entry);
+        // index entries must name the first record of each block
+        while let Some(index_entry) = index.next_if(|e| e.offset <= offset) {
+            if index_entry.offset != offset || index_entry.key != entry.key {
+                report.problems.push(format!(
+                    "index entry for offset {} does not match the record there",
+                    index_entry.offset
+                ));
+            }
+            block = Some(block.map_or(0, |b| b + 1));
+        }
+        match block.and_then(|b| report.blocks.get_mut(b)) {
+            Some(block) => block.entries += 1,
+            None if !report.index.is_empty() => report.problems.push(format!(
+                "record at offset {} is before the first block",
+                offset
+            )),
+            None => (),
+        }
+        if previous
+            .as_ref()
+            .is_some_and(|p| comparator.compare(p, &entry.key) != Ordering::Less)
+        {
+            report.problems.push(format!(
+                "key at offset {} is not greater than the previous key",
+                offset
+            ));
+        }
+        if filter.is_some_and(|f| !f.may_contain(&entry.key)) {
+            report
+                .problems
+                .push(format!("filter does not contain key at offset {}", offset));
+        }
+        report.entries += 1;
+        report.tombstones += entry.is_tombstone() as usize;
+        report.key_bytes += entry.key.len() as u64;
+        report.value_bytes += entry.value.as_ref().map_or(0, |v| v.len() as u64);
+        if report.smallest.is_none() {
+            report.smallest = Some(entry.key.clone());
+        }
+        previous = Some(entry.key);
+    }
+    report.largest = previous;
+    let offset = records.end();
+    if offset < report.data_bytes {
+        report.problems.push(format!(
+            "{} bytes at offset {} are not a complete record",
+            report.data_bytes - offset,
+            offset
+        ));
+    }
+    for index_entry in index {
+        report.problems.push(format!(
+            "index entry for offset {} points past the last record",
+            index_entry.offset
+        ));
+    }
+    Ok(())
+}
+
+fn inspect_checksums(path: &Path, data_path: &Path, report: &mut TableReport) -> Result<()> {
+    let checksums = match read_checksums(path) {
+        Ok(checksums) => checksums,
+        Err(err) => {
+            report.problems.push(err.to_string());
+            return Ok(());
+        }
+    };
+    let mut data = File::open(data_path)?;
+    for checksum in checksums {
+        let mut bytes = Vec::new();
+        data.seek(SeekFrom::Start(checksum.offset))?;
+        (&mut data).take(checksum.len).read_to_end(&mut bytes)?;
+        let ok = bytes.len() as u64 == checksum.len && crc32(&bytes) == checksum.crc;
+        if !ok {
+            report.problems.push(format!(
+                "checksum mismatch in block at offset {}",
+                checksum.offset
+            ));
+        }
+        match report
+            .blocks
+            .iter_mut()
+            .find(|b| b.offset == checksum.offset && b.len == checksum.len)
+        {
+            Some(block) => block.checksum_ok = Some(ok),
+            None => report.problems.push(format!(
+                "checksum for offset {} does not match a block of the index",
+                checksum.offset
+            )),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write};
+
+    use super::*;
+    use crate::{
+        database::{comparator::BytewiseComparator, options::Compression},
+        sstable::test_util::create_path,
+    };
+
+    fn create_table(name: &str) -> SSTable {
+        create_table_with(name, Compression::None)
+    }
+
+    fn create_table_with(name: &str, compression: Compression) -> SSTable {
+        let mut sstable = SSTable::new(&create_path(name), 1).unwrap();
+        sstable.set_compression(compression);
+        for i in 0..3000u32 {
+            sstable
+                .write(&Entry {
+                    key: i.to_be_bytes().to_vec(),
+                    value: (i % 10 != 0).then(|| vec![0; 64]),
+                    timestamp: i as u128,
+                    deleted: i % 10 == 0,
+                    single_delete: false,
+                    blob: None,
+                    expires_at: None,
+                    operands: Vec::new(),
+                })
+                .unwrap();
+        }
+        sstable.flush().unwrap();
+        sstable
+    }
+
+    #[test]
+    fn test_inspect_healthy_table() {
+        let sstable = create_table("dump_healthy");
+        let mut offsets = Vec::new();
+        let report = SSTable::inspect(&sstable.path, &BytewiseComparator, |offset, _| {
+            offsets.push(offset)
+        })
+        .unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.entries, 3000);
+        assert_eq!(report.tombstones, 300);
+        assert_eq!(offsets.len(), 3000);
+        assert!(report.blocks.len() > 1);
+        assert!(report.blocks.iter().all(|b| b.checksum_ok == Some(true)));
+        assert_eq!(report.blocks.iter().map(|b| b.entries).sum::<usize>(), 3000);
+        assert_eq!(report.smallest, Some(0u32.to_be_bytes().to_vec()));
+        assert_eq!(report.largest, Some(2999u32.to_be_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_inspect_compressed_table() {
+        let sstable = create_table_with("dump_compressed", Compression::Snappy);
+        let report = SSTable::inspect(&sstable.path, &BytewiseComparator, |_, _| ()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.entries, 3000);
+        assert!(report.blocks.len() > 1);
+        assert!(report.blocks.iter().all(|b| b.checksum_ok == Some(true)));
+        assert_eq!(report.blocks.iter().map(|b| b.entries).sum::<usize>(), 3000);
+        assert!(report.data_bytes < report.value_bytes);
+    }
+
+    #[test]
+    fn test_inspect_finds_corruption() {
+        let sstable = create_table("dump_corrupt");
+        let path = data_path(&sstable.path);
+        let mut data = fs::read(&path).unwrap();
+        // flip a byte of a value in the second block, then append half a record
+        let offset = SSTable::inspect(&sstable.path, &BytewiseComparator, |_, _| ())
+            .unwrap()
+            .blocks[1]
+            .offset;
+        data[offset as usize + 40] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[1, 0, 0]).unwrap();
+
+        let report = SSTable::inspect(&sstable.path, &BytewiseComparator, |_, _| ()).unwrap();
+        assert_eq!(report.blocks[1].checksum_ok, Some(false));
+        assert!(report
+            .problems
+            .iter()
+            .any(|p| p.contains("not a complete record")));
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::{Error, Result};
+
+pub const DEFAULT_BITS_PER_KEY: usize = 10;
+
+/// Bloom filter over the keys of one sstable, used to skip tables that cannot contain a key.
+pub struct BloomFilter {
+    hashes: u8,
+    bits: Vec<u8>,
+}
+
+impl BloomFilter {
+    pub fn from_keys(key_hashes: &[u64], bits_per_key: usize) -> BloomFilter {
+        let bit_count = (key_hashes.len() * bits_per_key).max(64);
+        // ln(2) * bits per key minimises the false positive rate
+        let hashes = ((bits_per_key as f64 * 0.69) as u8).clamp(1, 30);
+        let mut filter = BloomFilter {
+            hashes,
+            bits: vec![0; bit_count.div_ceil(8)],
+        };
+        for hash in key_hashes {
+            for bit in filter.bit_positions(*hash) {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        filter
+    }
+
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bit_positions(hash(key))
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    fn bit_positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let bit_count = (self.bits.len() * 8) as u64;
+        let delta = hash.rotate_right(17) | 1;
+        (0..self.hashes as u64)
+            .map(move |i| (hash.wrapping_add(i.wrapping_mul(delta)) % bit_count) as usize)
+    }
+
+    pub fn size(&self) -> usize {
+        self.bits.len() + 1
+    }
+
+    pub fn write_to(&self, path: &Path) -> Result<()> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut file = BufWriter::new(file);
+        file.write_all(&[self.hashes])?;
+        file.write_all(&self.bits)?;
+        Ok(file.flush()?)
+    }
+
+    pub fn read_from(path: &Path) -> Result<BloomFilter> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        match bytes.split_first() {
+            Some((&hashes, bits)) if !bits.is_empty() => Ok(BloomFilter {
+                hashes,
+                bits: bits.to_vec(),
+            }),
+            _ => Err(Error::Corruption(format!(
+                "filter file {} is truncated",
+                path.display()
+            ))),
+        }
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds since filters are persisted.
+pub fn hash(key: &[u8]) -> u64 {
+    key.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_contains_inserted_keys() {
+        let keys: Vec<Vec<u8>> = (0..1000u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let hashes: Vec<u64> = keys.iter().map(|k| hash(k)).collect();
+        let filter = BloomFilter::from_keys(&hashes, DEFAULT_BITS_PER_KEY);
+        assert!(keys.iter().all(|k| filter.may_contain(k)));
+    }
+
+    #[test]
+    fn test_filter_rejects_most_missing_keys() {
+        let hashes: Vec<u64> = (0..1000u32).map(|i| hash(&i.to_le_bytes())).collect();
+        let filter = BloomFilter::from_keys(&hashes, DEFAULT_BITS_PER_KEY);
+        let false_positives = (1000..11000u32)
+            .filter(|i| filter.may_contain(&i.to_le_bytes()))
+            .count();
+        assert!(false_positives < 500);
+    }
+}
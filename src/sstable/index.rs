@@ -1,6 +1,6 @@
-use crate::database::entry::Entry;
+use crate::{database::entry::Entry, Result};
 use std::fs::OpenOptions;
-use std::io::{self, BufReader};
+use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use std::{fs::File, io::BufWriter, io::Read, io::Write};
 
@@ -15,13 +15,14 @@ pub struct IndexIterator {
     reader: BufReader<File>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct IndexEntry {
     pub key: Vec<u8>,
     pub offset: u64,
 }
 
 impl IndexIterator {
-    pub fn new(path: PathBuf) -> io::Result<IndexIterator> {
+    pub fn new(path: PathBuf) -> Result<IndexIterator> {
         let file = OpenOptions::new().read(true).open(path)?;
         let reader = BufReader::new(file);
         Ok(IndexIterator { reader })
@@ -37,19 +38,19 @@ impl Iterator for IndexIterator {
 }
 
 impl Index {
-    pub fn new(path: &Path) -> io::Result<Index> {
+    pub fn new(path: &Path) -> Result<Index> {
         Self::from_path(path)
     }
 
-    pub fn from_path(path: &Path) -> io::Result<Index> {
-        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+    pub fn from_path(path: &Path) -> Result<Index> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
         let file = BufWriter::new(file);
         Ok(Index {
             path: path.to_owned(),
             file,
         })
     }
-    pub fn write(&mut self, entry: &Entry, offset: u64) -> io::Result<()> {
+    pub fn write(&mut self, entry: &Entry, offset: u64) -> Result<()> {
         self.file.write_all(&entry.key.len().to_le_bytes())?;
         self.file.write_all(&entry.key)?;
         self.file.write_all(&offset.to_le_bytes())?;
@@ -62,8 +63,10 @@ impl Index {
             return None;
         }
         let key_len = usize::from_le_bytes(len_buffer);
-        let mut key = vec![0; key_len];
-        if file.read_exact(&mut key).is_err() {
+        // a corrupt length must not decide how much is allocated
+        let mut key = Vec::new();
+        file.take(key_len as u64).read_to_end(&mut key).ok()?;
+        if key.len() != key_len {
             return None;
         }
 
@@ -75,11 +78,12 @@ impl Index {
         Some(IndexEntry { key, offset })
     }
 
-    pub fn flush(&mut self) -> io::Result<()> {
-        self.file.flush()
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.file.flush()?)
     }
 
-    pub fn get(&self, key: &[u8]) -> io::Result<Option<u64>> {
+    #[allow(dead_code)]
+    pub fn get(&self, key: &[u8]) -> Result<Option<u64>> {
         let iterator = IndexIterator::new(self.path.clone())?;
         for entry in iterator {
             if entry.key.as_slice() == key {
@@ -93,14 +97,13 @@ impl Index {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::{SystemTime, UNIX_EPOCH};
     #[test]
     fn test_get_offset_from_index() {
-        let mut index = create_index().unwrap();
+        let mut index = create_index("index_get_offset").unwrap();
         let entry = create_entry();
         index.write(&entry, 0).unwrap();
         index.flush().unwrap();
-        let result_offset = index.get(&entry.key.as_slice()).unwrap();
+        let result_offset = index.get(entry.key.as_slice()).unwrap();
         assert!(result_offset.is_some());
     }
 
@@ -110,24 +113,21 @@ mod tests {
             value: Some(vec![9]),
             timestamp: 1,
             deleted: false,
+            single_delete: false,
+            blob: None,
+            expires_at: None,
+            operands: Vec::new(),
         }
     }
 
-    fn create_path() -> PathBuf {
-        PathBuf::from("data")
+    fn create_path(name: &str) -> PathBuf {
+        let path = PathBuf::from("data").join(name);
+        std::fs::remove_dir_all(&path).ok();
+        std::fs::create_dir_all(&path).unwrap();
+        path
     }
 
-    fn create_timestamp() -> u128 {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
-        timestamp
-    }
-
-    fn create_index() -> io::Result<Index> {
-        let path = create_path();
-        let timestamp = create_timestamp();
-        Index::new(&path.join(timestamp.to_string() + ".index.sstable"))
+    fn create_index(name: &str) -> Result<Index> {
+        Index::new(&create_path(name).join("000001.index.sst"))
     }
 }
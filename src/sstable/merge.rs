@@ -1,59 +1,115 @@
-use std::{
-    cmp::Ordering,
-    io::{self, Result},
-    path::Path,
+use std::{cmp::Ordering, path::Path};
+
+use crate::{
+    database::{
+        entry::Entry,
+        merge_operator::{resolve, stack, MergeOperator},
+        range_tombstone::{cover, RangeTombstone},
+    },
+    Result,
 };
 
 use super::sstable::SSTable;
 
 impl SSTable {
-    pub fn merge(self, other: SSTable, dir: &Path) -> Result<SSTable> {
-        let mut merged = SSTable::new(dir)?;
-        let mut iterator = self.into_iter();
-        let mut other_iterator = other.into_iter();
-        let mut iterator_next = iterator.next();
-        let mut other_iterator_next = other_iterator.next();
+    /// Merges two tables into a new table numbered `number`, keeping the newest version of
+    /// each key. `self` has to be the newer table: where both hold a key, its version wins
+    /// whatever the timestamps, as it does for reads. Entries expired at `now` are handled
+    /// like tombstones, so they still hide older versions in other tables but lose their
+    /// value.
+    ///
+    /// Tombstones are kept, since tables left out of the merge may hold older versions of
+    /// their keys, unless the tables hold the oldest versions of their keys, `bottommost`.
+    /// A single delete is dropped together with the value it meets either way, see
+    /// `Database::single_delete`.
+    ///
+    /// Merge operands are put on top of the older version of their key and folded with
+    /// `merge_operator` once that version is known. If `bottommost`, operands without an
+    /// older version are folded too.
+    ///
+    /// Versions covered by a range tombstone of either table are dropped. Range tombstones
+    /// are kept like tombstones.
+    ///
+    /// Values in blob files stay there unless the blob file is relocated, see
+    /// `SSTable::set_blobs`.
+    ///
+    /// Both tables must be flushed. They are only read, since their files may be linked
+    /// into checkpoints. A record that cannot be read fails the merge.
+    pub fn merge(
+        self,
+        other: SSTable,
+        dir: &Path,
+        number: u64,
+        now: u128,
+        merge_operator: Option<&dyn MergeOperator>,
+        bottommost: bool,
+    ) -> Result<SSTable> {
+        let mut merged = SSTable::new(dir, number)?;
+        merged.set_bits_per_key(self.bits_per_key);
+        merged.set_compression(self.compression);
+        merged.set_comparator(self.comparator.clone());
+        if let Some(blobs) = self.blobs.clone() {
+            merged.set_blobs(blobs);
+        }
+        let tombstones: Vec<RangeTombstone> = self
+            .range_tombstones()
+            .iter()
+            .chain(other.range_tombstones())
+            .cloned()
+            .collect();
+        let comparator = self.comparator.clone();
+        let covered = |entry: &Entry| tombstones.iter().any(|t| t.covers(entry, &*comparator));
+        let mut iterator = self.iter()?.map(|entry| entry.map(|e| expire(e, now)));
+        let mut other_iterator = other.iter()?.map(|entry| entry.map(|e| expire(e, now)));
+        let mut finish = |entry: Entry| -> Result<()> {
+            // there is nothing older left for a bottommost tombstone to hide
+            if covered(&entry) || bottommost && entry.is_tombstone() {
+                return Ok(());
+            }
+            if entry.is_pending() && !bottommost {
+                return merged.write(&entry);
+            }
+            // operands are folded into the value itself
+            let entry = match merge_operator.is_some() && !entry.operands.is_empty() {
+                true => self.load_blob(entry)?,
+                false => entry,
+            };
+            merged.write(&resolve(entry, merge_operator, now))
+        };
+        let mut iterator_next = iterator.next().transpose()?;
+        let mut other_iterator_next = other_iterator.next().transpose()?;
         loop {
             (iterator_next, other_iterator_next) = match (iterator_next, other_iterator_next) {
                 (None, None) => (None, None), // both iterators are empty
                 (Some(entry), None) => {
-                    merged.write(&entry)?;
-                    (iterator.next(), None)
+                    finish(entry)?;
+                    (iterator.next().transpose()?, None)
                 }
                 (None, Some(entry)) => {
-                    merged.write(&entry)?;
-                    (None, other_iterator.next())
+                    finish(entry)?;
+                    (None, other_iterator.next().transpose()?)
                 }
 
                 (Some(entry), Some(other_entry)) => {
-                    match entry.key.as_slice().cmp(other_entry.key.as_slice()) {
+                    match self.comparator.compare(&entry.key, &other_entry.key) {
                         Ordering::Less => {
-                            merged.write(&entry);
-                            (iterator.next(), Some(other_entry))
+                            finish(entry)?;
+                            (iterator.next().transpose()?, Some(other_entry))
                         }
                         Ordering::Greater => {
-                            merged.write(&other_entry);
-                            (Some(entry), other_iterator.next())
+                            finish(other_entry)?;
+                            (Some(entry), other_iterator.next().transpose()?)
                         }
-                        Ordering::Equal => match entry.timestamp.cmp(&other_entry.timestamp) {
-                            Ordering::Greater => {
-                                match entry.deleted {
-                                    false => merged.write(&entry)?,
-                                    true => (),
-                                };
-                                (iterator.next(), other_iterator.next())
-                            }
-                            Ordering::Less => {
-                                match other_entry.deleted {
-                                    false => merged.write(&other_entry)?,
-                                    true => (),
-                                };
-                                (iterator.next(), other_iterator.next())
+                        Ordering::Equal => {
+                            let older = cover(other_entry, &tombstones, &*comparator);
+                            if let Some(entry) = combine(entry, older) {
+                                finish(entry)?;
                             }
-                            Ordering::Equal => {
-                                panic!("timestamps should not be equal between two items")
-                            }
-                        },
+                            (
+                                iterator.next().transpose()?,
+                                other_iterator.next().transpose()?,
+                            )
+                        }
                     }
                 }
             };
@@ -61,64 +117,250 @@ impl SSTable {
                 break;
             }
         }
+        if !bottommost {
+            for tombstone in tombstones {
+                merged.add_range_tombstone(tombstone);
+            }
+        }
+        merged.flush()?;
         Ok(merged)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::path::PathBuf;
-
-    use crate::database::entry::Entry;
-
-    use super::*;
-
-    fn create_path() -> PathBuf {
-        PathBuf::from("data")
+/// Two versions of a key as one entry, or `None` if a single delete meets its value.
+fn combine(newer: Entry, older: Entry) -> Option<Entry> {
+    let single_delete = newer.single_delete && newer.is_tombstone();
+    let value = older.value.is_some() || older.blob.is_some();
+    if single_delete && !older.deleted && value && older.operands.is_empty() {
+        return None;
     }
+    Some(stack(newer, older))
+}
 
-    fn create_sstable(path: &Path) -> SSTable {
-        SSTable::new(path).unwrap()
+fn expire(entry: Entry, now: u128) -> Entry {
+    if !entry.is_expired(now) {
+        return entry;
     }
+    // operands merged into the value expire with it
+    Entry {
+        value: None,
+        deleted: true,
+        expires_at: None,
+        operands: Vec::new(),
+        blob: None,
+        ..entry
+    }
+}
 
-    fn create_sstable_entry(key: Vec<u8>, timestamp: u128, deleted: bool) -> Entry {
-        Entry {
-            key,
-            value: Some(vec![9]),
-            timestamp,
-            deleted,
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::merge_operator::StringAppendOperator,
+        sstable::test_util::{create_entry, create_path},
+    };
+
+    fn create_sstable(path: &Path, number: u64) -> SSTable {
+        SSTable::new(path, number).unwrap()
     }
 
     #[test]
     fn test_deleted_records_no_longer_in_sstable() {
-        let path = create_path();
-        let entry = create_sstable_entry(vec![1], 0, false);
-        let mut sstable_a = create_sstable(&path);
+        let path = create_path("merge_deleted_records");
+        let entry = create_entry(&[1], Some(&[9]), 0);
+        let mut sstable_a = create_sstable(&path, 1);
         sstable_a.write(&entry).ok();
-        let mut sstable_b = create_sstable(&path);
-        let entry = create_sstable_entry(vec![1], 1, true);
+        let mut sstable_b = create_sstable(&path, 2);
+        let entry = create_entry(&[1], None, 1);
         sstable_b.write(&entry).ok();
-        let merged = sstable_a.merge(sstable_b, &path).ok().unwrap();
-        assert_eq!(merged.into_iter().count(), 0);
+        sstable_a.flush().unwrap();
+        sstable_b.flush().unwrap();
+        let merged = sstable_b
+            .merge(sstable_a, &path, 3, 0, None, false)
+            .ok()
+            .unwrap();
+        // the tombstone still hides versions in tables that were not merged
+        let entries: Vec<Entry> = merged.iter().unwrap().map(Result::unwrap).collect();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].is_tombstone() && entries[0].value.is_none());
+
+        let empty = create_sstable(&path, 4);
+        let merged = merged.merge(empty, &path, 5, 0, None, true).unwrap();
+        assert_eq!(merged.iter().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_single_delete_drops_the_value_it_meets() {
+        let path = create_path("merge_single_delete");
+        let mut sstable_a = create_sstable(&path, 1);
+        for key in 1..4 {
+            sstable_a.write(&create_entry(&[key], Some(&[9]), 1)).ok();
+        }
+        let mut sstable_b = create_sstable(&path, 2);
+        let single_delete = |key: u8| Entry {
+            single_delete: true,
+            blob: None,
+            ..create_entry(&[key], None, 2)
+        };
+        sstable_b.write(&single_delete(1)).ok();
+        sstable_b.write(&create_entry(&[2], None, 2)).ok();
+        sstable_b.write(&single_delete(4)).ok();
+        sstable_a.flush().unwrap();
+        sstable_b.flush().unwrap();
+        let merged = sstable_b
+            .merge(sstable_a, &path, 3, 0, None, false)
+            .unwrap();
+        let entries: Vec<(u8, bool)> = merged
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|entry| (entry.key[0], entry.single_delete))
+            .collect();
+        // a plain tombstone is kept, and so is a single delete that met no value
+        assert_eq!(entries, vec![(2, false), (3, false), (4, true)]);
     }
 
     #[test]
     fn test_records_are_merged_in_order() {
-        let path = create_path();
-        let mut sstable_a = create_sstable(&path);
+        let path = create_path("merge_in_order");
+        let mut sstable_a = create_sstable(&path, 1);
         for i in (1..10).step_by(2) {
-            let entry = create_sstable_entry(vec![i], i.into(), false);
+            let entry = create_entry(&[i], Some(&[9]), i.into());
             sstable_a.write(&entry).ok();
         }
-        let mut sstable_b = create_sstable(&path);
+        let mut sstable_b = create_sstable(&path, 2);
         for i in (0..9).step_by(2) {
-            let entry = create_sstable_entry(vec![i], i.into(), false);
+            let entry = create_entry(&[i], Some(&[9]), i.into());
             sstable_b.write(&entry).ok();
         }
-        let merged = sstable_a.merge(sstable_b, &path).ok().unwrap();
-        for (i, entry) in merged.into_iter().enumerate() {
+        sstable_a.flush().unwrap();
+        sstable_b.flush().unwrap();
+        let merged = sstable_a
+            .merge(sstable_b, &path, 3, 0, None, false)
+            .ok()
+            .unwrap();
+        assert_eq!(merged.iter().unwrap().count(), 10);
+        for (i, entry) in merged.iter().unwrap().map(Result::unwrap).enumerate() {
             assert_eq!(i, usize::try_from(entry.timestamp).unwrap())
         }
     }
+
+    #[test]
+    fn test_newer_table_wins_whatever_the_timestamps() {
+        let path = create_path("merge_newer_table_wins");
+        let mut newer = create_sstable(&path, 2);
+        newer.write(&create_entry(&[1], Some(&[2]), 1)).ok();
+        newer.write(&create_entry(&[2], Some(&[2]), 1)).ok();
+        let mut older = create_sstable(&path, 1);
+        older.write(&create_entry(&[1], Some(&[1]), 1)).ok();
+        older.write(&create_entry(&[2], Some(&[1]), 5)).ok();
+        newer.flush().unwrap();
+        older.flush().unwrap();
+        let merged = newer.merge(older, &path, 3, 0, None, false).unwrap();
+        let values: Vec<Option<Vec<u8>>> = merged
+            .iter()
+            .unwrap()
+            .map(|entry| entry.unwrap().value)
+            .collect();
+        assert_eq!(values, vec![Some(vec![2]), Some(vec![2])]);
+    }
+
+    #[test]
+    fn test_expired_entries_become_tombstones() {
+        let path = create_path("merge_expired");
+        let mut sstable_a = create_sstable(&path, 1);
+        let mut expiring = create_entry(&[1], Some(&[9]), 2);
+        expiring.expires_at = Some(10);
+        sstable_a.write(&expiring).ok();
+        let mut kept = create_entry(&[3], Some(&[9]), 2);
+        kept.expires_at = Some(11);
+        sstable_a.write(&kept).ok();
+        let mut sstable_b = create_sstable(&path, 2);
+        sstable_b.write(&create_entry(&[1], Some(&[9]), 1)).ok();
+        sstable_b.write(&create_entry(&[2], Some(&[9]), 1)).ok();
+        let mut lone = create_entry(&[4], Some(&[9]), 1);
+        lone.expires_at = Some(5);
+        sstable_b.write(&lone).ok();
+        sstable_a.flush().unwrap();
+        sstable_b.flush().unwrap();
+        let merged = sstable_a
+            .merge(sstable_b, &path, 3, 10, None, false)
+            .unwrap();
+        let entries: Vec<Entry> = merged.iter().unwrap().map(Result::unwrap).collect();
+        let keys: Vec<u8> = entries.iter().map(|entry| entry.key[0]).collect();
+        assert_eq!(keys, vec![1, 2, 3, 4]);
+        assert_eq!(entries[2].expires_at, Some(11));
+        // expired entries stay as tombstones to hide older versions elsewhere
+        assert!(entries[0].is_tombstone() && entries[0].value.is_none());
+        assert!(entries[3].deleted && entries[3].value.is_none());
+    }
+
+    #[test]
+    fn test_range_tombstones_drop_covered_entries() {
+        let path = create_path("merge_range_tombstones");
+        let mut sstable_a = create_sstable(&path, 1);
+        sstable_a.write(&create_entry(&[2], Some(&[9]), 6)).ok();
+        sstable_a.add_range_tombstone(RangeTombstone {
+            start: vec![1],
+            end: vec![3],
+            timestamp: 5,
+        });
+        let mut sstable_b = create_sstable(&path, 2);
+        for key in 1..4 {
+            sstable_b.write(&create_entry(&[key], Some(&[9]), 1)).ok();
+        }
+        sstable_a.flush().unwrap();
+        sstable_b.flush().unwrap();
+        let merged = sstable_a
+            .merge(sstable_b, &path, 3, 0, None, false)
+            .unwrap();
+        let entries: Vec<(u8, u128)> = merged
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|entry| (entry.key[0], entry.timestamp))
+            .collect();
+        assert_eq!(entries, vec![(2, 6), (3, 1)]);
+        // older tables may still hold versions the tombstone covers
+        assert_eq!(merged.range_tombstones().len(), 1);
+
+        let empty = create_sstable(&path, 4);
+        let merged = merged.merge(empty, &path, 5, 0, None, true).unwrap();
+        assert!(merged.range_tombstones().is_empty());
+        assert_eq!(merged.key_range(), Some((&[2][..], &[3][..])));
+    }
+
+    #[test]
+    fn test_operands_are_folded_into_older_values() {
+        let path = create_path("merge_operands");
+        let operand = |key: u8, timestamp: u128, operand: &[u8]| Entry {
+            value: None,
+            operands: vec![operand.to_vec()],
+            ..create_entry(&[key], Some(&[9]), timestamp)
+        };
+        let mut sstable_a = create_sstable(&path, 1);
+        sstable_a.write(&operand(1, 2, b"b")).ok();
+        sstable_a.write(&operand(2, 2, b"b")).ok();
+        let mut sstable_b = create_sstable(&path, 2);
+        sstable_b.write(&create_entry(&[1], Some(&[9]), 1)).ok();
+        let operator = StringAppendOperator::new(b",");
+        sstable_a.flush().unwrap();
+        sstable_b.flush().unwrap();
+        let merged = sstable_a
+            .merge(sstable_b, &path, 3, 0, Some(&operator), false)
+            .unwrap();
+        let entries: Vec<Entry> = merged.iter().unwrap().map(Result::unwrap).collect();
+        assert_eq!(entries[0].value, Some(b"\x09,b".to_vec()));
+        assert_eq!(entries[0].timestamp, 2);
+        // older tables may still hold a value for key 2
+        assert!(entries[1].is_pending());
+
+        let empty = create_sstable(&path, 4);
+        let merged = merged
+            .merge(empty, &path, 5, 0, Some(&operator), true)
+            .unwrap();
+        let entries: Vec<Entry> = merged.iter().unwrap().map(Result::unwrap).collect();
+        assert_eq!(entries[1].value, Some(b"b".to_vec()));
+        assert!(entries[1].operands.is_empty());
+    }
 }
@@ -1,5 +1,13 @@
-mod data;
-mod index;
+pub mod blob;
+pub mod cache;
+pub(crate) mod data;
+pub mod dump;
+pub(crate) mod filter;
+pub mod index;
 pub mod iterator;
 pub mod merge;
+pub mod reader;
 pub mod sstable;
+pub mod table_cache;
+#[cfg(test)]
+pub(crate) mod test_util;
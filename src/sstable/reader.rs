@@ -0,0 +1,275 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    checksum::crc32,
+    database::{comparator::Comparator, entry::Entry, range_tombstone::RangeTombstone},
+    Error, Result,
+};
+
+use super::{
+    cache::{Block, BlockCache, BlockKey, FILTER_BLOCK_OFFSET, INDEX_BLOCK_OFFSET},
+    data::{self, Data},
+    filter::BloomFilter,
+    index::IndexIterator,
+    iterator::SSTableIterator,
+    sstable::{
+        data_path, file_id, filter_path, index_path, range_path, read_checksums,
+        read_range_tombstones, BlockChecksum,
+    },
+};
+
+/// Read-only handle on a flushed sstable.
+///
+/// The index and filter are parsed once when the table is opened and kept pinned in the
+/// block cache until the reader is dropped; data blocks are read on demand through the
+/// cache and checked against their checksums. Range tombstones are few and kept by the
+/// reader itself.
+pub struct TableReader {
+    pub id: u64,
+    pub path: PathBuf,
+    reader_id: u64,
+    data: Mutex<File>,
+    data_len: u64,
+    compressed: bool,
+    index: Arc<Block>,
+    filter: Option<Arc<Block>>,
+    // by block offset; empty for tables written before checksums existed
+    checksums: HashMap<u64, BlockChecksum>,
+    range_tombstones: Vec<RangeTombstone>,
+    block_cache: Arc<BlockCache>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl TableReader {
+    pub fn open(
+        path: &Path,
+        block_cache: Arc<BlockCache>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<TableReader> {
+        let id = file_id(path);
+        let mut data = match OpenOptions::new().read(true).open(data_path(path)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::NotFound(format!("sstable {}", path.display())));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let data_len = data.metadata()?.len();
+        let compressed = data::is_compressed(&mut data)?;
+        let reader_id = block_cache.new_reader_id();
+        let index = Self::load_pinned(&block_cache, reader_id, INDEX_BLOCK_OFFSET, || {
+            let index = IndexIterator::new(index_path(path))?.collect();
+            Ok(Block::Index(index))
+        })?;
+        // tables written before filters existed have no filter file
+        let filter = if filter_path(path).exists() {
+            Some(Self::load_pinned(
+                &block_cache,
+                reader_id,
+                FILTER_BLOCK_OFFSET,
+                || Ok(Block::Filter(BloomFilter::read_from(&filter_path(path))?)),
+            )?)
+        } else {
+            None
+        };
+        let checksums = match path.exists() {
+            true => read_checksums(path)?,
+            false => Vec::new(),
+        };
+        Ok(TableReader {
+            id,
+            path: path.to_owned(),
+            reader_id,
+            data: Mutex::new(data),
+            data_len,
+            compressed,
+            index,
+            filter,
+            checksums: checksums.into_iter().map(|c| (c.offset, c)).collect(),
+            range_tombstones: read_range_tombstones(&range_path(path))?,
+            block_cache,
+            comparator,
+        })
+    }
+
+    fn load_pinned(
+        cache: &BlockCache,
+        reader_id: u64,
+        offset: u64,
+        load: impl FnOnce() -> Result<Block>,
+    ) -> Result<Arc<Block>> {
+        let key = BlockKey { reader_id, offset };
+        if let Some(block) = cache.get(&key) {
+            return Ok(block);
+        }
+        let block = Arc::new(load()?);
+        cache.insert_pinned(key, block.clone());
+        Ok(block)
+    }
+
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        match self.filter.as_deref() {
+            Some(Block::Filter(filter)) => filter.may_contain(key),
+            _ => true,
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Entry>> {
+        if !self.may_contain(key) {
+            return Ok(None);
+        }
+        let Block::Index(index) = self.index.as_ref() else {
+            return Ok(None);
+        };
+        // the block holding `key` starts at the last index entry not greater than it
+        let position = index.partition_point(|e| self.not_after(&e.key, key));
+        if position == 0 {
+            return Ok(None);
+        }
+        let offset = index[position - 1].offset;
+        let end = match index.get(position) {
+            Some(next) => next.offset,
+            None => self.data_len,
+        };
+        if offset > end || end > self.data_len {
+            return Err(Error::Corruption(format!(
+                "index of {} points past its data",
+                self.path.display()
+            )));
+        }
+        let block = self.read_block(offset, end - offset)?;
+        let Block::Data(entries) = block.as_ref() else {
+            return Ok(None);
+        };
+        Ok(entries
+            .binary_search_by(|e| self.comparator.compare(&e.key, key))
+            .ok()
+            .map(|idx| entries[idx].clone()))
+    }
+
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// Iterates over the entries of the table, starting at the block that may hold `start`
+    /// or at the first block. Entries before `start` in that block are still returned.
+    pub fn iter_from(&self, start: Option<&[u8]>) -> Result<SSTableIterator> {
+        let offset = match (self.index.as_ref(), start) {
+            (Block::Index(index), Some(start)) => {
+                let position = index.partition_point(|e| self.not_after(&e.key, start));
+                position.checked_sub(1).map_or(0, |p| index[p].offset)
+            }
+            _ => 0,
+        };
+        SSTableIterator::with_offset(data_path(&self.path), offset)
+    }
+
+    fn not_after(&self, key: &[u8], other: &[u8]) -> bool {
+        self.comparator.compare(key, other) != Ordering::Greater
+    }
+
+    fn read_block(&self, offset: u64, len: u64) -> Result<Arc<Block>> {
+        let key = BlockKey {
+            reader_id: self.reader_id,
+            offset,
+        };
+        if let Some(block) = self.block_cache.get(&key) {
+            return Ok(block);
+        }
+        let mut buffer = vec![0; len as usize];
+        {
+            let mut file = self.data.lock().unwrap();
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut buffer)?;
+        }
+        if let Some(checksum) = self.checksums.get(&offset) {
+            if checksum.len != len || crc32(&buffer) != checksum.crc {
+                return Err(Error::Corruption(format!(
+                    "block at offset {} of {} does not match its checksum",
+                    offset,
+                    self.path.display()
+                )));
+            }
+        }
+        if self.compressed {
+            buffer = data::decompress_block(&buffer)?;
+        }
+        let block = Arc::new(Block::Data(Data::decode_block(&buffer)?));
+        self.block_cache.insert(key, block.clone());
+        Ok(block)
+    }
+}
+
+impl Drop for TableReader {
+    fn drop(&mut self) {
+        self.block_cache.erase_reader(self.reader_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::comparator::BytewiseComparator,
+        sstable::{
+            sstable::SSTable,
+            test_util::{create_entry, create_path},
+        },
+    };
+
+    fn create_sstable() -> SSTable {
+        let mut sstable = SSTable::new(&create_path("reader_get_entry"), 1).unwrap();
+        for i in 0..100u8 {
+            sstable
+                .write(&create_entry(&[i], Some(&[i; 1024]), 1))
+                .unwrap();
+        }
+        sstable.flush().unwrap();
+        sstable
+    }
+
+    #[test]
+    fn test_get_entry_through_block_cache() {
+        let sstable = create_sstable();
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let reader =
+            TableReader::open(&sstable.path, cache.clone(), Arc::new(BytewiseComparator)).unwrap();
+        for i in 0..100u8 {
+            let entry = reader.get(&[i]).unwrap().unwrap();
+            assert_eq!(entry.value, Some(vec![i; 1024]));
+        }
+        assert!(reader.get(&[200]).unwrap().is_none());
+        assert!(cache.hits() > 0);
+    }
+
+    #[test]
+    fn test_iter_from_skips_earlier_blocks() {
+        let sstable = create_sstable();
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let reader = TableReader::open(&sstable.path, cache, Arc::new(BytewiseComparator)).unwrap();
+        let keys: Vec<u8> = reader
+            .iter_from(Some(&[80]))
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|e| e.key[0])
+            .collect();
+        assert!(keys[0] > 0 && keys[0] <= 80);
+        assert_eq!(keys.last(), Some(&99));
+    }
+
+    #[test]
+    fn test_open_missing_table_is_an_error() {
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let path = create_path("reader_missing_table").join("000001.sst");
+        let result = TableReader::open(&path, cache, Arc::new(BytewiseComparator));
+        assert!(matches!(result, Err(Error::NotFound(_))));
+        assert!(!data_path(&path).exists());
+    }
+}
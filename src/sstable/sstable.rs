@@ -1,15 +1,28 @@
 use std::{
     cmp::Ordering,
-    fs::{read_dir, File, OpenOptions},
-    io::{self, BufWriter, Write},
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    fs::{self, read_dir, remove_file, File, OpenOptions},
+    hash::{Hash, Hasher},
+    io::{BufWriter, ErrorKind, Write},
     path::{Path, PathBuf},
-    time::{SystemTime, UNIX_EPOCH},
+    sync::Arc,
 };
 
-use crate::database::entry::Entry;
+use crate::{
+    checksum::crc32,
+    database::{
+        comparator::{BytewiseComparator, Comparator},
+        entry::Entry,
+        options::Compression,
+        range_tombstone::RangeTombstone,
+    },
+    Error, Result,
+};
 
 use super::{
-    data::{Data, DataIterator},
+    blob::{self, BlobOptions, BlobWriter},
+    data::{self, Data, DataIterator},
+    filter::{self, BloomFilter, DEFAULT_BITS_PER_KEY},
     index::Index,
 };
 use super::{index::IndexIterator, iterator::SSTableIterator};
@@ -18,98 +31,334 @@ use super::{index::IndexIterator, iterator::SSTableIterator};
 // | Key Size (8B) | Tombstone(1B) | Value Size (8B) | Key | Value | Timestamp (16B) |
 // +---------------+---------------+-----------------+-...-+--...--+-----------------+
 
+// The table file itself lists a checksum per data block:
+// +-------------+-------------+-------------+
+// | Offset (8B) | Length (8B) | CRC32 (4B)  |
+// +-------------+-------------+-------------+
+
 const BLOCK_SIZE: usize = 65536;
+const CHECKSUM_LEN: usize = 8 + 8 + 4;
+
+/// CRC32 of the data block at `offset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockChecksum {
+    pub offset: u64,
+    pub len: u64,
+    pub crc: u32,
+}
 
 pub struct SSTable {
+    pub id: u64,
     pub path: PathBuf,
     data: Data,
     index: Index,
-    file: BufWriter<File>,
     current_block_size: usize,
-}
-
-impl IntoIterator for SSTable {
-    type IntoIter = SSTableIterator;
-    type Item = Entry;
-
-    fn into_iter(self) -> SSTableIterator {
-        SSTableIterator::new(self.path).unwrap()
-    }
+    // checksums of the finished blocks and the bytes of the block being written
+    block_checksums: Vec<BlockChecksum>,
+    block: Vec<u8>,
+    key_hashes: Vec<u64>,
+    key_range: Option<(Vec<u8>, Vec<u8>)>,
+    range_tombstones: Vec<RangeTombstone>,
+    // count and length of the values written per blob file
+    blob_refs: BTreeMap<u64, (u64, u64)>,
+    blob_writer: Option<BlobWriter>,
+    pub(super) blobs: Option<BlobOptions>,
+    pub(super) bits_per_key: usize,
+    pub(super) compression: Compression,
+    pub(super) comparator: Arc<dyn Comparator>,
 }
 
 impl SSTable {
-    pub fn new(dir: &Path) -> io::Result<SSTable> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
-
-        let path = Path::new(dir).join(timestamp.to_string() + ".sstable");
-        let data_path = Path::new(dir).join(timestamp.to_string() + ".data.sstable");
-        let index_path = Path::new(dir).join(timestamp.to_string() + ".index.sstable");
-        let file = OpenOptions::new().append(true).create(true).open(&path)?;
-        let file = BufWriter::new(file);
+    /// Creates an empty table named after `number`, which must come from the database's
+    /// file number counter so it is never reused.
+    pub fn new(dir: &Path, number: u64) -> Result<SSTable> {
+        let path = table_path(dir, number);
+        OpenOptions::new().append(true).create(true).open(&path)?;
         let current_block_size = 0;
-        let data = Data::new(&data_path)?;
-        let index = Index::new(&index_path)?;
+        let data = Data::new(&data_path(&path))?;
+        let index = Index::new(&index_path(&path))?;
 
         Ok(SSTable {
+            id: number,
             path,
             data,
             index,
-            file,
             current_block_size,
+            block_checksums: Vec::new(),
+            block: Vec::new(),
+            key_hashes: Vec::new(),
+            key_range: None,
+            range_tombstones: Vec::new(),
+            blob_refs: BTreeMap::new(),
+            blob_writer: None,
+            blobs: None,
+            bits_per_key: DEFAULT_BITS_PER_KEY,
+            compression: Compression::None,
+            comparator: Arc::new(BytewiseComparator),
         })
     }
 
-    pub fn from_path(path: &Path) -> io::Result<SSTable> {
-        let file = OpenOptions::new().append(true).create(true).open(&path)?;
-        let file = BufWriter::new(file);
+    pub fn from_path(path: &Path) -> Result<SSTable> {
+        OpenOptions::new().append(true).create(true).open(path)?;
         let current_block_size = 0;
-        let binding = path.clone().to_path_buf();
-        let data_path = binding
-            .to_str()
-            .unwrap()
-            .replace(".sstable", ".data.sstable");
-        let index_path = binding
-            .to_str()
-            .unwrap()
-            .replace(".sstable", ".index.sstable");
-        let data_path = Path::new(&data_path);
-        let data = Data::from_path(&data_path)?;
-        let index_path = Path::new(&index_path);
-        let index = Index::from_path(&index_path)?;
-        Ok(SSTable {
+        let data = Data::from_path(&data_path(path))?;
+        let index = Index::from_path(&index_path(path))?;
+        let compression = match data.is_compressed() {
+            true => Compression::Snappy,
+            false => Compression::None,
+        };
+        let mut sstable = SSTable {
+            id: file_id(path),
             path: path.to_owned(),
             data,
             index,
-            file,
             current_block_size,
-        })
+            block_checksums: Vec::new(),
+            block: Vec::new(),
+            key_hashes: Vec::new(),
+            key_range: None,
+            range_tombstones: read_range_tombstones(&range_path(path))?,
+            blob_refs: BTreeMap::new(),
+            blob_writer: None,
+            blobs: None,
+            bits_per_key: DEFAULT_BITS_PER_KEY,
+            compression,
+            comparator: Arc::new(BytewiseComparator),
+        };
+        // rebuild the filter input, key range, blob references and checksums so appending
+        // keeps them complete
+        for entry in DataIterator::new(sstable.data.path.clone(), 0)? {
+            sstable.track(&entry?);
+        }
+        let data = fs::read(&sstable.data.path)?;
+        let offsets: Vec<u64> = IndexIterator::new(sstable.index.path.clone())?
+            .map(|entry| entry.offset)
+            .collect();
+        for (i, &offset) in offsets.iter().enumerate() {
+            let end = offsets.get(i + 1).copied().unwrap_or(data.len() as u64);
+            let Some(block) = data.get(offset as usize..end as usize) else {
+                return Err(Error::Corruption(format!(
+                    "index of {} points past its data",
+                    path.display()
+                )));
+            };
+            sstable.block_checksums.push(BlockChecksum {
+                offset,
+                len: end - offset,
+                crc: crc32(block),
+            });
+        }
+        Ok(sstable)
+    }
+
+    /// Iterates over the records in the data file; buffered writes must be flushed first.
+    pub fn iter(&self) -> Result<SSTableIterator> {
+        SSTableIterator::new(self.data.path.clone())
     }
 
-    pub fn write(&mut self, entry: &Entry) -> io::Result<()> {
+    pub fn write(&mut self, entry: &Entry) -> Result<()> {
+        let moved = self.move_value(entry)?;
+        let entry = moved.as_ref().unwrap_or(entry);
         let entry_size = size(entry);
         if self.current_block_size == 0 || self.current_block_size + entry_size > BLOCK_SIZE {
-            let offset = self.data.get_offset();
-            self.index.write(&entry, offset)?;
-            self.current_block_size = 0;
+            self.finish_block()?;
+            let offset = self
+                .data
+                .start_block(self.compression != Compression::None)?;
             // write this item to index
+            self.index.write(entry, offset)?;
         }
         self.current_block_size += entry_size;
-        self.data.write(&entry)?;
+        self.track(entry);
+        Data::encode(&mut self.block, entry)?;
+        if !self.data.is_compressed() {
+            self.data.write(entry)?;
+        }
         Ok(())
     }
 
-    pub fn flush(&mut self) -> io::Result<()> {
-        self.file.flush()?;
-        self.data.flush()
+    /// Records the checksum of the block being written, which a compressed table only
+    /// writes now, so the next write starts a new block.
+    fn finish_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let checksum = if self.data.is_compressed() {
+            let frame = data::compress_block(&self.block)?;
+            let offset = self.data.get_offset();
+            self.data.write_frame(&frame)?;
+            BlockChecksum {
+                offset,
+                len: frame.len() as u64,
+                crc: crc32(&frame),
+            }
+        } else {
+            let len = self.block.len() as u64;
+            BlockChecksum {
+                offset: self.data.get_offset() - len,
+                len,
+                crc: crc32(&self.block),
+            }
+        };
+        self.block_checksums.push(checksum);
+        self.block.clear();
+        self.current_block_size = 0;
+        Ok(())
+    }
+
+    /// `entry` with its value moved to the table's blob file or back into the record, as
+    /// `set_blobs` asks for; `None` if it is written as it is.
+    fn move_value(&mut self, entry: &Entry) -> Result<Option<Entry>> {
+        let Some(blobs) = &self.blobs else {
+            return Ok(None);
+        };
+        let value = match (&entry.value, &entry.blob) {
+            (_, Some(index)) if blobs.relocate.contains(&index.file_number) => {
+                blobs.cache.get(index.file_number)?.read(index)?
+            }
+            (Some(value), None) if !entry.deleted => value.clone(),
+            _ => return Ok(None),
+        };
+        if blobs.min_blob_size.is_none_or(|min| value.len() < min) {
+            return Ok(entry.blob.is_some().then(|| Entry {
+                value: Some(value),
+                blob: None,
+                ..entry.clone()
+            }));
+        }
+        let writer = match &mut self.blob_writer {
+            Some(writer) => writer,
+            None => {
+                let dir = self.path.parent().unwrap_or(Path::new("."));
+                self.blob_writer.insert(BlobWriter::new(dir, self.id)?)
+            }
+        };
+        Ok(Some(Entry {
+            value: None,
+            blob: Some(writer.add(&value)?),
+            ..entry.clone()
+        }))
+    }
+
+    fn track(&mut self, entry: &Entry) {
+        let key = &entry.key;
+        self.key_hashes.push(filter::hash(key));
+        match self.key_range.as_mut() {
+            Some((_, largest)) => *largest = key.to_vec(),
+            None => self.key_range = Some((key.to_vec(), key.to_vec())),
+        }
+        if let Some(index) = entry.blob.filter(|_| !entry.deleted) {
+            let (count, bytes) = self.blob_refs.entry(index.file_number).or_default();
+            *count += 1;
+            *bytes += index.len;
+        }
+    }
+
+    /// Size of the filter written on `flush`. Tables merged from this one inherit it.
+    pub fn set_bits_per_key(&mut self, bits_per_key: usize) {
+        self.bits_per_key = bits_per_key;
+    }
+
+    /// How the data blocks of an empty table are compressed; a table that has blocks keeps
+    /// their format. Tables merged from this one inherit it.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Order the table is written in, used by `get` and `merge`. Tables merged from this
+    /// one inherit it.
+    pub fn set_comparator(&mut self, comparator: Arc<dyn Comparator>) {
+        self.comparator = comparator;
+    }
+
+    /// How values are written from now on: large ones go to a blob file named after the
+    /// table, and values in the blob files being relocated are written again. Tables merged
+    /// from this one inherit it, and read blob values from its cache to fold operands.
+    pub fn set_blobs(&mut self, blobs: BlobOptions) {
+        self.blobs = Some(blobs);
+    }
+
+    /// Number and length of the values in each blob file the table points to.
+    pub fn blob_refs(&self) -> &BTreeMap<u64, (u64, u64)> {
+        &self.blob_refs
+    }
+
+    /// The blob file the table wrote values to, if any.
+    pub fn blob_file(&self) -> Option<&BlobWriter> {
+        self.blob_writer.as_ref()
+    }
+
+    /// Replaces the blob index of `entry` with its value, read through the cache set by
+    /// `set_blobs`.
+    pub(super) fn load_blob(&self, entry: Entry) -> Result<Entry> {
+        let cache = self.blobs.as_ref().map(|blobs| blobs.cache.clone());
+        blob::load(entry, |number| match cache {
+            Some(cache) => cache.get(number),
+            None => Err(Error::NotFound(format!("blob file {:06}", number))),
+        })
+    }
+
+    /// Adds a range tombstone, written to its own file on `flush`.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.range_tombstones.push(tombstone);
+    }
+
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// Smallest and largest key written or bounding a range tombstone, `None` while the
+    /// table is empty.
+    pub fn key_range(&self) -> Option<(&[u8], &[u8])> {
+        let keys = self
+            .key_range
+            .as_ref()
+            .map(|(smallest, largest)| (smallest.as_slice(), largest.as_slice()));
+        let ranges = self
+            .range_tombstones
+            .iter()
+            .map(|tombstone| (tombstone.start.as_slice(), tombstone.end.as_slice()));
+        keys.into_iter().chain(ranges).reduce(|a, b| {
+            let smallest = match self.comparator.compare(a.0, b.0) {
+                Ordering::Greater => b.0,
+                _ => a.0,
+            };
+            let largest = match self.comparator.compare(a.1, b.1) {
+                Ordering::Less => b.1,
+                _ => a.1,
+            };
+            (smallest, largest)
+        })
+    }
+
+    /// Size of the data file in bytes.
+    pub fn size(&self) -> u64 {
+        self.data.get_offset()
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        // the blob file has to be complete before any record points into it
+        if let Some(writer) = &mut self.blob_writer {
+            writer.flush()?;
+        }
+        self.finish_block()?;
+        write_checksums(&self.path, self.block_checksums.iter().copied())?;
+        self.index.flush()?;
+        BloomFilter::from_keys(&self.key_hashes, self.bits_per_key)
+            .write_to(&filter_path(&self.path))?;
+        write_range_tombstones(&range_path(&self.path), &self.range_tombstones)?;
+        self.data.flush()?;
+        // a MANIFEST edit may name the table as soon as this returns
+        for file in table_files(&self.path) {
+            File::open(file)?.sync_all()?;
+        }
+        Ok(())
     }
 
-    pub fn get(&self, key: &[u8]) -> io::Result<Option<Entry>> {
+    pub fn get(&self, key: &[u8]) -> Result<Option<Entry>> {
         let mut offset: u64 = 0;
         for entry in IndexIterator::new(self.index.path.clone())? {
-            match key.cmp(entry.key.as_slice()) {
+            match self.comparator.compare(key, &entry.key) {
                 Ordering::Equal => {
                     offset = entry.offset;
                     break;
@@ -120,13 +369,9 @@ impl SSTable {
         }
         let iterator = DataIterator::new(self.data.path.clone(), offset)?;
         for entry in iterator {
-            if entry.key.as_slice() == key {
-                return Ok(Some(Entry {
-                    key: entry.key,
-                    value: entry.value,
-                    timestamp: entry.timestamp,
-                    deleted: entry.deleted,
-                }));
+            let entry = entry?;
+            if self.comparator.compare(&entry.key, key) == Ordering::Equal {
+                return Ok(Some(entry));
             }
         }
         Ok(None)
@@ -146,25 +391,156 @@ fn size(entry: &Entry) -> usize {
     }
 }
 
-pub fn files_with_ext(dir: &Path, ext: &str) -> Vec<PathBuf> {
+pub const TABLE_EXT: &str = "sst";
+
+fn write_checksums(path: &Path, checksums: impl Iterator<Item = BlockChecksum>) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    let mut file = BufWriter::new(file);
+    for checksum in checksums {
+        file.write_all(&checksum.offset.to_le_bytes())?;
+        file.write_all(&checksum.len.to_le_bytes())?;
+        file.write_all(&checksum.crc.to_le_bytes())?;
+    }
+    Ok(file.flush()?)
+}
+
+/// Block checksums stored in the table file at `path`; tables written before checksums
+/// existed have none.
+pub fn read_checksums(path: &Path) -> Result<Vec<BlockChecksum>> {
+    let bytes = fs::read(path)?;
+    if !bytes.len().is_multiple_of(CHECKSUM_LEN) {
+        return Err(Error::Corruption(format!(
+            "checksums of {} are truncated",
+            path.display()
+        )));
+    }
+    Ok(bytes
+        .chunks(CHECKSUM_LEN)
+        .map(|record| BlockChecksum {
+            offset: u64::from_le_bytes(record[..8].try_into().unwrap()),
+            len: u64::from_le_bytes(record[8..16].try_into().unwrap()),
+            crc: u32::from_le_bytes(record[16..].try_into().unwrap()),
+        })
+        .collect())
+}
+
+fn write_range_tombstones(path: &Path, tombstones: &[RangeTombstone]) -> Result<()> {
+    let mut buf = Vec::new();
+    for tombstone in tombstones {
+        tombstone.encode(&mut buf);
+    }
+    Ok(fs::write(path, buf)?)
+}
+
+/// Range tombstones stored next to the table at `path`; tables written before range
+/// deletes existed have none.
+pub fn read_range_tombstones(path: &Path) -> Result<Vec<RangeTombstone>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut reader = bytes.as_slice();
+    let mut tombstones = Vec::new();
+    while !reader.is_empty() {
+        let Some(tombstone) = RangeTombstone::decode(&mut reader) else {
+            return Err(Error::Corruption(format!(
+                "range tombstones of {} are truncated",
+                path.display()
+            )));
+        };
+        tombstones.push(tombstone);
+    }
+    Ok(tombstones)
+}
+
+pub fn data_path(path: &Path) -> PathBuf {
+    path.with_extension("data.sst")
+}
+
+pub fn index_path(path: &Path) -> PathBuf {
+    path.with_extension("index.sst")
+}
+
+pub fn filter_path(path: &Path) -> PathBuf {
+    path.with_extension("filter.sst")
+}
+
+pub fn range_path(path: &Path) -> PathBuf {
+    path.with_extension("range.sst")
+}
+
+/// Path of the table with the given id inside `dir`, e.g. `000124.sst`.
+pub fn table_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(file_name(id, TABLE_EXT))
+}
+
+/// Name of a numbered database file; zero padding keeps directory listings in order.
+pub fn file_name(number: u64, ext: &str) -> String {
+    format!("{:06}.{}", number, ext)
+}
+
+/// All files belonging to the table at `path`, the table file itself last.
+pub fn table_files(path: &Path) -> [PathBuf; 5] {
+    [
+        data_path(path),
+        index_path(path),
+        filter_path(path),
+        range_path(path),
+        path.to_owned(),
+    ]
+}
+
+/// Removes all files belonging to the table with the given id.
+pub fn remove_table(dir: &Path, id: u64) -> Result<()> {
+    for file in table_files(&table_path(dir, id)) {
+        match remove_file(&file) {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Derives a numeric id for a table from the number at the start of its file name.
+pub fn file_id(path: &Path) -> u64 {
+    file_number(path).unwrap_or_else(|| {
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        hasher.finish()
+    })
+}
+
+/// Number in the name of a database file, or `None` if the name is not numbered.
+pub fn file_number(path: &Path) -> Option<u64> {
+    let name = path.file_name().and_then(|n| n.to_str())?;
+    name.split('.').next()?.parse().ok()
+}
+
+pub fn files_with_ext(dir: &Path, ext: &str) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
-    for file in read_dir(dir).unwrap() {
-        let path = file.unwrap().path();
-        if path.extension().unwrap() == ext {
+    for file in read_dir(dir)? {
+        let path = file?.path();
+        if path.extension().is_some_and(|e| e == ext) {
             files.push(path);
         }
     }
-    files
+    Ok(files)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sstable::test_util::{create_entry, create_path};
 
     #[test]
     fn test_get_entry_from_sstable() {
         let mut sstable = create_sstable().unwrap();
-        let entry = create_entry();
+        let entry = create_entry(&[1, 2, 3], Some(&[9]), 1);
         sstable.write(&entry).unwrap();
         sstable.flush().unwrap();
         let return_value = sstable.get(entry.key.as_slice()).unwrap();
@@ -172,21 +548,59 @@ mod tests {
         assert_eq!(return_value.unwrap().key, entry.key);
     }
 
-    fn create_entry() -> Entry {
-        Entry {
-            key: vec![1, 2, 3],
-            value: Some(vec![9]),
-            timestamp: 1,
-            deleted: false,
+    #[test]
+    fn test_files_are_named_by_number() {
+        let path = create_path("sstable_file_names");
+        let sstable = SSTable::new(&path, 124).unwrap();
+        assert_eq!(sstable.id, 124);
+        assert_eq!(sstable.path, path.join("000124.sst"));
+        assert!(path.join("000124.data.sst").exists());
+        assert_eq!(file_number(&data_path(&sstable.path)), Some(124));
+    }
+
+    #[test]
+    fn test_checksums_survive_reopen() {
+        let path = create_path("sstable_checksums");
+        let mut sstable = SSTable::new(&path, 1).unwrap();
+        for i in 0..2000u32 {
+            let mut entry = create_entry(&[1, 2, 3], Some(&[9]), 1);
+            entry.key = i.to_be_bytes().to_vec();
+            entry.value = Some(vec![0; 100]);
+            sstable.write(&entry).unwrap();
         }
+        sstable.flush().unwrap();
+        let checksums = read_checksums(&sstable.path).unwrap();
+        assert!(checksums.len() > 1);
+        assert_eq!(checksums.iter().map(|c| c.len).sum::<u64>(), sstable.size());
+
+        let mut reopened = SSTable::from_path(&sstable.path).unwrap();
+        reopened.flush().unwrap();
+        assert_eq!(read_checksums(&sstable.path).unwrap(), checksums);
     }
 
-    fn create_path() -> PathBuf {
-        PathBuf::from("data")
+    #[test]
+    fn test_range_tombstones_widen_key_range() {
+        let path = create_path("sstable_range_tombstones");
+        let mut sstable = SSTable::new(&path, 1).unwrap();
+        sstable
+            .write(&create_entry(&[1, 2, 3], Some(&[9]), 1))
+            .unwrap();
+        let tombstone = RangeTombstone {
+            start: vec![1, 2],
+            end: vec![5],
+            timestamp: 2,
+        };
+        sstable.add_range_tombstone(tombstone.clone());
+        sstable.flush().unwrap();
+        assert_eq!(sstable.key_range(), Some((&[1, 2][..], &[5][..])));
+
+        let reopened = SSTable::from_path(&sstable.path).unwrap();
+        assert_eq!(reopened.range_tombstones(), &[tombstone]);
+        assert_eq!(reopened.key_range(), sstable.key_range());
     }
 
-    fn create_sstable() -> io::Result<SSTable> {
-        let path = create_path();
-        SSTable::new(&path)
+    fn create_sstable() -> Result<SSTable> {
+        let path = create_path("sstable_get_entry");
+        SSTable::new(&path, 1)
     }
 }
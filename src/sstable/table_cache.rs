@@ -0,0 +1,155 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{database::comparator::Comparator, Result};
+
+use super::{cache::BlockCache, reader::TableReader, sstable::table_path};
+
+#[derive(Default)]
+struct Handles {
+    tick: u64,
+    readers: HashMap<u64, (Arc<TableReader>, u64)>,
+    // least recently used table ids come first
+    lru: BTreeMap<u64, u64>,
+}
+
+/// Bounded cache of open sstable readers keyed by table id.
+///
+/// At most `max_open_files` readers are kept; the least recently used one is closed when
+/// another table has to be opened. Readers still in use elsewhere stay valid until dropped.
+pub struct TableCache {
+    dir: PathBuf,
+    max_open_files: usize,
+    block_cache: Arc<BlockCache>,
+    comparator: Arc<dyn Comparator>,
+    handles: Mutex<Handles>,
+}
+
+impl TableCache {
+    pub fn new(
+        dir: &Path,
+        max_open_files: usize,
+        block_cache: Arc<BlockCache>,
+        comparator: Arc<dyn Comparator>,
+    ) -> TableCache {
+        TableCache {
+            dir: dir.to_owned(),
+            max_open_files: max_open_files.max(1),
+            block_cache,
+            comparator,
+            handles: Mutex::new(Handles::default()),
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Result<Arc<TableReader>> {
+        let mut handles = self.handles.lock().unwrap();
+        handles.tick += 1;
+        let tick = handles.tick;
+        if let Some((reader, last_used)) = handles.readers.get_mut(&id) {
+            let reader = reader.clone();
+            let previous = std::mem::replace(last_used, tick);
+            handles.lru.remove(&previous);
+            handles.lru.insert(tick, id);
+            return Ok(reader);
+        }
+        let reader = Arc::new(TableReader::open(
+            &table_path(&self.dir, id),
+            self.block_cache.clone(),
+            self.comparator.clone(),
+        )?);
+        while handles.readers.len() >= self.max_open_files {
+            let Some((_, evicted)) = handles.lru.pop_first() else {
+                break;
+            };
+            handles.readers.remove(&evicted);
+        }
+        handles.readers.insert(id, (reader.clone(), tick));
+        handles.lru.insert(tick, id);
+        Ok(reader)
+    }
+
+    /// Closes the reader of a table that is no longer live. Its cached blocks are dropped
+    /// with the last handle on the reader.
+    pub fn evict(&self, id: u64) {
+        let mut handles = self.handles.lock().unwrap();
+        if let Some((_, last_used)) = handles.readers.remove(&id) {
+            handles.lru.remove(&last_used);
+        }
+    }
+
+    pub fn open_files(&self) -> usize {
+        self.handles.lock().unwrap().readers.len()
+    }
+
+    pub fn block_cache(&self) -> &Arc<BlockCache> {
+        &self.block_cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::comparator::BytewiseComparator,
+        sstable::{
+            sstable::SSTable,
+            test_util::{create_entry, create_path},
+        },
+    };
+
+    fn create_sstable(path: &Path, key: u8) -> SSTable {
+        let mut sstable = SSTable::new(path, key.into()).unwrap();
+        sstable
+            .write(&create_entry(&[key], Some(&[key]), 1))
+            .unwrap();
+        sstable.flush().unwrap();
+        sstable
+    }
+
+    #[test]
+    fn test_open_readers_are_bounded() {
+        let path = create_path("table_cache_bounded");
+        let cache = TableCache::new(
+            &path,
+            2,
+            Arc::new(BlockCache::new(1 << 20)),
+            Arc::new(BytewiseComparator),
+        );
+        let tables: Vec<SSTable> = (0..4).map(|key| create_sstable(&path, key)).collect();
+        for (key, table) in tables.iter().enumerate() {
+            let reader = cache.get(table.id).unwrap();
+            assert!(reader.get(&[key as u8]).unwrap().is_some());
+            assert!(cache.open_files() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_reader_is_reused() {
+        let path = create_path("table_cache_reuse");
+        let cache = TableCache::new(
+            &path,
+            2,
+            Arc::new(BlockCache::new(1 << 20)),
+            Arc::new(BytewiseComparator),
+        );
+        let table = create_sstable(&path, 1);
+        let first = cache.get(table.id).unwrap();
+        let second = cache.get(table.id).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn test_missing_table_returns_error() {
+        let path = create_path("table_cache_missing");
+        let cache = TableCache::new(
+            &path,
+            2,
+            Arc::new(BlockCache::new(1 << 20)),
+            Arc::new(BytewiseComparator),
+        );
+        assert!(cache.get(1).is_err());
+    }
+}
@@ -0,0 +1,25 @@
+use std::path::PathBuf;
+
+use crate::database::entry::Entry;
+
+/// Creates an empty `data/<name>` directory, removing any previous contents.
+pub fn create_path(name: &str) -> PathBuf {
+    let path = PathBuf::from("data").join(name);
+    std::fs::remove_dir_all(&path).ok();
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// An entry setting `key` to `value`, or a tombstone for `key` when `value` is `None`.
+pub fn create_entry(key: &[u8], value: Option<&[u8]>, timestamp: u128) -> Entry {
+    Entry {
+        key: key.to_vec(),
+        value: value.map(<[u8]>::to_vec),
+        timestamp,
+        deleted: value.is_none(),
+        single_delete: false,
+        blob: None,
+        expires_at: None,
+        operands: Vec::new(),
+    }
+}
@@ -0,0 +1,390 @@
+use crate::{Error, Result};
+
+use crate::database::options::{ColumnFamilyOptions, Compression};
+
+use super::version::{BlobFileGarbage, BlobFileMetaData, ColumnFamilyMetaData, FileMetaData};
+
+// every field of an edit is written as a tag byte followed by its value
+const TAG_LOG_NUMBER: u8 = 1;
+const TAG_NEXT_FILE_NUMBER: u8 = 2;
+const TAG_LAST_SEQUENCE: u8 = 3;
+const TAG_DELETED_FILE: u8 = 4;
+const TAG_NEW_FILE: u8 = 5;
+const TAG_NEW_COLUMN_FAMILY: u8 = 6;
+const TAG_DROPPED_COLUMN_FAMILY: u8 = 7;
+const TAG_NEXT_COLUMN_FAMILY: u8 = 8;
+// a new file of a column family other than the default one
+const TAG_NEW_FAMILY_FILE: u8 = 9;
+const TAG_COMPARATOR: u8 = 10;
+const TAG_NEW_BLOB_FILE: u8 = 11;
+const TAG_BLOB_FILE_GARBAGE: u8 = 12;
+// options of a new column family beyond its filter size, absent in older MANIFESTs
+const TAG_COLUMN_FAMILY_OPTIONS: u8 = 13;
+
+/// A change to the set of live tables, appended to the MANIFEST as one record.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct VersionEdit {
+    /// WAL files with a lower number only contain writes that are already in tables.
+    pub log_number: Option<u64>,
+    pub next_file_number: Option<u64>,
+    pub last_sequence: Option<u64>,
+    pub deleted_files: Vec<(usize, u64)>,
+    pub new_files: Vec<(usize, FileMetaData)>,
+    pub new_column_families: Vec<(u32, ColumnFamilyMetaData)>,
+    pub dropped_column_families: Vec<u32>,
+    pub next_column_family: Option<u32>,
+    /// Name of the comparator, logged when the database is created.
+    pub comparator: Option<String>,
+    pub new_blob_files: Vec<BlobFileMetaData>,
+    pub blob_file_garbage: Vec<BlobFileGarbage>,
+}
+
+impl VersionEdit {
+    pub fn add_file(&mut self, level: usize, file: FileMetaData) {
+        self.new_files.push((level, file));
+    }
+
+    pub fn delete_file(&mut self, level: usize, number: u64) {
+        self.deleted_files.push((level, number));
+    }
+
+    pub fn add_blob_file(&mut self, file: BlobFileMetaData) {
+        self.new_blob_files.push(file);
+    }
+
+    /// Records that `count` values with `bytes` bytes of blob file `number` became garbage.
+    pub fn add_blob_garbage(&mut self, number: u64, count: u64, bytes: u64) {
+        self.blob_file_garbage.push(BlobFileGarbage {
+            number,
+            count,
+            bytes,
+        });
+    }
+
+    pub fn add_column_family(&mut self, id: u32, family: ColumnFamilyMetaData) {
+        self.new_column_families.push((id, family));
+    }
+
+    pub fn drop_column_family(&mut self, id: u32) {
+        self.dropped_column_families.push(id);
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if let Some(log_number) = self.log_number {
+            buf.push(TAG_LOG_NUMBER);
+            buf.extend_from_slice(&log_number.to_le_bytes());
+        }
+        if let Some(next_file_number) = self.next_file_number {
+            buf.push(TAG_NEXT_FILE_NUMBER);
+            buf.extend_from_slice(&next_file_number.to_le_bytes());
+        }
+        if let Some(last_sequence) = self.last_sequence {
+            buf.push(TAG_LAST_SEQUENCE);
+            buf.extend_from_slice(&last_sequence.to_le_bytes());
+        }
+        if let Some(comparator) = &self.comparator {
+            buf.push(TAG_COMPARATOR);
+            buf.extend_from_slice(&(comparator.len() as u64).to_le_bytes());
+            buf.extend_from_slice(comparator.as_bytes());
+        }
+        if let Some(next_column_family) = self.next_column_family {
+            buf.push(TAG_NEXT_COLUMN_FAMILY);
+            buf.extend_from_slice(&next_column_family.to_le_bytes());
+        }
+        // families are created before their files are added and dropped after
+        for (id, family) in self.new_column_families.iter() {
+            buf.push(TAG_NEW_COLUMN_FAMILY);
+            buf.extend_from_slice(&id.to_le_bytes());
+            buf.extend_from_slice(&(family.name.len() as u64).to_le_bytes());
+            buf.extend_from_slice(family.name.as_bytes());
+            buf.extend_from_slice(&(family.options.bloom_bits_per_key as u64).to_le_bytes());
+            buf.push(TAG_COLUMN_FAMILY_OPTIONS);
+            buf.extend_from_slice(&id.to_le_bytes());
+            buf.push(match family.options.compression {
+                Compression::None => 0,
+                Compression::Snappy => 1,
+            });
+            let trigger = family.options.compaction_trigger.unwrap_or(0);
+            buf.extend_from_slice(&(trigger as u64).to_le_bytes());
+        }
+        for (level, number) in self.deleted_files.iter() {
+            buf.push(TAG_DELETED_FILE);
+            buf.extend_from_slice(&(*level as u64).to_le_bytes());
+            buf.extend_from_slice(&number.to_le_bytes());
+        }
+        for (level, file) in self.new_files.iter() {
+            if file.column_family == 0 {
+                buf.push(TAG_NEW_FILE);
+                buf.extend_from_slice(&(*level as u64).to_le_bytes());
+            } else {
+                buf.push(TAG_NEW_FAMILY_FILE);
+                buf.extend_from_slice(&(*level as u64).to_le_bytes());
+                buf.extend_from_slice(&file.column_family.to_le_bytes());
+            }
+            buf.extend_from_slice(&file.number.to_le_bytes());
+            buf.extend_from_slice(&file.size.to_le_bytes());
+            buf.extend_from_slice(&file.smallest_seqno.to_le_bytes());
+            buf.extend_from_slice(&file.largest_seqno.to_le_bytes());
+            buf.extend_from_slice(&(file.smallest.len() as u64).to_le_bytes());
+            buf.extend_from_slice(&file.smallest);
+            buf.extend_from_slice(&(file.largest.len() as u64).to_le_bytes());
+            buf.extend_from_slice(&file.largest);
+        }
+        for file in self.new_blob_files.iter() {
+            buf.push(TAG_NEW_BLOB_FILE);
+            buf.extend_from_slice(&file.number.to_le_bytes());
+            buf.extend_from_slice(&file.column_family.to_le_bytes());
+            buf.extend_from_slice(&file.count.to_le_bytes());
+            buf.extend_from_slice(&file.bytes.to_le_bytes());
+            buf.extend_from_slice(&file.garbage_count.to_le_bytes());
+            buf.extend_from_slice(&file.garbage_bytes.to_le_bytes());
+        }
+        for garbage in self.blob_file_garbage.iter() {
+            buf.push(TAG_BLOB_FILE_GARBAGE);
+            buf.extend_from_slice(&garbage.number.to_le_bytes());
+            buf.extend_from_slice(&garbage.count.to_le_bytes());
+            buf.extend_from_slice(&garbage.bytes.to_le_bytes());
+        }
+        for id in self.dropped_column_families.iter() {
+            buf.push(TAG_DROPPED_COLUMN_FAMILY);
+            buf.extend_from_slice(&id.to_le_bytes());
+        }
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<VersionEdit> {
+        let mut edit = VersionEdit::default();
+        while let Some((&tag, rest)) = buf.split_first() {
+            buf = rest;
+            match tag {
+                TAG_LOG_NUMBER => edit.log_number = Some(read_u64(&mut buf)?),
+                TAG_NEXT_FILE_NUMBER => edit.next_file_number = Some(read_u64(&mut buf)?),
+                TAG_LAST_SEQUENCE => edit.last_sequence = Some(read_u64(&mut buf)?),
+                TAG_DELETED_FILE => {
+                    let level = read_level(&mut buf)?;
+                    edit.delete_file(level, read_u64(&mut buf)?);
+                }
+                TAG_NEW_FILE | TAG_NEW_FAMILY_FILE => {
+                    let level = read_level(&mut buf)?;
+                    let column_family = match tag {
+                        TAG_NEW_FAMILY_FILE => read_u32(&mut buf)?,
+                        _ => 0,
+                    };
+                    let file = FileMetaData {
+                        number: read_u64(&mut buf)?,
+                        size: read_u64(&mut buf)?,
+                        smallest_seqno: read_u64(&mut buf)?,
+                        largest_seqno: read_u64(&mut buf)?,
+                        smallest: read_bytes(&mut buf)?,
+                        largest: read_bytes(&mut buf)?,
+                        column_family,
+                    };
+                    edit.add_file(level, file);
+                }
+                TAG_NEW_COLUMN_FAMILY => {
+                    let id = read_u32(&mut buf)?;
+                    let name = String::from_utf8(read_bytes(&mut buf)?).map_err(|_| {
+                        Error::Corruption("column family name is not UTF-8".to_string())
+                    })?;
+                    let options = ColumnFamilyOptions {
+                        bloom_bits_per_key: read_u64(&mut buf)? as usize,
+                        ..Default::default()
+                    };
+                    edit.add_column_family(id, ColumnFamilyMetaData { name, options });
+                }
+                TAG_COLUMN_FAMILY_OPTIONS => {
+                    let id = read_u32(&mut buf)?;
+                    let compression = match read_u8(&mut buf)? {
+                        0 => Compression::None,
+                        1 => Compression::Snappy,
+                        other => {
+                            return Err(Error::Corruption(format!("unknown compression {}", other)))
+                        }
+                    };
+                    let trigger = read_u64(&mut buf)? as usize;
+                    let Some((_, family)) = edit
+                        .new_column_families
+                        .iter_mut()
+                        .find(|(family_id, _)| *family_id == id)
+                    else {
+                        return Err(Error::Corruption(format!(
+                            "options of column family {} without the family",
+                            id
+                        )));
+                    };
+                    family.options.compression = compression;
+                    family.options.compaction_trigger = (trigger > 0).then_some(trigger);
+                }
+                TAG_NEW_BLOB_FILE => edit.add_blob_file(BlobFileMetaData {
+                    number: read_u64(&mut buf)?,
+                    column_family: read_u32(&mut buf)?,
+                    count: read_u64(&mut buf)?,
+                    bytes: read_u64(&mut buf)?,
+                    garbage_count: read_u64(&mut buf)?,
+                    garbage_bytes: read_u64(&mut buf)?,
+                }),
+                TAG_BLOB_FILE_GARBAGE => {
+                    let number = read_u64(&mut buf)?;
+                    edit.add_blob_garbage(number, read_u64(&mut buf)?, read_u64(&mut buf)?);
+                }
+                TAG_DROPPED_COLUMN_FAMILY => edit.drop_column_family(read_u32(&mut buf)?),
+                TAG_NEXT_COLUMN_FAMILY => edit.next_column_family = Some(read_u32(&mut buf)?),
+                TAG_COMPARATOR => {
+                    let name = String::from_utf8(read_bytes(&mut buf)?).map_err(|_| {
+                        Error::Corruption("comparator name is not UTF-8".to_string())
+                    })?;
+                    edit.comparator = Some(name);
+                }
+                tag => {
+                    return Err(Error::Corruption(format!(
+                        "unknown version edit tag {}",
+                        tag
+                    )))
+                }
+            }
+        }
+        Ok(edit)
+    }
+}
+
+fn read_u64(buf: &mut &[u8]) -> Result<u64> {
+    if buf.len() < 8 {
+        return Err(Error::Corruption("version edit is truncated".to_string()));
+    }
+    let (value, rest) = buf.split_at(8);
+    *buf = rest;
+    Ok(u64::from_le_bytes(value.try_into().unwrap_or_default()))
+}
+
+fn read_u8(buf: &mut &[u8]) -> Result<u8> {
+    let Some((&value, rest)) = buf.split_first() else {
+        return Err(Error::Corruption("version edit is truncated".to_string()));
+    };
+    *buf = rest;
+    Ok(value)
+}
+
+fn read_u32(buf: &mut &[u8]) -> Result<u32> {
+    if buf.len() < 4 {
+        return Err(Error::Corruption("version edit is truncated".to_string()));
+    }
+    let (value, rest) = buf.split_at(4);
+    *buf = rest;
+    Ok(u32::from_le_bytes(value.try_into().unwrap_or_default()))
+}
+
+fn read_level(buf: &mut &[u8]) -> Result<usize> {
+    let level = read_u64(buf)? as usize;
+    if level >= super::version::NUM_LEVELS {
+        return Err(Error::Corruption(format!("invalid level {}", level)));
+    }
+    Ok(level)
+}
+
+fn read_bytes(buf: &mut &[u8]) -> Result<Vec<u8>> {
+    let len = read_u64(buf)? as usize;
+    if buf.len() < len {
+        return Err(Error::Corruption("version edit is truncated".to_string()));
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(bytes.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_file(number: u64) -> FileMetaData {
+        FileMetaData {
+            number,
+            size: 100,
+            smallest: vec![1],
+            largest: vec![9, 9],
+            smallest_seqno: 3,
+            largest_seqno: 7,
+            column_family: 0,
+        }
+    }
+
+    #[test]
+    fn test_edit_roundtrip() {
+        let mut edit = VersionEdit {
+            log_number: Some(4),
+            next_file_number: Some(12),
+            last_sequence: Some(99),
+            ..Default::default()
+        };
+        edit.add_file(0, create_file(10));
+        edit.add_file(1, create_file(11));
+        edit.delete_file(0, 3);
+        edit.add_column_family(
+            2,
+            ColumnFamilyMetaData {
+                name: "users".to_string(),
+                options: ColumnFamilyOptions {
+                    bloom_bits_per_key: 4,
+                    compression: Compression::Snappy,
+                    compaction_trigger: Some(4),
+                },
+            },
+        );
+        edit.add_file(
+            0,
+            FileMetaData {
+                column_family: 2,
+                ..create_file(12)
+            },
+        );
+        edit.drop_column_family(1);
+        edit.next_column_family = Some(3);
+        edit.comparator = Some("rustdb.U64Comparator".to_string());
+        edit.add_blob_file(BlobFileMetaData {
+            number: 10,
+            column_family: 2,
+            count: 5,
+            bytes: 500,
+            garbage_count: 1,
+            garbage_bytes: 100,
+        });
+        edit.add_blob_garbage(10, 2, 200);
+        let decoded = VersionEdit::decode(&edit.encode()).unwrap();
+        assert_eq!(decoded, edit);
+    }
+
+    #[test]
+    fn test_family_without_options_record_has_defaults() {
+        let mut edit = VersionEdit::default();
+        edit.add_column_family(
+            1,
+            ColumnFamilyMetaData {
+                name: "users".to_string(),
+                options: ColumnFamilyOptions {
+                    bloom_bits_per_key: 4,
+                    compression: Compression::Snappy,
+                    compaction_trigger: Some(4),
+                },
+            },
+        );
+        // a MANIFEST written before the options record existed ends after the filter size
+        let encoded = edit.encode();
+        let decoded = VersionEdit::decode(&encoded[..encoded.len() - 14]).unwrap();
+        assert_eq!(
+            decoded.new_column_families[0].1.options,
+            ColumnFamilyOptions {
+                bloom_bits_per_key: 4,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_truncated_edit_is_corruption() {
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, create_file(10));
+        let encoded = edit.encode();
+        let result = VersionEdit::decode(&encoded[..encoded.len() - 1]);
+        assert!(matches!(result, Err(Error::Corruption(_))));
+    }
+}
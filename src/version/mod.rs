@@ -0,0 +1,3 @@
+pub mod edit;
+pub mod version;
+pub mod version_set;
@@ -0,0 +1,385 @@
+use std::{cmp::Ordering, collections::BTreeMap, ops::Bound};
+
+use crate::database::{comparator::Comparator, options::ColumnFamilyOptions};
+
+use super::edit::VersionEdit;
+
+pub const NUM_LEVELS: usize = 7;
+
+/// Metadata about one live sstable as recorded in the MANIFEST.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileMetaData {
+    pub number: u64,
+    pub size: u64,
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+    pub smallest_seqno: u64,
+    pub largest_seqno: u64,
+    /// Id of the column family the table belongs to, 0 for the default one.
+    pub column_family: u32,
+}
+
+impl FileMetaData {
+    pub fn contains(&self, key: &[u8], comparator: &dyn Comparator) -> bool {
+        comparator.compare(&self.smallest, key) != Ordering::Greater
+            && comparator.compare(key, &self.largest) != Ordering::Greater
+    }
+
+    pub fn overlaps(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        comparator: &dyn Comparator,
+    ) -> bool {
+        let after_start = match start {
+            Bound::Included(start) => comparator.compare(start, &self.largest) != Ordering::Greater,
+            Bound::Excluded(start) => comparator.compare(start, &self.largest) == Ordering::Less,
+            Bound::Unbounded => true,
+        };
+        let before_end = match end {
+            Bound::Included(end) => comparator.compare(&self.smallest, end) != Ordering::Greater,
+            Bound::Excluded(end) => comparator.compare(&self.smallest, end) == Ordering::Less,
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+}
+
+/// A live blob file as recorded in the MANIFEST, with how much of it compaction found to
+/// be garbage: values no live table points to anymore.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobFileMetaData {
+    pub number: u64,
+    /// Id of the column family whose tables point into the file.
+    pub column_family: u32,
+    pub count: u64,
+    pub bytes: u64,
+    pub garbage_count: u64,
+    pub garbage_bytes: u64,
+}
+
+impl BlobFileMetaData {
+    /// Share of the file's bytes that are garbage.
+    pub fn garbage_ratio(&self) -> f64 {
+        match self.bytes {
+            0 => self.garbage_count as f64 / self.count.max(1) as f64,
+            bytes => self.garbage_bytes as f64 / bytes as f64,
+        }
+    }
+}
+
+/// Values of a blob file that compaction dropped or moved elsewhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobFileGarbage {
+    pub number: u64,
+    pub count: u64,
+    pub bytes: u64,
+}
+
+/// A column family other than the default one, as recorded in the MANIFEST.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnFamilyMetaData {
+    pub name: String,
+    pub options: ColumnFamilyOptions,
+}
+
+/// The set of live tables per level at one point in time, the column families they
+/// belong to and the blob files they point into.
+///
+/// Level 0 holds flushed memtables which may overlap and are ordered oldest first; tables
+/// on higher levels are produced by compaction and ordered by their smallest key bytewise,
+/// whatever the database's comparator. Only tables of the same column family are ever
+/// compared, so families share the levels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Version {
+    levels: Vec<Vec<FileMetaData>>,
+    column_families: BTreeMap<u32, ColumnFamilyMetaData>,
+    // a blob file is dropped once all of its values are garbage
+    blob_files: BTreeMap<u64, BlobFileMetaData>,
+    // ids are never reused, so WAL records of a dropped family cannot be mistaken for
+    // writes to a new one
+    next_column_family: u32,
+    // name of the comparator the database was created with, `None` before comparators
+    // were recorded
+    comparator: Option<String>,
+}
+
+impl Default for Version {
+    fn default() -> Self {
+        Version {
+            levels: vec![Vec::new(); NUM_LEVELS],
+            column_families: BTreeMap::new(),
+            blob_files: BTreeMap::new(),
+            next_column_family: 1,
+            comparator: None,
+        }
+    }
+}
+
+impl Version {
+    pub fn files(&self, level: usize) -> &[FileMetaData] {
+        &self.levels[level]
+    }
+
+    /// All live tables together with their level.
+    pub fn all_files(&self) -> impl Iterator<Item = (usize, &FileMetaData)> {
+        self.levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, files)| files.iter().map(move |f| (level, f)))
+    }
+
+    /// Live tables of one column family together with their level.
+    pub fn family_files(&self, column_family: u32) -> impl Iterator<Item = (usize, &FileMetaData)> {
+        self.all_files()
+            .filter(move |(_, f)| f.column_family == column_family)
+    }
+
+    /// Live tables of one column family together with their level, newest first.
+    pub fn family_files_newest_first(
+        &self,
+        column_family: u32,
+    ) -> impl Iterator<Item = (usize, &FileMetaData)> {
+        let higher = self.levels.iter().enumerate().skip(1);
+        self.levels[0]
+            .iter()
+            .rev()
+            .map(|f| (0, f))
+            .chain(higher.flat_map(|(level, files)| files.iter().map(move |f| (level, f))))
+            .filter(move |(_, f)| f.column_family == column_family)
+    }
+
+    /// Tables of the column family that may contain `key`, in the order they have to be
+    /// searched (newest first).
+    pub fn tables_for_key(
+        &self,
+        column_family: u32,
+        key: &[u8],
+        comparator: &dyn Comparator,
+    ) -> Vec<&FileMetaData> {
+        self.newest_first()
+            .filter(|f| f.column_family == column_family && f.contains(key, comparator))
+            .collect()
+    }
+
+    /// Tables of the column family overlapping the key range, newest first.
+    pub fn tables_for_range(
+        &self,
+        column_family: u32,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        comparator: &dyn Comparator,
+    ) -> Vec<&FileMetaData> {
+        self.newest_first()
+            .filter(|f| f.column_family == column_family && f.overlaps(start, end, comparator))
+            .collect()
+    }
+
+    /// Column families other than the default one by id.
+    pub fn column_families(&self) -> &BTreeMap<u32, ColumnFamilyMetaData> {
+        &self.column_families
+    }
+
+    /// Live blob files by number.
+    pub fn blob_files(&self) -> &BTreeMap<u64, BlobFileMetaData> {
+        &self.blob_files
+    }
+
+    pub fn next_column_family(&self) -> u32 {
+        self.next_column_family
+    }
+
+    /// Name of the comparator the database was created with, see `Comparator::name`.
+    pub fn comparator(&self) -> Option<&str> {
+        self.comparator.as_deref()
+    }
+
+    /// An edit that recreates this version from scratch.
+    pub fn snapshot(&self) -> VersionEdit {
+        let mut snapshot = VersionEdit {
+            next_column_family: Some(self.next_column_family),
+            comparator: self.comparator.clone(),
+            ..Default::default()
+        };
+        for (id, family) in self.column_families.iter() {
+            snapshot.add_column_family(*id, family.clone());
+        }
+        for (level, file) in self.all_files() {
+            snapshot.add_file(level, file.clone());
+        }
+        for file in self.blob_files.values() {
+            snapshot.add_blob_file(file.clone());
+        }
+        snapshot
+    }
+
+    fn newest_first(&self) -> impl Iterator<Item = &FileMetaData> {
+        self.levels[0]
+            .iter()
+            .rev()
+            .chain(self.levels.iter().skip(1).flatten())
+    }
+
+    pub fn apply(&mut self, edit: &VersionEdit) {
+        if let Some(comparator) = &edit.comparator {
+            self.comparator = Some(comparator.clone());
+        }
+        for (id, family) in edit.new_column_families.iter() {
+            self.column_families.insert(*id, family.clone());
+            self.next_column_family = self.next_column_family.max(id + 1);
+        }
+        if let Some(next) = edit.next_column_family {
+            self.next_column_family = self.next_column_family.max(next);
+        }
+        for id in edit.dropped_column_families.iter() {
+            self.column_families.remove(id);
+            self.blob_files.retain(|_, f| f.column_family != *id);
+        }
+        for (level, number) in edit.deleted_files.iter() {
+            self.levels[*level].retain(|f| f.number != *number);
+        }
+        for (level, file) in edit.new_files.iter() {
+            self.levels[*level].push(file.clone());
+        }
+        for file in edit.new_blob_files.iter() {
+            self.blob_files.insert(file.number, file.clone());
+        }
+        for garbage in edit.blob_file_garbage.iter() {
+            if let Some(file) = self.blob_files.get_mut(&garbage.number) {
+                file.garbage_count += garbage.count;
+                file.garbage_bytes += garbage.bytes;
+            }
+        }
+        self.blob_files
+            .retain(|_, f| f.garbage_count < f.count || f.garbage_bytes < f.bytes);
+        self.levels[0].sort_by_key(|f| (f.largest_seqno, f.number));
+        for files in self.levels.iter_mut().skip(1) {
+            files.sort_by(|a, b| a.smallest.cmp(&b.smallest));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::comparator::BytewiseComparator;
+
+    fn create_file(number: u64, smallest: u8, largest: u8, seqno: u64) -> FileMetaData {
+        FileMetaData {
+            number,
+            size: 1,
+            smallest: vec![smallest],
+            largest: vec![largest],
+            smallest_seqno: seqno,
+            largest_seqno: seqno,
+            column_family: 0,
+        }
+    }
+
+    #[test]
+    fn test_newest_level_zero_table_is_searched_first() {
+        let mut version = Version::default();
+        let mut edit = VersionEdit::default();
+        edit.add_file(1, create_file(1, 0, 9, 1));
+        edit.add_file(0, create_file(2, 0, 9, 2));
+        edit.add_file(0, create_file(3, 0, 9, 3));
+        edit.add_file(0, create_file(4, 5, 9, 4));
+        version.apply(&edit);
+        let numbers: Vec<u64> = version
+            .tables_for_key(0, &[1], &BytewiseComparator)
+            .iter()
+            .map(|f| f.number)
+            .collect();
+        assert_eq!(numbers, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_tables_for_range_skip_disjoint_tables() {
+        let mut version = Version::default();
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, create_file(1, 0, 4, 1));
+        edit.add_file(0, create_file(2, 5, 9, 2));
+        edit.add_file(1, create_file(3, 3, 6, 1));
+        version.apply(&edit);
+        let numbers: Vec<u64> = version
+            .tables_for_range(
+                0,
+                Bound::Excluded(&[4]),
+                Bound::Unbounded,
+                &BytewiseComparator,
+            )
+            .iter()
+            .map(|f| f.number)
+            .collect();
+        assert_eq!(numbers, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_deleted_files_are_removed() {
+        let mut version = Version::default();
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, create_file(1, 0, 9, 1));
+        version.apply(&edit);
+        let mut edit = VersionEdit::default();
+        edit.delete_file(0, 1);
+        version.apply(&edit);
+        assert_eq!(version.all_files().count(), 0);
+    }
+
+    #[test]
+    fn test_column_families_keep_their_tables_apart() {
+        let mut version = Version::default();
+        let mut edit = VersionEdit::default();
+        edit.add_column_family(
+            1,
+            ColumnFamilyMetaData {
+                name: "users".to_string(),
+                options: ColumnFamilyOptions::default(),
+            },
+        );
+        let mut file = create_file(2, 0, 9, 2);
+        file.column_family = 1;
+        edit.add_file(0, create_file(1, 0, 9, 1));
+        edit.add_file(0, file);
+        version.apply(&edit);
+        assert_eq!(
+            version.tables_for_key(0, &[1], &BytewiseComparator)[0].number,
+            1
+        );
+        assert_eq!(
+            version.tables_for_key(1, &[1], &BytewiseComparator)[0].number,
+            2
+        );
+        assert_eq!(version.snapshot().new_column_families.len(), 1);
+
+        let mut edit = VersionEdit::default();
+        edit.drop_column_family(1);
+        edit.delete_file(0, 2);
+        version.apply(&edit);
+        assert!(version.column_families().is_empty());
+        // the id of a dropped family is not handed out again
+        assert_eq!(version.next_column_family(), 2);
+    }
+
+    #[test]
+    fn test_blob_file_is_dropped_once_all_garbage() {
+        let mut version = Version::default();
+        let mut edit = VersionEdit::default();
+        edit.add_blob_file(BlobFileMetaData {
+            number: 5,
+            column_family: 0,
+            count: 2,
+            bytes: 300,
+            garbage_count: 0,
+            garbage_bytes: 0,
+        });
+        edit.add_blob_garbage(5, 1, 100);
+        version.apply(&edit);
+        assert_eq!(version.blob_files()[&5].garbage_ratio(), 1.0 / 3.0);
+        assert_eq!(version.snapshot().new_blob_files[0].garbage_count, 1);
+        let mut edit = VersionEdit::default();
+        edit.add_blob_garbage(5, 1, 200);
+        version.apply(&edit);
+        assert!(version.blob_files().is_empty());
+    }
+}
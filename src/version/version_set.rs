@@ -0,0 +1,322 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use crate::{checksum::crc32, Error, Result};
+
+use super::{edit::VersionEdit, version::Version};
+
+pub const CURRENT_FILE: &str = "CURRENT";
+pub const MANIFEST_PREFIX: &str = "MANIFEST-";
+
+// +----------------+---------------+-----...-----+
+// | Checksum (4B)  | Length (4B)   | VersionEdit |
+// +----------------+---------------+-----...-----+
+
+/// Tracks the live tables of a database across restarts.
+///
+/// Every change is appended to the MANIFEST as a `VersionEdit`; CURRENT names the MANIFEST
+/// in use. Each time the database is opened a fresh MANIFEST starting with a snapshot of
+/// the recovered state is written, so the log never grows without bound.
+pub struct VersionSet {
+    current: Version,
+    manifest: BufWriter<File>,
+    manifest_number: u64,
+    next_file_number: u64,
+    pub last_sequence: u64,
+    pub log_number: u64,
+}
+
+impl VersionSet {
+    pub fn recover(dir: &Path) -> Result<VersionSet> {
+        let state = read_manifest(dir)?.unwrap_or_default();
+        Self::create(
+            dir,
+            state.next_file_number,
+            state.last_sequence,
+            state.snapshot(),
+        )
+    }
+
+    /// Starts a MANIFEST numbered `next_file_number` holding only `snapshot` and points
+    /// CURRENT at it once it is durable, replacing whatever MANIFEST was in use.
+    pub fn create(
+        dir: &Path,
+        next_file_number: u64,
+        last_sequence: u64,
+        snapshot: VersionEdit,
+    ) -> Result<VersionSet> {
+        let manifest_number = next_file_number;
+        let manifest = create_manifest(dir, manifest_number)?;
+        let mut versions = VersionSet {
+            current: Version::default(),
+            manifest,
+            manifest_number,
+            next_file_number: manifest_number + 1,
+            last_sequence,
+            log_number: 0,
+        };
+        versions.log_and_apply(snapshot)?;
+        set_current(dir, manifest_number)?;
+        Ok(versions)
+    }
+
+    pub fn current(&self) -> &Version {
+        &self.current
+    }
+
+    pub fn manifest_number(&self) -> u64 {
+        self.manifest_number
+    }
+
+    pub fn next_file_number(&self) -> u64 {
+        self.next_file_number
+    }
+
+    pub fn new_file_number(&mut self) -> u64 {
+        let number = self.next_file_number;
+        self.next_file_number += 1;
+        number
+    }
+
+    /// Makes sure `number`, found on disk but possibly never logged, is not handed out again.
+    pub fn mark_file_number_used(&mut self, number: u64) {
+        self.next_file_number = self.next_file_number.max(number + 1);
+    }
+
+    /// Numbers of every table referenced by the current version.
+    pub fn live_files(&self) -> HashSet<u64> {
+        self.current.all_files().map(|(_, f)| f.number).collect()
+    }
+
+    /// Numbers of every blob file referenced by the current version.
+    pub fn live_blob_files(&self) -> HashSet<u64> {
+        self.current.blob_files().keys().copied().collect()
+    }
+
+    /// Durably appends `edit` to the MANIFEST and makes it part of the current version.
+    pub fn log_and_apply(&mut self, mut edit: VersionEdit) -> Result<()> {
+        if let Some(log_number) = edit.log_number {
+            self.log_number = log_number;
+        }
+        for (_, file) in edit.new_files.iter() {
+            self.next_file_number = self.next_file_number.max(file.number + 1);
+        }
+        edit.next_file_number = Some(self.next_file_number);
+        edit.last_sequence = Some(self.last_sequence);
+
+        let payload = edit.encode();
+        self.manifest.write_all(&crc32(&payload).to_le_bytes())?;
+        self.manifest
+            .write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.manifest.write_all(&payload)?;
+        self.manifest.flush()?;
+        self.manifest.get_ref().sync_data()?;
+
+        self.current.apply(&edit);
+        Ok(())
+    }
+}
+
+/// What replaying a MANIFEST yields.
+#[derive(Debug)]
+pub struct ManifestState {
+    pub current: Version,
+    pub next_file_number: u64,
+    pub last_sequence: u64,
+    pub log_number: u64,
+}
+
+impl Default for ManifestState {
+    fn default() -> Self {
+        ManifestState {
+            current: Version::default(),
+            next_file_number: 1,
+            last_sequence: 0,
+            log_number: 0,
+        }
+    }
+}
+
+impl ManifestState {
+    /// The whole state as a single record.
+    pub fn snapshot(&self) -> VersionEdit {
+        VersionEdit {
+            log_number: Some(self.log_number),
+            next_file_number: Some(self.next_file_number),
+            last_sequence: Some(self.last_sequence),
+            ..self.current.snapshot()
+        }
+    }
+}
+
+/// Replays the MANIFEST named in CURRENT, or returns `None` for a new database.
+pub fn read_manifest(dir: &Path) -> Result<Option<ManifestState>> {
+    let Some(manifest) = read_current(dir)? else {
+        return Ok(None);
+    };
+    let mut state = ManifestState::default();
+    for record in read_records(&dir.join(manifest))? {
+        let edit = VersionEdit::decode(&record)?;
+        state.log_number = edit.log_number.unwrap_or(state.log_number);
+        state.next_file_number = edit.next_file_number.unwrap_or(state.next_file_number);
+        state.last_sequence = edit.last_sequence.unwrap_or(state.last_sequence);
+        state.current.apply(&edit);
+    }
+    Ok(Some(state))
+}
+
+pub fn manifest_name(number: u64) -> String {
+    format!("{}{:06}", MANIFEST_PREFIX, number)
+}
+
+fn create_manifest(dir: &Path, number: u64) -> Result<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dir.join(manifest_name(number)))?;
+    Ok(BufWriter::new(file))
+}
+
+/// Name of the MANIFEST recorded in CURRENT, or `None` for a new database.
+pub fn read_current(dir: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(dir.join(CURRENT_FILE)) {
+        Ok(contents) => {
+            let name = contents.trim_end();
+            if !name.starts_with(MANIFEST_PREFIX) {
+                return Err(Error::Corruption(format!(
+                    "CURRENT does not name a manifest: {:?}",
+                    name
+                )));
+            }
+            Ok(Some(name.to_string()))
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Atomically points CURRENT at the given MANIFEST.
+pub fn set_current(dir: &Path, manifest_number: u64) -> Result<()> {
+    let tmp = dir.join(CURRENT_FILE.to_string() + ".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all((manifest_name(manifest_number) + "\n").as_bytes())?;
+    file.sync_data()?;
+    fs::rename(tmp, dir.join(CURRENT_FILE))?;
+    sync_dir(dir)
+}
+
+/// Makes the files created, renamed or removed in `dir` so far survive a crash.
+pub fn sync_dir(dir: &Path) -> Result<()> {
+    Ok(File::open(dir)?.sync_all()?)
+}
+
+/// Reads all complete records of a MANIFEST. A torn record at the end, left by a crash
+/// while appending, is ignored.
+pub fn read_records(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let mut records = Vec::new();
+    let mut rest = bytes.as_slice();
+    while rest.len() >= 8 {
+        let checksum = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        let len = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        if rest.len() < 8 + len {
+            break;
+        }
+        let payload = &rest[8..8 + len];
+        if crc32(payload) != checksum {
+            return Err(Error::Corruption(format!(
+                "checksum mismatch in {}",
+                path.display()
+            )));
+        }
+        records.push(payload.to_vec());
+        rest = &rest[8 + len..];
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version::version::FileMetaData;
+    use std::path::PathBuf;
+
+    fn create_dir(name: &str) -> PathBuf {
+        let dir = PathBuf::from("data").join(name);
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn create_file(number: u64) -> FileMetaData {
+        FileMetaData {
+            number,
+            size: 10,
+            smallest: vec![1],
+            largest: vec![2],
+            smallest_seqno: 1,
+            largest_seqno: 2,
+            column_family: 0,
+        }
+    }
+
+    #[test]
+    fn test_recover_replays_edits() {
+        let dir = create_dir("version_set_recover");
+        let mut versions = VersionSet::recover(&dir).unwrap();
+        let first = versions.new_file_number();
+        let second = versions.new_file_number();
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, create_file(first));
+        edit.add_file(0, create_file(second));
+        versions.log_and_apply(edit).unwrap();
+        let mut edit = VersionEdit::default();
+        edit.delete_file(0, first);
+        versions.last_sequence = 42;
+        versions.log_and_apply(edit).unwrap();
+        drop(versions);
+
+        let mut versions = VersionSet::recover(&dir).unwrap();
+        assert_eq!(versions.live_files(), HashSet::from([second]));
+        assert_eq!(versions.last_sequence, 42);
+        assert!(versions.new_file_number() > second);
+    }
+
+    #[test]
+    fn test_torn_record_is_ignored() {
+        let dir = create_dir("version_set_torn");
+        let mut versions = VersionSet::recover(&dir).unwrap();
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, create_file(100));
+        versions.log_and_apply(edit).unwrap();
+        let manifest = dir.join(manifest_name(versions.manifest_number()));
+        drop(versions);
+        let mut file = OpenOptions::new().append(true).open(manifest).unwrap();
+        file.write_all(&[1, 2, 3, 4, 200, 0, 0, 0, 5]).unwrap();
+
+        let versions = VersionSet::recover(&dir).unwrap();
+        assert_eq!(versions.live_files(), HashSet::from([100]));
+    }
+
+    #[test]
+    fn test_corrupt_record_is_an_error() {
+        let dir = create_dir("version_set_corrupt");
+        let versions = VersionSet::recover(&dir).unwrap();
+        let manifest = dir.join(manifest_name(versions.manifest_number()));
+        drop(versions);
+        let mut bytes = fs::read(&manifest).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&manifest, bytes).unwrap();
+        assert!(matches!(
+            VersionSet::recover(&dir),
+            Err(Error::Corruption(_))
+        ));
+    }
+}
@@ -1,83 +1,223 @@
+use std::collections::VecDeque;
 use std::fs::{File, OpenOptions};
-use std::io::BufReader;
-use std::io::{self, Read};
+use std::io::{self, BufReader, Read};
 use std::path::PathBuf;
 
+use crate::{
+    database::column_family::DEFAULT_COLUMN_FAMILY,
+    sstable::data::{
+        operands_size, read_operands, COLUMN_FAMILY_FLAG, DELETED_FLAG, EXPIRES_FLAG, MERGE_FLAG,
+        NO_BASE_FLAG, RANGE_DELETE_FLAG, SINGLE_DELETE_FLAG,
+    },
+    Error, Result,
+};
+
 #[derive(Debug)]
 pub struct WALEntry {
+    /// Byte offset of the record in the WAL file.
+    pub offset: u64,
+    pub seqno: u64,
+    /// Seqno of the first record of the batch the record belongs to.
+    pub batch_seqno: u64,
     pub key: Vec<u8>,
     pub value: Option<Vec<u8>>,
     pub timestamp: u128,
     pub deleted: bool,
+    /// Whether the tombstone was written by `Database::single_delete`.
+    pub single_delete: bool,
+    pub expires_at: Option<u128>,
+    pub column_family: u32,
+    /// Holds the operand of a merge record.
+    pub operands: Vec<Vec<u8>>,
+    /// End of the range deleted by a range tombstone, which starts at `key`.
+    pub range_end: Option<Vec<u8>>,
 }
 
 #[derive(Debug)]
 pub struct WALIterator {
     reader: BufReader<File>,
+    // remaining records of the batch read last
+    batch: VecDeque<WALEntry>,
+    // bytes read so far and the end of the last complete batch
+    position: u64,
+    valid_len: u64,
+    tolerate_partial_batch: bool,
+    // set once a batch could not be read, since the position is then inside it
+    done: bool,
 }
 
 impl WALIterator {
-    pub fn new(path: PathBuf) -> io::Result<WALIterator> {
+    pub fn new(path: PathBuf) -> Result<WALIterator> {
         let file = OpenOptions::new().read(true).open(path)?;
         let reader = BufReader::new(file);
-        Ok(WALIterator { reader })
+        Ok(WALIterator {
+            reader,
+            batch: VecDeque::new(),
+            position: 0,
+            valid_len: 0,
+            tolerate_partial_batch: false,
+            done: false,
+        })
     }
-}
 
-// +---------------+---------------+-----------------+-...-+--...--+-----------------+
-// | Key Size (8B) | Tombstone(1B) | Value Size (8B) | Key | Value | Timestamp (16B) |
-// +---------------+---------------+-----------------+-...-+--...--+-----------------+
+    /// Ends the iteration at a partial batch instead of failing with `Error::Corruption`,
+    /// for WALs that a crash may have cut short or that are still being written.
+    pub fn tolerate_partial_batch(mut self) -> Self {
+        self.tolerate_partial_batch = true;
+        self
+    }
 
-impl Iterator for WALIterator {
-    type Item = WALEntry;
+    /// Length of the prefix of the file made of complete batches that were read so far.
+    /// Once the iterator is exhausted, anything after it is a partial batch.
+    pub fn valid_len(&self) -> u64 {
+        self.valid_len
+    }
 
-    fn next(&mut self) -> Option<WALEntry> {
-        let mut len_buffer = [0; 8];
-        if self.reader.read_exact(&mut len_buffer).is_err() {
-            return None;
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        // a corrupt length must not decide how much is allocated
+        let mut bytes = Vec::new();
+        (&mut self.reader)
+            .take(len as u64)
+            .read_to_end(&mut bytes)?;
+        self.position += bytes.len() as u64;
+        match bytes.len() == len {
+            true => Ok(bytes),
+            false => Err(io::ErrorKind::UnexpectedEof.into()),
         }
-        let key_len = usize::from_le_bytes(len_buffer);
-        let mut bool_buffer = [0; 1];
-        if self.reader.read_exact(&mut bool_buffer).is_err() {
-            return None;
+    }
+
+    /// Reads a whole batch, `false` at the end of the file.
+    fn read_batch(&mut self) -> io::Result<bool> {
+        let mut seqno_buffer = Vec::new();
+        (&mut self.reader).take(8).read_to_end(&mut seqno_buffer)?;
+        self.position += seqno_buffer.len() as u64;
+        if seqno_buffer.is_empty() {
+            return Ok(false);
+        }
+        let seqno_buffer: [u8; 8] = seqno_buffer
+            .try_into()
+            .map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        let seqno = u64::from_le_bytes(seqno_buffer);
+        let mut count_buffer = [0; 4];
+        self.read_exact(&mut count_buffer)?;
+        let count = u32::from_le_bytes(count_buffer) as u64;
+        let mut batch = VecDeque::new();
+        for i in 0..count {
+            let mut entry = self.read_record(seqno + i)?;
+            entry.batch_seqno = seqno;
+            batch.push_back(entry);
         }
-        let deleted = bool_buffer[0] != 0;
+        self.batch = batch;
+        self.valid_len = self.position;
+        Ok(true)
+    }
 
-        let mut key = vec![0; key_len];
+    fn read_record(&mut self, seqno: u64) -> io::Result<WALEntry> {
+        let offset = self.position;
+        let mut len_buffer = [0; 8];
+        self.read_exact(&mut len_buffer)?;
+        let key_len = usize::from_le_bytes(len_buffer);
+        let mut flags_buffer = [0; 1];
+        self.read_exact(&mut flags_buffer)?;
+        let deleted = flags_buffer[0] & DELETED_FLAG != 0;
+
+        let key;
         let mut value: Option<Vec<u8>> = None;
-        if deleted {
-            if self.reader.read_exact(&mut key).is_err() {
-                return None;
-            }
+        if deleted || flags_buffer[0] & NO_BASE_FLAG != 0 {
+            key = self.read_bytes(key_len)?;
         } else {
-            if self.reader.read_exact(&mut len_buffer).is_err() {
-                return None;
-            }
+            self.read_exact(&mut len_buffer)?;
             let value_len = usize::from_le_bytes(len_buffer);
-            if self.reader.read_exact(&mut key).is_err() {
-                return None;
-            }
-            let mut value_buf = vec![0; value_len];
-            if self.reader.read_exact(&mut value_buf).is_err() {
-                return None;
-            }
-            value = Some(value_buf)
+            key = self.read_bytes(key_len)?;
+            value = Some(self.read_bytes(value_len)?);
         }
         let mut timestamp_buffer = [0; 16];
-        if self.reader.read_exact(&mut timestamp_buffer).is_err() {
-            return None;
-        }
+        self.read_exact(&mut timestamp_buffer)?;
         let timestamp = u128::from_le_bytes(timestamp_buffer);
-        Some(WALEntry {
+        let mut expires_at = None;
+        if flags_buffer[0] & EXPIRES_FLAG != 0 {
+            self.read_exact(&mut timestamp_buffer)?;
+            expires_at = Some(u128::from_le_bytes(timestamp_buffer));
+        }
+        let mut column_family = DEFAULT_COLUMN_FAMILY;
+        if flags_buffer[0] & COLUMN_FAMILY_FLAG != 0 {
+            let mut column_family_buffer = [0; 4];
+            self.read_exact(&mut column_family_buffer)?;
+            column_family = u32::from_le_bytes(column_family_buffer);
+        }
+        let mut operands = Vec::new();
+        if flags_buffer[0] & MERGE_FLAG != 0 {
+            operands = read_operands(&mut self.reader)?;
+            self.position += operands_size(&operands) as u64;
+        }
+        let mut range_end = None;
+        if flags_buffer[0] & RANGE_DELETE_FLAG != 0 {
+            self.read_exact(&mut len_buffer)?;
+            range_end = Some(self.read_bytes(usize::from_le_bytes(len_buffer))?);
+        }
+        Ok(WALEntry {
+            offset,
+            seqno,
+            batch_seqno: seqno,
             key,
             value,
             timestamp,
             deleted,
+            single_delete: deleted && flags_buffer[0] & SINGLE_DELETE_FLAG != 0,
+            expires_at,
+            column_family,
+            operands,
+            range_end,
         })
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// Records are written in batches, numbered consecutively from the batch's seqno.
+// +-------------+-------------+-----...-----+
+// | Seqno (8B)  | Count (4B)  | Records     |
+// +-------------+-------------+-----...-----+
+//
+// +---------------+-----------+-----------------+-...-+--...--+-----------------+
+// | Key Size (8B) | Flags(1B) | Value Size (8B) | Key | Value | Timestamp (16B) |
+// +---------------+-----------+-----------------+-...-+--...--+-----------------+
+// followed by the expiry (16B), the column family (4B), the merge operand and the end of a
+// range tombstone (8B size, then the key) if their flags are set; the value size and value
+// are left out for tombstones and merges.
+
+impl Iterator for WALIterator {
+    type Item = Result<WALEntry>;
+
+    /// A batch cut short, by a crash or a damaged record, fails the iteration with
+    /// `Error::Corruption` unless partial batches are tolerated; none of its records are
+    /// returned either way.
+    fn next(&mut self) -> Option<Result<WALEntry>> {
+        while self.batch.is_empty() && !self.done {
+            let err = match self.read_batch() {
+                Ok(true) => continue,
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(err) => err,
+            };
+            self.done = true;
+            return match err.kind() {
+                io::ErrorKind::UnexpectedEof if self.tolerate_partial_batch => None,
+                io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData => {
+                    Some(Err(Error::Corruption(format!(
+                        "partial batch at offset {} of the WAL",
+                        self.valid_len
+                    ))))
+                }
+                _ => Some(Err(err.into())),
+            };
+        }
+        self.batch.pop_front().map(Ok)
+    }
 }
@@ -1,2 +1,3 @@
 pub mod iterator;
+pub mod repair;
 pub mod wal;
@@ -0,0 +1,162 @@
+use std::{
+    fs::{self, OpenOptions},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    database::comparator::Comparator, memtable::MemTable, sstable::sstable::SSTable, Result,
+};
+
+use super::iterator::{WALEntry, WALIterator};
+
+/// What `inspect` found in a WAL file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WALReport {
+    pub records: usize,
+    /// Length of the prefix made of complete batches.
+    pub valid_len: u64,
+    pub file_len: u64,
+}
+
+impl WALReport {
+    /// Offset at which a batch cut short by a crash begins, if there is one.
+    pub fn partial_batch(&self) -> Option<u64> {
+        (self.valid_len < self.file_len).then_some(self.valid_len)
+    }
+}
+
+/// Reads every complete record of the WAL at `path`, handing it to `on_record`.
+pub fn inspect(path: &Path, mut on_record: impl FnMut(&WALEntry)) -> Result<WALReport> {
+    let file_len = fs::metadata(path)?.len();
+    let mut iterator = WALIterator::new(path.to_owned())?.tolerate_partial_batch();
+    let mut records = 0;
+    for entry in iterator.by_ref() {
+        on_record(&entry?);
+        records += 1;
+    }
+    Ok(WALReport {
+        records,
+        valid_len: iterator.valid_len(),
+        file_len,
+    })
+}
+
+/// Cuts a partial batch off the end of the WAL at `path`. Returns the report from before
+/// the truncation.
+pub fn truncate(path: &Path) -> Result<WALReport> {
+    let report = inspect(path, |_| ())?;
+    if report.partial_batch().is_some() {
+        let file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(report.valid_len)?;
+        file.sync_all()?;
+    }
+    Ok(report)
+}
+
+/// Writes the newest version of every key of `column_family` in the complete batches of the
+/// WAL at `path` into a new table numbered `number` in `dir`, ordered by `comparator`.
+/// Returns `None` if there is nothing to keep.
+pub fn salvage(
+    path: &Path,
+    dir: &Path,
+    number: u64,
+    column_family: u32,
+    comparator: Arc<dyn Comparator>,
+) -> Result<Option<SSTable>> {
+    let mut memtable = MemTable::with_comparator(comparator.clone());
+    for entry in WALIterator::new(path.to_owned())?.tolerate_partial_batch() {
+        let entry = entry?;
+        if entry.column_family != column_family {
+            continue;
+        }
+        match entry.value {
+            _ if entry.range_end.is_some() => {
+                let end = entry.range_end.unwrap_or_default();
+                memtable.delete_range(&entry.key, &end, entry.timestamp)
+            }
+            _ if !entry.operands.is_empty() => {
+                for operand in &entry.operands {
+                    memtable.merge(&entry.key, operand, entry.timestamp);
+                }
+            }
+            Some(value) if !entry.deleted => {
+                memtable.set_with_expiry(&entry.key, &value, entry.timestamp, entry.expires_at)
+            }
+            _ if entry.single_delete => memtable.single_delete(&entry.key, entry.timestamp),
+            _ => memtable.delete(&entry.key, entry.timestamp),
+        }
+    }
+    if memtable.is_empty() {
+        return Ok(None);
+    }
+    let mut sstable = SSTable::new(dir, number)?;
+    sstable.set_comparator(comparator);
+    for entry in &memtable {
+        sstable.write(&entry)?;
+    }
+    for tombstone in memtable.range_tombstones() {
+        sstable.add_range_tombstone(tombstone.clone());
+    }
+    sstable.flush()?;
+    Ok(Some(sstable))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::PathBuf};
+
+    use crate::{database::comparator::BytewiseComparator, wal::wal::WAL};
+
+    use super::*;
+
+    fn create_torn_wal(name: &str) -> (PathBuf, PathBuf, u64) {
+        let dir = PathBuf::from("data").join(name);
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut wal = WAL::new(&dir, 1).unwrap();
+        wal.set(1, b"a", b"1", 1).unwrap();
+        wal.set(2, b"b", b"2", 2).unwrap();
+        wal.delete(3, b"a", 3).unwrap();
+        wal.flush().unwrap();
+        let valid_len = fs::metadata(&wal.path).unwrap().len();
+        // the start of a batch whose records never made it to disk
+        let mut file = OpenOptions::new().append(true).open(&wal.path).unwrap();
+        file.write_all(&4u64.to_le_bytes()).unwrap();
+        file.write_all(&2u32.to_le_bytes()).unwrap();
+        file.write_all(&[1, 0, 0]).unwrap();
+        (dir, wal.path, valid_len)
+    }
+
+    #[test]
+    fn test_inspect_reports_partial_batch() {
+        let (_, path, valid_len) = create_torn_wal("wal_repair_inspect");
+        let mut offsets = Vec::new();
+        let report = inspect(&path, |entry| offsets.push(entry.offset)).unwrap();
+        assert_eq!(report.records, 3);
+        assert_eq!(offsets[0], 12);
+        assert_eq!(report.partial_batch(), Some(valid_len));
+    }
+
+    #[test]
+    fn test_truncate_removes_partial_batch() {
+        let (_, path, valid_len) = create_torn_wal("wal_repair_truncate");
+        truncate(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), valid_len);
+        let report = inspect(&path, |_| ()).unwrap();
+        assert_eq!(report.records, 3);
+        assert_eq!(report.partial_batch(), None);
+    }
+
+    #[test]
+    fn test_salvage_writes_newest_versions() {
+        let (dir, path, _) = create_torn_wal("wal_repair_salvage");
+        let sstable = salvage(&path, &dir, 2, 0, Arc::new(BytewiseComparator))
+            .unwrap()
+            .unwrap();
+        let a = sstable.get(b"a").unwrap().unwrap();
+        assert!(a.deleted);
+        let b = sstable.get(b"b").unwrap().unwrap();
+        assert_eq!(b.value, Some(b"2".to_vec()));
+    }
+}
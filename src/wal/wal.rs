@@ -1,138 +1,436 @@
 use std::{
-    fs::{read_dir, remove_file, File, OpenOptions},
-    io::{self, BufWriter, Write},
+    collections::BTreeMap,
+    fs::{self, read_dir, remove_file, File, OpenOptions},
+    io::{BufWriter, ErrorKind, Read, Write},
     path::{Path, PathBuf},
-    time::{SystemTime, UNIX_EPOCH},
+    sync::Arc,
 };
 
-use crate::memtable::MemTable;
+use crate::{
+    database::{
+        column_family::DEFAULT_COLUMN_FAMILY,
+        comparator::Comparator,
+        write_batch::{BatchOp, WriteBatch},
+    },
+    memtable::MemTable,
+    sstable::{
+        data::{
+            write_operands, COLUMN_FAMILY_FLAG, DELETED_FLAG, EXPIRES_FLAG, MERGE_FLAG,
+            NO_BASE_FLAG, RANGE_DELETE_FLAG, SINGLE_DELETE_FLAG,
+        },
+        sstable::{file_name, file_number},
+    },
+    Result,
+};
 
 use super::iterator::{WALEntry, WALIterator};
 
+pub const LOG_EXT: &str = "log";
+
 pub struct WAL {
     pub path: PathBuf,
     file: BufWriter<File>,
-}
-
-impl IntoIterator for WAL {
-    type IntoIter = WALIterator;
-    type Item = WALEntry;
-
-    fn into_iter(self) -> WALIterator {
-        WALIterator::new(self.path).unwrap()
-    }
+    seqnos: Option<(u64, u64)>,
 }
 
 impl WAL {
-    pub fn new(dir: &Path) -> io::Result<WAL> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
-
-        let path = Path::new(dir).join(timestamp.to_string() + ".wal");
+    /// Creates a WAL named after `number`, e.g. `000123.log`.
+    pub fn new(dir: &Path, number: u64) -> Result<WAL> {
+        let path = log_path(dir, number);
         let file = OpenOptions::new().append(true).create(true).open(&path)?;
         let file = BufWriter::new(file);
 
-        Ok(WAL { path, file })
+        Ok(WAL {
+            path,
+            file,
+            seqnos: None,
+        })
     }
-    pub fn from_path(path: &Path) -> io::Result<WAL> {
-        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+    pub fn from_path(path: &Path) -> Result<WAL> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
         let file = BufWriter::new(file);
 
         Ok(WAL {
             path: path.to_owned(),
             file,
+            seqnos: None,
         })
     }
 
-    pub fn set(&mut self, key: &[u8], value: &[u8], timestamp: u128) -> io::Result<()> {
-        self.file.write_all(&key.len().to_le_bytes())?;
-        self.file.write_all(&(false as u8).to_le_bytes())?;
-        self.file.write_all(&value.len().to_le_bytes())?;
-        self.file.write_all(key)?;
-        self.file.write_all(value)?;
-        self.file.write_all(&timestamp.to_le_bytes())?;
+    /// Iterates over the records written so far; buffered writes must be flushed first.
+    pub fn iter(&self) -> Result<WALIterator> {
+        WALIterator::new(self.path.clone())
+    }
 
-        Ok(())
+    /// Smallest and largest sequence number written to this WAL.
+    pub fn seqno_range(&self) -> Option<(u64, u64)> {
+        self.seqnos
+    }
+
+    fn record_seqno(&mut self, seqno: u64) {
+        self.seqnos = match self.seqnos {
+            Some((first, last)) => Some((first.min(seqno), last.max(seqno))),
+            None => Some((seqno, seqno)),
+        };
+    }
+
+    pub fn set(&mut self, seqno: u64, key: &[u8], value: &[u8], timestamp: u128) -> Result<()> {
+        self.set_with_expiry(seqno, key, value, timestamp, None)
+    }
+
+    pub fn set_with_expiry(
+        &mut self,
+        seqno: u64,
+        key: &[u8],
+        value: &[u8],
+        timestamp: u128,
+        expires_at: Option<u128>,
+    ) -> Result<()> {
+        let mut buf = batch_header(seqno, 1);
+        encode_record(
+            &mut buf,
+            DEFAULT_COLUMN_FAMILY,
+            key,
+            Record::Put(value, expires_at),
+            timestamp,
+        );
+        self.append(&buf, seqno, 1)
+    }
+
+    pub fn delete(&mut self, seqno: u64, key: &[u8], timestamp: u128) -> Result<()> {
+        let mut buf = batch_header(seqno, 1);
+        encode_record(
+            &mut buf,
+            DEFAULT_COLUMN_FAMILY,
+            key,
+            Record::Delete,
+            timestamp,
+        );
+        self.append(&buf, seqno, 1)
+    }
+
+    /// Logs the records of a batch read from another WAL again, as one batch.
+    fn log_batch(&mut self, entries: &[WALEntry]) -> Result<()> {
+        let Some(first) = entries.first() else {
+            return Ok(());
+        };
+        let mut buf = batch_header(first.batch_seqno, entries.len() as u32);
+        for entry in entries {
+            let record = match (&entry.value, entry.operands.first(), &entry.range_end) {
+                (_, _, Some(end)) => Record::DeleteRange(end),
+                (_, Some(operand), None) => Record::Merge(operand),
+                (Some(value), None, None) if !entry.deleted => Record::Put(value, entry.expires_at),
+                _ if entry.single_delete => Record::SingleDelete,
+                _ => Record::Delete,
+            };
+            encode_record(
+                &mut buf,
+                entry.column_family,
+                &entry.key,
+                record,
+                entry.timestamp,
+            );
+        }
+        self.append(&buf, first.batch_seqno, entries.len() as u64)
+    }
+
+    /// Writes all changes of `batch` as one unit, numbered from `seqno` on.
+    pub fn write_batch(&mut self, seqno: u64, batch: &WriteBatch) -> Result<()> {
+        let mut buf = batch_header(seqno, batch.len() as u32);
+        for op in batch.ops() {
+            match op {
+                BatchOp::Put {
+                    column_family,
+                    key,
+                    value,
+                    timestamp,
+                    expires_at,
+                } => encode_record(
+                    &mut buf,
+                    *column_family,
+                    key,
+                    Record::Put(value, *expires_at),
+                    *timestamp,
+                ),
+                BatchOp::Delete {
+                    column_family,
+                    key,
+                    timestamp,
+                } => encode_record(&mut buf, *column_family, key, Record::Delete, *timestamp),
+                BatchOp::SingleDelete {
+                    column_family,
+                    key,
+                    timestamp,
+                } => encode_record(
+                    &mut buf,
+                    *column_family,
+                    key,
+                    Record::SingleDelete,
+                    *timestamp,
+                ),
+                BatchOp::Merge {
+                    column_family,
+                    key,
+                    operand,
+                    timestamp,
+                } => encode_record(
+                    &mut buf,
+                    *column_family,
+                    key,
+                    Record::Merge(operand),
+                    *timestamp,
+                ),
+                BatchOp::DeleteRange {
+                    column_family,
+                    start,
+                    end,
+                    timestamp,
+                } => encode_record(
+                    &mut buf,
+                    *column_family,
+                    start,
+                    Record::DeleteRange(end),
+                    *timestamp,
+                ),
+            }
+        }
+        self.append(&buf, seqno, batch.len() as u64)
     }
 
-    pub fn delete(&mut self, key: &[u8], timestamp: u128) -> io::Result<()> {
-        self.file.write_all(&key.len().to_le_bytes())?;
-        self.file.write_all(&(true as u8).to_le_bytes())?;
-        self.file.write_all(key)?;
-        self.file.write_all(&timestamp.to_le_bytes())?;
+    fn append(&mut self, buf: &[u8], seqno: u64, count: u64) -> Result<()> {
+        self.file.write_all(buf)?;
+        self.record_seqno(seqno);
+        self.record_seqno(seqno + count - 1);
         Ok(())
     }
 
-    pub fn flush(&mut self) -> io::Result<()> {
-        self.file.flush()
-    }
-
-    pub fn load_from_dir(dir: &Path) -> io::Result<(WAL, MemTable)> {
-        let mut wal_files = files_with_ext(dir, "wal");
-        wal_files.sort();
-
-        let mut new_mem_table = MemTable::new();
-        let mut new_wal = WAL::new(dir)?;
-        for wal_file in wal_files.iter() {
-            if let Ok(wal) = WAL::from_path(wal_file) {
-                for entry in wal.into_iter() {
-                    if entry.deleted {
-                        new_mem_table.delete(entry.key.as_slice(), entry.timestamp);
-                        new_wal.delete(entry.key.as_slice(), entry.timestamp)?;
-                    } else {
-                        new_mem_table.set(
-                            entry.key.as_slice(),
-                            entry.value.as_ref().unwrap().as_slice(),
-                            entry.timestamp,
-                        );
-                        new_wal.set(
-                            entry.key.as_slice(),
-                            entry.value.as_ref().unwrap().as_slice(),
-                            entry.timestamp,
-                        )?;
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.file.flush()?)
+    }
+
+    /// Flushes the WAL and waits until its records are on disk.
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(self.file.get_ref().sync_data()?)
+    }
+
+    /// Replays every WAL in `dir`, oldest first, into a memtable per column family ordered
+    /// by `comparator` and a new WAL numbered `number`, batch by batch. The new WAL is on
+    /// disk when this returns; the replayed WALs are left for the caller to retire once the
+    /// new one is recorded as the live WAL.
+    pub fn load_from_dir(
+        dir: &Path,
+        number: u64,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<(WAL, BTreeMap<u32, MemTable>)> {
+        let wal_files = log_files(dir)?;
+
+        let mut memtables: BTreeMap<u32, MemTable> = BTreeMap::new();
+        let mut new_wal = WAL::new(dir, number)?;
+        for (i, wal_file) in wal_files.iter().enumerate() {
+            let mut batch: Vec<WALEntry> = Vec::new();
+            let mut entries = WALIterator::new(wal_file.clone())?;
+            // only the newest WAL can have been cut short by a crash
+            if i == wal_files.len() - 1 {
+                entries = entries.tolerate_partial_batch();
+            }
+            for entry in entries {
+                let entry = entry?;
+                let memtable = memtables
+                    .entry(entry.column_family)
+                    .or_insert_with(|| MemTable::with_comparator(comparator.clone()));
+                match (&entry.value, entry.operands.first(), &entry.range_end) {
+                    (_, _, Some(end)) => memtable.delete_range(&entry.key, end, entry.timestamp),
+                    (_, Some(operand), None) => {
+                        memtable.merge(&entry.key, operand, entry.timestamp)
                     }
+                    (Some(value), None, None) if !entry.deleted => memtable.set_with_expiry(
+                        &entry.key,
+                        value,
+                        entry.timestamp,
+                        entry.expires_at,
+                    ),
+                    _ if entry.single_delete => memtable.single_delete(&entry.key, entry.timestamp),
+                    _ => memtable.delete(&entry.key, entry.timestamp),
+                }
+                if batch
+                    .first()
+                    .is_some_and(|b| b.batch_seqno != entry.batch_seqno)
+                {
+                    new_wal.log_batch(&batch)?;
+                    batch.clear();
                 }
+                batch.push(entry);
             }
+            new_wal.log_batch(&batch)?;
         }
-        new_wal.flush().unwrap();
-        wal_files.into_iter().for_each(|f| remove_file(f).unwrap());
-        Ok((new_wal, new_mem_table))
+        new_wal.sync()?;
+        Ok((new_wal, memtables))
+    }
+}
+
+fn batch_header(seqno: u64, count: u32) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&seqno.to_le_bytes());
+    buf.extend_from_slice(&count.to_le_bytes());
+    buf
+}
+
+// what a record does to its key
+enum Record<'a> {
+    Put(&'a [u8], Option<u128>),
+    Delete,
+    SingleDelete,
+    Merge(&'a [u8]),
+    // the key starts the range, this is its end
+    DeleteRange(&'a [u8]),
+}
+
+fn encode_record(
+    buf: &mut Vec<u8>,
+    column_family: u32,
+    key: &[u8],
+    record: Record,
+    timestamp: u128,
+) {
+    let single_delete = matches!(record, Record::SingleDelete);
+    let (value, expires_at, operand, range_end) = match record {
+        Record::Put(value, expires_at) => (Some(value), expires_at, None, None),
+        Record::Delete | Record::SingleDelete => (None, None, None, None),
+        Record::Merge(operand) => (None, None, Some(operand), None),
+        Record::DeleteRange(end) => (None, None, None, Some(end)),
+    };
+    let mut flags = 0;
+    if value.is_none() && operand.is_none() {
+        flags |= DELETED_FLAG;
+    }
+    if expires_at.is_some() {
+        flags |= EXPIRES_FLAG;
+    }
+    // records of the default column family keep the layout from before column families
+    if column_family != DEFAULT_COLUMN_FAMILY {
+        flags |= COLUMN_FAMILY_FLAG;
+    }
+    if operand.is_some() {
+        flags |= MERGE_FLAG | NO_BASE_FLAG;
+    }
+    if range_end.is_some() {
+        flags |= RANGE_DELETE_FLAG;
+    }
+    if single_delete {
+        flags |= SINGLE_DELETE_FLAG;
+    }
+    buf.extend_from_slice(&key.len().to_le_bytes());
+    buf.push(flags);
+    if let Some(value) = value {
+        buf.extend_from_slice(&value.len().to_le_bytes());
+    }
+    buf.extend_from_slice(key);
+    if let Some(value) = value {
+        buf.extend_from_slice(value);
+    }
+    buf.extend_from_slice(&timestamp.to_le_bytes());
+    if let Some(expires_at) = expires_at {
+        buf.extend_from_slice(&expires_at.to_le_bytes());
+    }
+    if column_family != DEFAULT_COLUMN_FAMILY {
+        buf.extend_from_slice(&column_family.to_le_bytes());
+    }
+    if let Some(operand) = operand {
+        write_operands(buf, &[operand.to_vec()]).unwrap_or_default();
+    }
+    if let Some(end) = range_end {
+        buf.extend_from_slice(&end.len().to_le_bytes());
+        buf.extend_from_slice(end);
     }
 }
 
-pub fn files_with_ext(dir: &Path, ext: &str) -> Vec<PathBuf> {
+/// Moves a WAL that is no longer needed into `archive`, or removes it if there is none.
+pub fn retire(path: &Path, archive: Option<&Path>) -> Result<()> {
+    let Some(archive) = archive else {
+        remove_file(path)?;
+        return Ok(());
+    };
+    let target = archive.join(path.file_name().unwrap_or_default());
+    // renaming fails across filesystems
+    if fs::rename(path, &target).is_err() {
+        fs::copy(path, &target)?;
+        remove_file(path)?;
+    }
+    Ok(())
+}
+
+pub fn log_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(file_name(number, LOG_EXT))
+}
+
+/// Numbered WAL files in `dir` ordered by their number, i.e. in the order they were created.
+pub fn log_files(dir: &Path) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
-    for file in read_dir(dir).unwrap() {
-        let path = file.unwrap().path();
-        if path.extension().unwrap() == ext {
-            files.push(path);
+    for file in read_dir(dir)? {
+        let path = file?.path();
+        if path.extension().is_some_and(|e| e == LOG_EXT) {
+            if let Some(number) = file_number(&path) {
+                files.push((number, path));
+            }
         }
     }
-    files
+    files.sort();
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Seqno of the first batch of the WAL at `path`, `None` if it has no complete batch
+/// header or is gone.
+pub fn first_seqno(path: &Path) -> Result<Option<u64>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut seqno = [0; 8];
+    match file.read_exact(&mut seqno) {
+        Ok(()) => Ok(Some(u64::from_le_bytes(seqno))),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err.into()),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{database::comparator::BytewiseComparator, Error};
 
-    fn create_wal() -> io::Result<WAL> {
-        let path = Path::new("data");
-        WAL::new(&path)
+    fn create_path(name: &str) -> PathBuf {
+        let path = PathBuf::from("data").join(name);
+        std::fs::remove_dir_all(&path).ok();
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn create_wal(name: &str) -> Result<WAL> {
+        WAL::new(&create_path(name), 1)
     }
 
     fn create_entry() -> WALEntry {
         WALEntry {
+            offset: 0,
+            seqno: 1,
+            batch_seqno: 1,
             key: vec![1, 2, 3],
             value: Some(vec![9]),
             timestamp: 1,
             deleted: false,
+            single_delete: false,
+            expires_at: None,
+            column_family: DEFAULT_COLUMN_FAMILY,
+            operands: Vec::new(),
+            range_end: None,
         }
     }
 
-    fn write_to_wal(wal: &mut WAL, entry: WALEntry) -> io::Result<()> {
+    fn write_to_wal(wal: &mut WAL, entry: WALEntry) -> Result<()> {
         wal.set(
+            entry.seqno,
             entry.key.as_slice(),
             entry.value.unwrap().as_slice(),
             entry.timestamp,
@@ -140,9 +438,166 @@ mod tests {
     }
 
     #[test]
-    fn test_write_to_wal() -> io::Result<()> {
-        let mut wal = create_wal().unwrap();
+    fn test_write_to_wal() -> Result<()> {
+        let mut wal = create_wal("wal_write").unwrap();
         let entry = create_entry();
         write_to_wal(&mut wal, entry)
     }
+
+    #[test]
+    fn test_records_keep_their_seqno() {
+        let mut wal = create_wal("wal_seqno").unwrap();
+        write_to_wal(&mut wal, create_entry()).unwrap();
+        wal.delete(2, &[1, 2, 3], 2).unwrap();
+        wal.flush().unwrap();
+        let seqnos: Vec<u64> = wal
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|e| e.seqno)
+            .collect();
+        assert_eq!(seqnos, vec![1, 2]);
+        assert_eq!(wal.seqno_range(), Some((1, 2)));
+    }
+
+    #[test]
+    fn test_wals_are_replayed_by_number() {
+        let path = create_path("wal_replay_order");
+        // the newer value has to win, so replay must follow the file numbers
+        let mut older = WAL::new(&path, 9).unwrap();
+        older.set(1, &[1], &[1], 1).unwrap();
+        older.flush().unwrap();
+        let mut newer = WAL::new(&path, 10).unwrap();
+        newer.set(2, &[1], &[2], 2).unwrap();
+        newer.flush().unwrap();
+        drop((older, newer));
+
+        let (wal, memtable) = WAL::load_from_dir(&path, 11, Arc::new(BytewiseComparator)).unwrap();
+        assert_eq!(wal.path, path.join("000011.log"));
+        let seqnos: Vec<u64> = wal
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|e| e.seqno)
+            .collect();
+        assert_eq!(seqnos, vec![1, 2]);
+        assert_eq!(memtable[&0].get(&[1]).unwrap().value, Some(vec![2]));
+        // retiring the replayed WALs is up to the caller
+        assert_eq!(log_files(&path).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_replay_keeps_batches() {
+        let path = create_path("wal_replay_batches");
+        let mut wal = WAL::new(&path, 1).unwrap();
+        wal.set(1, &[1], &[1], 1).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(&[2], &[2], 2);
+        batch.merge(&[3], &[3], 2);
+        batch.delete(&[1], 2);
+        wal.write_batch(2, &batch).unwrap();
+        wal.flush().unwrap();
+        drop(wal);
+
+        let (wal, _) = WAL::load_from_dir(&path, 2, Arc::new(BytewiseComparator)).unwrap();
+        let batches: Vec<(u64, u64)> = wal
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|e| (e.seqno, e.batch_seqno))
+            .collect();
+        assert_eq!(batches, vec![(1, 1), (2, 2), (3, 2), (4, 2)]);
+        assert_eq!(wal.seqno_range(), Some((1, 4)));
+    }
+
+    #[test]
+    fn test_torn_batch_is_dropped() {
+        let mut wal = create_wal("wal_torn_batch").unwrap();
+        write_to_wal(&mut wal, create_entry()).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(&[2], &[2], 2);
+        batch.delete(&[3], 2);
+        wal.write_batch(2, &batch).unwrap();
+        wal.flush().unwrap();
+        let len = std::fs::metadata(&wal.path).unwrap().len();
+        let seqnos: Vec<u64> = wal
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|e| e.seqno)
+            .collect();
+        assert_eq!(seqnos, vec![1, 2, 3]);
+        assert_eq!(wal.seqno_range(), Some((1, 3)));
+
+        // lose the end of the last record, as if the process crashed while writing it
+        let file = OpenOptions::new().write(true).open(&wal.path).unwrap();
+        file.set_len(len - 1).unwrap();
+        let seqnos: Vec<u64> = wal
+            .iter()
+            .unwrap()
+            .tolerate_partial_batch()
+            .map(Result::unwrap)
+            .map(|e| e.seqno)
+            .collect();
+        assert_eq!(seqnos, vec![1]);
+        let entries: Result<Vec<WALEntry>> = wal.iter().unwrap().collect();
+        assert!(matches!(entries, Err(Error::Corruption(_))));
+    }
+
+    #[test]
+    fn test_records_keep_their_column_family() {
+        let path = create_path("wal_column_family");
+        let mut wal = WAL::new(&path, 1).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(&[1], &[1], 1);
+        batch.push(BatchOp::Delete {
+            column_family: 3,
+            key: vec![1],
+            timestamp: 1,
+        });
+        wal.write_batch(1, &batch).unwrap();
+        wal.flush().unwrap();
+        let families: Vec<u32> = wal
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|e| e.column_family)
+            .collect();
+        assert_eq!(families, vec![0, 3]);
+        drop(wal);
+
+        let (_, memtables) = WAL::load_from_dir(&path, 2, Arc::new(BytewiseComparator)).unwrap();
+        assert_eq!(memtables[&0].get(&[1]).unwrap().value, Some(vec![1]));
+        assert!(memtables[&3].get(&[1]).unwrap().deleted);
+    }
+
+    #[test]
+    fn test_range_tombstones_are_replayed() {
+        let path = create_path("wal_range_tombstone");
+        let mut wal = WAL::new(&path, 1).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(&[1], &[1], 1);
+        batch.delete_range(&[0], &[5], 2);
+        wal.write_batch(1, &batch).unwrap();
+        wal.flush().unwrap();
+        let entries: Vec<WALEntry> = wal.iter().unwrap().map(Result::unwrap).collect();
+        assert_eq!(entries[1].key, vec![0]);
+        assert_eq!(entries[1].range_end, Some(vec![5]));
+        drop(wal);
+
+        let (wal, memtables) = WAL::load_from_dir(&path, 2, Arc::new(BytewiseComparator)).unwrap();
+        let tombstones = memtables[&0].range_tombstones();
+        assert_eq!(
+            (tombstones[0].end.as_slice(), tombstones[0].timestamp),
+            (&[5][..], 2)
+        );
+        // the replayed WAL logs the tombstone again
+        let ends: Vec<Option<Vec<u8>>> = wal
+            .iter()
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|e| e.range_end)
+            .collect();
+        assert_eq!(ends, vec![None, Some(vec![5])]);
+    }
 }
@@ -0,0 +1,184 @@
+use std::{fs, net::SocketAddr, path::PathBuf};
+
+use rustdb::{
+    client::client::Client,
+    database::{async_database::AsyncDatabase, entry::Entry, write_batch::WriteBatch},
+    server::{
+        binary::BinaryServer,
+        protocol::{self, Frame, Kind},
+    },
+    sstable::blob::BlobIndex,
+    Error,
+};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+async fn start_server(name: &str) -> SocketAddr {
+    let dir = PathBuf::from("data").join(name);
+    fs::remove_dir_all(&dir).ok();
+    let db = AsyncDatabase::open(dir).await.unwrap();
+    let server = BinaryServer::bind("127.0.0.1:0", db).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run());
+    addr
+}
+
+#[tokio::test]
+async fn test_get_set_delete() {
+    let client = Client::connect(start_server("binary_get_set").await)
+        .await
+        .unwrap();
+    assert_eq!(client.get(b"a").await.unwrap(), None);
+    client.set(b"a", b"1").await.unwrap();
+    assert_eq!(client.get(b"a").await.unwrap(), Some(b"1".to_vec()));
+    client.set(b"\0\xff", b"\r\n").await.unwrap();
+    assert_eq!(client.get(b"\0\xff").await.unwrap(), Some(b"\r\n".to_vec()));
+    client.delete(b"a").await.unwrap();
+    assert_eq!(client.get(b"a").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_batch_and_pipelining() {
+    let client = Client::connect(start_server("binary_batch").await)
+        .await
+        .unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(b"a", b"1", 0);
+    batch.put(b"b", b"2", 0);
+    batch.delete(b"a", 0);
+    client.write(&batch).await.unwrap();
+    assert_eq!(client.get(b"a").await.unwrap(), None);
+    assert_eq!(client.get(b"b").await.unwrap(), Some(b"2".to_vec()));
+
+    // concurrent requests share the connection
+    let writes = (0..100).map(|i| {
+        let client = client.clone();
+        async move { client.set(format!("k{}", i).as_bytes(), b"v").await }
+    });
+    for result in join_all(writes).await {
+        result.unwrap();
+    }
+    let reads = (0..100).map(|i| {
+        let client = client.clone();
+        async move { client.get(format!("k{}", i).as_bytes()).await }
+    });
+    for result in join_all(reads).await {
+        assert_eq!(result.unwrap(), Some(b"v".to_vec()));
+    }
+}
+
+#[tokio::test]
+async fn test_scan_with_back_pressure() {
+    let client = Client::connect(start_server("binary_scan").await)
+        .await
+        .unwrap();
+    let mut batch = WriteBatch::new();
+    for i in 0..5000 {
+        batch.put(format!("key{:05}", i).as_bytes(), b"v", 0);
+    }
+    client.write(&batch).await.unwrap();
+
+    // more entries than the window, so the scan only finishes if credit is returned
+    let mut scan = client.scan(.., None).await.unwrap();
+    let mut count = 0;
+    while let Some(entry) = scan.next().await.unwrap() {
+        assert_eq!(entry.key, format!("key{:05}", count).as_bytes());
+        count += 1;
+    }
+    assert_eq!(count, 5000);
+
+    let range = b"key00010".to_vec()..b"key00020".to_vec();
+    let mut scan = client.scan(range, Some(3)).await.unwrap();
+    let mut keys = Vec::new();
+    while let Some(entry) = scan.next().await.unwrap() {
+        keys.push(entry.key);
+    }
+    assert_eq!(keys, [b"key00010", b"key00011", b"key00012"]);
+
+    // a scan dropped halfway is cancelled and the connection stays usable
+    let mut scan = client.scan(.., None).await.unwrap();
+    scan.next().await.unwrap().unwrap();
+    drop(scan);
+    assert_eq!(client.get(b"key00001").await.unwrap(), Some(b"v".to_vec()));
+}
+
+#[tokio::test]
+async fn test_malformed_frame_closes_connection() {
+    let addr = start_server("binary_malformed").await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(&[0xff; 16]).await.unwrap();
+    let frame = Frame::read_from(&mut stream).await.unwrap().unwrap();
+    assert_eq!(frame.kind, Kind::Error);
+    assert_eq!(Frame::read_from(&mut stream).await.unwrap(), None);
+
+    let client = Client::connect(addr).await.unwrap();
+    client.set(b"a", b"1").await.unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.put(&vec![0; 65 << 20], b"", 0);
+    assert!(matches!(
+        client.write(&batch).await,
+        Err(Error::InvalidArgument(_))
+    ));
+    assert_eq!(client.get(b"a").await.unwrap(), Some(b"1".to_vec()));
+}
+
+#[tokio::test]
+async fn test_write_rejects_unknown_entry_shapes() {
+    let addr = start_server("binary_entry_shapes").await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let put = Entry {
+        key: b"a".to_vec(),
+        value: Some(b"1".to_vec()),
+        timestamp: 0,
+        deleted: false,
+        single_delete: false,
+        blob: None,
+        expires_at: None,
+        operands: Vec::new(),
+    };
+    let shapes = [
+        Entry {
+            operands: vec![b"2".to_vec()],
+            ..put.clone()
+        },
+        Entry {
+            value: None,
+            blob: Some(BlobIndex {
+                file_number: 1,
+                offset: 0,
+                len: 1,
+            }),
+            ..put.clone()
+        },
+    ];
+    for (id, entry) in shapes.iter().enumerate() {
+        // the valid put in the batch is not applied either
+        let payload = protocol::encode_entries([put.clone(), entry.clone()].iter());
+        Frame::new(id as u64, Kind::Write, payload)
+            .write_to(&mut stream)
+            .await
+            .unwrap();
+        let frame = Frame::read_from(&mut stream).await.unwrap().unwrap();
+        assert_eq!(frame.kind, Kind::Error);
+        assert!(matches!(
+            protocol::decode_error(&frame.payload),
+            Error::InvalidArgument(_)
+        ));
+    }
+    let client = Client::connect(addr).await.unwrap();
+    assert_eq!(client.get(b"a").await.unwrap(), None);
+}
+
+async fn join_all<F: std::future::Future + Send + 'static>(
+    futures: impl Iterator<Item = F>,
+) -> Vec<F::Output>
+where
+    F::Output: Send,
+{
+    let handles: Vec<_> = futures.map(tokio::spawn).collect();
+    let mut results = Vec::new();
+    for handle in handles {
+        results.push(handle.await.unwrap());
+    }
+    results
+}
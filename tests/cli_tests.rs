@@ -0,0 +1,218 @@
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+};
+
+fn run(dir: &PathBuf, args: &[&str], script: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rustdb"))
+        .arg("--dir")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn create_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from("data").join(name);
+    fs::remove_dir_all(&dir).ok();
+    dir
+}
+
+#[test]
+fn test_script_reads_and_writes() {
+    let dir = create_dir("cli_script");
+    let output = run(
+        &dir,
+        &[],
+        "set a 1\nset \"b c\" \"two words\"\nget a\nget missing\ndel a\nget a\nflush\n\
+         set hex:00ff b64:aGk=\nscan\nscan b - 1\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "OK\nOK\n1\n(nil)\nOK\n(nil)\nOK\nOK\n\\x00\\xff\thi\nb c\ttwo words\nb c\ttwo words\n"
+    );
+
+    // the data is still there when reopened
+    let output = run(&dir, &["--format", "hex"], "get \"b c\"\nstats\n");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("74776f20776f726473\n"));
+    assert!(stdout.contains("level_0: tables=1"));
+}
+
+#[test]
+fn test_script_stops_at_first_error() {
+    let dir = create_dir("cli_error");
+    let output = run(&dir, &[], "set a 1\nnope\nset b 2\n");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unknown command nope"));
+    let output = run(&dir, &["--format", "base64"], "get a\nget b\n");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "MQ==\n(nil)\n");
+}
+
+#[test]
+fn test_sst_dump_prints_and_checks_table() {
+    let dir = create_dir("cli_sst_dump");
+    let output = run(&dir, &[], "set a 1\nset b 2\ndel b\nflush\n");
+    assert!(output.status.success());
+    let table = fs::read_dir(&dir)
+        .unwrap()
+        .map(|file| file.unwrap().path())
+        .find(|path| path.to_string_lossy().ends_with(".data.sst"))
+        .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rustdb"))
+        .arg("sst-dump")
+        .arg(&table)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("offset=0 key=a timestamp="));
+    assert!(stdout.contains("key=b timestamp="));
+    assert!(stdout.contains(" tombstone\n"));
+    assert!(stdout.contains("checksum=ok"));
+    assert!(stdout.contains("  key_range: a .. b\n"));
+    assert!(stdout.contains("  tombstones: 1\n"));
+    assert!(stdout.ends_with("ok\n"));
+
+    // a truncated data file is reported
+    let len = fs::metadata(&table).unwrap().len();
+    fs::File::options()
+        .write(true)
+        .open(&table)
+        .unwrap()
+        .set_len(len - 1)
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rustdb"))
+        .args(["sst-dump", "--summary"])
+        .arg(&table)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("are not a complete record"));
+}
+
+#[test]
+fn test_wal_dump_and_repair() {
+    let dir = create_dir("cli_wal");
+    let output = run(&dir, &[], "set a 1\nset b 22\ndel a\n");
+    assert!(output.status.success());
+    let wal = fs::read_dir(&dir)
+        .unwrap()
+        .map(|file| file.unwrap().path())
+        .find(|path| path.extension().is_some_and(|e| e == "log"))
+        .unwrap();
+    let wal_dump = |wal: &PathBuf| {
+        Command::new(env!("CARGO_BIN_EXE_rustdb"))
+            .arg("wal-dump")
+            .arg(wal)
+            .output()
+            .unwrap()
+    };
+
+    let output = wal_dump(&wal);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("op=put key=a value_len=1 timestamp="));
+    assert!(stdout.contains("op=put key=b value_len=2 timestamp="));
+    assert!(stdout.contains("op=delete key=a timestamp="));
+    assert!(stdout.contains("  records: 3\n"));
+    assert!(stdout.ends_with("ok\n"));
+
+    // a batch header without its records
+    let len = fs::metadata(&wal).unwrap().len();
+    let mut file = fs::File::options().append(true).open(&wal).unwrap();
+    file.write_all(&9u64.to_le_bytes()).unwrap();
+    file.write_all(&1u32.to_le_bytes()).unwrap();
+    file.write_all(&[1, 2]).unwrap();
+    let output = wal_dump(&wal);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!("partial batch at offset {} (14 bytes)", len)));
+
+    let salvage = create_dir("cli_wal_salvage");
+    let output = Command::new(env!("CARGO_BIN_EXE_rustdb"))
+        .args(["wal-repair", "--salvage"])
+        .arg(&salvage)
+        .arg(&wal)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("salvaged into"));
+    assert_eq!(fs::metadata(&wal).unwrap().len(), len + 14);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rustdb"))
+        .arg("wal-repair")
+        .arg(&wal)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(fs::metadata(&wal).unwrap().len(), len);
+    assert!(wal_dump(&wal).status.success());
+}
+
+#[test]
+fn test_repair_rebuilds_missing_index() {
+    let dir = create_dir("cli_repair");
+    let output = run(&dir, &[], "set a 1\nflush\nset b 2\n");
+    assert!(output.status.success());
+    let index = fs::read_dir(&dir)
+        .unwrap()
+        .map(|file| file.unwrap().path())
+        .find(|path| path.to_string_lossy().ends_with(".index.sst"))
+        .unwrap();
+    fs::remove_file(index).unwrap();
+
+    let output = run(&dir, &["repair"], "");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(": 1 records into table"));
+    let output = run(&dir, &[], "get a\nget b\n");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n2\n");
+}
+
+#[test]
+fn test_check_reports_damaged_table() {
+    let dir = create_dir("cli_check");
+    let output = run(&dir, &[], "set a 1\nset b 2\nflush\nset c 3\n");
+    assert!(output.status.success());
+    let output = run(&dir, &["check"], "");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(" level=0 entries=2 blocks=1 ok\n"));
+    assert!(stdout.contains(".log records=1 ok\n"));
+    assert!(stdout.ends_with("ok\n"));
+
+    let data = fs::read_dir(&dir)
+        .unwrap()
+        .map(|file| file.unwrap().path())
+        .find(|path| path.to_string_lossy().ends_with(".data.sst"))
+        .unwrap();
+    let mut bytes = fs::read(&data).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    fs::write(&data, bytes).unwrap();
+    let output = run(&dir, &["check"], "");
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("checksum mismatch in block at offset 0"));
+    assert!(stdout.ends_with("damaged\n"));
+}
@@ -0,0 +1,227 @@
+use std::{fs, net::SocketAddr, path::PathBuf};
+
+use rustdb::{database::async_database::AsyncDatabase, server::http::HttpServer};
+use serde_json::Value as Json;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn json_lines(&self) -> Vec<Json> {
+        self.body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect()
+    }
+}
+
+struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    async fn connect(addr: SocketAddr) -> Client {
+        let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        Client {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    async fn request(&mut self, method: &str, target: &str, body: &[u8]) -> Response {
+        let head = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+            method,
+            target,
+            body.len()
+        );
+        self.writer.write_all(head.as_bytes()).await.unwrap();
+        self.writer.write_all(body).await.unwrap();
+        self.read_response().await
+    }
+
+    async fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).await.unwrap();
+        line.trim_end().to_string()
+    }
+
+    async fn read_response(&mut self) -> Response {
+        let status = self.read_line().await;
+        let status = status.split(' ').nth(1).unwrap().parse().unwrap();
+        let mut headers = Vec::new();
+        loop {
+            let line = self.read_line().await;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap();
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+        let mut response = Response {
+            status,
+            headers,
+            body: Vec::new(),
+        };
+        if response.header("Transfer-Encoding") == Some("chunked") {
+            loop {
+                let len = usize::from_str_radix(&self.read_line().await, 16).unwrap();
+                let mut chunk = vec![0; len + 2];
+                self.reader.read_exact(&mut chunk).await.unwrap();
+                if len == 0 {
+                    break;
+                }
+                response.body.extend_from_slice(&chunk[..len]);
+            }
+        } else if let Some(len) = response.header("Content-Length") {
+            response.body = vec![0; len.parse().unwrap()];
+            self.reader.read_exact(&mut response.body).await.unwrap();
+        }
+        response
+    }
+}
+
+async fn start_server(name: &str) -> SocketAddr {
+    let dir = PathBuf::from("data").join(name);
+    fs::remove_dir_all(&dir).ok();
+    let db = AsyncDatabase::open(dir).await.unwrap();
+    let server = HttpServer::bind("127.0.0.1:0", db).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run());
+    addr
+}
+
+#[tokio::test]
+async fn test_get_put_delete() {
+    let mut client = Client::connect(start_server("http_kv").await).await;
+    assert_eq!(client.request("GET", "/kv/a", b"").await.status, 404);
+    assert_eq!(client.request("PUT", "/kv/a", b"1").await.status, 204);
+    let response = client.request("GET", "/kv/a", b"").await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"1");
+
+    // keys and values are binary-safe
+    let value = [0u8, 255, b'\r', b'\n', 7];
+    let response = client.request("PUT", "/kv/%00%2F%FF", &value).await;
+    assert_eq!(response.status, 204);
+    assert_eq!(
+        client.request("GET", "/kv/%00%2F%FF", b"").await.body,
+        value
+    );
+
+    assert_eq!(client.request("DELETE", "/kv/a", b"").await.status, 204);
+    assert_eq!(client.request("GET", "/kv/a", b"").await.status, 404);
+}
+
+#[tokio::test]
+async fn test_scan_streams_range() {
+    let mut client = Client::connect(start_server("http_scan").await).await;
+    for i in 0..20 {
+        let target = format!("/kv/key{:02}", i);
+        client.request("PUT", &target, b"v").await;
+    }
+    client.request("DELETE", "/kv/key05", b"").await;
+
+    let response = client
+        .request("GET", "/scan?start=key03&end=key10&limit=5", b"")
+        .await;
+    assert_eq!(response.status, 200);
+    let keys: Vec<&str> = response
+        .json_lines()
+        .iter()
+        .map(|line| match line["key"].as_str().unwrap() {
+            "a2V5MDM=" => "key03",
+            "a2V5MDQ=" => "key04",
+            "a2V5MDY=" => "key06",
+            "a2V5MDc=" => "key07",
+            "a2V5MDg=" => "key08",
+            key => panic!("unexpected key {}", key),
+        })
+        .collect();
+    assert_eq!(keys, ["key03", "key04", "key06", "key07", "key08"]);
+
+    let response = client.request("GET", "/scan", b"").await;
+    assert_eq!(response.json_lines().len(), 19);
+}
+
+#[tokio::test]
+async fn test_batch_is_applied() {
+    let mut client = Client::connect(start_server("http_batch").await).await;
+    client.request("PUT", "/kv/old", b"v").await;
+    let batch = br#"{"ops": [
+        {"op": "put", "key": "YQ==", "value": "MQ=="},
+        {"op": "put", "key": "Yg==", "value": "Mg=="},
+        {"op": "delete", "key": "b2xk"}
+    ]}"#;
+    let response = client.request("POST", "/batch", batch).await;
+    assert_eq!(response.status, 200);
+    let body: Json = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(body["applied"], 3);
+    assert_eq!(client.request("GET", "/kv/a", b"").await.body, b"1");
+    assert_eq!(client.request("GET", "/kv/b", b"").await.body, b"2");
+    assert_eq!(client.request("GET", "/kv/old", b"").await.status, 404);
+
+    // a malformed batch applies nothing
+    let batch = br#"{"ops": [
+        {"op": "put", "key": "Yw==", "value": "Mw=="},
+        {"op": "merge", "key": "Yw=="}
+    ]}"#;
+    let response = client.request("POST", "/batch", batch).await;
+    assert_eq!(response.status, 400);
+    assert_eq!(client.request("GET", "/kv/c", b"").await.status, 404);
+}
+
+#[tokio::test]
+async fn test_errors() {
+    let mut client = Client::connect(start_server("http_errors").await).await;
+    assert_eq!(client.request("GET", "/nope", b"").await.status, 404);
+    assert_eq!(client.request("POST", "/kv/a", b"").await.status, 405);
+    assert_eq!(client.request("GET", "/kv/%zz", b"").await.status, 400);
+    let response = client.request("POST", "/batch", b"not json").await;
+    assert_eq!(response.status, 400);
+    let body: Json = serde_json::from_slice(&response.body).unwrap();
+    assert!(body["error"].as_str().unwrap().starts_with("invalid JSON"));
+    // the connection is still usable after errors
+    assert_eq!(client.request("PUT", "/kv/a", b"1").await.status, 204);
+}
+
+#[tokio::test]
+async fn test_writes_survive_restart() {
+    let dir = PathBuf::from("data").join("http_restart");
+    fs::remove_dir_all(&dir).ok();
+    let db = AsyncDatabase::open(dir.clone()).await.unwrap();
+    let server = HttpServer::bind("127.0.0.1:0", db).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let handle = tokio::spawn(server.run_until(async {
+        stopped.await.ok();
+    }));
+
+    let mut client = Client::connect(addr).await;
+    assert_eq!(client.request("PUT", "/kv/a", b"1").await.status, 204);
+    drop(client);
+    stop.send(()).unwrap();
+    handle.await.unwrap().unwrap();
+
+    let db = AsyncDatabase::open(dir).await.unwrap();
+    assert_eq!(db.get("a").await.unwrap().unwrap().value.unwrap(), b"1");
+}
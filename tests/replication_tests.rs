@@ -0,0 +1,207 @@
+use std::{fs, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use rustdb::{
+    database::{
+        async_database::AsyncDatabase,
+        comparator::U64Comparator,
+        merge_operator::U64AddOperator,
+        options::{ColumnFamilyOptions, Options},
+        write_batch::WriteBatch,
+    },
+    replication::follower::Follower,
+    server::binary::BinaryServer,
+    Error,
+};
+use tokio::{sync::oneshot, time};
+use tokio_stream::StreamExt;
+
+const MAX_STALENESS: Duration = Duration::from_secs(1);
+
+fn create_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from("data").join(name);
+    fs::remove_dir_all(&dir).ok();
+    dir
+}
+
+async fn start_leader(dir: PathBuf) -> (AsyncDatabase, SocketAddr, oneshot::Sender<()>) {
+    start_leader_with_options(dir, Options::default()).await
+}
+
+async fn start_leader_with_options(
+    dir: PathBuf,
+    options: Options,
+) -> (AsyncDatabase, SocketAddr, oneshot::Sender<()>) {
+    let db = AsyncDatabase::open_with_options(dir, options)
+        .await
+        .unwrap();
+    let server = BinaryServer::bind("127.0.0.1:0", db.clone()).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    tokio::spawn(server.run_until(async {
+        stopped.await.ok();
+    }));
+    (db, addr, stop)
+}
+
+/// Waits until the follower has `seqno` and knows it is caught up.
+async fn wait_for(follower: &Follower, seqno: u64) {
+    for _ in 0..200 {
+        let staleness = follower.staleness();
+        if follower.last_sequence().await >= Some(seqno)
+            && staleness.is_some_and(|s| s < MAX_STALENESS)
+        {
+            return;
+        }
+        time::sleep(Duration::from_millis(25)).await;
+    }
+    panic!("follower did not reach seqno {}", seqno);
+}
+
+async fn value(follower: &Follower, key: &[u8]) -> Option<Vec<u8>> {
+    let entry = follower.get(key.to_vec()).await.unwrap()?;
+    entry.value.filter(|_| !entry.deleted)
+}
+
+#[tokio::test]
+async fn test_follower_applies_writes_and_resumes() {
+    let dir = create_dir("replication_follow");
+    let (leader, addr, _stop) = start_leader(dir.join("leader")).await;
+    leader.set(b"a".to_vec(), b"1".to_vec(), 1).await.unwrap();
+    let follower = Follower::start(dir.join("follower"), addr, MAX_STALENESS)
+        .await
+        .unwrap();
+    wait_for(&follower, 1).await;
+    assert_eq!(value(&follower, b"a").await, Some(b"1".to_vec()));
+
+    let mut batch = WriteBatch::new();
+    batch.put(b"b", b"2", 2);
+    batch.delete(b"a", 2);
+    leader.write(batch).await.unwrap();
+    wait_for(&follower, 3).await;
+    assert_eq!(value(&follower, b"a").await, None);
+    let entry = follower.get(b"b".to_vec()).await.unwrap().unwrap();
+    assert_eq!((entry.value, entry.timestamp), (Some(b"2".to_vec()), 2));
+
+    // a restarted follower goes on from its last write
+    follower.stop().await;
+    leader.set(b"c".to_vec(), b"3".to_vec(), 3).await.unwrap();
+    let follower = Follower::start(dir.join("follower"), addr, MAX_STALENESS)
+        .await
+        .unwrap();
+    wait_for(&follower, 4).await;
+    let keys: Vec<Vec<u8>> = follower
+        .scan(..)
+        .await
+        .unwrap()
+        .map(|entry| entry.unwrap().key)
+        .collect()
+        .await;
+    assert_eq!(keys, vec![b"b".to_vec(), b"c".to_vec()]);
+}
+
+#[tokio::test]
+async fn test_follower_bootstraps_from_checkpoint() {
+    let dir = create_dir("replication_bootstrap");
+    let (leader, addr, _stop) = start_leader(dir.join("leader")).await;
+    for i in 0..10u8 {
+        leader.set(vec![i], vec![i], 1).await.unwrap();
+    }
+    // the WAL with the first writes is deleted
+    leader.flush().await.unwrap();
+    leader.set(vec![10], vec![10], 2).await.unwrap();
+
+    let follower = Follower::start(dir.join("follower"), addr, MAX_STALENESS)
+        .await
+        .unwrap();
+    wait_for(&follower, 11).await;
+    for i in 0..=10u8 {
+        assert_eq!(value(&follower, &[i]).await, Some(vec![i]));
+    }
+    leader.delete(vec![0], 3).await.unwrap();
+    wait_for(&follower, 12).await;
+    assert_eq!(value(&follower, &[0]).await, None);
+}
+
+#[tokio::test]
+async fn test_reads_fail_when_replica_is_stale() {
+    let dir = create_dir("replication_stale");
+    let (leader, addr, stop) = start_leader(dir.join("leader")).await;
+    let follower = Follower::start(dir.join("follower"), addr, Duration::from_millis(500))
+        .await
+        .unwrap();
+    assert!(matches!(
+        follower.get(b"a".to_vec()).await,
+        Err(Error::Busy(_))
+    ));
+    leader.set(b"a".to_vec(), b"1".to_vec(), 1).await.unwrap();
+    wait_for(&follower, 1).await;
+    assert_eq!(value(&follower, b"a").await, Some(b"1".to_vec()));
+
+    stop.send(()).unwrap();
+    time::sleep(Duration::from_millis(800)).await;
+    assert!(matches!(
+        follower.get(b"a".to_vec()).await,
+        Err(Error::Busy(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_follower_picks_up_new_column_family() {
+    let dir = create_dir("replication_column_family");
+    let (leader, addr, _stop) = start_leader(dir.join("leader")).await;
+    leader.set(b"a".to_vec(), b"1".to_vec(), 1).await.unwrap();
+    let follower = Follower::start(dir.join("follower"), addr, MAX_STALENESS)
+        .await
+        .unwrap();
+    wait_for(&follower, 1).await;
+
+    // the family is only in the leader's MANIFEST, so the follower needs a checkpoint
+    let users = leader
+        .create_column_family("users", ColumnFamilyOptions::default())
+        .await
+        .unwrap();
+    leader
+        .set_cf(&users, b"a".to_vec(), b"2".to_vec(), 2)
+        .await
+        .unwrap();
+    wait_for(&follower, 2).await;
+    let users = follower.column_family("users").await.unwrap().unwrap();
+    let entry = follower
+        .get_cf(&users, b"a".to_vec())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(entry.value, Some(b"2".to_vec()));
+    assert_eq!(value(&follower, b"a").await, Some(b"1".to_vec()));
+}
+
+#[tokio::test]
+async fn test_follower_uses_leader_options() {
+    let dir = create_dir("replication_options");
+    let options = || Options {
+        comparator: Arc::new(U64Comparator),
+        merge_operator: Some(Arc::new(U64AddOperator)),
+        ..Default::default()
+    };
+    let (leader, addr, _stop) = start_leader_with_options(dir.join("leader"), options()).await;
+    leader
+        .set(vec![1], 5u64.to_le_bytes().to_vec(), 1)
+        .await
+        .unwrap();
+    // the follower has to bootstrap, which checks the comparator
+    leader.flush().await.unwrap();
+    leader
+        .merge(vec![1], 2u64.to_le_bytes().to_vec(), 2)
+        .await
+        .unwrap();
+
+    let follower =
+        Follower::start_with_options(dir.join("follower"), addr, MAX_STALENESS, options())
+            .await
+            .unwrap();
+    wait_for(&follower, 2).await;
+    assert_eq!(
+        value(&follower, &[1]).await,
+        Some(7u64.to_le_bytes().to_vec())
+    );
+}
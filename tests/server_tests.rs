@@ -0,0 +1,175 @@
+use std::{fs, net::SocketAddr, path::PathBuf};
+
+use rustdb::{
+    database::async_database::AsyncDatabase,
+    server::{
+        resp::{read_value, Value},
+        server::Server,
+    },
+};
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    async fn connect(addr: SocketAddr) -> Client {
+        let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        Client {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    async fn send(&mut self, args: &[&str]) {
+        let command = Value::Array(
+            args.iter()
+                .map(|arg| Value::Bulk(arg.as_bytes().to_vec()))
+                .collect(),
+        );
+        command.write_to(&mut self.writer).await.unwrap();
+    }
+
+    async fn call(&mut self, args: &[&str]) -> Value {
+        self.send(args).await;
+        read_value(&mut self.reader).await.unwrap()
+    }
+}
+
+fn bulk(value: &str) -> Value {
+    Value::Bulk(value.as_bytes().to_vec())
+}
+
+async fn start_server(name: &str) -> SocketAddr {
+    let dir = PathBuf::from("data").join(name);
+    fs::remove_dir_all(&dir).ok();
+    let db = AsyncDatabase::open(dir).await.unwrap();
+    let server = Server::bind("127.0.0.1:0", db).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run());
+    addr
+}
+
+#[tokio::test]
+async fn test_ping_get_set() {
+    let mut client = Client::connect(start_server("server_get_set").await).await;
+    assert_eq!(client.call(&["PING"]).await, Value::Simple("PONG".into()));
+    assert_eq!(client.call(&["ping", "hi"]).await, bulk("hi"));
+    assert_eq!(client.call(&["GET", "a"]).await, Value::Null);
+    assert_eq!(client.call(&["SET", "a", "1"]).await, Value::ok());
+    assert_eq!(client.call(&["GET", "a"]).await, bulk("1"));
+    assert_eq!(client.call(&["SET", "a", "2"]).await, Value::ok());
+    assert_eq!(client.call(&["GET", "a"]).await, bulk("2"));
+}
+
+#[tokio::test]
+async fn test_multi_key_commands() {
+    let mut client = Client::connect(start_server("server_multi_key").await).await;
+    assert_eq!(
+        client.call(&["MSET", "a", "1", "b", "2"]).await,
+        Value::ok()
+    );
+    assert_eq!(
+        client.call(&["MGET", "a", "x", "b"]).await,
+        Value::Array(vec![bulk("1"), Value::Null, bulk("2")])
+    );
+    assert_eq!(
+        client.call(&["EXISTS", "a", "b", "x", "a"]).await,
+        Value::Integer(3)
+    );
+    assert_eq!(client.call(&["DEL", "a", "x"]).await, Value::Integer(1));
+    assert_eq!(client.call(&["EXISTS", "a"]).await, Value::Integer(0));
+    assert_eq!(client.call(&["GET", "a"]).await, Value::Null);
+}
+
+#[tokio::test]
+async fn test_scan_walks_all_keys() {
+    let mut client = Client::connect(start_server("server_scan").await).await;
+    for i in 0..25 {
+        let key = format!("key:{:02}", i);
+        client.call(&["SET", &key, "v"]).await;
+    }
+    client.call(&["SET", "other", "v"]).await;
+
+    let mut keys = Vec::new();
+    let mut cursor = "0".to_string();
+    loop {
+        let Value::Array(reply) = client
+            .call(&["SCAN", &cursor, "MATCH", "key:*", "COUNT", "7"])
+            .await
+        else {
+            panic!("SCAN did not return an array");
+        };
+        let [Value::Bulk(next), Value::Array(batch)] = reply.as_slice() else {
+            panic!("unexpected SCAN reply {:?}", reply);
+        };
+        keys.extend(batch.iter().cloned());
+        cursor = String::from_utf8(next.clone()).unwrap();
+        if cursor == "0" {
+            break;
+        }
+    }
+    let expected: Vec<Value> = (0..25).map(|i| bulk(&format!("key:{:02}", i))).collect();
+    assert_eq!(keys, expected);
+}
+
+#[tokio::test]
+async fn test_errors_and_inline_commands() {
+    let mut client = Client::connect(start_server("server_errors").await).await;
+    assert!(matches!(
+        client.call(&["NOPE"]).await,
+        Value::Error(message) if message.starts_with("ERR unknown command")
+    ));
+    assert!(matches!(
+        client.call(&["GET"]).await,
+        Value::Error(message) if message.starts_with("ERR wrong number of arguments")
+    ));
+    client
+        .writer
+        .write_all(b"SET x 1\r\nGET x\r\n")
+        .await
+        .unwrap();
+    assert_eq!(read_value(&mut client.reader).await.unwrap(), Value::ok());
+    assert_eq!(read_value(&mut client.reader).await.unwrap(), bulk("1"));
+    let Value::Bulk(info) = client.call(&["INFO"]).await else {
+        panic!("INFO did not return a bulk string");
+    };
+    assert!(String::from_utf8(info)
+        .unwrap()
+        .contains("connected_clients:1"));
+}
+
+#[tokio::test]
+async fn test_pipelined_writes_survive_restart() {
+    let dir = PathBuf::from("data").join("server_restart");
+    fs::remove_dir_all(&dir).ok();
+    let db = AsyncDatabase::open(dir.clone()).await.unwrap();
+    let server = Server::bind("127.0.0.1:0", db).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let handle = tokio::spawn(server.run_until(async {
+        stopped.await.ok();
+    }));
+
+    let mut client = Client::connect(addr).await;
+    for i in 0..10 {
+        client.send(&["SET", &i.to_string(), "v"]).await;
+    }
+    for _ in 0..10 {
+        assert_eq!(read_value(&mut client.reader).await.unwrap(), Value::ok());
+    }
+    drop(client);
+    stop.send(()).unwrap();
+    handle.await.unwrap().unwrap();
+
+    let db = AsyncDatabase::open(dir).await.unwrap();
+    assert!(db.get("9").await.unwrap().is_some());
+}